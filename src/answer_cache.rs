@@ -0,0 +1,159 @@
+//! On-disk cache of LLM answers, so re-running after a crash or an expired login doesn't ask (and pay for) the
+//! questions that were already answered
+//!
+//! Entries are keyed by a hash of the question's type, text and sorted option texts, and hold the answer in its
+//! answers-file form ([`StoredAnswer`]): options are referred to by text, since Moodle renames inputs and shuffles
+//! option values between attempts. Choice and ordering indices are carried over through the texts they pointed at.
+
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	Question,
+	answers::{StoredAnswer, fnv1a_hex, from_answer_result, normalize_whitespace, option_texts, to_answer_result},
+	llm::LlmAnswer,
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct CacheEntry {
+	/// Unix timestamp of when the answer was cached
+	saved_at: u64,
+	/// Choice (or ordering item) texts in the order they were displayed, which the answer's indices refer to
+	#[serde(default)]
+	displayed: Vec<String>,
+	#[serde(default)]
+	confidence: Option<u8>,
+	#[serde(default)]
+	reasoning: Option<String>,
+	answer: StoredAnswer,
+}
+
+/// LLM answers from previous runs, written through to disk after every new answer
+#[derive(Clone, Debug, Default)]
+pub struct AnswerCache {
+	path: PathBuf,
+	entries: BTreeMap<String, CacheEntry>,
+}
+
+impl AnswerCache {
+	/// Load the cache at `path`, dropping entries older than `ttl_secs`. A missing or unreadable file starts an
+	/// empty cache.
+	pub fn load(path: PathBuf, ttl_secs: u64) -> Self {
+		let mut entries: BTreeMap<String, CacheEntry> = match std::fs::read_to_string(&path) {
+			Ok(content) => serde_json::from_str(&content).unwrap_or_else(|e| {
+				tracing::warn!("Ignoring unreadable answer cache {}: {e}", path.display());
+				BTreeMap::new()
+			}),
+			Err(_) => BTreeMap::new(),
+		};
+		let now = now_secs();
+		entries.retain(|_, entry| now.saturating_sub(entry.saved_at) <= ttl_secs);
+		Self { path, entries }
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Cached answer for a question, if there is one that still fits it (same options, possibly reshuffled)
+	pub fn lookup(&self, question: &Question) -> Option<LlmAnswer> {
+		let entry = self.entries.get(&cache_key(question))?;
+		let stored = carry_over_indices(&entry.answer, &entry.displayed, &displayed_texts(question))?;
+		match to_answer_result(&stored, question) {
+			Ok(result) => Some(LlmAnswer {
+				result,
+				confidence: entry.confidence,
+				reasoning: entry.reasoning.clone(),
+			}),
+			Err(e) => {
+				tracing::debug!("Cached answer doesn't fit the question anymore: {e}");
+				None
+			}
+		}
+	}
+
+	/// Cache an answer and write the cache to disk
+	pub fn record(&mut self, question: &Question, answer: &LlmAnswer) {
+		let Some(stored) = from_answer_result(&answer.result, question) else {
+			tracing::warn!("Could not cache answer for question: {}", question.question_text());
+			return;
+		};
+		let entry = CacheEntry {
+			saved_at: now_secs(),
+			displayed: displayed_texts(question).into_iter().map(str::to_string).collect(),
+			confidence: answer.confidence,
+			reasoning: answer.reasoning.clone(),
+			answer: stored,
+		};
+		self.entries.insert(cache_key(question), entry);
+		self.save();
+	}
+
+	/// Drop the cached answer for a question (e.g. after Moodle marked it wrong)
+	pub fn forget(&mut self, question: &Question) {
+		if self.entries.remove(&cache_key(question)).is_some() {
+			self.save();
+		}
+	}
+
+	fn save(&self) {
+		if let Err(e) = write_entries(&self.path, &self.entries) {
+			tracing::warn!("Failed to write answer cache {}: {e}", self.path.display());
+		}
+	}
+}
+
+fn write_entries(path: &Path, entries: &BTreeMap<String, CacheEntry>) -> std::io::Result<()> {
+	let json = serde_json::to_string_pretty(entries).map_err(std::io::Error::other)?;
+	std::fs::write(path, json)
+}
+
+/// Hash of the question type, its whitespace-normalized text and its sorted option texts
+fn cache_key(question: &Question) -> String {
+	let mut options: Vec<String> = option_texts(question).into_iter().map(normalize_whitespace).collect();
+	options.sort_unstable();
+	let mut key = format!("{}\n{}", question.type_marker(), normalize_whitespace(question.question_text()));
+	for option in options {
+		key.push('\n');
+		key.push_str(&option);
+	}
+	fnv1a_hex(&key)
+}
+
+/// Texts that index-based answers (single, multi, ordering) point into, in display order
+fn displayed_texts(question: &Question) -> Vec<&str> {
+	match question {
+		Question::Ordering { items, .. } => items.iter().map(|i| i.text.as_str()).collect(),
+		_ => question.choices().iter().map(|c| c.text.as_str()).collect(),
+	}
+}
+
+/// Re-point the indices of a cached answer from the order it was answered in to the current display order.
+/// None if a text it pointed at is gone.
+fn carry_over_indices(answer: &StoredAnswer, then: &[String], now: &[&str]) -> Option<StoredAnswer> {
+	let remap = |idx: &mut usize| -> Option<()> {
+		let text = then.get(*idx)?;
+		*idx = now.iter().position(|t| *t == text.as_str())?;
+		Some(())
+	};
+	let mut answer = answer.clone();
+	match &mut answer {
+		StoredAnswer::Single { index, .. } => remap(index)?,
+		StoredAnswer::Multi { indices, .. } => indices.iter_mut().try_for_each(remap)?,
+		StoredAnswer::Ordering { order, .. } => order.iter_mut().try_for_each(remap)?,
+		_ => {}
+	}
+	Some(answer)
+}
+
+fn now_secs() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
@@ -0,0 +1,360 @@
+//! Answers typed in at the confirm prompt (`confirm_mode = "edit"`), replacing a rejected LLM answer
+//!
+//! The syntax follows what the terminal shows for each question kind:
+//! - single choice: the choice number, `2`
+//! - multi choice: choice numbers, `1,3`
+//! - short answer, essay: the text as is
+//! - numerical: the number, then the unit if the question has a unit dropdown, `9.81 m/s^2`
+//! - matching, fill in the blanks, drag and drop: `item=option` pairs, `3=b,4=a`. Options are given by letter in
+//!   the order they are listed, by number, or by their text; items left out keep the LLM's answer
+//! - ordering: item numbers in the new order, `3,1,2`
+//!
+//! Code answers can't be typed in on one line.
+
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+
+use crate::{
+	Blank, MatchOption, Question,
+	llm::{FillInBlanksAnswerItem, LlmAnswerResult, paragraphs_to_html},
+};
+
+/// What to type for `question`, for the prompt
+pub fn edit_hint(question: &Question) -> Option<&'static str> {
+	let hint = match question {
+		Question::SingleChoice { .. } => "choice number, e.g. 2",
+		Question::MultiChoice { .. } => "choice numbers, e.g. 1,3",
+		Question::ShortAnswer { .. } | Question::Essay { .. } => "the answer text",
+		Question::Numerical { units, .. } if !units.is_empty() => "number and unit, e.g. 9.81 m/s^2",
+		Question::Numerical { .. } => "the number",
+		Question::Matching { .. } => "item=option pairs, e.g. 3=b,4=a",
+		Question::FillInBlanks(_) => "blank=answer pairs, e.g. 1=Paris,2=b",
+		Question::DragDropIntoText(_) => "place=choice pairs, e.g. 1=b,2=cat",
+		Question::Ordering { .. } => "item numbers in order, e.g. 3,1,2",
+		Question::CodeBlock { .. } | Question::CodeSubmission { .. } | Question::Description { .. } | Question::Unknown { .. } => return None,
+	};
+	Some(hint)
+}
+
+/// Parse a typed-in answer for `question`; `base` (the rejected answer) fills in the items that aren't mentioned
+pub fn parse_answer_edit(input: &str, question: &Question, base: &LlmAnswerResult) -> Result<LlmAnswerResult> {
+	let input = input.trim();
+	if input.is_empty() {
+		bail!("empty answer");
+	}
+
+	let result = match question {
+		Question::SingleChoice { choices, .. } => {
+			let idx = parse_number(input, choices.len())?;
+			LlmAnswerResult::Single {
+				idx,
+				text: choices[idx].text.clone(),
+			}
+		}
+		Question::MultiChoice { choices, .. } => {
+			let mut indices = Vec::new();
+			for part in split_list(input) {
+				let idx = parse_number(part, choices.len())?;
+				if !indices.contains(&idx) {
+					indices.push(idx);
+				}
+			}
+			let texts = indices.iter().map(|&i| choices[i].text.clone()).collect();
+			LlmAnswerResult::Multi { indices, texts }
+		}
+		Question::ShortAnswer { .. } => LlmAnswerResult::Text { answer: input.to_string() },
+		Question::Essay { .. } => LlmAnswerResult::Essay { html: paragraphs_to_html(input) },
+		Question::Numerical { unit_select_name, units, .. } => {
+			let unit_of = |text: &str| units.iter().find(|u| u.text.trim().eq_ignore_ascii_case(text.trim()));
+			match (unit_select_name, input.split_once(char::is_whitespace)) {
+				(Some(select_name), Some((number, unit))) => {
+					let unit = unit_of(unit).ok_or_else(|| eyre!("unknown unit \"{}\"", unit.trim()))?;
+					LlmAnswerResult::Numerical {
+						answer: number.to_string(),
+						unit: Some((select_name.clone(), unit.value.clone())),
+					}
+				}
+				_ => {
+					let unit = match base {
+						LlmAnswerResult::Numerical { unit, .. } => unit.clone(),
+						_ => None,
+					};
+					LlmAnswerResult::Numerical { answer: input.to_string(), unit }
+				}
+			}
+		}
+		Question::Matching { items, .. } => {
+			let mut selections = match base {
+				LlmAnswerResult::Matching { selections } => selections.clone(),
+				_ => Vec::new(),
+			};
+			for (item_number, option) in parse_pairs(input)? {
+				let item = items.get(parse_number(item_number, items.len())?).expect("checked by parse_number");
+				let option = pick_option(&item.options, option)?;
+				selections.retain(|(name, _)| name != &item.select_name);
+				selections.push((item.select_name.clone(), option.value.clone()));
+			}
+			LlmAnswerResult::Matching { selections }
+		}
+		Question::FillInBlanks(fill) => {
+			let mut answers = match base {
+				LlmAnswerResult::FillInBlanks { answers } => answers.clone(),
+				_ => Vec::new(),
+			};
+			for (blank_number, value) in parse_pairs(input)? {
+				let item = match &fill.blanks[parse_number(blank_number, fill.blanks.len())?] {
+					Blank::Text { input_name, .. } => FillInBlanksAnswerItem::Text {
+						input_name: input_name.clone(),
+						answer: value.to_string(),
+					},
+					Blank::Select { select_name, options, .. } => FillInBlanksAnswerItem::Select {
+						select_name: select_name.clone(),
+						value: pick_option(options, value)?.value.clone(),
+					},
+				};
+				answers.retain(|existing| fill_item_name(existing) != fill_item_name(&item));
+				answers.push(item);
+			}
+			LlmAnswerResult::FillInBlanks { answers }
+		}
+		Question::DragDropIntoText(dd) => {
+			let mut placements = match base {
+				LlmAnswerResult::DragDropIntoText { placements } => placements.clone(),
+				_ => Vec::new(),
+			};
+			for (place_number, choice) in parse_pairs(input)? {
+				let zone = &dd.drop_zones[parse_number(place_number, dd.drop_zones.len())?];
+				let group: Vec<_> = dd.choices.iter().filter(|c| c.group == zone.group).collect();
+				let choice = match pick_index(choice, group.len()) {
+					Some(i) => group[i],
+					None => *group
+						.iter()
+						.find(|c| c.text.trim().eq_ignore_ascii_case(choice))
+						.ok_or_else(|| eyre!("place {place_number} has no choice \"{choice}\""))?,
+				};
+				placements.retain(|(name, _)| name != &zone.input_name);
+				placements.push((zone.input_name.clone(), choice.choice_number));
+			}
+			LlmAnswerResult::DragDropIntoText { placements }
+		}
+		Question::Ordering { items, .. } => {
+			let order = split_list(input).map(|part| parse_number(part, items.len())).collect::<Result<Vec<_>>>()?;
+			let mut sorted = order.clone();
+			sorted.sort_unstable();
+			if sorted != (0..items.len()).collect::<Vec<_>>() {
+				bail!("expected each of the {} item numbers once", items.len());
+			}
+			LlmAnswerResult::Ordering { order }
+		}
+		Question::CodeBlock { .. } | Question::CodeSubmission { .. } | Question::Description { .. } | Question::Unknown { .. } =>
+			bail!("{} answers can't be typed in", question.type_marker()),
+	};
+	Ok(result)
+}
+
+/// Entries separated by commas and/or spaces
+fn split_list(input: &str) -> impl Iterator<Item = &str> {
+	input.split([',', ' ']).map(str::trim).filter(|part| !part.is_empty())
+}
+
+/// `a=b` pairs separated by commas
+fn parse_pairs(input: &str) -> Result<Vec<(&str, &str)>> {
+	input
+		.split(',')
+		.map(str::trim)
+		.filter(|part| !part.is_empty())
+		.map(|part| {
+			let (key, value) = part.split_once('=').ok_or_else(|| eyre!("expected number=answer, got \"{part}\""))?;
+			Ok((key.trim(), value.trim()))
+		})
+		.collect()
+}
+
+/// 1-based number as shown in the terminal, to a 0-based index below `len`
+fn parse_number(input: &str, len: usize) -> Result<usize> {
+	let n: usize = input.trim().parse().map_err(|_| eyre!("expected a number, got \"{input}\""))?;
+	if n == 0 || n > len {
+		bail!("{n} is out of range (1-{len})");
+	}
+	Ok(n - 1)
+}
+
+/// Index of a letter (`a` first) or 1-based number below `len`
+fn pick_index(input: &str, len: usize) -> Option<usize> {
+	let idx = match input.as_bytes() {
+		[letter @ b'a'..=b'z'] => (letter - b'a') as usize,
+		[letter @ b'A'..=b'Z'] => (letter - b'A') as usize,
+		_ => input.parse::<usize>().ok()?.checked_sub(1)?,
+	};
+	(idx < len).then_some(idx)
+}
+
+/// A dropdown option by letter, number (both counting only real options, as listed) or text
+fn pick_option<'a>(options: &'a [MatchOption], input: &str) -> Result<&'a MatchOption> {
+	let available: Vec<&MatchOption> = options.iter().filter(|o| !o.value.is_empty() && o.value != "0").collect();
+	if let Some(i) = pick_index(input, available.len()) {
+		return Ok(available[i]);
+	}
+	available
+		.into_iter()
+		.find(|o| o.text.trim().eq_ignore_ascii_case(input))
+		.ok_or_else(|| eyre!("no option \"{input}\""))
+}
+
+fn fill_item_name(item: &FillInBlanksAnswerItem) -> &str {
+	match item {
+		FillInBlanksAnswerItem::Text { input_name, .. } => input_name,
+		FillInBlanksAnswerItem::Select { select_name, .. } => select_name,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use serde_json::{Value, json};
+
+	use super::*;
+	use crate::{MatchItem, parse::parse_questions_from_html};
+
+	fn quiz_page() -> Vec<Question> {
+		parse_questions_from_html(include_str!("../tests/integration/fixtures/quiz_page.html")).expect("fixture parses")
+	}
+
+	fn fixture_question(html: &str) -> Question {
+		parse_questions_from_html(html).expect("fixture parses").remove(0)
+	}
+
+	fn edit(input: &str, question: &Question, base: &LlmAnswerResult) -> Value {
+		serde_json::to_value(parse_answer_edit(input, question, base).unwrap_or_else(|e| panic!("{input:?}: {e}"))).unwrap()
+	}
+
+	fn no_base() -> LlmAnswerResult {
+		LlmAnswerResult::Text { answer: String::new() }
+	}
+
+	#[test]
+	fn choices() {
+		let questions = quiz_page();
+		assert_eq!(edit(" 2 ", &questions[0], &no_base()), json!({"type": "single", "idx": 1, "text": "Une pile"}));
+		assert_eq!(edit("3, 1 3", &questions[1], &no_base()), json!({"type": "multi", "indices": [2, 0], "texts": ["Rust", "C"]}));
+		assert!(parse_answer_edit("4", &questions[0], &no_base()).is_err());
+		assert!(parse_answer_edit("0", &questions[1], &no_base()).is_err());
+	}
+
+	#[test]
+	fn text_answers() {
+		let questions = quiz_page();
+		assert_eq!(edit("def", &questions[2], &no_base()), json!({"type": "text", "answer": "def"}));
+		assert_eq!(
+			edit("A stack is LIFO.\n\nA queue is <FIFO>.", &questions[6], &no_base()),
+			json!({"type": "essay", "html": "<p>A stack is LIFO.</p><p>A queue is &lt;FIFO&gt;.</p>"})
+		);
+		assert!(parse_answer_edit("   ", &questions[2], &no_base()).is_err());
+	}
+
+	#[test]
+	fn numerical() {
+		let question = &quiz_page()[3];
+		assert_eq!(
+			edit("0,12 KM", question, &no_base()),
+			json!({"type": "numerical", "answer": "0,12", "unit": ["q77:4_unit", "km"]})
+		);
+		// No unit typed: the rejected answer's unit stays
+		let base = LlmAnswerResult::Numerical {
+			answer: "100".to_string(),
+			unit: Some(("q77:4_unit".to_string(), "m".to_string())),
+		};
+		assert_eq!(edit("120", question, &base), json!({"type": "numerical", "answer": "120", "unit": ["q77:4_unit", "m"]}));
+		assert!(parse_answer_edit("120 miles", question, &no_base()).is_err());
+	}
+
+	#[test]
+	fn matching() {
+		let options = ["0:Choose...", "11:Paris", "12:Berlin", "13:Madrid"].map(|o| {
+			let (value, text) = o.split_once(':').unwrap();
+			MatchOption {
+				value: value.to_string(),
+				text: text.to_string(),
+			}
+		});
+		let items = ["France", "Germany", "Spain"]
+			.iter()
+			.enumerate()
+			.map(|(i, prompt)| MatchItem {
+				prompt: prompt.to_string(),
+				select_name: format!("q5:1_sub{i}"),
+				options: options.to_vec(),
+				selected_value: "0".to_string(),
+			})
+			.collect();
+		let question = Question::Matching {
+			question_text: "Capitals".to_string(),
+			items,
+			images: Vec::new(),
+		};
+		let base = LlmAnswerResult::Matching {
+			selections: vec![("q5:1_sub0".to_string(), "12".to_string()), ("q5:1_sub2".to_string(), "13".to_string())],
+		};
+
+		// By letter, by number and by text; the placeholder doesn't count; item 3 keeps the LLM's answer
+		assert_eq!(
+			edit("1=a, 2=Berlin", &question, &base),
+			json!({"type": "matching", "selections": [["q5:1_sub2", "13"], ["q5:1_sub0", "11"], ["q5:1_sub1", "12"]]})
+		);
+		assert_eq!(edit("2=3", &question, &no_base()), json!({"type": "matching", "selections": [["q5:1_sub1", "13"]]}));
+		assert!(parse_answer_edit("1=d", &question, &base).is_err());
+		assert!(parse_answer_edit("1 a", &question, &base).is_err());
+	}
+
+	#[test]
+	fn fill_in_blanks() {
+		let questions = quiz_page();
+		assert_eq!(
+			edit("2=b", &questions[4], &no_base()),
+			json!({"type": "fill_in_blanks", "answers": [{"type": "select", "select_name": "q77:5_sub1", "value": "2"}]})
+		);
+		let base = LlmAnswerResult::FillInBlanks {
+			answers: vec![FillInBlanksAnswerItem::Text {
+				input_name: "q77:6_sub1_answer".to_string(),
+				answer: "3".to_string(),
+			}],
+		};
+		assert_eq!(
+			edit("1=2, 2=immuables", &questions[5], &base),
+			json!({"type": "fill_in_blanks", "answers": [
+				{"type": "text", "input_name": "q77:6_sub1_answer", "answer": "2"},
+				{"type": "select", "select_name": "q77:6_sub2_answer", "value": "1"},
+			]})
+		);
+		assert!(parse_answer_edit("3=x", &questions[5], &base).is_err());
+	}
+
+	#[test]
+	fn drag_drop_into_text() {
+		let question = fixture_question(include_str!("../tests/integration/fixtures/ddwtos.html"));
+		// Choices count within the place's group: place 3 is in group 2, where `b` is FIFO
+		assert_eq!(
+			edit("1=heap, 3=b", &question, &no_base()),
+			json!({"type": "drag_drop_into_text", "placements": [["q1207:2_p1", 2], ["q1207:2_p3", 2]]})
+		);
+		assert!(parse_answer_edit("1=LIFO", &question, &no_base()).is_err());
+	}
+
+	#[test]
+	fn ordering() {
+		let question = fixture_question(include_str!("../tests/integration/fixtures/ordering.html"));
+		assert_eq!(edit("2 3,4 1", &question, &no_base()), json!({"type": "ordering", "order": [1, 2, 3, 0]}));
+		assert!(parse_answer_edit("2,3,4", &question, &no_base()).is_err());
+		assert!(parse_answer_edit("2,3,4,4", &question, &no_base()).is_err());
+	}
+
+	#[test]
+	fn not_typeable() {
+		let questions = quiz_page();
+		for question in &questions[7..] {
+			assert!(edit_hint(question).is_none());
+			assert!(parse_answer_edit("1", question, &no_base()).is_err());
+		}
+	}
+}
@@ -0,0 +1,586 @@
+//! Answers file - replay known answers instead of asking the LLM
+//!
+//! The file is a JSON object mapping a question identifier to its answer. The identifier is either the
+//! question's first form field name (e.g. `"q123:4_answer"`, only valid for one attempt) or the hash of its
+//! text and option texts as printed by `--export-answers` (stable across attempts). Each answer has a `"type"` tag:
+//!
+//! ```json
+//! {
+//!   "1f0c9a2e4b7d3a61": { "question": "What is 2+2?", "type": "single", "index": 1, "text": "4" },
+//!   "q123:5_answer":    { "type": "multi", "indices": [0, 2], "texts": ["Lists", "Dicts"] },
+//!   "q123:6_answer":    { "type": "text", "answer": "Paris" },
+//!   "q123:7_sub0":      { "type": "matching", "pairs": [["Cat", "Mammal"], ["Frog", "Amphibian"]] },
+//!   "q123:8_p1":        { "type": "drag_drop", "placements": ["cat", "mat"] },
+//!   "q123:9_sub1":      { "type": "fill_in_blanks", "blanks": ["42", "option text"] },
+//!   "q123:10_answer":   { "type": "code", "code": "print('hi')" },
+//!   "q123:11_answer":   { "type": "numerical", "answer": "9.81", "unit": "m/s^2" },
+//!   "q123:12_answer":   { "type": "essay", "html": "<p>...</p>" },
+//!   "q123:13_response": { "type": "ordering", "order": [2, 0, 1], "items": ["Boot", "Log in", "Open the quiz"] }
+//! }
+//! ```
+//!
+//! Everything that points at an option does so by its text, since Moodle shuffles choices, items and option
+//! values between attempts. Choices and ordering items also carry their 0-based index in the order they were
+//! displayed, only used when the text is missing (hand-written entries): a text that matches nothing is an error
+//! rather than a guess. `"question"` is informational only.
+
+use std::{collections::BTreeMap, path::Path};
+
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	Blank, Question,
+	answer_cache::AnswerCache,
+	llm::{FillInBlanksAnswerItem, LlmAnswerResult},
+};
+
+/// A stored answer, in a form that survives Moodle's per-attempt shuffling of option values
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StoredAnswer {
+	Single {
+		index: usize,
+		/// Text of the chosen choice, matched before `index`
+		#[serde(default, skip_serializing_if = "Option::is_none")]
+		text: Option<String>,
+	},
+	Multi {
+		indices: Vec<usize>,
+		/// Texts of the chosen choices, one per index, matched before `indices`
+		#[serde(default, skip_serializing_if = "Vec::is_empty")]
+		texts: Vec<String>,
+	},
+	Text {
+		answer: String,
+	},
+	/// (item prompt, option text) pairs
+	Matching {
+		pairs: Vec<(String, String)>,
+	},
+	/// Per blank, in order: the text to type, or the option text to select
+	FillInBlanks {
+		blanks: Vec<String>,
+	},
+	Code {
+		code: String,
+	},
+	/// Per drop zone, in order: the text of the choice to place there
+	DragDrop {
+		placements: Vec<String>,
+	},
+	Numerical {
+		answer: String,
+		#[serde(default)]
+		unit: Option<String>,
+	},
+	Essay {
+		html: String,
+	},
+	Ordering {
+		order: Vec<usize>,
+		/// Item texts in answer order, matched before `order`
+		#[serde(default, skip_serializing_if = "Vec::is_empty")]
+		items: Vec<String>,
+	},
+}
+
+/// One entry of the answers file
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AnswerEntry {
+	/// Question text, for humans editing the file
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub question: Option<String>,
+	#[serde(flatten)]
+	pub answer: StoredAnswer,
+}
+
+/// Answers keyed by question identifier (field name or text hash)
+#[derive(Clone, Debug, Default)]
+pub struct AnswersFile {
+	entries: BTreeMap<String, AnswerEntry>,
+}
+
+impl AnswersFile {
+	/// Load and validate an answers file
+	pub fn load(path: &Path) -> Result<Self> {
+		let content = std::fs::read_to_string(path).map_err(|e| eyre!("Failed to read answers file {}: {e}", path.display()))?;
+		let raw: BTreeMap<String, serde_json::Value> =
+			serde_json::from_str(&content).map_err(|e| eyre!("Answers file {} must be a JSON object of question id -> answer: {e}", path.display()))?;
+
+		let mut entries = BTreeMap::new();
+		for (key, value) in raw {
+			let entry: AnswerEntry = serde_json::from_value(value).map_err(|e| eyre!("Answers file {}, entry \"{key}\": {e}", path.display()))?;
+			validate_entry(&entry.answer).map_err(|e| eyre!("Answers file {}, entry \"{key}\": {e}", path.display()))?;
+			entries.insert(key, entry);
+		}
+
+		Ok(Self { entries })
+	}
+
+	/// Write the answers file (pretty-printed, keys sorted)
+	pub fn save(&self, path: &Path) -> Result<()> {
+		let json = serde_json::to_string_pretty(&self.entries).map_err(|e| eyre!("Failed to serialize answers: {e}"))?;
+		std::fs::write(path, json).map_err(|e| eyre!("Failed to write answers file {}: {e}", path.display()))
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	/// Look up the answer for a question, by its first field name and then by its text hash
+	/// Returns None if the file has no entry for it, Err if the entry doesn't fit the question
+	pub fn lookup(&self, question: &Question) -> Option<Result<LlmAnswerResult>> {
+		let field_key = question.field_names().into_iter().next().map(str::to_string);
+		let (key, entry) = field_key
+			.into_iter()
+			.chain(std::iter::once(question_hash(question)))
+			.find_map(|key| self.entries.get(&key).map(|entry| (key, entry)))?;
+
+		Some(to_answer_result(&entry.answer, question).map_err(|e| eyre!("Answers file entry \"{key}\": {e}")))
+	}
+
+	/// Record an answer, keyed by the question's text hash
+	pub fn record(&mut self, question: &Question, answer: &LlmAnswerResult) {
+		match from_answer_result(answer, question) {
+			Some(stored) => {
+				let entry = AnswerEntry {
+					question: Some(question.question_text().to_string()),
+					answer: stored,
+				};
+				self.entries.insert(question_hash(question), entry);
+			}
+			None => tracing::warn!("Could not export answer for question: {}", question.question_text()),
+		}
+	}
+}
+
+/// Answers replayed from `--answers-file`, answers collected for `--export-answers`, and the on-disk cache of LLM
+/// answers from previous runs
+#[derive(Clone, Debug, Default)]
+pub struct AnswerBook {
+	pub replay: Option<AnswersFile>,
+	pub export: Option<AnswersFile>,
+	pub cache: Option<AnswerCache>,
+}
+
+/// Stable identifier for a question across attempts: FNV-1a of its whitespace-normalized text and sorted option
+/// texts, which tells apart questions that only differ by their choices
+pub fn question_hash(question: &Question) -> String {
+	let mut options: Vec<String> = option_texts(question).into_iter().map(normalize_whitespace).collect();
+	options.sort_unstable();
+	let mut key = normalize_whitespace(question.question_text());
+	for option in options {
+		key.push('\n');
+		key.push_str(&option);
+	}
+	fnv1a_hex(&key)
+}
+
+/// Every option-like text of a question: choices, matching prompts and options, select blanks, drag choices,
+/// ordering items and units
+pub(crate) fn option_texts(question: &Question) -> Vec<&str> {
+	match question {
+		Question::SingleChoice { choices, .. } | Question::MultiChoice { choices, .. } => choices.iter().map(|c| c.text.as_str()).collect(),
+		Question::Matching { items, .. } => items
+			.iter()
+			.flat_map(|i| std::iter::once(i.prompt.as_str()).chain(i.options.iter().map(|o| o.text.as_str())))
+			.collect(),
+		Question::FillInBlanks(fill) => fill
+			.blanks
+			.iter()
+			.flat_map(|blank| match blank {
+				Blank::Select { options, .. } => options.iter().map(|o| o.text.as_str()).collect(),
+				Blank::Text { .. } => Vec::new(),
+			})
+			.collect(),
+		Question::DragDropIntoText(dd) => dd.choices.iter().map(|c| c.text.as_str()).collect(),
+		Question::Ordering { items, .. } => items.iter().map(|i| i.text.as_str()).collect(),
+		Question::Numerical { units, .. } => units.iter().map(|u| u.text.as_str()).collect(),
+		Question::ShortAnswer { .. } | Question::CodeSubmission { .. } | Question::CodeBlock { .. } | Question::Essay { .. } | Question::Description { .. } | Question::Unknown { .. } =>
+			Vec::new(),
+	}
+}
+
+pub(crate) fn normalize_whitespace(text: &str) -> String {
+	text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub(crate) fn fnv1a_hex(text: &str) -> String {
+	let mut hash: u64 = 0xcbf29ce484222325;
+	for byte in text.bytes() {
+		hash ^= byte as u64;
+		hash = hash.wrapping_mul(0x100000001b3);
+	}
+	format!("{hash:016x}")
+}
+
+/// Checks that don't need the question itself
+fn validate_entry(answer: &StoredAnswer) -> Result<()> {
+	match answer {
+		StoredAnswer::Multi { indices, .. } if indices.is_empty() => bail!("\"indices\" must not be empty"),
+		StoredAnswer::Multi { indices, texts } if !texts.is_empty() && texts.len() != indices.len() => bail!("\"texts\" has {} entries but \"indices\" has {}", texts.len(), indices.len()),
+		StoredAnswer::Code { code } if code.trim().is_empty() => bail!("\"code\" must not be empty"),
+		StoredAnswer::Numerical { answer, .. } if answer.trim().is_empty() => bail!("\"answer\" must not be empty"),
+		StoredAnswer::Ordering { order, items } => {
+			if !items.is_empty() && items.len() != order.len() {
+				bail!("\"items\" has {} entries but \"order\" has {}", items.len(), order.len());
+			}
+			if !is_permutation(order) {
+				bail!("\"order\" must be a permutation of 0..{}, got {order:?}", order.len());
+			}
+			Ok(())
+		}
+		_ => Ok(()),
+	}
+}
+
+fn is_permutation(order: &[usize]) -> bool {
+	let mut sorted = order.to_vec();
+	sorted.sort_unstable();
+	sorted.iter().enumerate().all(|(i, &idx)| i == idx)
+}
+
+/// Position of the displayed text matching `text` (whitespace-normalized). `index` is only used for entries
+/// stored without a text.
+fn locate(displayed: &[&str], text: Option<&str>, index: usize, what: &str) -> Result<usize> {
+	match text {
+		Some(text) => {
+			let wanted = normalize_whitespace(text);
+			displayed.iter().position(|t| normalize_whitespace(t) == wanted).ok_or_else(|| eyre!("no {what} \"{text}\""))
+		}
+		None if index < displayed.len() => Ok(index),
+		None => bail!("index {index} out of range (question has {} {what}s)", displayed.len()),
+	}
+}
+
+/// Convert a stored answer into what `apply_answer` expects, checking it against the question. Choices and
+/// ordering items are found by text first, so a reshuffled attempt still gets the same answer.
+pub(crate) fn to_answer_result(stored: &StoredAnswer, question: &Question) -> Result<LlmAnswerResult> {
+	let mismatch = || eyre!("answer type doesn't match question {}", question.type_marker());
+
+	let result = match (stored, question) {
+		(StoredAnswer::Single { index, text }, Question::SingleChoice { choices, .. }) => {
+			let displayed: Vec<&str> = choices.iter().map(|c| c.text.as_str()).collect();
+			let idx = locate(&displayed, text.as_deref(), *index, "choice")?;
+			LlmAnswerResult::Single {
+				idx,
+				text: choices[idx].text.clone(),
+			}
+		}
+		(StoredAnswer::Multi { indices, texts }, Question::MultiChoice { choices, .. }) => {
+			let displayed: Vec<&str> = choices.iter().map(|c| c.text.as_str()).collect();
+			let mut located = Vec::with_capacity(indices.len());
+			for (i, &index) in indices.iter().enumerate() {
+				let idx = locate(&displayed, texts.get(i).map(String::as_str), index, "choice")?;
+				// Applying the same choice twice would check and then uncheck it
+				if located.contains(&idx) {
+					bail!("entry {} is choice \"{}\" again", i + 1, choices[idx].text);
+				}
+				located.push(idx);
+			}
+			let indices = located;
+			let texts = indices.iter().map(|&i| choices[i].text.clone()).collect();
+			LlmAnswerResult::Multi { indices, texts }
+		}
+		(StoredAnswer::Text { answer }, Question::ShortAnswer { .. }) => LlmAnswerResult::Text { answer: answer.clone() },
+		(StoredAnswer::Matching { pairs }, Question::Matching { items, .. }) => {
+			let selections = pairs
+				.iter()
+				.map(|(prompt, option_text)| {
+					let item = items
+						.iter()
+						.find(|i| i.prompt.trim() == prompt.trim())
+						.ok_or_else(|| eyre!("no matching item with prompt \"{prompt}\""))?;
+					let option = item
+						.options
+						.iter()
+						.find(|o| o.text.trim() == option_text.trim())
+						.ok_or_else(|| eyre!("item \"{prompt}\" has no option \"{option_text}\""))?;
+					Ok((item.select_name.clone(), option.value.clone()))
+				})
+				.collect::<Result<Vec<_>>>()?;
+			LlmAnswerResult::Matching { selections }
+		}
+		(StoredAnswer::FillInBlanks { blanks }, Question::FillInBlanks(fill)) => {
+			if blanks.len() != fill.blanks.len() {
+				bail!("expected {} blanks, got {}", fill.blanks.len(), blanks.len());
+			}
+			let answers = fill
+				.blanks
+				.iter()
+				.zip(blanks)
+				.enumerate()
+				.map(|(i, (blank, answer))| match blank {
+					Blank::Text { input_name, .. } => Ok(FillInBlanksAnswerItem::Text {
+						input_name: input_name.clone(),
+						answer: answer.clone(),
+					}),
+					Blank::Select { select_name, options, .. } => {
+						let option = options
+							.iter()
+							.find(|o| o.text.trim() == answer.trim())
+							.ok_or_else(|| eyre!("blank {} has no option \"{answer}\"", i + 1))?;
+						Ok(FillInBlanksAnswerItem::Select {
+							select_name: select_name.clone(),
+							value: option.value.clone(),
+						})
+					}
+				})
+				.collect::<Result<Vec<_>>>()?;
+			LlmAnswerResult::FillInBlanks { answers }
+		}
+		(StoredAnswer::Code { code }, Question::CodeBlock { .. }) => LlmAnswerResult::CodeBlock { code: code.clone() },
+		(StoredAnswer::DragDrop { placements }, Question::DragDropIntoText(dd)) => {
+			if placements.len() != dd.drop_zones.len() {
+				bail!("expected {} placements, got {}", dd.drop_zones.len(), placements.len());
+			}
+			let placements = dd
+				.drop_zones
+				.iter()
+				.zip(placements)
+				.map(|(zone, text)| {
+					let choice = dd
+						.choices
+						.iter()
+						.find(|c| c.group == zone.group && c.text.trim() == text.trim())
+						.ok_or_else(|| eyre!("no choice \"{text}\" for drop zone {}", zone.place_number))?;
+					Ok((zone.input_name.clone(), choice.choice_number))
+				})
+				.collect::<Result<Vec<_>>>()?;
+			LlmAnswerResult::DragDropIntoText { placements }
+		}
+		(StoredAnswer::Numerical { answer, unit }, Question::Numerical { unit_select_name, units, .. }) => {
+			let unit = match (unit_select_name, unit) {
+				(Some(select_name), Some(unit_text)) => {
+					let option = units.iter().find(|u| u.text.trim() == unit_text.trim()).ok_or_else(|| eyre!("unknown unit \"{unit_text}\""))?;
+					Some((select_name.clone(), option.value.clone()))
+				}
+				_ => None,
+			};
+			LlmAnswerResult::Numerical { answer: answer.clone(), unit }
+		}
+		(StoredAnswer::Essay { html }, Question::Essay { .. }) => LlmAnswerResult::Essay { html: html.clone() },
+		(StoredAnswer::Ordering { order, items: texts }, Question::Ordering { items, .. }) => {
+			if order.len() != items.len() {
+				bail!("\"order\" has {} entries but the question has {} items", order.len(), items.len());
+			}
+			let displayed: Vec<&str> = items.iter().map(|i| i.text.as_str()).collect();
+			let order = order
+				.iter()
+				.enumerate()
+				.map(|(i, &index)| locate(&displayed, texts.get(i).map(String::as_str), index, "item"))
+				.collect::<Result<Vec<_>>>()?;
+			if !is_permutation(&order) {
+				bail!("\"items\" don't match the question's items one to one");
+			}
+			LlmAnswerResult::Ordering { order }
+		}
+		_ => return Err(mismatch()),
+	};
+
+	Ok(result)
+}
+
+/// Convert an answer back into its stored form (None if it references options the question doesn't have)
+pub(crate) fn from_answer_result(answer: &LlmAnswerResult, question: &Question) -> Option<StoredAnswer> {
+	let stored = match (answer, question) {
+		(LlmAnswerResult::Single { idx, .. }, _) => StoredAnswer::Single {
+			index: *idx,
+			text: question.choices().get(*idx).map(|c| c.text.clone()),
+		},
+		(LlmAnswerResult::Multi { indices, .. }, _) => StoredAnswer::Multi {
+			indices: indices.clone(),
+			texts: indices.iter().map(|&i| question.choices().get(i).map(|c| c.text.clone())).collect::<Option<_>>()?,
+		},
+		(LlmAnswerResult::Text { answer }, _) => StoredAnswer::Text { answer: answer.clone() },
+		(LlmAnswerResult::Matching { selections }, Question::Matching { items, .. }) => {
+			let pairs = selections
+				.iter()
+				.map(|(select_name, value)| {
+					let item = items.iter().find(|i| &i.select_name == select_name)?;
+					let option = item.options.iter().find(|o| &o.value == value)?;
+					Some((item.prompt.clone(), option.text.clone()))
+				})
+				.collect::<Option<Vec<_>>>()?;
+			StoredAnswer::Matching { pairs }
+		}
+		(LlmAnswerResult::FillInBlanks { answers }, Question::FillInBlanks(fill)) => {
+			let blanks = fill
+				.blanks
+				.iter()
+				.map(|blank| match blank {
+					Blank::Text { input_name, .. } => answers.iter().find_map(|a| match a {
+						FillInBlanksAnswerItem::Text { input_name: name, answer } if name == input_name => Some(answer.clone()),
+						_ => None,
+					}),
+					Blank::Select { select_name, options, .. } => answers.iter().find_map(|a| match a {
+						FillInBlanksAnswerItem::Select { select_name: name, value } if name == select_name => options.iter().find(|o| &o.value == value).map(|o| o.text.clone()),
+						_ => None,
+					}),
+				})
+				.map(Option::unwrap_or_default)
+				.collect();
+			StoredAnswer::FillInBlanks { blanks }
+		}
+		(LlmAnswerResult::CodeBlock { code }, _) => StoredAnswer::Code { code: code.clone() },
+		(LlmAnswerResult::DragDropIntoText { placements }, Question::DragDropIntoText(dd)) => {
+			let placements = dd
+				.drop_zones
+				.iter()
+				.map(|zone| {
+					let (_, choice_number) = placements.iter().find(|(name, _)| name == &zone.input_name)?;
+					dd.choices.iter().find(|c| c.group == zone.group && c.choice_number == *choice_number).map(|c| c.text.clone())
+				})
+				.map(Option::unwrap_or_default)
+				.collect();
+			StoredAnswer::DragDrop { placements }
+		}
+		(LlmAnswerResult::Numerical { answer, unit }, Question::Numerical { units, .. }) => StoredAnswer::Numerical {
+			answer: answer.clone(),
+			unit: unit.as_ref().and_then(|(_, value)| units.iter().find(|u| &u.value == value)).map(|u| u.text.clone()),
+		},
+		(LlmAnswerResult::Essay { html }, _) => StoredAnswer::Essay { html: html.clone() },
+		(LlmAnswerResult::Ordering { order }, _) => StoredAnswer::Ordering {
+			order: order.clone(),
+			items: order.iter().map(|&i| question.ordering_items().get(i).map(|item| item.text.clone())).collect::<Option<_>>()?,
+		},
+		_ => return None,
+	};
+
+	Some(stored)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{Choice, OrderingItem};
+
+	fn single_choice(texts: &[&str]) -> Question {
+		Question::SingleChoice {
+			question_text: "Which structure gives O(1) average lookup?".to_string(),
+			choices: texts
+				.iter()
+				.enumerate()
+				.map(|(i, text)| Choice {
+					input_name: "q5:2_answer".to_string(),
+					input_value: i.to_string(),
+					text: text.to_string(),
+					selected: false,
+					images: Vec::new(),
+				})
+				.collect(),
+			images: Vec::new(),
+		}
+	}
+
+	fn ordering(texts: &[&str]) -> Question {
+		Question::Ordering {
+			question_text: "Put the stages of a compiler in the order they run.".to_string(),
+			items: texts
+				.iter()
+				.map(|text| OrderingItem {
+					id: format!("ordering_item_{}", text.to_lowercase()),
+					text: text.to_string(),
+				})
+				.collect(),
+			input_name: "q5:3_response_5_3".to_string(),
+			images: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn choice_answers_follow_their_text_across_shuffles() {
+		let exported = single_choice(&["List", "Hash map", "Tree"]);
+		let stored = from_answer_result(
+			&LlmAnswerResult::Single {
+				idx: 1,
+				text: "Hash map".to_string(),
+			},
+			&exported,
+		)
+		.unwrap();
+
+		let reshuffled = single_choice(&["Tree", "List", "Hash map"]);
+		let LlmAnswerResult::Single { idx, text } = to_answer_result(&stored, &reshuffled).unwrap() else {
+			unreachable!()
+		};
+		assert_eq!((idx, text.as_str()), (2, "Hash map"));
+		assert_eq!(question_hash(&exported), question_hash(&reshuffled));
+	}
+
+	#[test]
+	fn ordering_answers_follow_their_text_across_shuffles() {
+		let exported = ordering(&["Parsing", "Lexing", "Codegen"]);
+		let stored = from_answer_result(&LlmAnswerResult::Ordering { order: vec![1, 0, 2] }, &exported).unwrap();
+
+		let reshuffled = ordering(&["Codegen", "Parsing", "Lexing"]);
+		let LlmAnswerResult::Ordering { order } = to_answer_result(&stored, &reshuffled).unwrap() else {
+			unreachable!()
+		};
+		assert_eq!(reshuffled.ordering_item_ids(&order).join(","), "ordering_item_lexing,ordering_item_parsing,ordering_item_codegen");
+	}
+
+	#[test]
+	fn index_is_only_the_fallback_without_a_text() {
+		let question = single_choice(&["List", "Hash map", "Tree"]);
+		let by_index: StoredAnswer = serde_json::from_str(r#"{ "type": "single", "index": 2 }"#).unwrap();
+		let LlmAnswerResult::Single { idx, .. } = to_answer_result(&by_index, &question).unwrap() else {
+			unreachable!()
+		};
+		assert_eq!(idx, 2);
+
+		// The text is what was answered; its old index now points at something else
+		let renamed = StoredAnswer::Single {
+			index: 0,
+			text: Some("Linked list".to_string()),
+		};
+		assert!(to_answer_result(&renamed, &question).is_err());
+	}
+
+	#[test]
+	fn multi_rejects_entries_that_land_on_the_same_choice() {
+		let question = Question::MultiChoice {
+			question_text: "Which are ordered collections?".to_string(),
+			choices: ["List", "Tuple", "Set"]
+				.iter()
+				.enumerate()
+				.map(|(i, text)| Choice {
+					input_name: format!("q5:4_choice{i}"),
+					input_value: "1".to_string(),
+					text: text.to_string(),
+					selected: false,
+					images: Vec::new(),
+				})
+				.collect(),
+			images: Vec::new(),
+		};
+		let twice = StoredAnswer::Multi {
+			indices: vec![0, 2],
+			texts: vec!["Tuple".to_string(), " Tuple".to_string()],
+		};
+		let err = to_answer_result(&twice, &question).unwrap_err().to_string();
+		assert_eq!(err, "entry 2 is choice \"Tuple\" again");
+
+		let by_index = StoredAnswer::Multi {
+			indices: vec![1, 0],
+			texts: Vec::new(),
+		};
+		let LlmAnswerResult::Multi { indices, texts } = to_answer_result(&by_index, &question).unwrap() else {
+			unreachable!()
+		};
+		assert_eq!((indices, texts), (vec![1, 0], vec!["Tuple".to_string(), "List".to_string()]));
+	}
+
+	#[test]
+	fn question_hash_tells_choices_apart() {
+		assert_ne!(question_hash(&single_choice(&["List", "Hash map"])), question_hash(&single_choice(&["List", "Tree"])));
+	}
+}
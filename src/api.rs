@@ -0,0 +1,272 @@
+//! Moodle webservice (REST) client - the mobile-app API, usable with a `ws_token`
+//!
+//! Functions return typed responses; Moodle's `{"exception": ..., "errorcode": ..., "message": ...}` payloads are
+//! turned into a [`WsError`] that callers can downcast to decide whether to fall back to the browser.
+
+use std::fmt;
+
+use color_eyre::{Result, eyre::eyre};
+use serde::{Deserialize, de::DeserializeOwned};
+use v_utils::elog;
+
+use crate::{config::AppConfig, login::moodle_base_url, marks::QuestionMarks, proxy::http_client};
+
+/// Error payload returned by the webservice instead of the expected response
+#[derive(Clone, Debug, Deserialize)]
+pub struct WsError {
+	pub exception: String,
+	#[serde(default)]
+	pub errorcode: String,
+	#[serde(default)]
+	pub message: String,
+}
+
+impl WsError {
+	/// The token or its service doesn't allow this function (as opposed to a bad request)
+	pub fn is_capability_error(&self) -> bool {
+		const CAPABILITY_CODES: &[&str] = &[
+			"nopermissions",
+			"accessexception",
+			"invalidtoken",
+			"servicenotavailable",
+			"webservicesnotenabled",
+			"requireloginerror",
+		];
+		CAPABILITY_CODES.contains(&self.errorcode.as_str()) || self.exception.ends_with("webservice_access_exception")
+	}
+}
+
+impl fmt::Display for WsError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "Moodle webservice error {} ({}): {}", self.errorcode, self.exception, self.message)
+	}
+}
+
+impl std::error::Error for WsError {}
+
+/// A quiz attempt
+#[derive(Clone, Debug, Deserialize)]
+pub struct WsAttempt {
+	pub id: u64,
+	/// "inprogress", "overdue", "finished" or "abandoned"
+	pub state: String,
+}
+
+/// One question of an attempt page, as rendered by Moodle
+#[derive(Clone, Debug, Deserialize)]
+pub struct WsQuestion {
+	pub slot: u32,
+	/// qtype name ("multichoice", "shortanswer", "description", ...)
+	#[serde(rename = "type")]
+	pub qtype: String,
+	pub page: u32,
+	/// Full `.que` HTML, the same markup the attempt page shows
+	pub html: String,
+	/// State as a class name ("notyetanswered", "answersaved", ...), see [`crate::quiz_nav::QuestionState`]
+	#[serde(default)]
+	pub stateclass: String,
+	/// What the question is worth, when the quiz shows marks
+	#[serde(default)]
+	pub maxmark: Option<f64>,
+	/// The mark it got once graded, formatted for display ("0,50")
+	#[serde(default)]
+	pub mark: Option<String>,
+}
+
+impl WsQuestion {
+	pub fn marks(&self) -> QuestionMarks {
+		QuestionMarks {
+			max: self.maxmark,
+			current: self.mark.as_deref().and_then(|mark| mark.trim().replace(',', ".").parse().ok()),
+		}
+	}
+}
+
+/// `mod_quiz_get_attempt_data` response
+#[derive(Clone, Debug, Deserialize)]
+pub struct WsAttemptData {
+	pub attempt: WsAttempt,
+	/// Next page number, -1 on the last page
+	pub nextpage: i32,
+	pub questions: Vec<WsQuestion>,
+}
+
+/// `mod_vpl_get_result` response: the last evaluation of the user's submission
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct WsVplResult {
+	/// Compiler output
+	#[serde(default)]
+	pub compilation: String,
+	/// Test comments, in VPL's `-Test name` format
+	#[serde(default)]
+	pub evaluation: String,
+	/// "Proposed grade: x / y", empty while evaluation hasn't finished
+	#[serde(default)]
+	pub grade: String,
+}
+
+/// One file of a VPL submission
+#[derive(Clone, Debug, Deserialize)]
+pub struct WsVplFile {
+	pub name: String,
+	#[serde(default)]
+	pub data: String,
+}
+
+/// `mod_vpl_open` response: the user's current submission
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct WsVplSubmission {
+	#[serde(default)]
+	pub files: Vec<WsVplFile>,
+	/// "Proposed grade: x / y" of the last evaluation, empty if never evaluated
+	#[serde(default)]
+	pub grade: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsCourseModule {
+	cm: WsCourseModuleInfo,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsCourseModuleInfo {
+	instance: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsUserAttempts {
+	attempts: Vec<WsAttempt>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsStartAttempt {
+	attempt: WsAttempt,
+}
+
+#[derive(Debug, Deserialize)]
+struct WsProcessAttempt {
+	state: String,
+}
+
+/// Client for `<base>/webservice/rest/server.php`
+#[derive(Clone, Debug)]
+pub struct MoodleWs {
+	client: reqwest::Client,
+	base_url: String,
+	token: String,
+}
+
+impl MoodleWs {
+	/// Client from the configured `ws_token`, against `ws_base_url` or the Moodle root of `target_url`
+	pub fn from_config(config: &AppConfig, target_url: &str) -> Option<Self> {
+		let token = config.ws_token.as_ref()?;
+		let base_url = config.ws_base_url.clone().unwrap_or_else(|| moodle_base_url(target_url).to_string());
+		let client = http_client(config).inspect_err(|e| elog!("Webservice unavailable: {e}")).ok()?;
+		Some(Self::with_client(client, base_url, token.clone()))
+	}
+
+	pub fn new(base_url: impl Into<String>, token: impl Into<String>) -> Self {
+		Self::with_client(reqwest::Client::new(), base_url, token)
+	}
+
+	pub fn with_client(client: reqwest::Client, base_url: impl Into<String>, token: impl Into<String>) -> Self {
+		Self {
+			client,
+			base_url: base_url.into().trim_end_matches('/').to_string(),
+			token: token.into(),
+		}
+	}
+
+	/// Call a webservice function with already-flattened parameters (`data[0][name]` style)
+	pub async fn call<T: DeserializeOwned>(&self, function: &str, params: &[(String, String)]) -> Result<T> {
+		let url = format!("{}/webservice/rest/server.php", self.base_url);
+		let mut form: Vec<(String, String)> = vec![
+			("wstoken".to_string(), self.token.clone()),
+			("wsfunction".to_string(), function.to_string()),
+			("moodlewsrestformat".to_string(), "json".to_string()),
+		];
+		form.extend_from_slice(params);
+
+		let response = self.client.post(&url).form(&form).send().await.map_err(|e| eyre!("{function}: request failed: {e}"))?;
+		let status = response.status();
+		let body = response.text().await.map_err(|e| eyre!("{function}: failed to read response: {e}"))?;
+		if !status.is_success() {
+			return Err(eyre!("{function}: HTTP {status}"));
+		}
+
+		let value: serde_json::Value = serde_json::from_str(&body).map_err(|e| eyre!("{function}: invalid JSON response: {e}"))?;
+		if value.get("exception").is_some() {
+			let ws_error: WsError = serde_json::from_value(value).map_err(|e| eyre!("{function}: malformed error response: {e}"))?;
+			return Err(ws_error.into());
+		}
+		serde_json::from_value(value).map_err(|e| eyre!("{function}: unexpected response shape: {e}"))
+	}
+
+	/// Quiz instance id of a course module (the `id=`/`cmid=` in quiz URLs)
+	pub async fn quiz_instance_id(&self, cmid: u64) -> Result<u64> {
+		let response: WsCourseModule = self.call("core_course_get_course_module", &[("cmid".to_string(), cmid.to_string())]).await?;
+		Ok(response.cm.instance)
+	}
+
+	/// The user's in-progress attempt on a quiz, if any
+	pub async fn unfinished_attempt(&self, quiz_id: u64) -> Result<Option<WsAttempt>> {
+		let params = [("quizid".to_string(), quiz_id.to_string()), ("status".to_string(), "unfinished".to_string())];
+		let response: WsUserAttempts = self.call("mod_quiz_get_user_attempts", &params).await?;
+		Ok(response.attempts.into_iter().next())
+	}
+
+	/// Start a new attempt, passing the quiz password through the preflight data if one is needed
+	pub async fn start_attempt(&self, quiz_id: u64, password: Option<&str>) -> Result<WsAttempt> {
+		let mut params = vec![("quizid".to_string(), quiz_id.to_string())];
+		if let Some(password) = password {
+			params.push(("preflightdata[0][name]".to_string(), "quizpassword".to_string()));
+			params.push(("preflightdata[0][value]".to_string(), password.to_string()));
+		}
+		let response: WsStartAttempt = self.call("mod_quiz_start_attempt", &params).await?;
+		Ok(response.attempt)
+	}
+
+	pub async fn get_attempt_data(&self, attempt_id: u64, page: u32) -> Result<WsAttemptData> {
+		let params = [("attemptid".to_string(), attempt_id.to_string()), ("page".to_string(), page.to_string())];
+		self.call("mod_quiz_get_attempt_data", &params).await
+	}
+
+	/// Save form field values for an attempt; with `finish`, also submit it for grading
+	/// Returns the attempt state afterwards
+	pub async fn process_attempt(&self, attempt_id: u64, data: &[(String, String)], finish: bool) -> Result<String> {
+		let mut params = vec![("attemptid".to_string(), attempt_id.to_string()), ("finishattempt".to_string(), (finish as u8).to_string())];
+		for (i, (name, value)) in data.iter().enumerate() {
+			params.push((format!("data[{i}][name]"), name.clone()));
+			params.push((format!("data[{i}][value]"), value.clone()));
+		}
+		let response: WsProcessAttempt = self.call("mod_quiz_process_attempt", &params).await?;
+		Ok(response.state)
+	}
+
+	/// The user's current VPL submission (empty files list if nothing was submitted yet)
+	pub async fn vpl_open(&self, vpl_id: u64) -> Result<WsVplSubmission> {
+		self.call("mod_vpl_open", &[("id".to_string(), vpl_id.to_string())]).await
+	}
+
+	/// Replace the files of the user's VPL submission
+	pub async fn vpl_save(&self, vpl_id: u64, files: &[(String, String)]) -> Result<()> {
+		let mut params = vec![("id".to_string(), vpl_id.to_string())];
+		for (i, (name, data)) in files.iter().enumerate() {
+			params.push((format!("files[{i}][name]"), name.clone()));
+			params.push((format!("files[{i}][data]"), data.clone()));
+		}
+		// Returns null on success
+		let _: serde_json::Value = self.call("mod_vpl_save", &params).await?;
+		Ok(())
+	}
+
+	/// Start evaluating the saved submission; results show up in [`Self::vpl_get_result`] once it's done
+	pub async fn vpl_evaluate(&self, vpl_id: u64) -> Result<()> {
+		let _: serde_json::Value = self.call("mod_vpl_evaluate", &[("id".to_string(), vpl_id.to_string())]).await?;
+		Ok(())
+	}
+
+	pub async fn vpl_get_result(&self, vpl_id: u64) -> Result<WsVplResult> {
+		self.call("mod_vpl_get_result", &[("id".to_string(), vpl_id.to_string())]).await
+	}
+}
@@ -0,0 +1,129 @@
+//! Screenshots of question elements, for questions whose text doesn't survive extraction (formulas rendered as
+//! SVG without LaTeX, tables pasted as images)
+
+use base64::Engine;
+use chromiumoxide::{
+	Page,
+	cdp::browser_protocol::page::{CaptureScreenshotFormat, Viewport},
+	page::ScreenshotParams,
+};
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+use serde::Deserialize;
+
+use crate::{Question, js_string};
+
+/// Question text shorter than this (in chars) has probably lost its content to images or unparsed markup
+const MIN_QUESTION_TEXT_CHARS: usize = 40;
+
+/// Position of an element in page coordinates (CSS pixels)
+#[derive(Debug, Deserialize)]
+struct ElementRect {
+	x: f64,
+	y: f64,
+	width: f64,
+	height: f64,
+}
+
+/// PNG of the first element matching `selector`, clipped to its bounding box, as (base64, media type)
+pub async fn screenshot_element(page: &Page, selector: &str) -> Result<(String, String)> {
+	let script = format!(
+		r#"
+		(function() {{
+			const el = document.querySelector({selector});
+			if (!el) return JSON.stringify(null);
+			el.scrollIntoView({{ block: 'center' }});
+			const r = el.getBoundingClientRect();
+			return JSON.stringify({{ x: r.left + window.scrollX, y: r.top + window.scrollY, width: r.width, height: r.height }});
+		}})()
+	"#,
+		selector = js_string(selector)
+	);
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to locate {selector}: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("null");
+	let rect: Option<ElementRect> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse element bounds: {e}"))?;
+	let Some(rect) = rect else {
+		bail!("No element matches {selector}");
+	};
+	if rect.width < 1.0 || rect.height < 1.0 {
+		bail!("Element {selector} is not visible");
+	}
+
+	let params = ScreenshotParams::builder()
+		.format(CaptureScreenshotFormat::Png)
+		.clip(Viewport {
+			x: rect.x,
+			y: rect.y,
+			width: rect.width,
+			height: rect.height,
+			scale: 1.0,
+		})
+		.capture_beyond_viewport(true)
+		.build();
+	let png = page.screenshot(params).await.map_err(|e| eyre!("Failed to screenshot {selector}: {e}"))?;
+
+	Ok((base64::engine::general_purpose::STANDARD.encode(png), "image/png".to_string()))
+}
+
+/// Selector of the question's formulation block, found through one of its form fields
+pub fn question_selector(question: &Question) -> Option<String> {
+	let name = question.field_names().into_iter().next()?;
+	let name = name.replace('\\', "\\\\").replace('"', "\\\"");
+	Some(format!(".que:has([name=\"{name}\"]) .formulation"))
+}
+
+/// Whether the extracted text of `question` looks incomplete: very short, or its formulation has math rendered
+/// without any LaTeX source left to read back
+async fn extraction_incomplete(page: &Page, question: &Question, selector: &str) -> bool {
+	if question.question_text().chars().count() < MIN_QUESTION_TEXT_CHARS {
+		return true;
+	}
+
+	// Same sources the question parser reads LaTeX from
+	let script = format!(
+		r#"
+		(function() {{
+			const el = document.querySelector({selector});
+			if (!el) return JSON.stringify(false);
+			const mj3 = [...el.querySelectorAll('mjx-container')].some(c =>
+				!c.querySelector('annotation[encoding="application/x-tex"], script[type="math/tex"]') && !c.dataset.latex);
+			const mj2 = [...el.querySelectorAll('.MathJax')].some(s => {{
+				// Display math is wrapped in a .MathJax_Display div, the script follows the wrapper
+				const holder = s.parentElement?.classList.contains('MathJax_Display') ? s.parentElement : s;
+				const next = holder.nextElementSibling;
+				return !(next && next.tagName === 'SCRIPT' && next.type && next.type.includes('math/tex'));
+			}});
+			return JSON.stringify(mj3 || mj2);
+		}})()
+	"#,
+		selector = js_string(selector)
+	);
+	match page.evaluate(script).await {
+		Ok(result) => result.value().and_then(|v| v.as_str()) == Some("true"),
+		Err(e) => {
+			tracing::debug!("Failed to inspect question math: {e}");
+			false
+		}
+	}
+}
+
+/// Screenshot of the question's formulation when its extracted text looks incomplete; None otherwise, or when the
+/// screenshot can't be taken
+pub async fn question_screenshot(page: &Page, question: &Question) -> Option<(String, String)> {
+	let selector = question_selector(question)?;
+	if !extraction_incomplete(page, question, &selector).await {
+		return None;
+	}
+	match screenshot_element(page, &selector).await {
+		Ok(screenshot) => {
+			tracing::info!("Question text looks incomplete, attaching a screenshot of it");
+			Some(screenshot)
+		}
+		Err(e) => {
+			tracing::warn!("Failed to screenshot question: {e}");
+			None
+		}
+	}
+}
@@ -1,9 +1,107 @@
+use std::collections::HashMap;
+
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+use serde::{Deserialize, Serialize};
 use v_utils::macros::{MyConfigPrimitives, Settings};
 
+use crate::{QuestionKind, emit::Emitter, timing::Timings};
+
+/// Institution picked in the Renater federation dropdown without `institution`
+const DEFAULT_INSTITUTION: &str = "Université Clermont Auvergne";
+/// `Accept-Language` without `browser_language`
+const DEFAULT_BROWSER_LANGUAGE: &str = "fr-FR,fr;q=0.9,en;q=0.8";
+/// `answer_language` when unset: the language of the question
+const DEFAULT_ANSWER_LANGUAGE: &str = "auto";
+
+/// How to log in to a site, when overriding detection through the `sites` table
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LoginFlow {
+	/// CAS single sign-on (the UCA flow)
+	Cas,
+	/// Moodle's own login form at `<base>/login/index.php`
+	Direct,
+	/// Wait for the user to log in by hand (requires `visible`)
+	Manual,
+}
+
+/// How answers are confirmed before a quiz page is submitted (unless `auto_submit`)
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmMode {
+	/// One prompt for all the answers on the page
+	#[default]
+	Page,
+	/// One prompt per answer; rejected answers are left out
+	Question,
+	/// One prompt per answer; a rejected answer can be replaced by one typed in (see [`crate::answer_edit`])
+	Edit,
+}
+
+/// Which API the LLM calls go to
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LlmProvider {
+	/// Anthropic, with the token in `CLAUDE_TOKEN`
+	#[default]
+	Anthropic,
+	/// An OpenAI-style `/chat/completions` endpoint: Ollama, llama.cpp, vLLM, OpenAI itself...
+	OpenaiCompatible,
+}
+
+/// How question images are drawn in the terminal
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageRenderer {
+	/// The best the terminal supports, in the order below
+	#[default]
+	Auto,
+	/// Kitty graphics protocol (kitty, WezTerm, Ghostty)
+	Kitty,
+	/// Sixel graphics, encoded by img2sixel or chafa
+	Sixel,
+	/// chafa's character art
+	Chafa,
+	/// Alt text or URL only
+	Text,
+}
+
+/// A username/password pair for one site
+#[derive(Clone, Default, Deserialize, Serialize)]
+pub struct Credentials {
+	pub username: String,
+	pub password: String,
+}
+impl std::fmt::Debug for Credentials {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		f.debug_struct("Credentials").field("username", &self.username).field("password", &"<redacted>").finish()
+	}
+}
+
+/// Price of an LLM model in USD per million tokens, for the cost estimate in the usage summary
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize)]
+pub struct ModelPrice {
+	pub input: f64,
+	pub output: f64,
+}
+
 #[derive(Clone, Debug, Default, MyConfigPrimitives, Settings)]
 pub struct AppConfig {
+	/// Fallback username for sites without a `credentials` entry
+	#[serde(default)]
 	pub username: String,
+	/// Fallback password for sites without a `credentials` entry
+	#[serde(default)]
 	pub password: String,
+	/// Command printing the username (e.g. `pass show uca/user`); overrides `username`
+	#[serde(default)]
+	pub username_cmd: Option<String>,
+	/// Command printing the password (e.g. `pass show uca` or `secret-tool lookup service uca`); overrides `password`
+	#[serde(default)]
+	pub password_cmd: Option<String>,
 	/// Auto-submit all LLM answers without confirmation
 	#[serde(default)]
 	pub auto_submit: bool,
@@ -12,6 +110,35 @@ pub struct AppConfig {
 	/// confirmation dialog appears after clicking, that is also auto-confirmed.
 	#[serde(default)]
 	pub continuation_prompts: bool,
+	/// Confirm quiz answers per page ("page", default), per answer ("question"), or per answer with the option to
+	/// type in a replacement ("edit")
+	#[serde(default)]
+	#[settings(skip)]
+	pub confirm_mode: ConfirmMode,
+	/// Words that make a button in the quiz form, quiz navigation or a modal a confirmation button, matched
+	/// case-insensitively anywhere in its label (default: envoyer, terminer, submit, finir, confirm, valider)
+	#[serde(default = "default_confirmation_keywords")]
+	#[settings(skip)]
+	pub confirmation_keywords: Vec<String>,
+	/// Classes of the question wrapper (`.que`) whose text fields are typed into key by key instead of set from JS,
+	/// for widgets that only react to real key events (default: stack)
+	#[serde(default = "default_typed_question_classes")]
+	#[settings(skip)]
+	pub typed_question_classes: Vec<String>,
+	/// Also click the "Mark as done" completion toggles on the page along with the confirmation buttons
+	#[serde(default)]
+	pub auto_mark_done: bool,
+	/// How question images are drawn in the terminal: auto, kitty, sixel, chafa or text (default: auto)
+	#[serde(default)]
+	#[settings(skip)]
+	pub image_renderer: ImageRenderer,
+	/// Command reading the text in a question image, for images that can't be fetched through the page, e.g.
+	/// `tesseract {file} - -l fra+eng`. `{file}` is replaced with the image's path; what it prints goes in the prompt.
+	#[serde(default)]
+	pub ocr_cmd: Option<String>,
+	/// Run `ocr_cmd` on every question image, and give the LLM its text along with the image
+	#[serde(default)]
+	pub prefer_ocr: bool,
 	/// Command to run on completion/error (receives message as argument)
 	#[serde(default)]
 	pub stop_hook: Option<String>,
@@ -21,6 +148,13 @@ pub struct AppConfig {
 	/// Base delay in ms between API retries, multiplied by attempt number (default: 1000)
 	#[serde(default = "default_api_retry_delay_ms")]
 	pub api_retry_delay_ms: u64,
+	/// Seconds a quiz answer LLM call may take before it is abandoned and retried like a transient API error
+	/// (default: 90)
+	#[serde(default = "default_llm_timeout_secs")]
+	pub llm_timeout_secs: u64,
+	/// Seconds a code generation LLM call may take before it is abandoned and retried (default: 300)
+	#[serde(default = "default_code_llm_timeout_secs")]
+	pub code_llm_timeout_secs: u64,
 	/// Max consecutive LLM failures before stopping (quiz questions or VPL code retries) (default: 5)
 	#[serde(default = "default_max_consecutive_failures")]
 	pub max_consecutive_failures: u32,
@@ -30,16 +164,268 @@ pub struct AppConfig {
 	/// Run with visible browser window (non-headless mode)
 	#[serde(default)]
 	pub visible: bool,
-	/// In headless mode, when no questions are found on a page, skip to the next page instead of
-	/// exiting. Conflicts with `visible` and `continuation_prompts` (both of which handle this
-	/// interactively).
+	/// DevTools endpoint of an already running Chrome to use instead of launching one (see `--connect`)
+	#[serde(default)]
+	pub browser_ws: Option<String>,
+	/// Chrome/Chromium binary to launch; found on PATH when unset
+	#[serde(default)]
+	pub chrome_executable: Option<String>,
+	/// Extra command-line arguments for the launched Chrome, e.g. `["--no-sandbox", "--lang=fr"]`
+	#[serde(default)]
+	#[settings(skip)]
+	pub chrome_args: Vec<String>,
+	/// Chrome profile directory for the launched browser, so cookies, local storage and the federation's
+	/// "remember my institution" choice carry over between runs
+	#[serde(default)]
+	pub user_data_dir: Option<String>,
+	/// Keep a profile in the state directory when `user_data_dir` isn't set
+	#[serde(default)]
+	pub persist_profile: bool,
+	/// In headless mode, pass for a regular Chrome (user agent, `navigator.webdriver`, languages) so login pages
+	/// don't serve their bot challenge (default: true)
+	#[serde(default = "default_stealth")]
+	pub stealth: bool,
+	/// Proxy for the browser and HTTP requests: `http://host:port`, `https://...`, `socks5://...` or `socks4://...`
+	#[serde(default)]
+	pub proxy: Option<String>,
+	/// Hosts to reach without the proxy, comma-separated (e.g. "localhost,*.internal")
+	#[serde(default)]
+	pub proxy_bypass: Option<String>,
+	/// Username for a proxy that needs authentication (can also be given in the `proxy` URL)
+	#[serde(default)]
+	pub proxy_username: Option<String>,
+	/// Password for a proxy that needs authentication
+	#[serde(default)]
+	pub proxy_password: Option<String>,
+	/// `Accept-Language` sent by the browser, also used for `navigator.languages` with `stealth`
+	/// (default: "fr-FR,fr;q=0.9,en;q=0.8")
+	#[serde(default)]
+	pub browser_language: Option<String>,
+	/// Waits between browser actions and the random pause before each click, e.g. `[timings] post_save_wait_ms =
+	/// 5000` (see [`Timings`] for the fields)
+	#[serde(default)]
+	#[settings(skip)]
+	pub timings: Timings,
+	/// Save a full-page screenshot next to each page HTML snapshot; error pages get one regardless (default: true)
+	#[serde(default = "default_save_screenshots")]
+	pub save_screenshots: bool,
+	/// Write every LLM call (full prompt, attached images, model, raw reply, whether it parsed, timing) to a numbered
+	/// JSON file in the session directory's `llm/`, for auditing; config credentials are redacted (default: false)
+	#[serde(default)]
+	pub log_llm_exchanges: bool,
+	/// In headless mode, when no questions are found on a page, or none of them could be answered, skip to the next
+	/// page instead of exiting; also lets `continuation_prompts` finalize an attempt with unsupported questions left
+	/// blank. Questions left unanswered this way still make the run exit nonzero. Conflicts with `visible` (which
+	/// handles this interactively).
 	#[serde(default)]
 	pub allow_skip: bool,
 	/// Extra context appended to all LLM prompts (e.g. "code should be written in C")
 	#[serde(default)]
 	pub context: Option<String>,
+	/// Files (lecture notes, a glossary) whose contents go before every LLM conversation, in order
+	#[serde(default)]
+	#[settings(skip)]
+	pub context_file: Vec<String>,
+	/// More context files for the URLs starting with a prefix, after `context_file`, e.g.
+	/// `[site_context_files] "https://moodle.example.fr/course/view.php?id=42" = ["/home/me/notes/algo_glossary.md"]`
+	#[serde(default)]
+	#[settings(skip)]
+	pub site_context_files: HashMap<String, Vec<String>>,
+	/// Most of the context files given to the LLM, in tokens (roughly 4 characters each); past it they are cut,
+	/// with a warning (default: 4000)
+	#[serde(default = "default_context_max_tokens")]
+	pub context_max_tokens: usize,
+	/// Fill in answers (or paste and save VPL code) without ever submitting, checking or confirming
+	/// anything. Conflicts with `auto_submit`.
+	#[serde(default)]
+	pub dry_run: bool,
+	/// Resubmit VPL activities even when they already have full marks (they are skipped otherwise)
+	#[serde(default)]
+	pub force_vpl: bool,
+	/// Answer quiz questions again even when the navigation panel shows an answer already saved (a resumed
+	/// attempt); by default those are kept
+	#[serde(default)]
+	pub reanswer: bool,
+	/// Seconds to wait for a page change (manual submission, manual navigation) before giving up; 0 waits
+	/// forever (default: 600)
+	#[serde(default = "default_page_change_timeout_secs")]
+	pub page_change_timeout_secs: u64,
+	/// Timed quizzes: below this many seconds per question left, stop showing images in the terminal and drop large
+	/// images from LLM requests (default: 60)
+	#[serde(default = "default_tight_question_budget_secs")]
+	pub tight_question_budget_secs: u64,
+	/// Images smaller than this many pixels in both dimensions are taken for icons and left out of the questions, so
+	/// they aren't shown or attached to LLM requests; 0 keeps them all (default: 32)
+	#[serde(default = "default_min_image_size_px")]
+	pub min_image_size_px: u32,
+	/// Timed quizzes: with fewer seconds than this left, stop answering and submit the attempt as it is, without
+	/// asking for confirmation (default: 90)
+	#[serde(default = "default_panic_threshold_secs")]
+	pub panic_threshold_secs: u64,
+	/// Base32 TOTP secret for CAS two-factor login (the one encoded in the enrollment QR code)
+	#[serde(default)]
+	pub totp_secret: Option<String>,
+	/// Institution to pick in the Renater federation dropdown when logging in to caseine.org, as listed there
+	/// (default: "Université Clermont Auvergne")
+	#[serde(default)]
+	pub institution: Option<String>,
+	/// Times to rerun the whole login flow after a transient failure (timeout, missing element, 5xx page);
+	/// rejected credentials are never retried (default: 2)
+	#[serde(default = "default_login_retries")]
+	pub login_retries: u32,
+	/// Seconds to wait before rerunning the login flow (default: 10)
+	#[serde(default = "default_login_retry_delay_secs")]
+	pub login_retry_delay_secs: u64,
+	/// Per-domain credentials overriding the top-level username/password, e.g.
+	/// `[credentials."caseine.org"] username = "..."`. Subdomains match too.
+	#[serde(default)]
+	#[settings(skip)]
+	pub credentials: HashMap<String, Credentials>,
+	/// Login flow overrides by domain, e.g. `[sites] "moodle.example.edu" = "direct"`. Subdomains match too.
+	#[serde(default)]
+	#[settings(skip)]
+	pub sites: HashMap<String, LoginFlow>,
+	/// Password for quizzes that require one before starting an attempt
+	#[serde(default)]
+	pub quiz_password: Option<String>,
+	/// Moodle webservice token (the mobile app's `moodle_mobile_app` token) used by `--webservice`
+	#[serde(default)]
+	pub ws_token: Option<String>,
+	/// Moodle root for webservice calls; derived from the target URL when unset
+	#[serde(default)]
+	pub ws_base_url: Option<String>,
+	/// Commands checking generated VPL code locally before it is pasted, by language (or file extension), e.g.
+	/// `[local_check_cmd] python = "python -m py_compile {file}"`. `{file}` is replaced with the file's path.
+	#[serde(default)]
+	#[settings(skip)]
+	pub local_check_cmd: HashMap<String, String>,
+	/// Where LLM calls go: "anthropic", or "openai_compatible" for a local Ollama or any other OpenAI-style endpoint
+	/// (default: anthropic)
+	#[serde(default)]
+	#[settings(skip)]
+	pub llm_provider: LlmProvider,
+	/// Base URL of the openai_compatible endpoint, up to the API version (default: http://localhost:11434/v1, Ollama)
+	#[serde(default)]
+	pub llm_base_url: Option<String>,
+	/// Environment variable holding the openai_compatible endpoint's API key; unset sends none, as local servers want
+	#[serde(default)]
+	pub llm_api_key_env: Option<String>,
+	/// Model the openai_compatible endpoint runs, e.g. "qwen2.5vl:7b"; used for every quiz_model/code_model tier
+	#[serde(default)]
+	pub llm_model_name: Option<String>,
+	/// Whether the openai_compatible model takes images; if not, questions with images fail instead of being sent
+	/// (default: true)
+	#[serde(default = "default_llm_supports_images")]
+	pub llm_supports_images: bool,
+	/// LLM model for quiz questions: "fast", "medium" or "slow" (default: medium)
+	#[serde(default)]
+	pub quiz_model: Option<String>,
+	/// LLM model for VPL code generation: "fast", "medium" or "slow" (default: medium)
+	#[serde(default)]
+	pub code_model: Option<String>,
+	/// Output token budget for quiz answers; unset uses a per-question-type default (128 to 2048)
+	#[serde(default)]
+	pub quiz_max_tokens: Option<usize>,
+	/// Output token budget for generated VPL code (default: 8192)
+	#[serde(default = "default_code_max_tokens")]
+	pub code_max_tokens: usize,
+	/// Retry once with the next-larger model when an answer or code reply is malformed or out of range
+	/// (default: true)
+	#[serde(default = "default_escalate_on_failure")]
+	pub escalate_on_failure: bool,
+	/// Answers the LLM is less confident about than this (0-100) are never auto-submitted: they go through the
+	/// confirm prompt, or are left blank when running headless
+	#[serde(default)]
+	pub min_confidence: Option<u8>,
+	/// Language the LLM writes free-text answers (short answers, essays, text blanks) and code comments in: a code
+	/// like "fr" or a name, "auto" for the language of the question, empty to not say (default: auto)
+	#[serde(default)]
+	pub answer_language: Option<String>,
+	/// Directory of prompt templates (`single_choice.txt`, `code_submission.txt`, ...) replacing the built-in ones,
+	/// see `--dump-prompts` (default: prompts/ in the config directory)
+	#[serde(default)]
+	pub prompts_dir: Option<String>,
+	/// Ask the LLM for a one-sentence reasoning with each quiz answer, shown in the answer review and the report
+	/// (default: true)
+	#[serde(default = "default_show_reasoning")]
+	pub show_reasoning: bool,
+	/// Model prices for the end-of-run cost estimate, by model name, e.g. `[llm_prices] medium = { input = 3.0,
+	/// output = 15.0 }` (USD per million tokens). Only used for calls whose provider reports tokens but no cost
+	/// (OpenAI-compatible endpoints); models without a price are left out of the estimate.
+	#[serde(default)]
+	#[settings(skip)]
+	pub llm_prices: HashMap<String, ModelPrice>,
+	/// Progress output: "human" (default) or "json" for one JSON event per line on stdout (see the `emit` module).
+	/// "json" needs `auto_submit` (or `dry_run`), since there is nobody to answer confirmation prompts.
+	#[serde(default)]
+	#[settings(skip)]
+	pub output: Emitter,
+	/// Days LLM answers stay in the on-disk answer cache (default: 7)
+	#[serde(default = "default_answer_cache_ttl_days")]
+	pub answer_cache_ttl_days: u64,
+	/// Kinds of questions to answer, e.g. `["single", "multi", "match", "fill"]` (`"vpl"` for VPL code); the others
+	/// are displayed and left to the user, and block finalizing the attempt unless `allow_skip` is set. Unset
+	/// answers everything.
+	#[serde(default)]
+	#[settings(skip)]
+	pub question_types: Option<Vec<QuestionKind>>,
 }
 impl AppConfig {
+	/// Run `username_cmd`/`password_cmd` (if set) and use their trimmed output as the top-level credentials.
+	/// Call once at startup; the output is never logged.
+	pub fn resolve_secret_commands(&mut self) -> Result<()> {
+		if let Some(cmd) = &self.username_cmd {
+			self.username = run_secret_command("username_cmd", cmd)?;
+		}
+		if let Some(cmd) = &self.password_cmd {
+			self.password = run_secret_command("password_cmd", cmd)?;
+		}
+		Ok(())
+	}
+
+	/// Login flow configured in `sites` for the host of `url`, if any
+	pub fn login_flow(&self, url: &str) -> Option<LoginFlow> {
+		let host = crate::login::url_host(url);
+		self.sites
+			.iter()
+			.filter(|(domain, _)| domain_matches(host, domain))
+			.max_by_key(|(domain, _)| domain.len())
+			.map(|(_, flow)| *flow)
+	}
+
+	/// Credentials to log in to the site at `url`: the most specific `credentials` entry for its host, else the
+	/// top-level username/password. None if neither is set.
+	pub fn credentials_for(&self, url: &str) -> Option<Credentials> {
+		let host = crate::login::url_host(url);
+		if let Some((_, creds)) = self.credentials.iter().filter(|(domain, _)| domain_matches(host, domain)).max_by_key(|(domain, _)| domain.len()) {
+			return Some(creds.clone());
+		}
+		(!self.username.is_empty() && !self.password.is_empty()).then(|| Credentials {
+			username: self.username.clone(),
+			password: self.password.clone(),
+		})
+	}
+
+	/// `institution`, else the default
+	pub fn institution(&self) -> &str {
+		self.institution.as_deref().unwrap_or(DEFAULT_INSTITUTION)
+	}
+
+	/// `browser_language`, else the default
+	pub fn browser_language(&self) -> &str {
+		self.browser_language.as_deref().unwrap_or(DEFAULT_BROWSER_LANGUAGE)
+	}
+
+	/// `answer_language`, else the default
+	pub fn answer_language(&self) -> &str {
+		self.answer_language.as_deref().unwrap_or(DEFAULT_ANSWER_LANGUAGE)
+	}
+
+	/// Whether questions of this kind are answered (see `question_types`)
+	pub fn answers_kind(&self, kind: QuestionKind) -> bool {
+		self.question_types.as_ref().is_none_or(|kinds| kinds.contains(&kind))
+	}
+
 	/// Set auto_submit at runtime
 	///
 	/// # Safety
@@ -49,10 +435,56 @@ impl AppConfig {
 	}
 }
 
+/// Run a shell command and return its trimmed stdout. Errors mention the option name, never the output.
+fn run_secret_command(option: &str, cmd: &str) -> Result<String> {
+	let output = std::process::Command::new("sh")
+		.arg("-c")
+		.arg(cmd)
+		.stdin(std::process::Stdio::inherit()) // let e.g. `pass` ask for the GPG passphrase
+		.stderr(std::process::Stdio::inherit())
+		.output()
+		.map_err(|e| eyre!("Failed to run {option} `{cmd}`: {e}"))?;
+	if !output.status.success() {
+		bail!("{option} `{cmd}` failed with {}", output.status);
+	}
+	let value = String::from_utf8(output.stdout).map_err(|_| eyre!("{option} `{cmd}` printed non-UTF-8 output"))?;
+	// Only the first line: `pass show` puts extra metadata after the secret
+	let value = value.lines().next().unwrap_or("").trim().to_string();
+	if value.is_empty() {
+		bail!("{option} `{cmd}` printed nothing");
+	}
+	Ok(value)
+}
+
+/// Whether `host` is `domain` or one of its subdomains
+fn domain_matches(host: &str, domain: &str) -> bool {
+	host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+fn default_escalate_on_failure() -> bool {
+	true
+}
+
+fn default_answer_cache_ttl_days() -> u64 {
+	7
+}
+
+fn default_code_max_tokens() -> usize {
+	8192
+}
+
 fn default_api_retries() -> u32 {
 	3
 }
 
+fn default_llm_timeout_secs() -> u64 {
+	90
+}
+
+fn default_code_llm_timeout_secs() -> u64 {
+	300
+}
+
 fn default_api_retry_delay_ms() -> u64 {
 	1000
 }
@@ -64,3 +496,55 @@ fn default_max_consecutive_failures() -> u32 {
 fn default_button_click_retries() -> u32 {
 	5
 }
+
+fn default_page_change_timeout_secs() -> u64 {
+	600
+}
+
+fn default_tight_question_budget_secs() -> u64 {
+	60
+}
+
+fn default_min_image_size_px() -> u32 {
+	crate::images::DEFAULT_MIN_IMAGE_SIZE_PX
+}
+
+fn default_panic_threshold_secs() -> u64 {
+	90
+}
+
+fn default_stealth() -> bool {
+	true
+}
+
+fn default_confirmation_keywords() -> Vec<String> {
+	["envoyer", "terminer", "submit", "finir", "confirm", "valider"].map(String::from).to_vec()
+}
+
+fn default_typed_question_classes() -> Vec<String> {
+	vec!["stack".to_string()]
+}
+
+fn default_context_max_tokens() -> usize {
+	4000
+}
+
+fn default_show_reasoning() -> bool {
+	true
+}
+
+fn default_llm_supports_images() -> bool {
+	true
+}
+
+fn default_save_screenshots() -> bool {
+	true
+}
+
+fn default_login_retries() -> u32 {
+	2
+}
+
+fn default_login_retry_delay_secs() -> u64 {
+	10
+}
@@ -0,0 +1,87 @@
+//! Course notes given to the LLM before every conversation (`context_file`, `site_context_files`), for quizzes that
+//! assume the lecture's definitions and conventions
+
+use std::sync::{Arc, Mutex};
+
+use color_eyre::{Result, eyre::eyre};
+use v_utils::elog;
+
+use crate::config::AppConfig;
+
+/// Rough size of a token, for `context_max_tokens`
+const CHARS_PER_TOKEN: usize = 4;
+
+/// The files read at startup, and the preamble built from them for the URL being processed
+#[derive(Debug, Default)]
+pub struct ContextFiles {
+	/// `context_file`, as (path, content)
+	global: Vec<(String, String)>,
+	/// `site_context_files`, by URL prefix
+	by_prefix: Vec<(String, Vec<(String, String)>)>,
+	max_chars: usize,
+	current: Mutex<Option<Arc<str>>>,
+}
+
+impl ContextFiles {
+	/// Read every configured file; a missing one is an error
+	pub fn load(config: &AppConfig) -> Result<Self> {
+		let read_all = |paths: &[String]| -> Result<Vec<(String, String)>> {
+			paths
+				.iter()
+				.map(|path| {
+					std::fs::read_to_string(path)
+						.map(|content| (path.clone(), content))
+						.map_err(|e| eyre!("Failed to read context file {path}: {e}"))
+				})
+				.collect()
+		};
+		let mut by_prefix = Vec::new();
+		for (prefix, paths) in &config.site_context_files {
+			by_prefix.push((prefix.clone(), read_all(paths)?));
+		}
+		// Shorter prefixes first, so a course's files come after its site's
+		by_prefix.sort_by_key(|(prefix, _)| prefix.len());
+		let files = Self {
+			global: read_all(&config.context_file)?,
+			by_prefix,
+			max_chars: config.context_max_tokens * CHARS_PER_TOKEN,
+			current: Mutex::default(),
+		};
+		*files.current.lock().unwrap() = files.preamble_for(None);
+		Ok(files)
+	}
+
+	/// Use the files for `url` from now on: `context_file`, then the `site_context_files` whose prefix it starts with
+	pub fn select(&self, url: &str) {
+		*self.current.lock().unwrap() = self.preamble_for(Some(url));
+	}
+
+	/// The notes to put before a new conversation, None without any
+	pub fn preamble(&self) -> Option<Arc<str>> {
+		self.current.lock().unwrap().clone()
+	}
+
+	fn preamble_for(&self, url: Option<&str>) -> Option<Arc<str>> {
+		let site_files = self
+			.by_prefix
+			.iter()
+			.filter(|(prefix, _)| url.is_some_and(|url| url.starts_with(prefix.as_str())))
+			.flat_map(|(_, files)| files);
+		let files: Vec<&(String, String)> = self.global.iter().chain(site_files).collect();
+		if files.is_empty() {
+			return None;
+		}
+
+		let mut notes = files.iter().map(|(_, content)| content.trim()).collect::<Vec<_>>().join("\n\n");
+		if let Some((cut, _)) = notes.char_indices().nth(self.max_chars) {
+			let paths: Vec<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+			elog!(
+				"Context files ({}) are over context_max_tokens, only their first ~{} tokens are given to the LLM",
+				paths.join(", "),
+				self.max_chars / CHARS_PER_TOKEN
+			);
+			notes.truncate(cut);
+		}
+		Some(format!("Course notes (definitions and conventions the questions may assume):\n{notes}\n\n---\n\n").into())
+	}
+}
@@ -0,0 +1,137 @@
+//! ANSI styling of the questions and answers shown in the terminal
+//!
+//! Only the human output is styled; logs and LLM prompts keep the plain text. Everything here returns its input
+//! unchanged when color is off: `--no-color`, `NO_COLOR` set, or stderr (where the human output goes) not a terminal.
+
+use std::{io::IsTerminal, sync::OnceLock};
+
+use crate::{Question, marks::QuestionMarks};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const GREEN: &str = "\x1b[32m";
+const MAGENTA: &str = "\x1b[35m";
+const CYAN: &str = "\x1b[36m";
+
+/// Width of the ruler between questions
+const RULER_WIDTH: usize = 60;
+
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+/// Style the output for the rest of the run unless `no_color`; only the first call has an effect
+pub fn init(no_color: bool) {
+	let enabled = !no_color && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && std::io::stderr().is_terminal();
+	let _ = COLOR.set(enabled);
+}
+
+fn enabled() -> bool {
+	COLOR.get().copied().unwrap_or(false)
+}
+
+fn paint(style: &str, text: &str) -> String {
+	match enabled() && !text.is_empty() {
+		true => format!("{style}{text}{RESET}"),
+		false => text.to_string(),
+	}
+}
+
+/// "--- Question 3 [multi] (2.0 pts) ---", the title bold and the type and marks dim
+pub fn question_header(number: usize, question: &Question, marks: QuestionMarks) -> String {
+	let marks = match marks.max {
+		Some(_) => format!(" {}", paint(DIM, &format!("({marks})"))),
+		None => String::new(),
+	};
+	format!(
+		"{} {}{marks} {}",
+		paint(BOLD, &format!("--- Question {number}")),
+		paint(DIM, question.type_marker()),
+		paint(BOLD, "---")
+	)
+}
+
+/// Separates the questions of a page: a dim rule, or a blank line without color
+pub fn ruler() -> String {
+	match enabled() {
+		true => paint(DIM, &"─".repeat(RULER_WIDTH)),
+		false => String::new(),
+	}
+}
+
+/// A question as displayed (its `Display`), with `**bold**` spans, inline code, code blocks and LaTeX colored, and
+/// list items indented
+pub fn question_text(text: &str) -> String {
+	if !enabled() {
+		return text.to_string();
+	}
+	let mut out = Vec::new();
+	let mut in_code_block = false;
+	for line in text.lines() {
+		if line.trim_start().starts_with("```") {
+			in_code_block = !in_code_block;
+			out.push(paint(DIM, line));
+		} else if in_code_block {
+			out.push(paint(CYAN, line));
+		} else if line.starts_with("• ") {
+			out.push(format!("  {}", inline_spans(line)));
+		} else {
+			out.push(inline_spans(line));
+		}
+	}
+	out.join("\n")
+}
+
+/// `**bold**`, `` `code` `` and `\(...\)`/`\[...\]` spans of one line styled; unclosed ones are left as they are
+fn inline_spans(line: &str) -> String {
+	const SPANS: [(&str, &str, &str, bool); 4] = [("**", "**", BOLD, false), ("`", "`", CYAN, true), ("\\(", "\\)", MAGENTA, true), ("\\[", "\\]", MAGENTA, true)];
+	let mut out = String::new();
+	let mut rest = line;
+	loop {
+		let next = SPANS
+			.iter()
+			.filter_map(|&(open, close, style, keep)| {
+				let start = rest.find(open)?;
+				let len = rest[start + open.len()..].find(close)?;
+				Some((start, open, close, style, keep, len))
+			})
+			.min_by_key(|&(start, ..)| start);
+		let Some((start, open, close, style, keep, len)) = next else {
+			out.push_str(rest);
+			return out;
+		};
+		out.push_str(&rest[..start]);
+		let inner = &rest[start + open.len()..start + open.len() + len];
+		match keep {
+			true => out.push_str(&paint(style, &format!("{open}{inner}{close}"))),
+			false => out.push_str(&paint(style, inner)),
+		}
+		rest = &rest[start + open.len() + len + close.len()..];
+	}
+}
+
+/// An answer log entry: "Question 3 [single] answer:" headers bold, the chosen options and answers green, the
+/// reasoning dim
+pub fn answer_log(entry: &str) -> String {
+	if !enabled() {
+		return entry.to_string();
+	}
+	entry
+		.lines()
+		.map(|line| {
+			let content = line.trim_start();
+			let indent = &line[..line.len() - content.len()];
+			if line.starts_with("Question ") {
+				paint(BOLD, line)
+			} else if content.starts_with("Reasoning: ") {
+				format!("{indent}{}", paint(DIM, content))
+			} else if content.ends_with(':') {
+				line.to_string()
+			} else if let Some((label, separator, answer)) = [" -> ", ": "].into_iter().find_map(|sep| content.split_once(sep).map(|(label, answer)| (label, sep, answer))) {
+				format!("{indent}{label}{separator}{}", paint(GREEN, answer))
+			} else {
+				format!("{indent}{}", paint(GREEN, content))
+			}
+		})
+		.collect::<Vec<_>>()
+		.join("\n")
+}
@@ -0,0 +1,98 @@
+//! How a run reports progress: decorated text on the terminal, or one JSON object per event on stdout for wrapper
+//! programs (`--output json`)
+//!
+//! The runner sends its decorative output through [`Emitter::human`] and the notable moments through
+//! [`Emitter::emit`], at the same call sites, so the two modes can't drift apart:
+//!
+//! ```text
+//! {"event":"question","index":3,"kind":"multi","text":"Which of these are primes?"}
+//! {"event":"answer","index":3,"answer":{"type":"multi","indices":[0,2],"texts":["2","5"]},"confidence":90}
+//! {"event":"submitted","count":5}
+//! {"event":"vpl_grade","value":0.8}
+//! {"event":"error","message":"Exceeded 5 consecutive LLM failures"}
+//! ```
+
+use std::{
+	fmt,
+	io::{Write, stdout},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Question, llm::LlmAnswerResult};
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Emitter {
+	/// Question text, images, answers and code for a person at the terminal
+	#[default]
+	Human,
+	/// JSON lines on stdout, nothing decorative
+	Json,
+}
+
+/// Something that happened during the run, as emitted in JSON mode
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+	/// A question about to be answered
+	Question {
+		index: usize,
+		kind: &'a str,
+		text: &'a str,
+	},
+	Answer {
+		index: usize,
+		answer: &'a LlmAnswerResult,
+		confidence: Option<u8>,
+	},
+	/// Answers submitted (or saved through the webservice) for a page
+	Submitted {
+		count: usize,
+	},
+	/// Proposed grade of a VPL evaluation, 0.0 to 1.0
+	VplGrade {
+		value: f64,
+	},
+	Error {
+		message: String,
+	},
+}
+
+impl<'a> Event<'a> {
+	pub fn question(index: usize, question: &'a Question) -> Self {
+		Event::Question {
+			index,
+			kind: question.kind().name(),
+			text: question.question_text(),
+		}
+	}
+}
+
+impl Emitter {
+	pub fn is_json(self) -> bool {
+		self == Emitter::Json
+	}
+
+	/// Decorative output for the terminal (stderr); nothing in JSON mode
+	pub fn human(self, text: impl fmt::Display) {
+		if self == Emitter::Human {
+			eprintln!("{text}");
+		}
+	}
+
+	/// One JSON line on stdout in JSON mode; nothing in human mode, where the decorated output already says it
+	pub fn emit(self, event: &Event) {
+		if self != Emitter::Json {
+			return;
+		}
+		match serde_json::to_string(event) {
+			Ok(line) => {
+				let mut out = stdout().lock();
+				let _ = writeln!(out, "{line}");
+				let _ = out.flush();
+			}
+			Err(e) => tracing::warn!("Failed to serialize event: {e}"),
+		}
+	}
+}
@@ -0,0 +1,111 @@
+//! Audit log of the LLM calls of a run (`log_llm_exchanges`): one numbered JSON file per call in the session
+//! directory's `llm/`, with the full prompt, the attached images, the model, the raw reply, whether it parsed and
+//! how long it took
+//!
+//! Credentials from the config (passwords, TOTP secret, webservice token) are replaced with `<redacted>` wherever
+//! they show up; nothing else is.
+
+use std::{
+	path::PathBuf,
+	sync::{
+		Mutex,
+		atomic::{AtomicU32, Ordering},
+	},
+};
+
+use v_utils::elog;
+
+use crate::config::AppConfig;
+
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug)]
+pub struct ExchangeLog {
+	dir: PathBuf,
+	/// Config values never written out
+	secrets: Vec<String>,
+	/// Calls logged so far
+	seq: AtomicU32,
+	/// File of the last call and what went in it, to add the parse outcome to once known
+	last: Mutex<Option<(PathBuf, serde_json::Value)>>,
+}
+
+impl ExchangeLog {
+	/// Log into `dir` (created on the first call), redacting the credentials of `config`
+	pub fn new(dir: impl Into<PathBuf>, config: &AppConfig) -> Self {
+		Self {
+			dir: dir.into(),
+			secrets: config_secrets(config),
+			seq: AtomicU32::new(0),
+			last: Mutex::default(),
+		}
+	}
+
+	/// Write `exchange` to the next numbered file, `001.json`, `002.json`...
+	pub fn record(&self, mut exchange: serde_json::Value) {
+		let n = self.seq.fetch_add(1, Ordering::Relaxed) + 1;
+		if let Some(fields) = exchange.as_object_mut() {
+			fields.insert("n".to_string(), n.into());
+		}
+		redact(&mut exchange, &self.secrets);
+		if let Err(e) = std::fs::create_dir_all(&self.dir) {
+			elog!("Failed to create LLM exchange log dir {}: {e}", self.dir.display());
+			return;
+		}
+		let path = self.dir.join(format!("{n:03}.json"));
+		write_json(&path, &exchange);
+		*self.last.lock().unwrap() = Some((path, exchange));
+	}
+
+	/// Note whether the reply of the last call parsed: `error` is why it didn't, None if it did
+	pub fn record_parse(&self, error: Option<&str>) {
+		let mut last = self.last.lock().unwrap();
+		let Some((path, exchange)) = last.as_mut() else {
+			return;
+		};
+		if let Some(fields) = exchange.as_object_mut() {
+			fields.insert("parsed".to_string(), error.is_none().into());
+			if let Some(error) = error {
+				fields.insert("parse_error".to_string(), error.into());
+			}
+		}
+		redact(exchange, &self.secrets);
+		write_json(path, exchange);
+	}
+}
+
+/// Passwords, secrets and tokens set in `config`
+fn config_secrets(config: &AppConfig) -> Vec<String> {
+	let mut secrets: Vec<String> = [&config.proxy_password, &config.totp_secret, &config.quiz_password, &config.ws_token]
+		.into_iter()
+		.flatten()
+		.cloned()
+		.collect();
+	secrets.push(config.password.clone());
+	secrets.extend(config.credentials.values().map(|c| c.password.clone()));
+	secrets.retain(|s| !s.trim().is_empty());
+	// Longest first, so a secret containing another one is redacted whole
+	secrets.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+	secrets.dedup();
+	secrets
+}
+
+fn redact(value: &mut serde_json::Value, secrets: &[String]) {
+	match value {
+		serde_json::Value::String(s) =>
+			for secret in secrets {
+				if s.contains(secret.as_str()) {
+					*s = s.replace(secret.as_str(), REDACTED);
+				}
+			},
+		serde_json::Value::Array(items) => items.iter_mut().for_each(|item| redact(item, secrets)),
+		serde_json::Value::Object(fields) => fields.values_mut().for_each(|field| redact(field, secrets)),
+		_ => {}
+	}
+}
+
+fn write_json(path: &std::path::Path, value: &serde_json::Value) {
+	if let Err(e) = std::fs::write(path, serde_json::to_string_pretty(value).unwrap_or_default()) {
+		elog!("Failed to write LLM exchange {}: {e}", path.display());
+	}
+}
@@ -0,0 +1,51 @@
+//! Question export - dump parsed questions to JSON or Markdown without answering them
+
+use std::{fmt::Write as _, path::Path};
+
+use color_eyre::{Result, eyre::eyre};
+
+use crate::Question;
+
+/// Write questions to `path`: Markdown if the extension is `.md`, pretty JSON otherwise
+pub fn write_questions(path: &Path, questions: &[Question]) -> Result<()> {
+	let content = if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("md")) {
+		questions_to_markdown(questions)
+	} else {
+		serde_json::to_string_pretty(questions).map_err(|e| eyre!("Failed to serialize questions: {e}"))?
+	};
+	std::fs::write(path, content).map_err(|e| eyre!("Failed to write export file {}: {e}", path.display()))
+}
+
+/// Render questions as a readable Markdown document
+pub fn questions_to_markdown(questions: &[Question]) -> String {
+	let mut out = String::new();
+	for (i, question) in questions.iter().enumerate() {
+		let _ = writeln!(out, "## Question {} {}\n", i + 1, question.type_marker());
+		let _ = writeln!(out, "{question}");
+
+		for img in question.images() {
+			let _ = writeln!(out, "![{}]({})", img.alt.as_deref().unwrap_or(""), img.url);
+		}
+		for choice in question.choices() {
+			for img in &choice.images {
+				let _ = writeln!(out, "- choice \"{}\": ![{}]({})", choice.text, img.alt.as_deref().unwrap_or(""), img.url);
+			}
+		}
+
+		// VPL: include the file templates themselves
+		for file in question.required_files() {
+			if !file.content.is_empty() {
+				let _ = writeln!(out, "\n`{}`:\n```\n{}\n```", file.name, file.content.trim_end());
+			}
+		}
+
+		let fields = question.field_names();
+		if !fields.is_empty() {
+			let mut fields: Vec<String> = fields.into_iter().map(|name| format!("`{name}`")).collect();
+			fields.dedup();
+			let _ = writeln!(out, "\nFields: {}", fields.join(", "));
+		}
+		out.push('\n');
+	}
+	out
+}
@@ -0,0 +1,115 @@
+//! Failure classes and the process exit code each one maps to
+//!
+//! | code | meaning |
+//! |------|---------|
+//! | 0    | success |
+//! | 1    | any other error |
+//! | 2    | login failed |
+//! | 3    | page couldn't be parsed: no questions, or not a quiz/VPL page |
+//! | 4    | LLM kept failing after all retries |
+//! | 5    | below target: VPL short of full marks, or the quiz attempt wasn't submitted |
+//! | 6    | browser or CDP failure |
+//! | 7    | Moodle refused the page: attempt already submitted, not enrolled, no permission, error page |
+//! | 8    | the LLM API rejected the credentials (`CLAUDE_TOKEN`, or `llm_api_key_env`) |
+//! | 130  | interrupted (Ctrl+C) |
+//!
+//! Errors are tagged with `.wrap_err(FailureKind::X)` where they arise; [`FailureKind::of`] reads the tag back. The tag
+//! becomes the outermost message, so print tagged errors with `{:#}` to keep the cause.
+
+use std::fmt;
+
+use color_eyre::Report;
+
+use crate::{llm_error::LlmErrorKind, login::LoginError};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FailureKind {
+	Login,
+	Parse,
+	Llm,
+	LlmAuth,
+	BelowTarget,
+	Browser,
+	Access,
+	Interrupted,
+	Other,
+}
+
+impl FailureKind {
+	pub fn exit_code(self) -> i32 {
+		match self {
+			FailureKind::Other => 1,
+			FailureKind::Login => 2,
+			FailureKind::Parse => 3,
+			FailureKind::Llm => 4,
+			FailureKind::BelowTarget => 5,
+			FailureKind::Browser => 6,
+			FailureKind::Access => 7,
+			FailureKind::LlmAuth => 8,
+			FailureKind::Interrupted => 130,
+		}
+	}
+
+	/// The kind an error was tagged with, [`FailureKind::Other`] if untagged. A [`LoginError`] is a login failure
+	/// even untagged, and so is an LLM call tagged [`LlmErrorKind::AuthFailed`] an LLM auth failure.
+	pub fn of(error: &Report) -> Self {
+		if let Some(kind) = error.downcast_ref::<FailureKind>() {
+			return *kind;
+		}
+		if error.downcast_ref::<LoginError>().is_some() {
+			return FailureKind::Login;
+		}
+		if error.downcast_ref::<LlmErrorKind>() == Some(&LlmErrorKind::AuthFailed) {
+			return FailureKind::LlmAuth;
+		}
+		FailureKind::Other
+	}
+}
+
+impl fmt::Display for FailureKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			FailureKind::Login => "login failed",
+			FailureKind::Parse => "could not parse the page",
+			FailureKind::Llm => "LLM failed",
+			FailureKind::LlmAuth => "LLM credentials rejected",
+			FailureKind::BelowTarget => "below target",
+			FailureKind::Browser => "browser failure",
+			FailureKind::Access => "Moodle refused the page",
+			FailureKind::Interrupted => "interrupted",
+			FailureKind::Other => "error",
+		};
+		write!(f, "{s}")
+	}
+}
+
+/// Why processing a URL didn't succeed
+#[derive(Debug)]
+pub struct Failure {
+	pub kind: FailureKind,
+	pub error: Report,
+}
+
+impl Failure {
+	pub fn below_target(reason: &str) -> Self {
+		Self {
+			kind: FailureKind::BelowTarget,
+			error: Report::msg(reason.to_string()),
+		}
+	}
+}
+
+impl From<Report> for Failure {
+	fn from(error: Report) -> Self {
+		Self {
+			kind: FailureKind::of(&error),
+			error,
+		}
+	}
+}
+
+impl fmt::Display for Failure {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{:#}", self.error)
+	}
+}
@@ -0,0 +1,385 @@
+//! Images referenced by questions, fetched through the browser (so the session cookies apply) once per run and
+//! shared between the terminal display and the LLM attachments
+//!
+//! `Image.url` is whatever the page's `src` said when it was parsed from HTML rather than the live DOM, so URLs are
+//! resolved against the page before fetching, and `data:` images are decoded without fetching anything.
+//!
+//! Lazily-loaded images are made to load before a page is parsed ([`load_lazy_images`]), the parsers take their real
+//! URL over the placeholder, and icons and spacers are left out so they aren't attached to LLM requests.
+//!
+//! With `ocr_cmd` configured, images that can't be fetched are read by OCR instead (see [`crate::ocr`]).
+
+use std::{collections::HashMap, sync::Mutex};
+
+use base64::Engine;
+use chromiumoxide::Page;
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+use futures::StreamExt;
+
+use crate::{Question, config::AppConfig, js_string, ocr, proxy::http_client, timing::timings};
+
+/// Images fetched at the same time
+const MAX_CONCURRENT_FETCHES: usize = 4;
+
+/// Images smaller than this in both dimensions are decorative (icons), unless configured otherwise
+pub const DEFAULT_MIN_IMAGE_SIZE_PX: u32 = 32;
+
+/// `extractImages(element)`, for the page parsers: the URL of each `<img>` in `element` (the lazy-loading
+/// `data-src`/`data-original` over the placeholder `src`, then the largest `srcset` candidate), leaving out icons,
+/// spacer gifs and images under `MIN_IMAGE_SIZE_PX` in both dimensions. Mirrored by [`crate::parse`] for saved HTML.
+const IMAGE_JS: &str = r#"
+	function largestSrcsetCandidate(srcset) {
+		let best = null;
+		let bestSize = -1;
+		for (const candidate of srcset.split(/,\s+/)) {
+			const [url, descriptor] = candidate.trim().split(/\s+/);
+			if (!url) continue;
+			const size = descriptor ? parseFloat(descriptor) || 0 : 1;
+			if (size > bestSize) { best = url; bestSize = size; }
+		}
+		return best;
+	}
+
+	function imageUrl(img) {
+		const lazy = img.getAttribute('data-src') || img.getAttribute('data-original');
+		const srcset = img.getAttribute('srcset') || img.getAttribute('data-srcset');
+		const url = lazy || (srcset && largestSrcsetCandidate(srcset));
+		if (url) {
+			try { return new URL(url, document.baseURI).href; } catch (e) {}
+		}
+		return img.currentSrc || img.src || '';
+	}
+
+	function isDecorative(img, url) {
+		if (img.classList.contains('icon')) return true;
+		if (/\/(spacer|blank|pixel|transparent)\.gif(\?|$)/i.test(url)) return true;
+		// The natural size is the placeholder's until the real image has loaded
+		const loaded = img.complete && img.naturalWidth > 0 && (img.currentSrc || img.src) === url;
+		const width = loaded ? img.naturalWidth : parseInt(img.getAttribute('width')) || 0;
+		const height = loaded ? img.naturalHeight : parseInt(img.getAttribute('height')) || 0;
+		return width > 0 && height > 0 && width < MIN_IMAGE_SIZE_PX && height < MIN_IMAGE_SIZE_PX;
+	}
+
+	function extractImages(element) {
+		if (!element) return [];
+		const images = [];
+		for (const img of element.querySelectorAll('img')) {
+			const url = imageUrl(img);
+			if (url && !isDecorative(img, url)) images.push({ url: url, alt: img.alt || null });
+		}
+		return images;
+	}
+"#;
+
+/// A page parser script (an expression, like `(function() { ... })()`) with `extractImages` in scope
+pub(crate) fn with_image_js(script: &str, min_image_size_px: u32) -> String {
+	format!("(function() {{ const MIN_IMAGE_SIZE_PX = {min_image_size_px}; {IMAGE_JS} return {}; }})()", script.trim())
+}
+
+/// Make the lazily-loaded images on the page load before it's parsed: they're switched to eager loading and the page
+/// is scrolled through (for the themes that load them from a scroll handler), then given up to
+/// `timings.page_settle_wait_ms` to decode
+pub async fn load_lazy_images(page: &Page) -> Result<()> {
+	let script = format!(
+		r#"
+		(async function() {{
+			const images = Array.from(document.querySelectorAll('img'));
+			if (images.length === 0) return 0;
+			for (const img of images) img.loading = 'eager';
+			const start = window.scrollY;
+			const step = Math.max(window.innerHeight, 200);
+			for (let y = 0; y < document.documentElement.scrollHeight; y += step) {{
+				window.scrollTo(0, y);
+				await new Promise(resolve => setTimeout(resolve, 50));
+			}}
+			window.scrollTo(0, start);
+			const decoded = Promise.allSettled(images.map(img => img.decode()));
+			await Promise.race([decoded, new Promise(resolve => setTimeout(resolve, {timeout}))]);
+			return images.length;
+		}})()
+	"#,
+		timeout = timings().page_settle_wait_ms
+	);
+	page.evaluate(script).await.map_err(|e| eyre!("Failed to load lazy images: {e}"))?;
+	Ok(())
+}
+
+/// Fetched images by URL, as (base64 data, media type)
+#[derive(Debug, Default)]
+pub struct ImageCache {
+	images: Mutex<HashMap<String, (String, String)>>,
+	/// Largest image to attach to LLM requests, in bytes; None attaches them all
+	attachment_limit: Mutex<Option<usize>>,
+	/// None without `ocr_cmd`
+	ocr: Option<OcrFallback>,
+	/// What OCR read in each image it ran on, by URL; None when it found nothing or failed
+	ocr_texts: Mutex<HashMap<String, Option<String>>>,
+}
+
+#[derive(Debug)]
+struct OcrFallback {
+	cmd: String,
+	/// Run on every image, not only those that couldn't be fetched
+	prefer: bool,
+	/// For downloading images outside the page
+	client: reqwest::Client,
+}
+
+impl ImageCache {
+	pub fn new(config: &AppConfig) -> Result<Self> {
+		let ocr = match &config.ocr_cmd {
+			Some(cmd) => Some(OcrFallback {
+				cmd: cmd.clone(),
+				prefer: config.prefer_ocr,
+				client: http_client(config)?,
+			}),
+			None => None,
+		};
+		Ok(Self { ocr, ..Self::default() })
+	}
+
+	/// Fetch the given URLs that aren't cached yet, a few at a time. Failures are logged and not cached.
+	pub async fn prefetch<'a>(&self, page: &Page, urls: impl IntoIterator<Item = &'a str>) {
+		let mut missing: Vec<&str> = Vec::new();
+		{
+			let images = self.images.lock().unwrap();
+			for url in urls {
+				if !images.contains_key(url) && !missing.contains(&url) {
+					missing.push(url);
+				}
+			}
+		}
+		if missing.is_empty() {
+			return;
+		}
+
+		let fetched: Vec<(&str, Result<(String, String)>)> = futures::stream::iter(missing)
+			.map(|url| async move { (url, fetch_image_as_base64(page, url).await) })
+			.buffer_unordered(MAX_CONCURRENT_FETCHES)
+			.collect()
+			.await;
+
+		let mut images = self.images.lock().unwrap();
+		for (url, result) in fetched {
+			match result {
+				Ok(image) => {
+					images.insert(url.to_string(), image);
+				}
+				Err(e) => tracing::warn!("Failed to fetch image {url}: {e}"),
+			}
+		}
+	}
+
+	/// Leave images over `limit` bytes out of LLM requests from now on (None: attach them all again)
+	pub fn set_attachment_limit(&self, limit: Option<usize>) {
+		*self.attachment_limit.lock().unwrap() = limit;
+	}
+
+	pub fn attachment_limit(&self) -> Option<usize> {
+		*self.attachment_limit.lock().unwrap()
+	}
+
+	/// Base64 data and media type of the image at `url`, fetched now if it isn't cached
+	pub async fn get(&self, page: &Page, url: &str) -> Result<(String, String)> {
+		if let Some(image) = self.images.lock().unwrap().get(url) {
+			return Ok(image.clone());
+		}
+		let image = fetch_image_as_base64(page, url).await?;
+		self.images.lock().unwrap().insert(url.to_string(), image.clone());
+		Ok(image)
+	}
+
+	/// Text OCR reads in the image at `url`, for images that couldn't be fetched (or all of them with `prefer_ocr`).
+	/// An image fetch failed for is downloaded outside the page, and cached for the display and attachments too if that
+	/// works. None without `ocr_cmd`, and when OCR fails, which is only logged.
+	pub async fn ocr_text(&self, page: &Page, url: &str) -> Option<String> {
+		let ocr = self.ocr.as_ref()?;
+		if let Some(text) = self.ocr_texts.lock().unwrap().get(url) {
+			return text.clone();
+		}
+		let fetched = self.get(page, url).await;
+		if fetched.is_ok() && !ocr.prefer {
+			return None;
+		}
+
+		let bytes = match fetched {
+			Ok((base64, _)) => base64::engine::general_purpose::STANDARD.decode(base64).map_err(|e| eyre!("Failed to decode base64: {e}")),
+			Err(_) => self.download(page, &ocr.client, url).await,
+		};
+		let text = match bytes {
+			Ok(bytes) => ocr::run_ocr(&ocr.cmd, &bytes).await,
+			Err(e) => Err(e),
+		};
+		let text = text.unwrap_or_else(|e| {
+			tracing::warn!("OCR of image {url} failed: {e}");
+			None
+		});
+		self.ocr_texts.lock().unwrap().insert(url.to_string(), text.clone());
+		text
+	}
+
+	/// Download the image at `url` outside the page, caching it on success
+	async fn download(&self, page: &Page, client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+		if url.starts_with("data:") {
+			bail!("Undecodable data URI");
+		}
+		let page_url = page.url().await.ok().flatten().unwrap_or_default();
+		let bytes = ocr::download_image(page, client, &resolve_url(&page_url, url)).await?;
+		let media_type = match bytes.as_slice() {
+			[0x89, b'P', b'N', b'G', ..] => "image/png",
+			[0xff, 0xd8, ..] => "image/jpeg",
+			[b'G', b'I', b'F', ..] => "image/gif",
+			[b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => "image/webp",
+			_ => "application/octet-stream",
+		};
+		if media_type != "application/octet-stream" {
+			let base64 = base64::engine::general_purpose::STANDARD.encode(&bytes);
+			self.images.lock().unwrap().insert(url.to_string(), (base64, media_type.to_string()));
+		}
+		Ok(bytes)
+	}
+}
+
+/// URLs of every image in a question, its choices' included
+pub fn question_image_urls(question: &Question) -> Vec<&str> {
+	let choice_images = question.choices().iter().flat_map(|c| &c.images);
+	question.images().iter().chain(choice_images).map(|img| img.url.as_str()).collect()
+}
+
+/// `url` made absolute against `base` (the URL of the page it appeared on): relative paths, `../` and
+/// protocol-relative `//host/...` URLs. Absolute URLs and `data:` URIs are returned as they are, and so is `url` when
+/// `base` isn't a URL.
+pub fn resolve_url(base: &str, url: &str) -> String {
+	if url.starts_with("data:") {
+		return url.to_string();
+	}
+	match reqwest::Url::parse(base).and_then(|base| base.join(url)) {
+		Ok(resolved) => resolved.into(),
+		Err(_) => url.to_string(),
+	}
+}
+
+/// Base64 data and media type of a `data:` URI
+fn decode_data_uri(uri: &str) -> Result<(String, String)> {
+	let Some((meta, data)) = uri.strip_prefix("data:").and_then(|rest| rest.split_once(',')) else {
+		bail!("Malformed data URI");
+	};
+	let (meta, is_base64) = match meta.strip_suffix(";base64") {
+		Some(meta) => (meta, true),
+		None => (meta, false),
+	};
+	let media_type = meta.split(';').next().filter(|t| !t.is_empty()).unwrap_or("image/png").to_string();
+	let engine = base64::engine::general_purpose::STANDARD;
+	let base64 = if is_base64 {
+		let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+		engine.decode(&data).map_err(|e| eyre!("Invalid base64 in data URI: {e}"))?;
+		data
+	} else {
+		engine.encode(percent_decode(data))
+	};
+	Ok((base64, media_type))
+}
+
+/// `%XX` escapes decoded; malformed ones are kept as they are
+fn percent_decode(s: &str) -> Vec<u8> {
+	let bytes = s.as_bytes();
+	let mut out = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		let escaped = (bytes[i] == b'%').then(|| s.get(i + 1..i + 3)).flatten().and_then(|hex| u8::from_str_radix(hex, 16).ok());
+		match escaped {
+			Some(byte) => {
+				out.push(byte);
+				i += 3;
+			}
+			None => {
+				out.push(bytes[i]);
+				i += 1;
+			}
+		}
+	}
+	out
+}
+
+/// Fetch an image via the browser and return its base64 data and media type
+async fn fetch_image_as_base64(page: &Page, url: &str) -> Result<(String, String)> {
+	if url.starts_with("data:") {
+		return decode_data_uri(url);
+	}
+	let page_url = page.url().await.ok().flatten().unwrap_or_default();
+	let url_js = js_string(&resolve_url(&page_url, url));
+	let fetch_script = format!(
+		r#"
+		(async function() {{
+			try {{
+				const response = await fetch({url_js});
+				if (!response.ok) return null;
+				const blob = await response.blob();
+				const mediaType = blob.type || 'image/png';
+				return new Promise((resolve) => {{
+					const reader = new FileReader();
+					reader.onloadend = () => {{
+						const base64 = reader.result.split(',')[1];
+						resolve(JSON.stringify({{base64: base64, mediaType: mediaType}}));
+					}};
+					reader.readAsDataURL(blob);
+				}});
+			}} catch (e) {{
+				return null;
+			}}
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(fetch_script).await.map_err(|e| eyre!("Failed to fetch image: {e}"))?;
+
+	let json_str = result.value().and_then(|v| v.as_str()).ok_or_else(|| eyre!("Failed to fetch image: browser returned null"))?;
+
+	let parsed: serde_json::Value = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse image data: {e}"))?;
+
+	let base64 = parsed["base64"].as_str().ok_or_else(|| eyre!("Missing base64 data"))?.to_string();
+	let media_type = parsed["mediaType"].as_str().unwrap_or("image/png").to_string();
+
+	Ok((base64, media_type))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn resolve_url_cases() {
+		let base = "https://moodle.example.fr/mod/quiz/attempt.php?attempt=5120&cmid=388";
+		let cases = [
+			("pluginfile.php/77/question/img.png", "https://moodle.example.fr/mod/quiz/pluginfile.php/77/question/img.png"),
+			("/pluginfile.php/77/img%20one.png", "https://moodle.example.fr/pluginfile.php/77/img%20one.png"),
+			(
+				"../../theme/image.php/boost/core/1/i/grade_correct",
+				"https://moodle.example.fr/theme/image.php/boost/core/1/i/grade_correct",
+			),
+			("//cdn.example.org/mathjax/fig.svg", "https://cdn.example.org/mathjax/fig.svg"),
+			("data:image/png;base64,iVBORw0KGgo=", "data:image/png;base64,iVBORw0KGgo="),
+			("http://other.example.org/a.png?x=1", "http://other.example.org/a.png?x=1"),
+		];
+		for (url, expected) in cases {
+			assert_eq!(resolve_url(base, url), expected, "{url}");
+		}
+		assert_eq!(resolve_url("", "pluginfile.php/1/a.png"), "pluginfile.php/1/a.png");
+	}
+
+	#[test]
+	fn data_uris() {
+		assert_eq!(decode_data_uri("data:image/gif;base64,R0lG\nODlh").unwrap(), ("R0lGODlh".to_string(), "image/gif".to_string()));
+		// Not base64: percent-decoded, then encoded
+		assert_eq!(
+			decode_data_uri("data:image/svg+xml;charset=utf-8,%3Csvg%2F%3E").unwrap(),
+			("PHN2Zy8+".to_string(), "image/svg+xml".to_string())
+		);
+		assert_eq!(decode_data_uri("data:,a%zz").unwrap(), ("YSV6eg==".to_string(), "image/png".to_string()));
+		assert!(decode_data_uri("data:image/png;base64,@@@").is_err());
+		assert!(decode_data_uri("data:image/png").is_err());
+	}
+}
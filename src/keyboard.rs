@@ -0,0 +1,84 @@
+//! Typing into text fields with real key events, for question types that don't take a value set from JS
+//!
+//! STACK inputs (and some JS-validated short answers) validate on key events; setting `.value` and dispatching
+//! synthetic `input`/`change` events never reaches them, so the preview under the box stays empty and Moodle doesn't
+//! record the answer. Key events sent through CDP (`Input.dispatchKeyEvent`) are trusted like a user's.
+
+use chromiumoxide::{
+	Page,
+	cdp::browser_protocol::input::{DispatchKeyEventParams, DispatchKeyEventType},
+};
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+	js_string,
+	timing::{keystroke_delay, timings, wait},
+};
+
+/// CDP modifier bit for Ctrl
+const MODIFIER_CTRL: i64 = 2;
+
+/// Focus the text input or textarea named `input_name`, clear it with select-all + delete, and type `text` one key at
+/// a time
+pub async fn type_text_answer(page: &Page, input_name: &str, text: &str) -> Result<()> {
+	let name = js_string(input_name);
+	let element = page
+		.find_element(format!("input[name={name}]:not([type=hidden]), textarea[name={name}]"))
+		.await
+		.map_err(|e| eyre!("Failed to find a text field named \"{input_name}\": {e}"))?;
+	element.scroll_into_view().await.map_err(|e| eyre!("Failed to scroll to \"{input_name}\": {e}"))?;
+	element.focus().await.map_err(|e| eyre!("Failed to focus \"{input_name}\": {e}"))?;
+
+	press_key(page, "a", "KeyA", 65, MODIFIER_CTRL).await?;
+	press_key(page, "Backspace", "Backspace", 8, 0).await?;
+	for c in text.chars() {
+		keystroke_delay().await;
+		type_char(page, c).await?;
+	}
+
+	// Validation usually runs on blur as well
+	page.evaluate(format!("document.querySelector('[name=' + JSON.stringify({name}) + ']')?.blur()"))
+		.await
+		.map_err(|e| eyre!("Failed to leave \"{input_name}\": {e}"))?;
+	wait(timings().post_click_wait_ms).await;
+	Ok(())
+}
+
+/// A key without text (editing keys, shortcuts)
+async fn press_key(page: &Page, key: &str, code: &str, key_code: i64, modifiers: i64) -> Result<()> {
+	for kind in [DispatchKeyEventType::RawKeyDown, DispatchKeyEventType::KeyUp] {
+		let params = DispatchKeyEventParams::builder()
+			.r#type(kind)
+			.key(key)
+			.code(code)
+			.windows_virtual_key_code(key_code)
+			.modifiers(modifiers)
+			.build()
+			.map_err(|e| eyre!("Failed to build the {key} key event: {e}"))?;
+		page.execute(params).await.map_err(|e| eyre!("Failed to press {key}: {e}"))?;
+	}
+	Ok(())
+}
+
+/// A key that produces `c`; newlines are typed as Enter
+async fn type_char(page: &Page, c: char) -> Result<()> {
+	let (key, text) = match c {
+		'\n' => ("Enter".to_string(), "\r".to_string()),
+		c => (c.to_string(), c.to_string()),
+	};
+	let down = DispatchKeyEventParams::builder()
+		.r#type(DispatchKeyEventType::KeyDown)
+		.key(key.clone())
+		.text(text.clone())
+		.unmodified_text(text)
+		.build()
+		.map_err(|e| eyre!("Failed to build the key event for {c:?}: {e}"))?;
+	page.execute(down).await.map_err(|e| eyre!("Failed to type {c:?}: {e}"))?;
+	let up = DispatchKeyEventParams::builder()
+		.r#type(DispatchKeyEventType::KeyUp)
+		.key(key)
+		.build()
+		.map_err(|e| eyre!("Failed to build the key event for {c:?}: {e}"))?;
+	page.execute(up).await.map_err(|e| eyre!("Failed to type {c:?}: {e}"))?;
+	Ok(())
+}
@@ -0,0 +1,104 @@
+//! Which language free-text answers are written in (`answer_language`), guessed from the question when "auto"
+
+/// Stopwords counted to tell the languages apart; short, frequent, and not shared between them
+const STOPWORDS: [(&str, &[&str]); 5] = [
+	(
+		"fr",
+		&[
+			"le", "la", "les", "des", "du", "une", "est", "et", "que", "qui", "dans", "pour", "sur", "pas", "avec", "ce", "cette", "sont", "au", "aux", "quel", "quelle", "vous",
+		],
+	),
+	(
+		"en",
+		&[
+			"the", "is", "are", "and", "of", "to", "in", "that", "which", "what", "for", "with", "this", "on", "be", "by", "an", "it", "you", "does",
+		],
+	),
+	(
+		"es",
+		&[
+			"el", "los", "las", "es", "y", "que", "del", "en", "una", "por", "para", "con", "cual", "cuál", "como", "este", "esta", "son",
+		],
+	),
+	(
+		"de",
+		&[
+			"der", "die", "das", "und", "ist", "nicht", "ein", "eine", "mit", "den", "dem", "zu", "von", "welche", "sind", "auf", "für",
+		],
+	),
+	(
+		"it",
+		&["il", "gli", "della", "di", "che", "è", "per", "una", "con", "sono", "questo", "quale", "nel", "alla", "non"],
+	),
+];
+
+/// Fewest stopwords for a guess
+const MIN_STOPWORDS: usize = 2;
+
+/// Language code of `text` by stopword count; None when it's too short or too close to call
+pub fn detect(text: &str) -> Option<&'static str> {
+	let words: Vec<String> = text.split(|c: char| !c.is_alphabetic()).filter(|w| !w.is_empty()).map(str::to_lowercase).collect();
+	let mut counts: Vec<(&str, usize)> = STOPWORDS
+		.iter()
+		.map(|(code, stopwords)| (*code, words.iter().filter(|w| stopwords.contains(&w.as_str())).count()))
+		.collect();
+	counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+	match counts.as_slice() {
+		[(code, best), (_, second), ..] if *best >= MIN_STOPWORDS && best > second => Some(code),
+		_ => None,
+	}
+}
+
+/// English name of a language code, for the prompt; anything else (a name already, an unlisted code) as given
+pub fn name(code: &str) -> &str {
+	match code.to_ascii_lowercase().as_str() {
+		"fr" => "French",
+		"en" => "English",
+		"es" => "Spanish",
+		"de" => "German",
+		"it" => "Italian",
+		"pt" => "Portuguese",
+		"nl" => "Dutch",
+		_ => code,
+	}
+}
+
+/// Language answers to `question_text` should be in, by `answer_language` ("auto" guesses from the text)
+pub fn answer_language<'a>(setting: &'a str, question_text: &str) -> Option<&'a str> {
+	match setting.trim() {
+		"" => None,
+		auto if auto.eq_ignore_ascii_case("auto") => detect(question_text).map(name),
+		fixed => Some(name(fixed)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn detects_french_and_english() {
+		assert_eq!(detect("Quelle est la complexité de la recherche dans une liste chaînée ?"), Some("fr"));
+		assert_eq!(detect("Expliquez la différence entre une pile et une file."), Some("fr"));
+		assert_eq!(detect("What is the time complexity of a lookup in a hash map?"), Some("en"));
+		// Code and LaTeX don't tip it over: `for`, `in` and `is` are English stopwords, but the prose is French
+		assert_eq!(detect("Que vaut `x` après `for i in range(3): x = i` dans la boucle ? \\(x \\geq 0\\)"), Some("fr"));
+	}
+
+	#[test]
+	fn too_short_or_too_close() {
+		assert_eq!(detect("O(n log n)"), None);
+		assert_eq!(detect("Python"), None);
+		assert_eq!(detect("Pile : la structure est LIFO ; stack: the structure and its top"), None);
+	}
+
+	#[test]
+	fn answer_language_setting() {
+		assert_eq!(answer_language("auto", "Quelle structure de données est LIFO ?"), Some("French"));
+		assert_eq!(answer_language("AUTO", "Which data structure is LIFO?"), Some("English"));
+		assert_eq!(answer_language("auto", "LIFO ?"), None);
+		assert_eq!(answer_language("fr", "Which data structure is LIFO?"), Some("French"));
+		assert_eq!(answer_language("Esperanto", "Which data structure is LIFO?"), Some("Esperanto"));
+		assert_eq!(answer_language(" ", "Which data structure is LIFO?"), None);
+	}
+}
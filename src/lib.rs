@@ -1,18 +1,99 @@
 #![feature(default_field_values)]
-use std::fmt;
+use std::{fmt, str::FromStr};
 
 use serde::{Deserialize, Serialize};
 
+pub mod answer_cache;
+pub mod answer_edit;
+pub mod answers;
+pub mod api;
+pub mod capture;
 pub mod config;
+pub mod context_files;
+pub mod course;
+pub mod display;
+pub mod emit;
+pub mod exchange_log;
+pub mod export;
+pub mod failure;
+pub mod images;
+pub mod keyboard;
+pub mod language;
 pub mod llm;
+pub mod llm_backend;
+pub mod llm_error;
+pub mod local_check;
 pub mod login;
+pub mod marks;
+pub mod ocr;
+pub mod page_state;
+pub mod parse;
+pub mod prompts;
+pub mod proxy;
+pub mod quiz_nav;
+pub mod quiz_timer;
+pub mod report;
 pub mod runner;
+#[cfg(feature = "xdg")]
+pub mod session;
+pub mod shutdown;
+pub mod stack;
+pub mod stealth;
+pub mod store;
+pub mod terminal_image;
+pub mod timing;
+pub mod totp;
+pub mod usage;
+
+/// Encode a string as a JS string literal (quotes included), safe to splice into scripts passed to `page.evaluate`.
+/// Handles quotes, backslashes, backticks, `${}`, newlines and non-ASCII text alike.
+pub(crate) fn js_string(s: &str) -> String {
+	serde_json::to_string(s).expect("serializing a str is infallible")
+}
+
+/// Primary language subtags whose locales write decimals with a comma ("3,14").
+const COMMA_DECIMAL_LANGS: &[&str] = &[
+	"az", "be", "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "it", "kk", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl",
+	"sq", "sr", "sv", "tr", "uk", "vi",
+];
+
+/// Decimal separator Moodle expects in numerical answers for a page in language `lang` (a BCP 47 tag like "pt-BR"
+/// or a Moodle code like "pt_br"). Shared by the browser and HTML parsers so both read a page the same way.
+pub fn decimal_separator(lang: &str) -> &'static str {
+	let primary = lang.split(['-', '_']).next().unwrap_or_default().to_ascii_lowercase();
+	if COMMA_DECIMAL_LANGS.contains(&primary.as_str()) { "," } else { "." }
+}
 
 /// Detects if a URL is a VPL (Virtual Programming Lab) activity
 pub fn is_vpl_url(url: &str) -> bool {
 	url.contains("/mod/vpl/")
 }
 
+/// One URL of the run's queue
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UrlTask {
+	pub url: String,
+	/// Whether the URLs after it still run when it fails
+	pub optional: bool,
+}
+
+impl UrlTask {
+	/// Parse a URL argument, optionally prefixed with `optional:` (e.g. `optional:https://...`). URLs without a scheme
+	/// get `https://`.
+	pub fn parse(arg: &str) -> Self {
+		let (url, optional) = match arg.strip_prefix("optional:") {
+			Some(url) => (url, true),
+			None => (arg, false),
+		};
+		let url = if url.starts_with("http://") || url.starts_with("https://") {
+			url.to_string()
+		} else {
+			format!("https://{url}")
+		};
+		Self { url, optional }
+	}
+}
+
 /// Represents an image in a question
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Image {
@@ -38,6 +119,13 @@ pub struct Choice {
 	pub images: Vec<Image>,
 }
 
+/// The Maxima input of a STACK question
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct StackInput {
+	/// Syntax hints shown with the input (its placeholder, syntax examples), one per line
+	pub requirements: String,
+}
+
 /// Represents a required file for code submission
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct RequiredFile {
@@ -81,6 +169,9 @@ pub struct DropZone {
 	pub group: usize,
 	/// Currently selected choice (0 = none)
 	pub current_choice: usize,
+	/// Pixel position of the zone on the background image (qtype_ddimageortext only)
+	#[serde(default)]
+	pub position: Option<(i64, i64)>,
 }
 
 /// A draggable choice in a DragDropIntoText question
@@ -94,11 +185,14 @@ pub struct DragChoice {
 	pub text: String,
 }
 
-/// A DragDropIntoText question (qtype_ddwtos)
+/// A DragDropIntoText question (qtype_ddwtos, also used for qtype_ddimageortext)
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DragDropIntoText {
 	/// The question prompt with drop zones indicated
 	pub question_text: String,
+	/// Whether the drop zones sit on a background image (qtype_ddimageortext) rather than inline in the text
+	#[serde(default)]
+	pub on_image: bool,
 	/// Available choices to drag
 	pub choices: Vec<DragChoice>,
 	/// Drop zones where choices can be placed
@@ -118,10 +212,39 @@ impl fmt::Display for DragDropIntoText {
 		}
 		writeln!(f)?;
 		writeln!(f, "Drop zones: {} places to fill", self.drop_zones.len())?;
+		if self.on_image {
+			for zone in &self.drop_zones {
+				match zone.position {
+					Some((x, y)) => writeln!(f, "  Place {} at ({x}, {y}) on the image", zone.place_number)?,
+					None => writeln!(f, "  Place {}", zone.place_number)?,
+				}
+			}
+		}
 		Ok(())
 	}
 }
 
+/// A sortable item in an Ordering question
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct OrderingItem {
+	/// The item's identifier as written into the hidden response input (e.g. "ordering_item_3f2a...")
+	pub id: String,
+	/// The text label
+	pub text: String,
+}
+
+/// The rich-text editor an Essay question is rendered with
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum EditorKind {
+	/// Atto: a contenteditable div next to the hidden textarea
+	Atto,
+	/// TinyMCE: an iframe whose body is the editable region
+	TinyMce,
+	/// A plain textarea with no rich-text editor
+	#[default]
+	Plain,
+}
+
 /// A blank (input field) within a FillInBlanks question
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Blank {
@@ -231,6 +354,100 @@ impl fmt::Display for MatchItem {
 	}
 }
 
+/// The type of a [`Question`], without its content. Displays (and parses from) the short name used in markers and
+/// CLI filters, e.g. "single", "fill", "order".
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum QuestionKind {
+	SingleChoice,
+	MultiChoice,
+	ShortAnswer,
+	Matching,
+	CodeSubmission,
+	FillInBlanks,
+	DragDropIntoText,
+	CodeBlock,
+	Numerical,
+	Essay,
+	Ordering,
+	Description,
+	Unknown,
+}
+
+impl QuestionKind {
+	pub const ALL: [QuestionKind; 13] = [
+		QuestionKind::SingleChoice,
+		QuestionKind::MultiChoice,
+		QuestionKind::ShortAnswer,
+		QuestionKind::Matching,
+		QuestionKind::CodeSubmission,
+		QuestionKind::FillInBlanks,
+		QuestionKind::DragDropIntoText,
+		QuestionKind::CodeBlock,
+		QuestionKind::Numerical,
+		QuestionKind::Essay,
+		QuestionKind::Ordering,
+		QuestionKind::Description,
+		QuestionKind::Unknown,
+	];
+
+	/// Tag shown in question headers, e.g. "[text]", "[single]", "[multi]"
+	pub fn marker(&self) -> &'static str {
+		match self {
+			QuestionKind::ShortAnswer => "[text]",
+			QuestionKind::Matching => "[match]",
+			QuestionKind::FillInBlanks => "[fill]",
+			QuestionKind::CodeBlock => "[code]",
+			QuestionKind::DragDropIntoText => "[drag]",
+			QuestionKind::MultiChoice => "[multi]",
+			QuestionKind::SingleChoice => "[single]",
+			QuestionKind::CodeSubmission => "[vpl]",
+			QuestionKind::Numerical => "[num]",
+			QuestionKind::Essay => "[essay]",
+			QuestionKind::Ordering => "[order]",
+			QuestionKind::Description => "[description]",
+			QuestionKind::Unknown => "[unsupported]",
+		}
+	}
+
+	/// The marker without its brackets
+	pub fn name(&self) -> &'static str {
+		self.marker().trim_matches(['[', ']'])
+	}
+}
+
+impl fmt::Display for QuestionKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}", self.name())
+	}
+}
+
+impl FromStr for QuestionKind {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim().trim_matches(['[', ']']);
+		QuestionKind::ALL.into_iter().find(|kind| kind.name().eq_ignore_ascii_case(s)).ok_or_else(|| {
+			let names: Vec<&str> = QuestionKind::ALL.iter().map(|kind| kind.name()).collect();
+			format!("unknown question type '{s}' (expected one of: {})", names.join(", "))
+		})
+	}
+}
+
+impl TryFrom<String> for QuestionKind {
+	type Error = String;
+
+	fn try_from(s: String) -> Result<Self, Self::Error> {
+		s.parse()
+	}
+}
+
+impl From<QuestionKind> for String {
+	fn from(kind: QuestionKind) -> Self {
+		kind.to_string()
+	}
+}
+
 /// Represents different types of quiz questions
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Question {
@@ -262,6 +479,9 @@ pub enum Question {
 		input_name: String,
 		/// Current answer value (if any)
 		current_answer: String,
+		/// Set for STACK questions, whose answer is a Maxima expression
+		#[serde(default)]
+		stack: Option<StackInput>,
 		/// Images in the question
 		#[serde(default)]
 		images: Vec<Image>,
@@ -306,9 +526,104 @@ pub enum Question {
 		#[serde(default)]
 		images: Vec<Image>,
 	},
+	/// Numerical question (qtype_numerical) - a number, optionally with a unit
+	Numerical {
+		/// The question text/prompt
+		question_text: String,
+		/// The answer input's name attribute
+		input_name: String,
+		/// Current answer value (if any)
+		current_answer: String,
+		/// The name attribute of the unit control (a dropdown, or radio buttons sharing it), if units are chosen
+		/// from one
+		#[serde(default)]
+		unit_select_name: Option<String>,
+		/// Available units (empty when there is no unit control)
+		#[serde(default)]
+		units: Vec<MatchOption>,
+		/// Whether the unit control is radio buttons rather than a dropdown
+		#[serde(default)]
+		unit_radios: bool,
+		/// Whether the unit must be typed into the answer field itself (Moodle's "unit in input" mode): there is no
+		/// unit control
+		#[serde(default)]
+		unit_in_input: bool,
+		/// Decimal separator expected by the site's locale ("." or ",")
+		decimal_separator: String,
+		/// Images in the question
+		#[serde(default)]
+		images: Vec<Image>,
+	},
+	/// Essay question (qtype_essay) - free-form text in a rich-text editor
+	Essay {
+		/// The question text/prompt
+		question_text: String,
+		/// The answer textarea's name attribute (e.g. "q123:4_answer")
+		input_name: String,
+		/// Which editor wraps the textarea
+		editor_kind: EditorKind,
+		/// Current answer HTML (if any)
+		current_html: String,
+		/// Whether the essay also accepts file attachments (not supported - only the text is filled)
+		#[serde(default)]
+		accepts_attachments: bool,
+		/// Images in the question
+		#[serde(default)]
+		images: Vec<Image>,
+	},
+	/// Ordering question (qtype_ordering) - drag items into the correct sequence
+	Ordering {
+		/// The question text/prompt
+		question_text: String,
+		/// Items in their current order on the page
+		items: Vec<OrderingItem>,
+		/// The hidden input's name attribute storing the comma-separated item order
+		input_name: String,
+		/// Images in the question
+		#[serde(default)]
+		images: Vec<Image>,
+	},
+	/// Informational block between questions (qtype_description): nothing to answer, but often the shared text the
+	/// next questions refer to ("Using the code below, answer questions 4-7")
+	Description {
+		text: String,
+		#[serde(default)]
+		images: Vec<Image>,
+	},
+	/// A question with form inputs that matched none of the types above. Left unanswered, and blocks finalizing the
+	/// attempt unless `allow_skip` is set.
+	Unknown {
+		/// The question text/prompt, if any could be found
+		question_text: String,
+		/// Question type class of the enclosing `.que` element (e.g. "ddmarker" for qtype_ddmarker)
+		qtype_class: String,
+		/// Start of the formulation's HTML, to add support for the type later
+		raw_html_excerpt: String,
+		/// Names of the form fields in the formulation
+		input_names: Vec<String>,
+	},
 }
 
 impl Question {
+	/// The type of this question
+	pub fn kind(&self) -> QuestionKind {
+		match self {
+			Question::SingleChoice { .. } => QuestionKind::SingleChoice,
+			Question::MultiChoice { .. } => QuestionKind::MultiChoice,
+			Question::ShortAnswer { .. } => QuestionKind::ShortAnswer,
+			Question::Matching { .. } => QuestionKind::Matching,
+			Question::CodeSubmission { .. } => QuestionKind::CodeSubmission,
+			Question::FillInBlanks(_) => QuestionKind::FillInBlanks,
+			Question::DragDropIntoText(_) => QuestionKind::DragDropIntoText,
+			Question::CodeBlock { .. } => QuestionKind::CodeBlock,
+			Question::Numerical { .. } => QuestionKind::Numerical,
+			Question::Essay { .. } => QuestionKind::Essay,
+			Question::Ordering { .. } => QuestionKind::Ordering,
+			Question::Description { .. } => QuestionKind::Description,
+			Question::Unknown { .. } => QuestionKind::Unknown,
+		}
+	}
+
 	/// Extract question text for display
 	pub fn question_text(&self) -> &str {
 		match self {
@@ -316,14 +631,19 @@ impl Question {
 			| Question::MultiChoice { question_text, .. }
 			| Question::ShortAnswer { question_text, .. }
 			| Question::Matching { question_text, .. }
-			| Question::CodeBlock { question_text, .. } => question_text,
+			| Question::CodeBlock { question_text, .. }
+			| Question::Numerical { question_text, .. }
+			| Question::Essay { question_text, .. }
+			| Question::Ordering { question_text, .. }
+			| Question::Unknown { question_text, .. } => question_text,
 			Question::CodeSubmission { description, .. } => description,
+			Question::Description { text, .. } => text,
 			Question::FillInBlanks(fill) => &fill.question_text,
 			Question::DragDropIntoText(ddwtos) => &ddwtos.question_text,
 		}
 	}
 
-	/// Get choices for this question (empty for CodeSubmission, ShortAnswer, Matching, FillInBlanks, DragDropIntoText, and CodeBlock)
+	/// Get choices for this question (empty for every kind except SingleChoice and MultiChoice)
 	pub fn choices(&self) -> &[Choice] {
 		match self {
 			Question::SingleChoice { choices, .. } | Question::MultiChoice { choices, .. } => choices,
@@ -332,7 +652,12 @@ impl Question {
 			| Question::Matching { .. }
 			| Question::FillInBlanks { .. }
 			| Question::DragDropIntoText { .. }
-			| Question::CodeBlock { .. } => &[],
+			| Question::CodeBlock { .. }
+			| Question::Numerical { .. }
+			| Question::Essay { .. }
+			| Question::Ordering { .. }
+			| Question::Description { .. }
+			| Question::Unknown { .. } => &[],
 		}
 	}
 
@@ -344,20 +669,54 @@ impl Question {
 			| Question::ShortAnswer { images, .. }
 			| Question::Matching { images, .. }
 			| Question::CodeSubmission { images, .. }
-			| Question::CodeBlock { images, .. } => images,
+			| Question::CodeBlock { images, .. }
+			| Question::Numerical { images, .. }
+			| Question::Essay { images, .. }
+			| Question::Ordering { images, .. }
+			| Question::Description { images, .. } => images,
 			Question::FillInBlanks(fill) => &fill.images,
 			Question::DragDropIntoText(ddwtos) => &ddwtos.images,
+			Question::Unknown { .. } => &[],
+		}
+	}
+
+	/// Names of all form fields this question writes its answer into
+	pub fn field_names(&self) -> Vec<&str> {
+		match self {
+			Question::SingleChoice { choices, .. } | Question::MultiChoice { choices, .. } => choices.iter().map(|c| c.input_name.as_str()).collect(),
+			Question::ShortAnswer { input_name, .. } | Question::CodeBlock { input_name, .. } | Question::Essay { input_name, .. } | Question::Ordering { input_name, .. } =>
+				vec![input_name.as_str()],
+			Question::Numerical { input_name, unit_select_name, .. } => std::iter::once(input_name.as_str()).chain(unit_select_name.as_deref()).collect(),
+			Question::Matching { items, .. } => items.iter().map(|i| i.select_name.as_str()).collect(),
+			Question::FillInBlanks(fill) => fill
+				.blanks
+				.iter()
+				.map(|b| match b {
+					Blank::Text { input_name, .. } => input_name.as_str(),
+					Blank::Select { select_name, .. } => select_name.as_str(),
+				})
+				.collect(),
+			Question::DragDropIntoText(ddwtos) => ddwtos.drop_zones.iter().map(|z| z.input_name.as_str()).collect(),
+			Question::Unknown { input_names, .. } => input_names.iter().map(String::as_str).collect(),
+			Question::CodeSubmission { .. } | Question::Description { .. } => Vec::new(),
 		}
 	}
 
+	/// Moodle's question slot key (e.g. "q123:4"), the prefix shared by all of this question's field names
+	pub fn slot_key(&self) -> Option<&str> {
+		let name = self.field_names().into_iter().next()?;
+		let (slot, _) = name.split_once('_')?;
+		slot.contains(':').then_some(slot)
+	}
+
 	/// Returns true if this is a multi-choice (checkbox) question
 	pub fn is_multi(&self) -> bool {
-		matches!(self, Question::MultiChoice { .. })
+		self.kind() == QuestionKind::MultiChoice
 	}
 
 	/// Returns true if this is a short answer (text response) question
 	pub fn is_short_answer(&self) -> bool {
-		matches!(self, Question::ShortAnswer { .. })
+		self.kind() == QuestionKind::ShortAnswer
 	}
 
 	/// Get the input name for short answer questions
@@ -368,9 +727,17 @@ impl Question {
 		}
 	}
 
+	/// The Maxima input of a STACK question
+	pub fn stack_input(&self) -> Option<&StackInput> {
+		match self {
+			Question::ShortAnswer { stack, .. } => stack.as_ref(),
+			_ => None,
+		}
+	}
+
 	/// Returns true if this is a matching question
 	pub fn is_matching(&self) -> bool {
-		matches!(self, Question::Matching { .. })
+		self.kind() == QuestionKind::Matching
 	}
 
 	/// Get match items for matching questions
@@ -399,7 +766,7 @@ impl Question {
 
 	/// Returns true if this is a fill-in-the-blanks question
 	pub fn is_fill_in_blanks(&self) -> bool {
-		matches!(self, Question::FillInBlanks { .. })
+		self.kind() == QuestionKind::FillInBlanks
 	}
 
 	/// Get fill-in-blanks data for FillInBlanks questions
@@ -412,7 +779,7 @@ impl Question {
 
 	/// Returns true if this is a code block (inline code editor) question
 	pub fn is_code_block(&self) -> bool {
-		matches!(self, Question::CodeBlock { .. })
+		self.kind() == QuestionKind::CodeBlock
 	}
 
 	/// Get the input name for code block questions
@@ -433,21 +800,12 @@ impl Question {
 
 	/// Short marker string for display, e.g. "[text]", "[single]", "[multi]"
 	pub fn type_marker(&self) -> &'static str {
-		match self {
-			Question::ShortAnswer { .. } => "[text]",
-			Question::Matching { .. } => "[match]",
-			Question::FillInBlanks { .. } => "[fill]",
-			Question::CodeBlock { .. } => "[code]",
-			Question::DragDropIntoText { .. } => "[drag]",
-			Question::MultiChoice { .. } => "[multi]",
-			Question::SingleChoice { .. } => "[single]",
-			Question::CodeSubmission { .. } => "[vpl]",
-		}
+		self.kind().marker()
 	}
 
 	/// Returns true if this is a drag-drop-into-text question
 	pub fn is_drag_drop_into_text(&self) -> bool {
-		matches!(self, Question::DragDropIntoText { .. })
+		self.kind() == QuestionKind::DragDropIntoText
 	}
 
 	/// Get drag-drop-into-text data for DragDropIntoText questions
@@ -457,6 +815,87 @@ impl Question {
 			_ => None,
 		}
 	}
+
+	/// Returns true if this is a numerical question
+	pub fn is_numerical(&self) -> bool {
+		self.kind() == QuestionKind::Numerical
+	}
+
+	/// Get the answer input name for numerical questions
+	pub fn numerical_input_name(&self) -> Option<&str> {
+		match self {
+			Question::Numerical { input_name, .. } => Some(input_name),
+			_ => None,
+		}
+	}
+
+	/// Whether a numerical question's units are radio buttons rather than a dropdown
+	pub fn numerical_unit_radios(&self) -> bool {
+		matches!(self, Question::Numerical { unit_radios: true, .. })
+	}
+
+	/// Get the unit control name and its options for numerical questions with a unit dropdown or radio buttons
+	pub fn numerical_units(&self) -> Option<(&str, &[MatchOption])> {
+		match self {
+			Question::Numerical {
+				unit_select_name: Some(select_name),
+				units,
+				..
+			} => Some((select_name.as_str(), units.as_slice())),
+			_ => None,
+		}
+	}
+
+	/// Returns true if this is an essay question
+	pub fn is_essay(&self) -> bool {
+		self.kind() == QuestionKind::Essay
+	}
+
+	/// Get the textarea name and editor kind for essay questions
+	pub fn essay_editor(&self) -> Option<(&str, EditorKind)> {
+		match self {
+			Question::Essay { input_name, editor_kind, .. } => Some((input_name.as_str(), *editor_kind)),
+			_ => None,
+		}
+	}
+
+	/// Returns true if this is an ordering question
+	pub fn is_ordering(&self) -> bool {
+		self.kind() == QuestionKind::Ordering
+	}
+
+	/// Get items for ordering questions
+	pub fn ordering_items(&self) -> &[OrderingItem] {
+		match self {
+			Question::Ordering { items, .. } => items,
+			_ => &[],
+		}
+	}
+
+	/// Get the hidden response input name for ordering questions
+	pub fn ordering_input_name(&self) -> Option<&str> {
+		match self {
+			Question::Ordering { input_name, .. } => Some(input_name),
+			_ => None,
+		}
+	}
+
+	/// Ids of the ordering items at `order` (0-based indices into `ordering_items()`), i.e. the sequence Moodle
+	/// keeps in the hidden response input. Out-of-range indices are skipped.
+	pub fn ordering_item_ids(&self, order: &[usize]) -> Vec<&str> {
+		let items = self.ordering_items();
+		order.iter().filter_map(|&i| items.get(i)).map(|item| item.id.as_str()).collect()
+	}
+
+	/// Returns true for descriptions, which are shown but not answered
+	pub fn is_description(&self) -> bool {
+		self.kind() == QuestionKind::Description
+	}
+
+	/// Returns true if this question's type isn't supported (it will be left unanswered)
+	pub fn is_unknown(&self) -> bool {
+		self.kind() == QuestionKind::Unknown
+	}
 }
 
 impl fmt::Display for Question {
@@ -469,8 +908,13 @@ impl fmt::Display for Question {
 					writeln!(f, "{}. {}", i + 1, choice.text)?;
 				}
 			}
-			Question::ShortAnswer { question_text, .. } => {
+			Question::ShortAnswer { question_text, stack, .. } => {
 				writeln!(f, "{question_text}")?;
+				if let Some(stack) = stack.as_ref().filter(|s| !s.requirements.is_empty()) {
+					writeln!(f)?;
+					writeln!(f, "Input requirements:")?;
+					writeln!(f, "{}", stack.requirements)?;
+				}
 			}
 			Question::Matching { question_text, items, .. } => {
 				writeln!(f, "{question_text}")?;
@@ -512,7 +956,156 @@ impl fmt::Display for Question {
 					writeln!(f, "Template code provided")?;
 				}
 			}
+			Question::Numerical {
+				question_text,
+				units,
+				unit_in_input,
+				decimal_separator,
+				..
+			} => {
+				writeln!(f, "{question_text}")?;
+				writeln!(f)?;
+				writeln!(f, "Numeric answer (decimal separator: '{decimal_separator}')")?;
+				if !units.is_empty() {
+					let available: Vec<&str> = units.iter().map(|u| u.text.as_str()).collect();
+					writeln!(f, "Unit: select from: {}", available.join(", "))?;
+				} else if *unit_in_input {
+					writeln!(f, "Unit: type it after the number in the answer field, if the answer has one")?;
+				}
+			}
+			Question::Essay {
+				question_text,
+				current_html,
+				accepts_attachments,
+				..
+			} => {
+				writeln!(f, "{question_text}")?;
+				writeln!(f)?;
+				writeln!(f, "Essay answer (free text)")?;
+				if !current_html.is_empty() {
+					writeln!(f, "Draft already present")?;
+				}
+				if *accepts_attachments {
+					writeln!(f, "Accepts file attachments (not supported, text only)")?;
+				}
+			}
+			Question::Ordering { question_text, items, .. } => {
+				writeln!(f, "{question_text}")?;
+				writeln!(f)?;
+				writeln!(f, "Items to order:")?;
+				for (i, item) in items.iter().enumerate() {
+					writeln!(f, "{}. {}", i + 1, item.text)?;
+				}
+			}
+			Question::Description { text, .. } => {
+				writeln!(f, "{text}")?;
+			}
+			Question::Unknown { question_text, qtype_class, .. } => {
+				if !question_text.is_empty() {
+					writeln!(f, "{question_text}")?;
+					writeln!(f)?;
+				}
+				writeln!(f, "[UNSUPPORTED qtype_{qtype_class}] — will be left unanswered")?;
+			}
 		}
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn ordering_question() -> Question {
+		let item = |id: &str, text: &str| OrderingItem {
+			id: id.to_string(),
+			text: text.to_string(),
+		};
+		Question::Ordering {
+			question_text: "Put the stages of a compiler in the order they run.".to_string(),
+			items: vec![
+				item("ordering_item_5f1c", "Code generation"),
+				item("ordering_item_09ab", "Lexing"),
+				item("ordering_item_77d0", "Parsing"),
+				item("ordering_item_c3e2", "Type checking"),
+			],
+			input_name: "q1207:3_response_1207_3".to_string(),
+			images: Vec::new(),
+		}
+	}
+
+	#[test]
+	fn ordering_display_numbers_items() {
+		let display = ordering_question().to_string();
+		assert!(display.contains("Items to order:\n1. Code generation\n2. Lexing\n3. Parsing\n4. Type checking\n"), "{display}");
+	}
+
+	#[test]
+	fn ordering_item_ids_follow_answer_order() {
+		let question = ordering_question();
+		assert_eq!(question.ordering_input_name(), Some("q1207:3_response_1207_3"));
+		assert_eq!(
+			question.ordering_item_ids(&[1, 2, 3, 0]).join(","),
+			"ordering_item_09ab,ordering_item_77d0,ordering_item_c3e2,ordering_item_5f1c"
+		);
+		assert_eq!(question.ordering_item_ids(&[1, 7]), ["ordering_item_09ab"]);
+	}
+
+	#[test]
+	fn js_string_round_trips() {
+		let cases = [
+			r#"say "hi" and 'bye'"#,
+			"back\\slash",
+			"`template ${injected}`",
+			"line one\nline two\r\n\ttabbed",
+			"</script><script>alert(1)</script>",
+			"Université, 中文, emoji 🎉, \u{2028}separator",
+		];
+		for s in cases {
+			let literal = js_string(s);
+			assert!(literal.starts_with('"') && literal.ends_with('"'), "{literal}");
+			assert!(!literal.contains(['\n', '\r']), "raw line break in {literal}");
+			// No quote closes the literal early: every inner one is escaped
+			let inner = &literal[1..literal.len() - 1];
+			assert!(inner.match_indices('"').all(|(i, _)| inner[..i].ends_with('\\')), "{literal}");
+			assert_eq!(serde_json::from_str::<String>(&literal).unwrap(), s);
+		}
+	}
+
+	#[test]
+	fn decimal_separator_follows_the_primary_language() {
+		for lang in ["fr", "de", "pt-BR", "pt_br", "RU", "es-419"] {
+			assert_eq!(decimal_separator(lang), ",", "{lang}");
+		}
+		for lang in ["en", "en-US", "ja", "zh_cn", "", "frr"] {
+			assert_eq!(decimal_separator(lang), ".", "{lang}");
+		}
+	}
+
+	#[test]
+	fn url_task_parse() {
+		let task = |url: &str, optional| UrlTask { url: url.to_string(), optional };
+		assert_eq!(
+			UrlTask::parse("https://moodle.example.fr/mod/quiz/view.php?id=5"),
+			task("https://moodle.example.fr/mod/quiz/view.php?id=5", false)
+		);
+		assert_eq!(
+			UrlTask::parse("http://localhost:8080/mod/vpl/view.php?id=2"),
+			task("http://localhost:8080/mod/vpl/view.php?id=2", false)
+		);
+		assert_eq!(
+			UrlTask::parse("moodle.example.fr/mod/quiz/view.php?id=5"),
+			task("https://moodle.example.fr/mod/quiz/view.php?id=5", false)
+		);
+		assert_eq!(
+			UrlTask::parse("optional:https://moodle.example.fr/mod/quiz/view.php?id=6"),
+			task("https://moodle.example.fr/mod/quiz/view.php?id=6", true)
+		);
+		assert_eq!(
+			UrlTask::parse("optional:moodle.example.fr/course/view.php?id=1"),
+			task("https://moodle.example.fr/course/view.php?id=1", true)
+		);
+		// Only a leading prefix counts
+		assert_eq!(UrlTask::parse("https://example.fr/optional:x"), task("https://example.fr/optional:x", false));
+	}
+}
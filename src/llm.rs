@@ -1,13 +1,39 @@
-use ask_llm::{Client as LlmClient, Conversation, Model, Response, Role};
+use std::{
+	collections::HashMap,
+	fmt,
+	path::{Path, PathBuf},
+	sync::{Arc, Mutex},
+	time::Duration,
+};
+
+use ask_llm::Model;
 use chromiumoxide::Page;
 use color_eyre::{
 	Result,
 	eyre::{bail, eyre},
 };
-
-use crate::{Blank, Question, config::AppConfig};
+use serde::{Deserialize, Serialize};
+use v_utils::{elog, log};
+
+use crate::{
+	Blank, MatchItem, Question,
+	answers::normalize_whitespace,
+	capture::question_screenshot,
+	config::{AppConfig, ModelPrice},
+	context_files::ContextFiles,
+	exchange_log::ExchangeLog,
+	images::{ImageCache, question_image_urls},
+	language,
+	llm_backend::{Attachment, ChatBackend, ChatResponse, CompletionOptions, Conversation, JsonMode, LlmBackend, Role},
+	llm_error::{LlmErrorKind, LlmTimeout},
+	prompts::{PromptKind, Prompts, default_prompts_dir},
+	stack,
+	usage::{LlmTask, UsageEntry, UsageTotals, UsageTracker},
+};
 
 /// Result of LLM answering a question
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum LlmAnswerResult {
 	Single {
 		idx: usize,
@@ -38,495 +64,1426 @@ pub enum LlmAnswerResult {
 	DragDropIntoText {
 		placements: Vec<(String, usize)>,
 	},
+	/// Numerical: the number to enter, plus (select_name, value) for the unit dropdown if there is one
+	Numerical {
+		answer: String,
+		unit: Option<(String, String)>,
+	},
+	/// Essay: the answer as HTML paragraphs, ready for the rich-text editor
+	Essay {
+		html: String,
+	},
+	/// Ordering: item indices (0-based, into the question's items) in the chosen sequence
+	Ordering {
+		order: Vec<usize>,
+	},
+}
+/// An LLM answer with the confidence and reasoning the model reported for it
+pub struct LlmAnswer {
+	pub result: LlmAnswerResult,
+	/// 0-100; None if the model didn't say, or for answers that didn't come from the LLM
+	pub confidence: Option<u8>,
+	/// One sentence on why the model chose this answer; None if it wasn't asked for (`show_reasoning`) or given
+	pub reasoning: Option<String>,
+}
+impl LlmAnswer {
+	fn new(result: LlmAnswerResult, confidence: Option<f64>, reasoning: Option<String>) -> Self {
+		Self {
+			result,
+			confidence: confidence.map(|c| c.clamp(0.0, 100.0).round() as u8),
+			reasoning: reasoning.map(|r| normalize_whitespace(&r)).filter(|r| !r.is_empty()),
+		}
+	}
+
+	/// Below the configured `min_confidence` (answers without a reported confidence never are)
+	pub fn is_low_confidence(&self, config: &AppConfig) -> bool {
+		matches!((self.confidence, config.min_confidence), (Some(confidence), Some(min)) if confidence < min)
+	}
 }
 /// An answer for a single blank in a FillInBlanks question
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum FillInBlanksAnswerItem {
 	/// Text input answer
 	Text { input_name: String, answer: String },
 	/// Select/dropdown answer
 	Select { select_name: String, value: String },
 }
-/// Ask the LLM to answer a quiz question (multiple-choice or short answer)
-pub async fn ask_llm_for_answer(page: &Page, question: &Question, config: &AppConfig) -> Result<LlmAnswerResult> {
-	let question_display = question.to_string();
-	let context_line = config.context.as_deref().map(|c| format!("IMPORTANT: {c}\n\n")).unwrap_or_default();
-
-	// Handle short answer questions
-	if question.is_short_answer() {
-		let prompt = format!(
-			r#"{context_line}You are answering a short answer question. Provide a concise, direct answer.
-
-{question_display}
-Respond with JSON only, no markdown, in this exact format:
-{{"answer": "<your concise answer>"}}"#
-		);
+impl LlmAnswerResult {
+	/// Display with option values resolved to their text through the question the answer is for
+	pub fn display_with<'a>(&'a self, question: &'a Question) -> AnswerDisplay<'a> {
+		AnswerDisplay {
+			answer: self,
+			question: Some(question),
+		}
+	}
+}
 
-		let mut client = LlmClient::new().model(Model::Medium).max_tokens(128).force_json();
+/// Answer lines as shown in the terminal (one per choice, match, blank...), indented under the question header
+impl fmt::Display for LlmAnswerResult {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		AnswerDisplay { answer: self, question: None }.fmt(f)
+	}
+}
+
+/// [`LlmAnswerResult`] displayed in the context of its question; without one, raw input names and values are shown
+pub struct AnswerDisplay<'a> {
+	answer: &'a LlmAnswerResult,
+	question: Option<&'a Question>,
+}
 
-		// Attach question images
-		for img in question.images() {
-			match fetch_image_as_base64(page, &img.url).await {
-				Ok((base64, media_type)) => {
-					client = client.append_file(base64, media_type);
+impl fmt::Display for AnswerDisplay<'_> {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let question = self.question;
+		let mut lines = Vec::new();
+		match self.answer {
+			LlmAnswerResult::Single { idx, text } => {
+				lines.push(format!("  Selected: {}. {}", idx + 1, text));
+			}
+			LlmAnswerResult::Multi { indices, texts } => {
+				lines.push("  Selected:".to_string());
+				for (idx, text) in indices.iter().zip(texts.iter()) {
+					lines.push(format!("    {}. {}", idx + 1, text));
+				}
+			}
+			LlmAnswerResult::Text { answer } => {
+				lines.push(format!("  Answer: {answer}"));
+			}
+			LlmAnswerResult::Matching { selections } => {
+				lines.push("  Matches:".to_string());
+				let items = question.map(|q| q.match_items()).unwrap_or_default();
+				for (select_name, value) in selections {
+					match items.iter().find(|item| &item.select_name == select_name) {
+						Some(item) => {
+							let answer_text = item.options.iter().find(|o| &o.value == value).map(|o| o.text.as_str()).unwrap_or("?");
+							lines.push(format!("    {} -> {answer_text}", item.prompt));
+						}
+						None if question.is_none() => lines.push(format!("    {select_name} -> {value}")),
+						None => {}
+					}
+				}
+			}
+			LlmAnswerResult::FillInBlanks { answers } => {
+				lines.push("  Blanks:".to_string());
+				match question.and_then(|q| q.fill_in_blanks()) {
+					Some(fill) =>
+						for (i, blank) in fill.blanks.iter().enumerate() {
+							let answer_text = answers
+								.iter()
+								.find(|a| match (a, blank) {
+									(FillInBlanksAnswerItem::Text { input_name, .. }, Blank::Text { input_name: bn, .. }) => input_name == bn,
+									(FillInBlanksAnswerItem::Select { select_name, .. }, Blank::Select { select_name: sn, .. }) => select_name == sn,
+									_ => false,
+								})
+								.map(|a| match (a, blank) {
+									(FillInBlanksAnswerItem::Text { answer, .. }, _) => answer.clone(),
+									(FillInBlanksAnswerItem::Select { value, .. }, Blank::Select { options, .. }) =>
+										options.iter().find(|o| &o.value == value).map(|o| o.text.clone()).unwrap_or_else(|| value.clone()),
+									(FillInBlanksAnswerItem::Select { value, .. }, _) => value.clone(),
+								})
+								.unwrap_or_else(|| "?".to_string());
+							lines.push(format!("    [{}]: {}", i + 1, answer_text));
+						},
+					None if question.is_none() =>
+						for (i, answer) in answers.iter().enumerate() {
+							let answer_text = match answer {
+								FillInBlanksAnswerItem::Text { answer, .. } => answer,
+								FillInBlanksAnswerItem::Select { value, .. } => value,
+							};
+							lines.push(format!("    [{}]: {}", i + 1, answer_text));
+						},
+					None => {}
+				}
+			}
+			LlmAnswerResult::CodeBlock { code } => {
+				// Show first few lines of code
+				lines.push("  Code:".to_string());
+				for line in code.lines().take(5) {
+					lines.push(format!("    {line}"));
+				}
+				if code.lines().count() > 5 {
+					lines.push(format!("    ... ({} more lines)", code.lines().count() - 5));
+				}
+			}
+			LlmAnswerResult::DragDropIntoText { placements } => {
+				lines.push("  Placements:".to_string());
+				match question.and_then(|q| q.drag_drop_into_text()) {
+					Some(ddwtos) =>
+						for (input_name, choice_num) in placements {
+							let choice_text = ddwtos.choices.iter().find(|c| c.choice_number == *choice_num).map(|c| c.text.as_str()).unwrap_or("?");
+							let place_num = ddwtos.drop_zones.iter().find(|z| &z.input_name == input_name).map(|z| z.place_number).unwrap_or(0);
+							lines.push(format!("    Place {place_num} -> {choice_text}"));
+						},
+					None if question.is_none() =>
+						for (input_name, choice_num) in placements {
+							lines.push(format!("    {input_name} -> choice {choice_num}"));
+						},
+					None => {}
+				}
+			}
+			LlmAnswerResult::Numerical { answer, unit } => {
+				let unit_text = match (unit, question.and_then(|q| q.numerical_units())) {
+					(Some((_, value)), Some((_, units))) => units.iter().find(|u| &u.value == value).map(|u| format!(" {}", u.text)).unwrap_or_default(),
+					(Some((_, value)), None) => format!(" {value}"),
+					(None, _) => String::new(),
+				};
+				lines.push(format!("  Answer: {answer}{unit_text}"));
+			}
+			LlmAnswerResult::Essay { html } => {
+				// Preview the first few paragraphs as plain text
+				lines.push("  Essay:".to_string());
+				let paragraphs: Vec<&str> = html.split("</p>").map(|p| p.trim_start_matches("<p>")).filter(|p| !p.is_empty()).collect();
+				for p in paragraphs.iter().take(3) {
+					lines.push(format!("    {}", p.replace("<br>", " ")));
+				}
+				if paragraphs.len() > 3 {
+					lines.push(format!("    ... ({} more paragraphs)", paragraphs.len() - 3));
+				}
+				if let Some(Question::Essay { accepts_attachments: true, .. }) = question {
+					lines.push("  Warning: this essay accepts attachments, which are not supported - only the text will be filled".to_string());
 				}
-				Err(e) => {
-					tracing::warn!("Failed to fetch image for LLM: {e}");
+			}
+			LlmAnswerResult::Ordering { order } => {
+				lines.push("  Order:".to_string());
+				let items = question.map(|q| q.ordering_items()).unwrap_or_default();
+				for (pos, &idx) in order.iter().enumerate() {
+					match items.get(idx) {
+						Some(item) => lines.push(format!("    {}. {}", pos + 1, item.text)),
+						None if question.is_none() => lines.push(format!("    {}. item {}", pos + 1, idx + 1)),
+						None => lines.push(format!("    {}. ?", pos + 1)),
+					}
 				}
 			}
 		}
+		write!(f, "{}", lines.join("\n"))
+	}
+}
+
+/// Feedback from a previous, incorrect attempt at a question (interactive quizzes with multiple tries)
+pub struct AnswerFeedback {
+	/// What was answered, as shown to the user
+	pub previous_answer: String,
+	/// The feedback text Moodle displayed after checking
+	pub feedback: String,
+}
+/// The LLM for a whole run, created once from the config: the provider's backend, the model choices and retry
+/// settings, and a count of the requests made
+pub struct QuizLlm {
+	backend: LlmBackend,
+	quiz_model: String,
+	code_model: String,
+	/// Unset uses a per-question-type default
+	quiz_max_tokens: Option<usize>,
+	code_max_tokens: usize,
+	escalate_on_failure: bool,
+	api_retries: u32,
+	api_retry_delay_ms: u64,
+	/// Longest a single quiz answer call may take
+	quiz_timeout: Duration,
+	/// Longest a single code generation call may take
+	code_timeout: Duration,
+	/// Extra context for every prompt
+	context: Option<String>,
+	/// Ask for a one-sentence reasoning with every quiz answer
+	show_reasoning: bool,
+	/// Language of free-text answers and code comments: a language, or "auto" for the question's
+	answer_language: String,
+	prompts: Prompts,
+	/// Course notes put before every conversation
+	context_files: ContextFiles,
+	/// Text shown before a question on its page (a description, a section heading), by the question's slot key.
+	/// Questions under the same passage share one copy.
+	shared_contexts: Mutex<HashMap<String, Arc<str>>>,
+	prices: HashMap<String, ModelPrice>,
+	usage: UsageTracker,
+	/// Every call's prompt and reply, when `log_llm_exchanges` is on
+	exchange_log: Option<ExchangeLog>,
+}
+
+/// Per-request options on top of the backend
+struct LlmRequest<'a> {
+	task: LlmTask,
+	/// Question number the request is for, for the usage report
+	question: Option<usize>,
+	model: &'a str,
+	max_tokens: usize,
+	/// Longest one call may take
+	timeout: Duration,
+	files: &'a [Attachment],
+}
+
+impl QuizLlm {
+	pub fn new(config: &AppConfig) -> Result<Self> {
+		validate_model_settings(config)?;
+		if let Some(dir) = config.prompts_dir.as_deref().filter(|dir| !Path::new(dir).is_dir()) {
+			bail!("prompts_dir {dir} is not a directory");
+		}
+		let prompts_dir = config.prompts_dir.as_ref().map(PathBuf::from).or_else(default_prompts_dir);
+		Ok(Self {
+			backend: LlmBackend::new(config)?,
+			quiz_model: config.quiz_model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+			code_model: config.code_model.clone().unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+			quiz_max_tokens: config.quiz_max_tokens,
+			code_max_tokens: config.code_max_tokens,
+			escalate_on_failure: config.escalate_on_failure,
+			api_retries: config.api_retries,
+			api_retry_delay_ms: config.api_retry_delay_ms,
+			quiz_timeout: Duration::from_secs(config.llm_timeout_secs),
+			code_timeout: Duration::from_secs(config.code_llm_timeout_secs),
+			context: config.context.clone(),
+			show_reasoning: config.show_reasoning,
+			answer_language: config.answer_language().to_string(),
+			prompts: Prompts::load(prompts_dir.as_deref())?,
+			context_files: ContextFiles::load(config)?,
+			shared_contexts: Mutex::default(),
+			prices: config.llm_prices.clone(),
+			usage: UsageTracker::default(),
+			exchange_log: None,
+		})
+	}
+
+	/// Record every call into `log` (see [`ExchangeLog`])
+	pub fn with_exchange_log(mut self, log: Option<ExchangeLog>) -> Self {
+		self.exchange_log = log;
+		self
+	}
 
+	/// One-token call to find out, before anything else happens, whether the LLM API takes the credentials
+	pub async fn check_credentials(&self) -> Result<()> {
 		let mut conv = Conversation::new();
-		conv.add(Role::User, prompt);
+		conv.add(Role::User, "ping");
+		let options = CompletionOptions {
+			model: &self.quiz_model,
+			max_tokens: 1,
+			force_json: false,
+			files: &[],
+		};
+		call_with_retry(&self.backend, &conv, &options, self.quiz_timeout, self.api_retries, self.api_retry_delay_ms)
+			.await
+			.map(drop)
+	}
 
-		let response = call_with_retry(&client, &conv, config.api_retries, config.api_retry_delay_ms).await?;
-		tracing::debug!("LLM raw response: {}", response.text);
+	/// What to fix when the provider rejects the credentials
+	pub fn credential_hint(&self) -> String {
+		self.backend.credential_hint()
+	}
 
-		let json_str = response.text.trim();
-		let answer: LlmTextAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM JSON response: {e} - raw: '{json_str}'"))?;
+	/// Use the context files for `url` (`context_file` and the matching `site_context_files`) from now on
+	pub fn select_context_files(&self, url: &str) {
+		self.context_files.select(url);
+	}
 
-		return Ok(LlmAnswerResult::Text { answer: answer.answer });
+	/// A quiz conversation for `prompt` (see [`new_conversation`]), after the context files
+	fn conversation(&self, prompt: String, feedback: &[AnswerFeedback], escalation: Option<&Escalation>) -> Conversation {
+		new_conversation(prompt, feedback, escalation, self.show_reasoning, self.context_files.preamble().as_deref())
 	}
 
-	// Handle matching questions
-	if question.is_matching() {
-		let items = question.match_items();
+	/// Give `text` (the passage above it on the page) to the LLM along with `question`, cut to
+	/// [`MAX_SHARED_CONTEXT_CHARS`]
+	pub fn set_shared_context(&self, question: &Question, text: &str) {
+		let Some(slot) = question.slot_key() else { return };
+		let text = text.trim();
+		let text: String = match text.char_indices().nth(MAX_SHARED_CONTEXT_CHARS) {
+			Some((end, _)) => format!("{}…", &text[..end]),
+			None => text.to_string(),
+		};
+		let mut contexts = self.shared_contexts.lock().unwrap();
+		let shared = contexts.values().find(|existing| ***existing == *text).cloned().unwrap_or_else(|| Arc::from(text));
+		contexts.insert(slot.to_string(), shared);
+	}
 
-		let prompt = format!(
-			r#"{context_line}You are answering a matching question. For each item, select the correct option from its available choices.
+	/// Calls, tokens and estimated cost of the requests made so far
+	pub fn usage_totals(&self) -> UsageTotals {
+		self.usage.totals(&self.prices)
+	}
 
-{question_display}
-Respond with JSON only, no markdown, in this exact format:
-{{"matches": [{{"prompt": "<item prompt text or slot number like '[1]'>", "answer": "<chosen option text>"}}]}}"#
-		);
+	/// Usage so far as a table by task and model
+	pub fn usage_summary(&self) -> String {
+		self.usage.summary_table(&self.prices)
+	}
 
-		let mut client = LlmClient::new().model(Model::Medium).max_tokens(512).force_json();
+	/// Answer quiz question number `question_num`; `feedback` holds the previous wrong attempts at it (interactive
+	/// quizzes), if any
+	pub async fn answer_question(&self, page: &Page, question: &Question, question_num: usize, feedback: &[AnswerFeedback], images: &ImageCache) -> Result<LlmAnswer> {
+		let answer = self.answer_with_escalation(page, question, question_num, feedback, images).await;
+		tracing::debug!("Question {question_num}: {}", self.usage.question_totals(question_num, &self.prices));
+		answer
+	}
 
-		// Attach question images
-		for img in question.images() {
-			match fetch_image_as_base64(page, &img.url).await {
-				Ok((base64, media_type)) => {
-					client = client.append_file(base64, media_type);
-				}
-				Err(e) => {
-					tracing::warn!("Failed to fetch image for LLM: {e}");
-				}
-			}
+	async fn answer_with_escalation(&self, page: &Page, question: &Question, question_num: usize, feedback: &[AnswerFeedback], images: &ImageCache) -> Result<LlmAnswer> {
+		let err = match self.answer_once(page, question, question_num, feedback, images, None).await {
+			Ok(answer) => return Ok(answer),
+			Err(e) => e,
+		};
+		// Unusable output (as opposed to an API failure): give the next-larger model one more try
+		let Some(output_err) = err.downcast_ref::<LlmOutputError>() else {
+			return Err(err);
+		};
+		let Some(larger) = larger_model(&self.quiz_model).filter(|_| self.escalate_on_failure) else {
+			return Err(err);
+		};
+		log!("Escalating question to the {larger} model after {} failure: {}", output_err.kind, output_err.reason);
+		let escalation = Escalation {
+			model: larger,
+			bad_output: output_err.raw.clone(),
+			reason: output_err.reason.clone(),
+		};
+		self.answer_once(page, question, question_num, feedback, images, Some(&escalation)).await
+	}
+
+	fn quiz_request<'a>(&'a self, question_num: usize, escalation: Option<&Escalation>, default_max_tokens: usize, files: &'a [Attachment]) -> LlmRequest<'a> {
+		LlmRequest {
+			task: LlmTask::Quiz,
+			question: Some(question_num),
+			model: escalation.map(|e| e.model).unwrap_or(&self.quiz_model),
+			max_tokens: self.quiz_max_tokens.unwrap_or(default_max_tokens),
+			timeout: self.quiz_timeout,
+			files,
 		}
+	}
 
-		let mut conv = Conversation::new();
-		conv.add(Role::User, prompt);
+	fn code_request<'a>(&self, model: &'a str) -> LlmRequest<'a> {
+		LlmRequest {
+			task: LlmTask::Code,
+			question: None,
+			model,
+			max_tokens: self.code_max_tokens,
+			timeout: self.code_timeout,
+			files: &[],
+		}
+	}
 
-		let response = call_with_retry(&client, &conv, config.api_retries, config.api_retry_delay_ms).await?;
-		tracing::debug!("LLM raw response: {}", response.text);
+	async fn send(&self, request: &LlmRequest<'_>, conv: &Conversation) -> Result<ChatResponse> {
+		if !request.files.is_empty() && !self.backend.supports_images() {
+			bail!(
+				"The question comes with {} image(s), but the model doesn't take images (llm_supports_images = false)",
+				request.files.len()
+			);
+		}
+		let options = CompletionOptions {
+			model: request.model,
+			max_tokens: request.max_tokens,
+			force_json: true,
+			files: request.files,
+		};
+		let started = std::time::Instant::now();
+		let response = call_with_retry(&self.backend, conv, &options, request.timeout, self.api_retries, self.api_retry_delay_ms).await;
+		if let Some(log) = &self.exchange_log {
+			log.record(exchange_record(request, conv, self.backend.json_mode(), &response, started.elapsed()));
+		}
+		let response = response?;
+		self.usage.record(UsageEntry {
+			question: request.question,
+			task: request.task,
+			model: request.model.to_string(),
+			tokens: response.tokens,
+			cost_cents: response.cost_cents,
+		});
+		Ok(response)
+	}
 
-		let json_str = response.text.trim();
-		let answer: LlmMatchingAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM JSON response: {e} - raw: '{json_str}'"))?;
-
-		// Convert LLM answer to selections (select_name, value)
-		let mut selections = Vec::new();
-		for match_pair in answer.matches {
-			// Find the item that matches this prompt
-			// For inline selects, the prompt might be a slot number like "[1]"
-			for (i, item) in items.iter().enumerate() {
-				let slot_format = format!("[{}]", i + 1);
-				let matches_prompt = if item.prompt.is_empty() {
-					// For inline selects, check if LLM returned the slot number
-					match_pair.prompt == slot_format || match_pair.prompt == (i + 1).to_string()
-				} else {
-					item.prompt.contains(&match_pair.prompt) || match_pair.prompt.contains(&item.prompt)
-				};
+	/// [`parse_llm_json`], noting the outcome in the exchange log
+	fn parse_reply<T: serde::de::DeserializeOwned>(&self, json_str: &str) -> std::result::Result<T, LlmOutputError> {
+		let parsed = parse_llm_json(json_str);
+		self.record_parse(parsed.as_ref().err().map(|e| e.reason.as_str()));
+		parsed
+	}
 
-				if matches_prompt {
-					// Find the option value for the answer text
-					for opt in &item.options {
-						if opt.text == match_pair.answer {
-							selections.push((item.select_name.clone(), opt.value.clone()));
-							break;
-						}
-					}
-					break;
-				}
-			}
+	/// Add whether the last reply parsed to its exchange log entry
+	fn record_parse(&self, error: Option<&str>) {
+		if let Some(log) = &self.exchange_log {
+			log.record_parse(error);
 		}
-
-		return Ok(LlmAnswerResult::Matching { selections });
 	}
+}
+
+/// Ask the LLM to answer a quiz question (multiple-choice or short answer)
+#[deprecated(note = "create a `QuizLlm` once and use `QuizLlm::answer_question`")]
+pub async fn ask_llm_for_answer(page: &Page, question: &Question, images: &ImageCache, config: &AppConfig) -> Result<LlmAnswer> {
+	QuizLlm::new(config)?.answer_question(page, question, 1, &[], images).await
+}
+/// Ask the LLM to answer a quiz question again, given the feedback on its previous wrong attempts
+#[deprecated(note = "create a `QuizLlm` once and use `QuizLlm::answer_question`")]
+pub async fn ask_llm_for_answer_with_feedback(page: &Page, question: &Question, feedback: &[AnswerFeedback], images: &ImageCache, config: &AppConfig) -> Result<LlmAnswer> {
+	QuizLlm::new(config)?.answer_question(page, question, 1, feedback, images).await
+}
 
-	// Handle fill-in-the-blanks questions
-	if question.is_fill_in_blanks() {
-		let fill = question.fill_in_blanks().unwrap();
+impl QuizLlm {
+	async fn answer_once(
+		&self,
+		page: &Page,
+		question: &Question,
+		question_num: usize,
+		feedback: &[AnswerFeedback],
+		images: &ImageCache,
+		escalation: Option<&Escalation>,
+	) -> Result<LlmAnswer> {
+		// Formulas rendered without LaTeX, tables pasted as images...: let the model see the question itself
+		let screenshot = question_screenshot(page, question).await;
+		let question_display = match screenshot {
+			Some(_) => format!("{question}\n{SCREENSHOT_NOTE}\n"),
+			None => question.to_string(),
+		};
+		let shared_context = question.slot_key().and_then(|slot| self.shared_contexts.lock().unwrap().get(slot).cloned());
+		let question_display = match shared_context {
+			Some(text) => format!("Context (the text above this question on the quiz page, which it may refer to):\n{text}\n\nQuestion:\n{question_display}"),
+			None => question_display,
+		};
+		let files = question_files(page, question, images, screenshot).await;
+		let question_display = question_display + &question_ocr_text(page, question, images).await;
+		let context_line = self.context.as_deref().map(|c| format!("IMPORTANT: {c}\n\n")).unwrap_or_default();
+		let answer_language = language::answer_language(&self.answer_language, question.question_text());
+		tracing::debug!("Question {question_num}: answer language {}", answer_language.unwrap_or("not set"));
+		let answer_in = |what: &str| answer_language.map(|lang| format!("{what} in {lang}.")).unwrap_or_default();
+
+		// Handle short answer questions
+		if question.is_short_answer() {
+			let instructions = match question.stack_input() {
+				Some(stack) => stack::llm_instructions(stack),
+				None => "You are answering a short answer question. Provide a concise, direct answer.".to_string(),
+			};
+			// A Maxima expression has no language
+			let answer_language = if question.stack_input().is_some() { String::new() } else { answer_in("Write your answer") };
+			let prompt = self.prompts.render(
+				PromptKind::ShortAnswer,
+				&[
+					("context", &context_line),
+					("question", &question_display),
+					("instructions", &instructions),
+					("answer_language", &answer_language),
+				],
+			);
+
+			let request = self.quiz_request(question_num, escalation, 128, &files);
+
+			let conv = self.conversation(prompt, feedback, escalation);
+
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
+
+			let json_str = response.text.trim();
+			let answer: LlmTextAnswer = self.parse_reply(json_str)?;
+
+			return Ok(LlmAnswer::new(LlmAnswerResult::Text { answer: answer.answer }, answer.confidence, answer.reasoning));
+		}
 
-		let prompt = format!(
-			r#"{context_line}You are answering a fill-in-the-blanks question. Fill in each numbered blank with the correct answer.
+		// Handle matching questions
+		if question.is_matching() {
+			let items = question.match_items();
 
-{question_display}
-Respond with JSON only, no markdown, in this exact format:
-{{"blanks": [{{"blank_number": <number>, "answer": "<the answer for this blank>"}}]}}
+			let prompt = self.prompts.render(PromptKind::Matching, &[("context", &context_line), ("question", &question_display)]);
 
-For text input blanks, provide the exact text to enter.
-For dropdown blanks, provide the exact text of the option to select (one of the listed choices)."#
-		);
+			let request = self.quiz_request(question_num, escalation, 512, &files);
+
+			let conv = self.conversation(prompt, feedback, escalation);
+
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
 
-		let mut client = LlmClient::new().model(Model::Medium).max_tokens(1024).force_json();
+			let json_str = response.text.trim();
+			let answer: LlmMatchingAnswer = self.parse_reply(json_str)?;
 
-		// Attach question images
-		for img in question.images() {
-			match fetch_image_as_base64(page, &img.url).await {
-				Ok((base64, media_type)) => {
-					client = client.append_file(base64, media_type);
+			// Convert LLM answer to selections (select_name, value), at most one per item
+			let mut selections = Vec::new();
+			let mut resolved = vec![false; items.len()];
+			for match_pair in answer.matches {
+				let Some(item_idx) = matching_item_index(items, &match_pair) else {
+					tracing::warn!("LLM answered an unknown matching item: {:?} '{}'", match_pair.item, match_pair.prompt);
+					continue;
+				};
+				let item = &items[item_idx];
+				if resolved[item_idx] {
+					tracing::warn!("LLM answered matching item {} twice, keeping the first answer", item_idx + 1);
+					continue;
 				}
-				Err(e) => {
-					tracing::warn!("Failed to fetch image for LLM: {e}");
+
+				// Find the option value for the answer text
+				let texts: Vec<&str> = item.options.iter().map(|o| o.text.as_str()).collect();
+				let Some(opt) = match_option(&texts, &match_pair.answer).map(|idx| &item.options[idx]) else {
+					tracing::warn!("LLM returned unknown option '{}' for matching item {}", match_pair.answer, item_idx + 1);
+					continue;
+				};
+				// The "Choose..." placeholder is an option too, with value 0
+				if opt.value.is_empty() || opt.value == "0" {
+					tracing::warn!("LLM picked the placeholder option for matching item {}", item_idx + 1);
+					continue;
 				}
+				resolved[item_idx] = true;
+				selections.push((item.select_name.clone(), opt.value.clone()));
+			}
+
+			let unresolved: Vec<String> = items
+				.iter()
+				.zip(&resolved)
+				.enumerate()
+				.filter(|(_, (_, resolved))| !**resolved)
+				.map(|(i, (item, _))| {
+					if item.prompt.is_empty() {
+						format!("[{}]", i + 1)
+					} else {
+						format!("{}. {}", i + 1, item.prompt)
+					}
+				})
+				.collect();
+			if !unresolved.is_empty() {
+				elog!("{} of {} matches unresolved: {}", unresolved.len(), items.len(), unresolved.join("; "));
 			}
+
+			return Ok(LlmAnswer::new(LlmAnswerResult::Matching { selections }, answer.confidence, answer.reasoning));
 		}
 
-		let mut conv = Conversation::new();
-		conv.add(Role::User, prompt);
+		// Handle fill-in-the-blanks questions
+		if question.is_fill_in_blanks() {
+			let fill = question.fill_in_blanks().unwrap();
 
-		let response = call_with_retry(&client, &conv, config.api_retries, config.api_retry_delay_ms).await?;
-		tracing::debug!("LLM raw response: {}", response.text);
+			let answer_language = answer_in("Write the answers to text input blanks");
+			let prompt = self.prompts.render(
+				PromptKind::FillInBlanks,
+				&[("context", &context_line), ("question", &question_display), ("answer_language", &answer_language)],
+			);
 
-		let json_str = response.text.trim();
-		let answer: LlmFillInBlanksAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM JSON response: {e} - raw: '{json_str}'"))?;
-
-		// Convert LLM answer to FillInBlanksAnswerItem
-		let mut answers = Vec::new();
-		for blank_answer in answer.blanks {
-			let blank_idx = blank_answer.blank_number.saturating_sub(1); // Convert 1-indexed to 0-indexed
-			if blank_idx >= fill.blanks.len() {
-				tracing::warn!("LLM returned invalid blank number: {} (max: {})", blank_answer.blank_number, fill.blanks.len());
-				continue;
-			}
+			let request = self.quiz_request(question_num, escalation, 1024, &files);
+
+			let conv = self.conversation(prompt, feedback, escalation);
+
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
+
+			let json_str = response.text.trim();
+			let answer: LlmFillInBlanksAnswer = self.parse_reply(json_str)?;
 
-			let blank = &fill.blanks[blank_idx];
-			match blank {
-				Blank::Text { input_name, .. } => {
-					answers.push(FillInBlanksAnswerItem::Text {
-						input_name: input_name.clone(),
-						answer: blank_answer.answer,
-					});
+			// Convert LLM answer to FillInBlanksAnswerItem
+			let mut answers = Vec::new();
+			for blank_answer in answer.blanks {
+				let blank_idx = blank_answer.blank_number.saturating_sub(1); // Convert 1-indexed to 0-indexed
+				if blank_idx >= fill.blanks.len() {
+					tracing::warn!("LLM returned invalid blank number: {} (max: {})", blank_answer.blank_number, fill.blanks.len());
+					continue;
 				}
-				Blank::Select { select_name, options, .. } => {
-					// Find the option value for the answer text
-					if let Some(opt) = options.iter().find(|o| o.text == blank_answer.answer) {
-						answers.push(FillInBlanksAnswerItem::Select {
-							select_name: select_name.clone(),
-							value: opt.value.clone(),
+
+				let blank = &fill.blanks[blank_idx];
+				match blank {
+					Blank::Text { input_name, .. } => {
+						answers.push(FillInBlanksAnswerItem::Text {
+							input_name: input_name.clone(),
+							answer: blank_answer.answer,
 						});
-					} else {
-						tracing::warn!("LLM returned unknown option '{}' for blank {}", blank_answer.answer, blank_answer.blank_number);
+					}
+					Blank::Select { select_name, options, .. } => {
+						// Find the option value for the answer text
+						let texts: Vec<&str> = options.iter().map(|o| o.text.as_str()).collect();
+						if let Some(idx) = match_option(&texts, &blank_answer.answer) {
+							answers.push(FillInBlanksAnswerItem::Select {
+								select_name: select_name.clone(),
+								value: options[idx].value.clone(),
+							});
+						} else {
+							tracing::warn!("LLM returned unknown option '{}' for blank {}", blank_answer.answer, blank_answer.blank_number);
+						}
 					}
 				}
 			}
+
+			return Ok(LlmAnswer::new(LlmAnswerResult::FillInBlanks { answers }, answer.confidence, answer.reasoning));
 		}
 
-		return Ok(LlmAnswerResult::FillInBlanks { answers });
-	}
+		// Handle code block questions
+		if question.is_code_block() {
+			let language = question.code_block_language().unwrap_or("text");
 
-	// Handle code block questions
-	if question.is_code_block() {
-		let language = question.code_block_language().unwrap_or("text");
+			let answer_language = answer_in("Write any comments and user-facing strings");
+			let prompt = self.prompts.render(
+				PromptKind::CodeBlock,
+				&[
+					("context", &context_line),
+					("question", &question_display),
+					("language", language),
+					("answer_language", &answer_language),
+				],
+			);
 
-		let prompt = format!(
-			r#"{context_line}You are solving a programming problem. Write the complete solution code.
-Think in English.
+			let request = self.quiz_request(question_num, escalation, 2048, &files);
 
-{question_display}
+			let conv = self.conversation(prompt, feedback, escalation);
 
-The programming language is: {language}
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
 
-IMPORTANT: Respond with JSON only, no markdown, in this exact format:
-{{"code": "<your complete solution code>"}}
+			let json_str = response.text.trim();
+			let answer: LlmCodeBlockAnswer = self.parse_reply(json_str)?;
 
-Write correct, working code. Do not include docstrings or comments."#
-		);
+			return Ok(LlmAnswer::new(LlmAnswerResult::CodeBlock { code: answer.code }, answer.confidence, answer.reasoning));
+		}
 
-		let mut client = LlmClient::new().model(Model::Medium).max_tokens(2048).force_json();
+		// Handle drag-drop-into-text questions
+		if question.is_drag_drop_into_text() {
+			let ddwtos = question.drag_drop_into_text().unwrap();
 
-		// Attach question images
-		for img in question.images() {
-			match fetch_image_as_base64(page, &img.url).await {
-				Ok((base64, media_type)) => {
-					client = client.append_file(base64, media_type);
-				}
-				Err(e) => {
-					tracing::warn!("Failed to fetch image for LLM: {e}");
+			let image_note = if ddwtos.on_image {
+				"The drop zones are positioned on the attached background image (coordinates are in pixels from its top-left corner).\n"
+			} else {
+				""
+			};
+
+			let prompt = self
+				.prompts
+				.render(PromptKind::DragDrop, &[("context", &context_line), ("question", &question_display), ("image_note", image_note)]);
+
+			let request = self.quiz_request(question_num, escalation, 512, &files);
+
+			let conv = self.conversation(prompt, feedback, escalation);
+
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
+
+			let json_str = response.text.trim();
+			let answer: LlmDragDropAnswer = self.parse_reply(json_str)?;
+
+			// Convert LLM answer to placements (input_name, choice_number)
+			let mut placements = Vec::new();
+			for placement in answer.placements {
+				// Find the drop zone for this place
+				if let Some(zone) = ddwtos.drop_zones.iter().find(|z| z.place_number == placement.place_number) {
+					// Find the choice by text AND matching group (choices from same group as the zone)
+					let group: Vec<_> = ddwtos.choices.iter().filter(|c| c.group == zone.group).collect();
+					let texts: Vec<&str> = group.iter().map(|c| c.text.as_str()).collect();
+					if let Some(choice) = match_option(&texts, &placement.choice).map(|idx| group[idx]) {
+						placements.push((zone.input_name.clone(), choice.choice_number));
+					} else {
+						tracing::warn!("LLM returned unknown choice '{}' for place {} (group {})", placement.choice, placement.place_number, zone.group);
+					}
+				} else {
+					tracing::warn!("LLM returned unknown place number: {}", placement.place_number);
 				}
 			}
+
+			return Ok(LlmAnswer::new(LlmAnswerResult::DragDropIntoText { placements }, answer.confidence, answer.reasoning));
 		}
 
-		let mut conv = Conversation::new();
-		conv.add(Role::User, prompt);
+		// Handle numerical questions
+		if let Question::Numerical {
+			units,
+			unit_select_name,
+			unit_in_input,
+			decimal_separator,
+			..
+		} = question
+		{
+			let unit_instructions = if !units.is_empty() {
+				r#"Put ONLY the number in "answer" and the exact text of the chosen unit option in "unit"."#
+			} else if *unit_in_input {
+				r#"There is no unit to choose: if the answer has a unit, type it after the number in "answer", e.g. "9.81 m/s^2". Set "unit" to null."#
+			} else {
+				r#"Set "unit" to null."#
+			};
+
+			let decimal_separator = decimal_separator.to_string();
+			let prompt = self.prompts.render(
+				PromptKind::Numerical,
+				&[
+					("context", &context_line),
+					("question", &question_display),
+					("decimal_separator", &decimal_separator),
+					("unit_instructions", unit_instructions),
+				],
+			);
+
+			let request = self.quiz_request(question_num, escalation, 128, &files);
+
+			let conv = self.conversation(prompt, feedback, escalation);
+
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
+
+			let json_str = response.text.trim();
+			let answer: LlmNumericalAnswer = self.parse_reply(json_str)?;
+
+			let unit = match (unit_select_name, answer.unit) {
+				(Some(select_name), Some(unit_text)) => match units.iter().find(|u| u.text == unit_text) {
+					Some(opt) => Some((select_name.clone(), opt.value.clone())),
+					None => {
+						tracing::warn!("LLM returned unknown unit '{unit_text}'");
+						None
+					}
+				},
+				_ => None,
+			};
 
-		let response = call_with_retry(&client, &conv, config.api_retries, config.api_retry_delay_ms).await?;
-		tracing::debug!("LLM raw response: {}", response.text);
+			return Ok(LlmAnswer::new(LlmAnswerResult::Numerical { answer: answer.answer, unit }, answer.confidence, answer.reasoning));
+		}
 
-		let json_str = response.text.trim();
-		let answer: LlmCodeBlockAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM JSON response: {e} - raw: '{json_str}'"))?;
+		// Handle essay questions
+		if question.is_essay() {
+			let answer_language = answer_in("Write your answer");
+			let prompt = self.prompts.render(
+				PromptKind::Essay,
+				&[("context", &context_line), ("question", &question_display), ("answer_language", &answer_language)],
+			);
 
-		return Ok(LlmAnswerResult::CodeBlock { code: answer.code });
-	}
+			let request = self.quiz_request(question_num, escalation, 2048, &files);
 
-	// Handle drag-drop-into-text questions
-	if question.is_drag_drop_into_text() {
-		let ddwtos = question.drag_drop_into_text().unwrap();
+			let conv = self.conversation(prompt, feedback, escalation);
 
-		let prompt = format!(
-			r#"{context_line}You are answering a drag-and-drop question. Place each choice into the correct drop zone.
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
 
-{question_display}
-Respond with JSON only, no markdown, in this exact format:
-{{"placements": [{{"place_number": <drop zone number>, "choice": "<the exact text of the choice to place there>"}}]}}
+			let json_str = response.text.trim();
+			let answer: LlmTextAnswer = self.parse_reply(json_str)?;
 
-Each place_number corresponds to a drop zone (1, 2, 3, etc.). Choose the correct option for each zone from the available choices.
-IMPORTANT: Each drop zone can only accept choices from its group. Match the groups correctly."#
-		);
+			let result = LlmAnswerResult::Essay {
+				html: paragraphs_to_html(&answer.answer),
+			};
+			return Ok(LlmAnswer::new(result, answer.confidence, answer.reasoning));
+		}
+
+		// Handle ordering questions
+		if question.is_ordering() {
+			let items = question.ordering_items();
+
+			let prompt = self.prompts.render(PromptKind::Ordering, &[("context", &context_line), ("question", &question_display)]);
+
+			let request = self.quiz_request(question_num, escalation, 256, &files);
+
+			let conv = self.conversation(prompt, feedback, escalation);
 
-		let mut client = LlmClient::new().model(Model::Medium).max_tokens(512).force_json();
+			let response = self.send(&request, &conv).await?;
+			tracing::debug!("LLM raw response: {}", response.text);
 
-		// Attach question images
-		for img in question.images() {
-			match fetch_image_as_base64(page, &img.url).await {
-				Ok((base64, media_type)) => {
-					client = client.append_file(base64, media_type);
+			let json_str = response.text.trim();
+			let answer: LlmOrderingAnswer = self.parse_reply(json_str)?;
+
+			// Validate that the answer is a permutation of all items
+			let mut seen = vec![false; items.len()];
+			for &num in &answer.order {
+				if num == 0 || num > items.len() {
+					return Err(LlmOutputError::invalid(json_str, format!("LLM returned invalid item number: {num} (expected 1-{})", items.len())).into());
 				}
-				Err(e) => {
-					tracing::warn!("Failed to fetch image for LLM: {e}");
+				if std::mem::replace(&mut seen[num - 1], true) {
+					return Err(LlmOutputError::invalid(json_str, format!("LLM returned item number {num} more than once")).into());
 				}
 			}
+			if answer.order.len() != items.len() {
+				return Err(LlmOutputError::invalid(json_str, format!("LLM ordered {} of {} items", answer.order.len(), items.len())).into());
+			}
+
+			let order = answer.order.iter().map(|n| n - 1).collect();
+			return Ok(LlmAnswer::new(LlmAnswerResult::Ordering { order }, answer.confidence, answer.reasoning));
 		}
 
-		let mut conv = Conversation::new();
-		conv.add(Role::User, prompt);
+		// Handle multiple-choice questions
+		let choices = question.choices();
+		let (kind, max_tokens) = if question.is_multi() {
+			(PromptKind::MultiChoice, 256)
+		} else {
+			(PromptKind::SingleChoice, 128)
+		};
+		let prompt = self.prompts.render(kind, &[("context", &context_line), ("question", &question_display)]);
+
+		// Build client and attach images
+		let request = self.quiz_request(question_num, escalation, max_tokens, &files);
+
+		let conv = self.conversation(prompt, feedback, escalation);
+
+		let response = self.send(&request, &conv).await?;
 
-		let response = call_with_retry(&client, &conv, config.api_retries, config.api_retry_delay_ms).await?;
 		tracing::debug!("LLM raw response: {}", response.text);
 
 		let json_str = response.text.trim();
-		let answer: LlmDragDropAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM JSON response: {e} - raw: '{json_str}'"))?;
-
-		// Convert LLM answer to placements (input_name, choice_number)
-		let mut placements = Vec::new();
-		for placement in answer.placements {
-			// Find the drop zone for this place
-			if let Some(zone) = ddwtos.drop_zones.iter().find(|z| z.place_number == placement.place_number) {
-				// Find the choice by text AND matching group (choices from same group as the zone)
-				if let Some(choice) = ddwtos.choices.iter().find(|c| c.text == placement.choice && c.group == zone.group) {
-					placements.push((zone.input_name.clone(), choice.choice_number));
-				} else {
-					tracing::warn!("LLM returned unknown choice '{}' for place {} (group {})", placement.choice, placement.place_number, zone.group);
+
+		if question.is_multi() {
+			let answer: LlmMultiAnswer = self.parse_reply(json_str)?;
+
+			// Validate all indices
+			for &num in &answer.response_numbers {
+				if num == 0 || num > choices.len() {
+					return Err(LlmOutputError::invalid(json_str, format!("LLM returned invalid answer index: {num} (expected 1-{})", choices.len())).into());
 				}
-			} else {
-				tracing::warn!("LLM returned unknown place number: {}", placement.place_number);
 			}
+
+			let indices: Vec<usize> = answer.response_numbers.iter().map(|n| n - 1).collect();
+			Ok(LlmAnswer::new(LlmAnswerResult::Multi { indices, texts: answer.responses }, answer.confidence, answer.reasoning))
+		} else {
+			let answer: LlmSingleAnswer = self.parse_reply(json_str)?;
+
+			if answer.response_number == 0 || answer.response_number > choices.len() {
+				return Err(LlmOutputError::invalid(json_str, format!("LLM returned invalid answer index: {} (expected 1-{})", answer.response_number, choices.len())).into());
+			}
+
+			let result = LlmAnswerResult::Single {
+				idx: answer.response_number - 1,
+				text: answer.response,
+			};
+			Ok(LlmAnswer::new(result, answer.confidence, answer.reasoning))
 		}
+	}
+}
+/// Result of asking LLM for code - includes conversation for potential retries
+pub struct LlmCodeResult {
+	/// Generated files (filename -> content)
+	pub files: Vec<(String, String)>,
+	/// The conversation history (for retries with test results)
+	pub conversation: Conversation,
+}
+/// What is currently submitted for a VPL, so the LLM can improve it instead of starting over
+#[derive(Clone, Debug, Default)]
+pub struct PreviousSubmission {
+	/// Submitted files (filename -> content)
+	pub files: Vec<(String, String)>,
+	/// Grade of the last evaluation as shown by VPL (e.g. "6 / 10")
+	pub grade: Option<String>,
+}
+/// Ask the LLM to generate code for a VPL submission
+#[deprecated(note = "create a `QuizLlm` once and use `QuizLlm::generate_code`")]
+pub async fn ask_llm_for_code(question: &Question, previous: Option<&PreviousSubmission>, config: &AppConfig) -> Result<LlmCodeResult> {
+	QuizLlm::new(config)?.generate_code(question, previous).await
+}
+/// Retry code generation with test results feedback
+#[deprecated(note = "create a `QuizLlm` once and use `QuizLlm::retry_code`")]
+pub async fn retry_llm_with_test_results(conversation: Conversation, test_results: &str, config: &AppConfig) -> Result<LlmCodeResult> {
+	QuizLlm::new(config)?.retry_code(conversation, test_results).await
+}
+
+impl QuizLlm {
+	/// Generate code for a VPL submission, improving on `previous` if there is one
+	pub async fn generate_code(&self, question: &Question, previous: Option<&PreviousSubmission>) -> Result<LlmCodeResult> {
+		let Question::CodeSubmission { description, required_files, .. } = question else {
+			bail!("Expected CodeSubmission question");
+		};
+
+		let context_line = self.context.as_deref().map(|c| format!("CONTEXT: {c}\n\n")).unwrap_or_default();
+		let answer_language = language::answer_language(&self.answer_language, description)
+			.map(|lang| format!("Write any comments and user-facing strings in {lang}."))
+			.unwrap_or_default();
+		tracing::debug!("VPL answer language: {}", if answer_language.is_empty() { "not set" } else { &answer_language });
+
+		let files_list = if required_files.is_empty() {
+			"No specific files required - determine appropriate filename(s) based on the problem.".to_string()
+		} else {
+			required_files
+				.iter()
+				.map(|f| {
+					if f.content.is_empty() {
+						format!("- {}", f.name)
+					} else {
+						format!("- {} (template provided):\n```\n{}\n```", f.name, f.content)
+					}
+				})
+				.collect::<Vec<_>>()
+				.join("\n")
+		};
+
+		let previous_section = match previous {
+			Some(previous) if !previous.files.is_empty() => {
+				let grade = previous.grade.as_deref().map(|g| format!(" (current grade is {g})")).unwrap_or_default();
+				let files = previous
+					.files
+					.iter()
+					.map(|(name, content)| format!("- {name}:\n```\n{content}\n```"))
+					.collect::<Vec<_>>()
+					.join("\n");
+				format!("\nCurrent Submission{grade}:\n{files}\nThis is what is submitted right now. If it already mostly works, fix and improve it rather than rewriting it from scratch.\n")
+			}
+			_ => String::new(),
+		};
+
+		let prompt = self.prompts.render(
+			PromptKind::CodeSubmission,
+			&[
+				("context", &context_line),
+				("question", description),
+				("files_list", &files_list),
+				("previous_submission", &previous_section),
+				("answer_language", &answer_language),
+			],
+		);
+
+		// Retries continue this conversation, so the context files are only in its first message
+		let mut conv = Conversation::new();
+		conv.add(Role::User, format!("{}{prompt}", self.context_files.preamble().as_deref().unwrap_or_default()));
+
+		let response = self.send(&self.code_request(&self.code_model), &conv).await?;
+
+		tracing::debug!("LLM code response: {}", response.text);
 
-		return Ok(LlmAnswerResult::DragDropIntoText { placements });
+		// Add assistant response to conversation for potential retries
+		conv.add(Role::Assistant, &response.text);
+
+		let parsed = parse_code_files(response.text.trim());
+		self.record_parse(parsed.as_ref().err().map(|e| e.reason.as_str()));
+		let files = match parsed {
+			Ok(files) => files,
+			Err(e) => {
+				let Some(larger) = larger_model(&self.code_model).filter(|_| self.escalate_on_failure) else {
+					return Err(e.into());
+				};
+				log!("Escalating code generation to the {larger} model after {} failure: {}", e.kind, e.reason);
+				// The bad reply is already the last assistant message
+				conv.add(Role::User, strict_reminder(&e.reason));
+				let response = self.send(&self.code_request(larger), &conv).await?;
+				tracing::debug!("LLM escalated code response: {}", response.text);
+				conv.add(Role::Assistant, &response.text);
+				let parsed = parse_code_files(response.text.trim());
+				self.record_parse(parsed.as_ref().err().map(|e| e.reason.as_str()));
+				parsed?
+			}
+		};
+
+		Ok(LlmCodeResult { files, conversation: conv })
 	}
 
-	// Handle multiple-choice questions
-	let choices = question.choices();
-	let (prompt, max_tokens) = if question.is_multi() {
-		(
-			format!(
-				r#"{context_line}You are answering a multiple-choice question where MULTIPLE answers may be correct. Select ALL correct answers.
+	/// Send the evaluation results of the last generated code back, for a fixed version
+	pub async fn retry_code(&self, mut conversation: Conversation, test_results: &str) -> Result<LlmCodeResult> {
+		// Add test results as a new user message (no additional commentary)
+		conversation.add(Role::User, test_results);
 
-{question_display}
-Respond with JSON only, no markdown, in this exact format:
-{{"responses": ["<text of first correct answer>", "<text of second correct answer>", ...], "response_numbers": [<number of first correct answer>, <number of second correct answer>, ...]}}"#
-			),
-			256,
-		)
-	} else {
-		(
-			format!(
-				r#"{context_line}You are answering a single-choice question. Pick the ONE correct answer.
+		let response = self.send(&self.code_request(&self.code_model), &conversation).await?;
 
-{question_display}
-Respond with JSON only, no markdown, in this exact format:
-{{"response": "<the text of the correct answer>", "response_number": <the number of the correct answer>}}"#
-			),
-			128,
-		)
-	};
+		tracing::debug!("LLM retry response: {}", response.text);
+
+		// Add assistant response to conversation
+		conversation.add(Role::Assistant, &response.text);
 
-	// Build client and attach images
-	let mut client = LlmClient::new().model(Model::Medium).max_tokens(max_tokens).force_json();
+		let json_str = response.text.trim();
+		let answer = repair_llm_json(json_str).and_then(|json| serde_json::from_str::<LlmCodeAnswer>(&json).map_err(Into::into));
+		self.record_parse(answer.as_ref().err().map(|e| e.to_string()).as_deref());
+		let answer = answer.map_err(|e| eyre!("Failed to parse LLM retry response: {e} - raw: '{json_str}'"))?;
 
-	// Attach question images
-	for img in question.images() {
-		match fetch_image_as_base64(page, &img.url).await {
-			Ok((base64, media_type)) => {
-				client = client.append_file(base64, media_type);
+		let files = answer.files.into_iter().map(|f| (f.filename, f.content)).collect();
+		Ok(LlmCodeResult { files, conversation })
+	}
+}
+/// Longest passage from above a question that is put in its prompt
+pub const MAX_SHARED_CONTEXT_CHARS: usize = 2000;
+
+/// Prompt note for questions sent along with a screenshot of themselves
+const SCREENSHOT_NOTE: &str = "A screenshot of the question is attached. The text above may be missing formulas or tables; where they differ, the screenshot is authoritative.";
+
+/// The question's images (its choices' included), plus its screenshot if one was taken, as (base64, media type)
+async fn question_files(page: &Page, question: &Question, images: &ImageCache, screenshot: Option<(String, String)>) -> Vec<Attachment> {
+	let urls = question_image_urls(question);
+	images.prefetch(page, urls.iter().copied()).await;
+	let mut files = Vec::new();
+	for url in urls {
+		match images.get(page, url).await {
+			// Decoded size of the base64 data
+			Ok(image) if images.attachment_limit().is_some_and(|limit| image.0.len() / 4 * 3 > limit) => {
+				tracing::info!("Short on time, not attaching {url} ({} KiB)", image.0.len() / 4 * 3 / 1024);
 			}
+			Ok((base64, media_type)) => files.push(Attachment {
+				source: url.to_string(),
+				base64,
+				media_type,
+			}),
 			Err(e) => {
 				tracing::warn!("Failed to fetch image for LLM: {e}");
 			}
 		}
 	}
+	files.extend(screenshot.map(|(base64, media_type)| Attachment {
+		source: "question screenshot".to_string(),
+		base64,
+		media_type,
+	}));
+	files
+}
 
-	// Attach choice images
-	for choice in choices {
-		for img in &choice.images {
-			match fetch_image_as_base64(page, &img.url).await {
-				Ok((base64, media_type)) => {
-					client = client.append_file(base64, media_type);
-				}
-				Err(e) => {
-					tracing::warn!("Failed to fetch choice image for LLM: {e}");
-				}
+/// Exchange log entry for a call of `request` with `conv` (see [`ExchangeLog`]); the parse outcome is added later
+fn exchange_record(request: &LlmRequest<'_>, conv: &Conversation, json_mode: JsonMode, response: &Result<ChatResponse>, elapsed: Duration) -> serde_json::Value {
+	let attachments: Vec<serde_json::Value> = request
+		.files
+		.iter()
+		// Decoded size of the base64 data
+		.map(|file| serde_json::json!({ "source": file.source, "media_type": file.media_type, "bytes": file.base64.len() / 4 * 3 }))
+		.collect();
+	let mut record = serde_json::json!({
+		"task": request.task.to_string(),
+		"question": request.question,
+		"model": request.model,
+		"max_tokens": request.max_tokens,
+		"json_mode": json_mode.as_str(),
+		"messages": serde_json::to_value(conv).unwrap_or_default(),
+		"attachments": attachments,
+		"duration_ms": elapsed.as_millis() as u64,
+	});
+	let fields = record.as_object_mut().expect("built as an object");
+	match response {
+		Ok(response) => {
+			fields.insert("response".to_string(), response.text.clone().into());
+			if let Some((input, output)) = response.tokens {
+				fields.insert("input_tokens".to_string(), input.into());
+				fields.insert("output_tokens".to_string(), output.into());
 			}
+			if let Some(cost) = response.cost_cents {
+				fields.insert("cost_cents".to_string(), cost.into());
+			}
+		}
+		Err(e) => {
+			fields.insert("error".to_string(), format!("{e:#}").into());
 		}
 	}
+	record
+}
 
-	let mut conv = Conversation::new();
-	conv.add(Role::User, prompt);
-
-	let response = call_with_retry(&client, &conv, config.api_retries, config.api_retry_delay_ms).await?;
-
-	tracing::debug!("LLM raw response: {}", response.text);
+/// What OCR read in the question's images, labelled for the prompt (empty when it didn't run or found nothing)
+async fn question_ocr_text(page: &Page, question: &Question, images: &ImageCache) -> String {
+	let mut text = String::new();
+	for url in question_image_urls(question) {
+		if let Some(ocr) = images.ocr_text(page, url).await {
+			text.push_str(&format!("Text extracted from image {url}:\n{ocr}\n"));
+		}
+	}
+	text
+}
 
-	let json_str = response.text.trim();
+/// Model names accepted by `quiz_model`/`code_model`/`--model`
+const MODEL_NAMES: &str = "fast, medium, slow";
+/// Model used when none is configured
+const DEFAULT_MODEL: &str = "medium";
+
+/// The next model up from `name`, for escalation (None for the largest)
+fn larger_model(name: &str) -> Option<&'static str> {
+	match name.to_ascii_lowercase().as_str() {
+		"fast" => Some("medium"),
+		"medium" => Some("slow"),
+		_ => None,
+	}
+}
 
-	if question.is_multi() {
-		let answer: LlmMultiAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM JSON response: {e} - raw: '{json_str}'"))?;
+/// The model replied, but not with something usable: malformed JSON, an out-of-range number, no files...
+#[derive(Debug)]
+pub struct LlmOutputError {
+	/// "parse" or "validation"
+	pub kind: &'static str,
+	pub reason: String,
+	/// The reply as received
+	pub raw: String,
+}
 
-		// Validate all indices
-		for &num in &answer.response_numbers {
-			if num == 0 || num > choices.len() {
-				bail!("LLM returned invalid answer index: {num} (expected 1-{})", choices.len());
-			}
+impl LlmOutputError {
+	fn parse(raw: &str, reason: impl Into<String>) -> Self {
+		Self {
+			kind: "parse",
+			reason: reason.into(),
+			raw: raw.to_string(),
 		}
+	}
 
-		let indices: Vec<usize> = answer.response_numbers.iter().map(|n| n - 1).collect();
-		Ok(LlmAnswerResult::Multi { indices, texts: answer.responses })
-	} else {
-		let answer: LlmSingleAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM JSON response: {e} - raw: '{json_str}'"))?;
-
-		if answer.response_number == 0 || answer.response_number > choices.len() {
-			bail!("LLM returned invalid answer index: {} (expected 1-{})", answer.response_number, choices.len());
+	fn invalid(raw: &str, reason: impl Into<String>) -> Self {
+		Self {
+			kind: "validation",
+			reason: reason.into(),
+			raw: raw.to_string(),
 		}
+	}
+}
 
-		Ok(LlmAnswerResult::Single {
-			idx: answer.response_number - 1,
-			text: answer.response,
-		})
+impl std::fmt::Display for LlmOutputError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} - raw: '{}'", self.reason, self.raw)
 	}
 }
-/// Result of asking LLM for code - includes conversation for potential retries
-pub struct LlmCodeResult {
-	/// Generated files (filename -> content)
-	pub files: Vec<(String, String)>,
-	/// The conversation history (for retries with test results)
-	pub conversation: Conversation,
+
+impl std::error::Error for LlmOutputError {}
+
+/// A second attempt at a question whose first answer couldn't be used: a larger model, shown its bad reply
+struct Escalation {
+	model: &'static str,
+	bad_output: String,
+	reason: String,
 }
-/// Ask the LLM to generate code for a VPL submission
-pub async fn ask_llm_for_code(question: &Question, config: &AppConfig) -> Result<LlmCodeResult> {
-	let Question::CodeSubmission { description, required_files, .. } = question else {
-		bail!("Expected CodeSubmission question");
-	};
 
-	let context_line = config.context.as_deref().map(|c| format!("CONTEXT: {c}\n\n")).unwrap_or_default();
+/// The JSON object in an LLM reply that isn't clean JSON: code fences and prose around it are dropped, single- and
+/// curly-quoted strings become double-quoted, trailing commas go. Input that already parses is returned untouched.
+fn extract_json(raw: &str) -> Result<String> {
+	if serde_json::from_str::<serde_json::Value>(raw).is_ok() {
+		return Ok(raw.to_string());
+	}
+	let unfenced = strip_code_fence(raw);
+	let start = unfenced.find('{').ok_or_else(|| eyre!("no JSON object in LLM response"))?;
+	let repaired = normalize_json_object(&unfenced[start..]).ok_or_else(|| eyre!("unbalanced JSON object in LLM response"))?;
+	serde_json::from_str::<serde_json::Value>(&repaired).map_err(|e| eyre!("LLM response is not valid JSON even after repair: {e}"))?;
+	Ok(repaired)
+}
 
-	let files_list = if required_files.is_empty() {
-		"No specific files required - determine appropriate filename(s) based on the problem.".to_string()
-	} else {
-		required_files
-			.iter()
-			.map(|f| {
-				if f.content.is_empty() {
-					format!("- {}", f.name)
-				} else {
-					format!("- {} (template provided):\n```\n{}\n```", f.name, f.content)
-				}
-			})
-			.collect::<Vec<_>>()
-			.join("\n")
+/// Inside of the first ```-fenced block (language tag removed), or the whole text if there is none
+fn strip_code_fence(text: &str) -> &str {
+	let Some((_, after)) = text.split_once("```") else {
+		return text;
 	};
+	let body = after.trim_start_matches(|c: char| c.is_ascii_alphanumeric());
+	body.split_once("```").map_or(body, |(inside, _)| inside)
+}
 
-	let prompt = format!(
-		r#"{context_line}You are solving a programming assignment. Write the complete solution code.
-Think in English.
-
-Problem Description:
-{description}
+/// Copy the `{...}` block at the start of `text` up to its matching brace, as strict JSON. None if it never closes.
+fn normalize_json_object(text: &str) -> Option<String> {
+	let mut out = String::with_capacity(text.len());
+	let mut depth = 0usize;
+	let mut chars = text.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'"' | '\'' | '“' | '”' | '‘' | '’' => {
+				let closes: &[char] = match c {
+					'“' | '”' => &['“', '”'],
+					'‘' | '’' => &['‘', '’'],
+					_ => &[c],
+				};
+				out.push('"');
+				loop {
+					match chars.next()? {
+						'\\' => match chars.next()? {
+							// `\'` is only valid inside single-quoted strings
+							'\'' => out.push('\''),
+							escaped => {
+								out.push('\\');
+								out.push(escaped);
+							}
+						},
+						c if closes.contains(&c) => break,
+						'"' => out.push_str("\\\""),
+						// Raw line breaks and tabs, common in generated code
+						'\n' => out.push_str("\\n"),
+						'\r' => out.push_str("\\r"),
+						'\t' => out.push_str("\\t"),
+						c => out.push(c),
+					}
+				}
+				out.push('"');
+			}
+			'{' | '[' => {
+				depth += 1;
+				out.push(c);
+			}
+			'}' | ']' => {
+				depth = depth.checked_sub(1)?;
+				out.push(c);
+				if depth == 0 {
+					return Some(out);
+				}
+			}
+			// Trailing comma before a closing bracket
+			',' if matches!(chars.clone().find(|c| !c.is_whitespace()), Some('}' | ']')) => {}
+			c => out.push(c),
+		}
+	}
+	None
+}
 
-Required Files:
-{files_list}
+/// [`extract_json`], noting in the debug log when the reply had to be repaired
+fn repair_llm_json(raw: &str) -> Result<String> {
+	let json = extract_json(raw)?;
+	if json != raw {
+		tracing::debug!("Repaired malformed LLM JSON: '{raw}' -> '{json}'");
+	}
+	Ok(json)
+}
 
-IMPORTANT: Respond with JSON only, no markdown, in this exact format:
-{{"files": [{{"filename": "<filename>", "content": "<complete file content>"}}]}}
+fn parse_llm_json<T: serde::de::DeserializeOwned>(json_str: &str) -> std::result::Result<T, LlmOutputError> {
+	let json = repair_llm_json(json_str).map_err(|e| LlmOutputError::parse(json_str, format!("Failed to parse LLM JSON response: {e}")))?;
+	serde_json::from_str(&json).map_err(|e| LlmOutputError::parse(json_str, format!("Failed to parse LLM JSON response: {e}")))
+}
 
-Make sure the code is correct and ready to submit. Do not include docstrings or comments."#
-	);
+fn parse_code_files(json_str: &str) -> std::result::Result<Vec<(String, String)>, LlmOutputError> {
+	let json = repair_llm_json(json_str).map_err(|e| LlmOutputError::parse(json_str, format!("Failed to parse LLM code response: {e}")))?;
+	let answer: LlmCodeAnswer = serde_json::from_str(&json).map_err(|e| LlmOutputError::parse(json_str, format!("Failed to parse LLM code response: {e}")))?;
+	if answer.files.is_empty() {
+		return Err(LlmOutputError::invalid(json_str, "LLM returned no files"));
+	}
+	Ok(answer.files.into_iter().map(|f| (f.filename, f.content)).collect())
+}
 
-	let mut conv = Conversation::new();
-	conv.add(Role::User, prompt);
+/// Follow-up message after an unusable reply
+fn strict_reminder(reason: &str) -> String {
+	format!(
+		"Your previous reply could not be used ({reason}). Respond again with ONLY the JSON object, in exactly the format requested above, \
+		 with every number within the valid range."
+	)
+}
 
-	let client = LlmClient::new().model(Model::Medium).max_tokens(4096).force_json();
+/// How an LLM-returned option text was matched to one of the real options
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MatchQuality {
+	Exact,
+	/// Equal once case, accents, punctuation and whitespace are ignored
+	Normalized,
+	/// Normalized Levenshtein similarity (0..1) above [`FUZZY_MATCH_MIN`]
+	Fuzzy(f64),
+}
 
-	let response = call_with_retry(&client, &conv, config.api_retries, config.api_retry_delay_ms).await?;
+/// Lowest similarity accepted as a fuzzy match
+const FUZZY_MATCH_MIN: f64 = 0.8;
+/// Fuzzy matches below this are accepted with a warning
+const FUZZY_MATCH_CONFIDENT: f64 = 0.9;
+/// Two candidates closer than this to the answer are ambiguous, and neither is picked
+const FUZZY_MATCH_MARGIN: f64 = 0.05;
+
+/// Index of the candidate the LLM meant by `answer`: exact match, else normalized match, else the clearly closest
+/// candidate by edit distance. None if nothing is close enough or several candidates are equally plausible.
+fn best_option_match(candidates: &[impl AsRef<str>], answer: &str) -> Option<(usize, MatchQuality)> {
+	if let Some(i) = candidates.iter().position(|c| c.as_ref() == answer) {
+		return Some((i, MatchQuality::Exact));
+	}
 
-	tracing::debug!("LLM code response: {}", response.text);
+	let answer = normalize_option_text(answer);
+	let normalized: Vec<String> = candidates.iter().map(|c| normalize_option_text(c.as_ref())).collect();
+	let mut equal = normalized.iter().enumerate().filter(|(_, c)| **c == answer).map(|(i, _)| i);
+	match (equal.next(), equal.next()) {
+		(Some(i), None) => return Some((i, MatchQuality::Normalized)),
+		(Some(_), Some(_)) => return None,
+		_ => {}
+	}
 
-	// Add assistant response to conversation for potential retries
-	conv.add(Role::Assistant, &response.text);
+	let mut scored: Vec<(usize, f64)> = normalized.iter().map(|c| strsim::normalized_levenshtein(c, &answer)).enumerate().collect();
+	scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+	match scored.as_slice() {
+		[(i, best), rest @ ..] if *best >= FUZZY_MATCH_MIN && rest.first().is_none_or(|(_, second)| best - second >= FUZZY_MATCH_MARGIN) => Some((*i, MatchQuality::Fuzzy(*best))),
+		_ => None,
+	}
+}
 
-	let json_str = response.text.trim();
-	let answer: LlmCodeAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM code response: {e} - raw: '{json_str}'"))?;
+/// [`best_option_match`], logging when the match wasn't exact
+fn match_option(candidates: &[&str], answer: &str) -> Option<usize> {
+	let (i, quality) = best_option_match(candidates, answer)?;
+	match quality {
+		MatchQuality::Exact => {}
+		MatchQuality::Normalized => tracing::info!("Matched LLM option '{answer}' to '{}'", candidates[i]),
+		MatchQuality::Fuzzy(similarity) if similarity < FUZZY_MATCH_CONFIDENT => {
+			tracing::warn!("Weak match for LLM option '{answer}': using '{}' (similarity {similarity:.2})", candidates[i])
+		}
+		MatchQuality::Fuzzy(similarity) => tracing::info!("Fuzzy-matched LLM option '{answer}' to '{}' (similarity {similarity:.2})", candidates[i]),
+	}
+	Some(i)
+}
 
-	let files = answer.files.into_iter().map(|f| (f.filename, f.content)).collect();
-	Ok(LlmCodeResult { files, conversation: conv })
+/// Index of the matching item an LLM match pair is about: its item number if valid, else the slot number ("[2]")
+/// of an inline select, else the item whose prompt is closest to the echoed one
+fn matching_item_index(items: &[MatchItem], pair: &LlmMatchPair) -> Option<usize> {
+	if let Some(n) = pair.item.filter(|n| (1..=items.len()).contains(n)) {
+		return Some(n - 1);
+	}
+	let slot = pair.prompt.trim().trim_start_matches('[').trim_end_matches(']');
+	if let Ok(n) = slot.parse::<usize>()
+		&& (1..=items.len()).contains(&n)
+		&& items[n - 1].prompt.is_empty()
+	{
+		return Some(n - 1);
+	}
+	let prompts: Vec<&str> = items.iter().map(|i| i.prompt.as_str()).collect();
+	match_option(&prompts, &pair.prompt).or_else(|| {
+		// A truncated echo of exactly one prompt
+		let echoed = normalize_option_text(&pair.prompt);
+		let mut prefixed = prompts
+			.iter()
+			.enumerate()
+			.filter(|(_, p)| !echoed.is_empty() && normalize_option_text(p).starts_with(&echoed))
+			.map(|(i, _)| i);
+		match (prefixed.next(), prefixed.next()) {
+			(Some(i), None) => Some(i),
+			_ => None,
+		}
+	})
 }
-/// Retry code generation with test results feedback
-pub async fn retry_llm_with_test_results(mut conversation: Conversation, test_results: &str, config: &AppConfig) -> Result<LlmCodeResult> {
-	// Add test results as a new user message (no additional commentary)
-	conversation.add(Role::User, test_results);
 
-	let client = LlmClient::new().model(Model::Medium).max_tokens(4096).force_json();
+/// Lowercased, accents folded, punctuation dropped, whitespace collapsed
+fn normalize_option_text(text: &str) -> String {
+	let mut folded = String::with_capacity(text.len());
+	for c in text.chars().flat_map(char::to_lowercase) {
+		match c {
+			'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' => folded.push('a'),
+			'ç' => folded.push('c'),
+			'è' | 'é' | 'ê' | 'ë' => folded.push('e'),
+			'ì' | 'í' | 'î' | 'ï' => folded.push('i'),
+			'ñ' => folded.push('n'),
+			'ò' | 'ó' | 'ô' | 'õ' | 'ö' => folded.push('o'),
+			'ù' | 'ú' | 'û' | 'ü' => folded.push('u'),
+			'ý' | 'ÿ' => folded.push('y'),
+			'œ' => folded.push_str("oe"),
+			'æ' => folded.push_str("ae"),
+			c if c.is_alphanumeric() => folded.push(c),
+			_ => folded.push(' '),
+		}
+	}
+	folded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
 
-	let response = call_with_retry(&client, &conversation, config.api_retries, config.api_retry_delay_ms).await?;
+/// Map a model name from the config to the ask_llm model
+pub fn parse_model(name: &str) -> Result<Model> {
+	Ok(match name.to_ascii_lowercase().as_str() {
+		"fast" => Model::Fast,
+		"medium" => Model::Medium,
+		"slow" => Model::Slow,
+		other => bail!("Unknown LLM model {other:?}, expected one of: {MODEL_NAMES}"),
+	})
+}
 
-	tracing::debug!("LLM retry response: {}", response.text);
+/// Check the configured model names up front, so a typo fails at startup rather than on the first question
+pub fn validate_model_settings(config: &AppConfig) -> Result<()> {
+	for (option, name) in [("quiz_model", &config.quiz_model), ("code_model", &config.code_model)] {
+		if let Some(name) = name {
+			parse_model(name).map_err(|e| eyre!("{option}: {e}"))?;
+		}
+	}
+	Ok(())
+}
 
-	// Add assistant response to conversation
-	conversation.add(Role::Assistant, &response.text);
+/// Appended to every quiz prompt; the answer structs all accept the field
+const CONFIDENCE_INSTRUCTION: &str = "Also include a \"confidence\" field in the JSON object: an integer from 0 to 100 for how sure you are that the answer is correct.";
 
-	let json_str = response.text.trim();
-	let answer: LlmCodeAnswer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse LLM retry response: {e} - raw: '{json_str}'"))?;
+/// Appended to quiz prompts with `show_reasoning`; the answer structs all accept the field, and none need it
+const REASONING_INSTRUCTION: &str = "Also include a \"reasoning\" field in the JSON object: one short sentence on why this is the answer.";
 
-	let files = answer.files.into_iter().map(|f| (f.filename, f.content)).collect();
-	Ok(LlmCodeResult { files, conversation })
-}
-/// Check if an error is transient and should be retried
-fn is_transient_error(err: &color_eyre::Report) -> bool {
-	let err_str = err.to_string();
-	// API errors that indicate transient issues
-	err_str.contains("api_error")
-		|| err_str.contains("Internal server error")
-		|| err_str.contains("overloaded")
-		|| err_str.contains("rate_limit")
-		|| err_str.contains("timeout")
-		|| err_str.contains("missing field `id`") // This happens when API returns error instead of response
+/// Build a conversation from the question prompt, replaying previous wrong attempts and their feedback, and the
+/// unusable reply being escalated if any
+fn new_conversation(prompt: String, feedback: &[AnswerFeedback], escalation: Option<&Escalation>, reasoning: bool, notes: Option<&str>) -> Conversation {
+	let mut conv = Conversation::new();
+	let reasoning = if reasoning { format!(" {REASONING_INSTRUCTION}") } else { String::new() };
+	conv.add(Role::User, format!("{}{prompt}\n\n{CONFIDENCE_INSTRUCTION}{reasoning}", notes.unwrap_or_default()));
+	for attempt in feedback {
+		conv.add(Role::Assistant, format!("My answer:\n{}", attempt.previous_answer));
+		conv.add(
+			Role::User,
+			format!(
+				"That answer was marked incorrect. Feedback from the quiz:\n{}\n\nReconsider and answer again, in the same JSON format.",
+				attempt.feedback
+			),
+		);
+	}
+	if let Some(escalation) = escalation {
+		conv.add(Role::Assistant, &escalation.bad_output);
+		conv.add(Role::User, strict_reminder(&escalation.reason));
+	}
+	conv
 }
 
-/// Call LLM with retry logic for transient errors
-async fn call_with_retry(client: &LlmClient, conv: &Conversation, max_retries: u32, retry_delay_ms: u64) -> Result<Response> {
+/// Call LLM, retrying rate limits, server errors and timeouts: after the wait the provider asked for, else
+/// `retry_delay_ms` times the attempt number. Auth failures come back tagged with [`LlmErrorKind::AuthFailed`].
+async fn call_with_retry(backend: &impl ChatBackend, conv: &Conversation, options: &CompletionOptions<'_>, timeout: Duration, max_retries: u32, retry_delay_ms: u64) -> Result<ChatResponse> {
 	let mut last_error = None;
 	for attempt in 0..max_retries {
-		match client.conversation(conv).await {
+		// A hung connection never errors out by itself; dropping the call on timeout closes it
+		let result = match tokio::time::timeout(timeout, backend.complete(conv, options)).await {
+			Ok(result) => result,
+			Err(_) => Err(LlmTimeout(timeout).into()),
+		};
+		let e = match result {
 			Ok(response) => return Ok(response),
-			Err(e) =>
-				if is_transient_error(&e) && attempt < max_retries - 1 {
-					let delay = retry_delay_ms * (attempt as u64 + 1);
-					tracing::warn!("Transient API error (attempt {}/{}): {}. Retrying in {}ms...", attempt + 1, max_retries, e, delay);
-					tokio::time::sleep(tokio::time::Duration::from_millis(delay)).await;
-					last_error = Some(e);
-				} else {
-					return Err(e);
-				},
+			Err(e) => e,
+		};
+		let kind = LlmErrorKind::of(&e);
+		if kind == LlmErrorKind::AuthFailed {
+			elog!("{kind}: {}", backend.credential_hint());
+			return Err(e.wrap_err(kind));
+		}
+		if !kind.is_transient() || attempt + 1 >= max_retries {
+			return Err(e);
 		}
+		let delay = match kind {
+			LlmErrorKind::RateLimited { retry_after: Some(wait) } => wait,
+			_ => Duration::from_millis(retry_delay_ms * (attempt as u64 + 1)),
+		};
+		tracing::warn!("LLM call failed: {kind} (attempt {}/{max_retries}). Retrying in {}ms...", attempt + 1, delay.as_millis());
+		tracing::debug!("LLM call error: {e}");
+		tokio::time::sleep(delay).await;
+		last_error = Some(e);
 	}
 	Err(last_error.unwrap_or_else(|| eyre!("Retry loop exhausted without error")))
 }
@@ -536,6 +1493,12 @@ async fn call_with_retry(client: &LlmClient, conv: &Conversation, max_retries: u
 struct LlmSingleAnswer {
 	response: String,
 	response_number: usize,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
 }
 
 /// LLM response for multi-choice questions
@@ -543,22 +1506,44 @@ struct LlmSingleAnswer {
 struct LlmMultiAnswer {
 	responses: Vec<String>,
 	response_numbers: Vec<usize>,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
 }
 
 /// LLM response for short answer questions
 #[derive(Debug, serde::Deserialize)]
 struct LlmTextAnswer {
 	answer: String,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
 }
 
 /// LLM response for matching questions
 #[derive(Debug, serde::Deserialize)]
 struct LlmMatchingAnswer {
 	matches: Vec<LlmMatchPair>,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize)]
 struct LlmMatchPair {
+	/// 1-based item number, as listed in the question
+	#[serde(default)]
+	item: Option<usize>,
+	#[serde(default)]
 	prompt: String,
 	answer: String,
 }
@@ -567,18 +1552,36 @@ struct LlmMatchPair {
 #[derive(Debug, serde::Deserialize)]
 struct LlmFillInBlanksAnswer {
 	blanks: Vec<LlmBlankAnswer>,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
 }
 
 /// LLM response for code block questions
 #[derive(Debug, serde::Deserialize)]
 struct LlmCodeBlockAnswer {
 	code: String,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
 }
 
 /// LLM response for drag-drop-into-text questions
 #[derive(Debug, serde::Deserialize)]
 struct LlmDragDropAnswer {
 	placements: Vec<LlmPlacement>,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize)]
@@ -589,6 +1592,33 @@ struct LlmPlacement {
 	choice: String,
 }
 
+/// LLM response for numerical questions
+#[derive(Debug, serde::Deserialize)]
+struct LlmNumericalAnswer {
+	answer: String,
+	#[serde(default)]
+	unit: Option<String>,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
+}
+
+/// LLM response for ordering questions
+#[derive(Debug, serde::Deserialize)]
+struct LlmOrderingAnswer {
+	/// Item numbers (1-indexed as shown to the LLM) in the chosen sequence
+	order: Vec<usize>,
+	/// Self-reported confidence, 0-100
+	#[serde(default)]
+	confidence: Option<f64>,
+	/// One sentence on why, with `show_reasoning`
+	#[serde(default)]
+	reasoning: Option<String>,
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct LlmBlankAnswer {
 	/// The blank number (1-indexed as shown to the LLM)
@@ -609,39 +1639,81 @@ struct LlmCodeFile {
 	content: String,
 }
 
-/// Fetch an image via the browser and return its base64 data and media type
-async fn fetch_image_as_base64(page: &Page, url: &str) -> Result<(String, String)> {
-	let fetch_script = format!(
-		r#"
-		(async function() {{
-			try {{
-				const response = await fetch("{url}");
-				if (!response.ok) return null;
-				const blob = await response.blob();
-				const mediaType = blob.type || 'image/png';
-				return new Promise((resolve) => {{
-					const reader = new FileReader();
-					reader.onloadend = () => {{
-						const base64 = reader.result.split(',')[1];
-						resolve(JSON.stringify({{base64: base64, mediaType: mediaType}}));
-					}};
-					reader.readAsDataURL(blob);
-				}});
-			}} catch (e) {{
-				return null;
-			}}
-		}})()
-		"#
-	);
-
-	let result = page.evaluate(fetch_script).await.map_err(|e| eyre!("Failed to fetch image: {e}"))?;
-
-	let json_str = result.value().and_then(|v| v.as_str()).ok_or_else(|| eyre!("Failed to fetch image: browser returned null"))?;
-
-	let parsed: serde_json::Value = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse image data: {e}"))?;
-
-	let base64 = parsed["base64"].as_str().ok_or_else(|| eyre!("Missing base64 data"))?.to_string();
-	let media_type = parsed["mediaType"].as_str().unwrap_or("image/png").to_string();
-
-	Ok((base64, media_type))
+/// Convert plain text with blank-line separated paragraphs into escaped `<p>` HTML
+pub(crate) fn paragraphs_to_html(text: &str) -> String {
+	text.split("\n\n")
+		.map(str::trim)
+		.filter(|p| !p.is_empty())
+		.map(|p| {
+			let escaped = p.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('\n', "<br>");
+			format!("<p>{escaped}</p>")
+		})
+		.collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn repaired(raw: &str) -> serde_json::Value {
+		serde_json::from_str(&extract_json(raw).unwrap_or_else(|e| panic!("{raw:?}: {e}"))).unwrap()
+	}
+
+	#[test]
+	fn extract_json_passes_clean_json_through() {
+		let raw = "{ \"answer\" : 2, \"confidence\": 90 }";
+		assert_eq!(extract_json(raw).unwrap(), raw);
+	}
+
+	#[test]
+	fn extract_json_strips_fences_and_prose() {
+		let expected = serde_json::json!({ "answer": "B", "confidence": 90 });
+		assert_eq!(repaired("```json\n{\"answer\": \"B\", \"confidence\": 90}\n```"), expected);
+		assert_eq!(repaired("```\n{\"answer\": \"B\", \"confidence\": 90}\n```\nLet me know if you need more."), expected);
+		assert_eq!(
+			repaired("Sure! Here is my answer:\n{\"answer\": \"B\", \"confidence\": 90}\nThe second option is right because..."),
+			expected
+		);
+	}
+
+	#[test]
+	fn extract_json_fixes_quotes_and_commas() {
+		assert_eq!(repaired("{“answer”: “l’ordinateur”}"), serde_json::json!({ "answer": "l’ordinateur" }));
+		assert_eq!(repaired("{'answer': 'it\\'s \"B\"'}"), serde_json::json!({ "answer": "it's \"B\"" }));
+		assert_eq!(repaired("{\"indices\": [1, 3,], }"), serde_json::json!({ "indices": [1, 3] }));
+		assert_eq!(repaired("{\"code\": \"def f():\n\treturn 1\"}"), serde_json::json!({ "code": "def f():\n\treturn 1" }));
+	}
+
+	#[test]
+	fn extract_json_rejects_what_it_cannot_repair() {
+		assert!(extract_json("The answer is B.").is_err());
+		assert!(extract_json("{\"answer\": \"B\"").is_err());
+		assert_eq!(normalize_json_object("{\"a\": {\"b\": 1}} trailing"), Some("{\"a\": {\"b\": 1}}".to_string()));
+		assert_eq!(normalize_json_object("{\"a\": [1, 2}"), None);
+	}
+
+	#[test]
+	fn option_match_ignores_case_accents_and_punctuation() {
+		assert_eq!(best_option_match(&["Paris", "Lyon"], "Lyon"), Some((1, MatchQuality::Exact)));
+		assert_eq!(best_option_match(&["Élément neutre", "Élément absorbant"], "element NEUTRE"), Some((0, MatchQuality::Normalized)));
+		assert_eq!(best_option_match(&["The heap.", "The stack."], "the stack"), Some((1, MatchQuality::Normalized)));
+		assert_eq!(best_option_match(&["O(n log n)", "O(n²)"], "O(n log n)."), Some((0, MatchQuality::Normalized)));
+	}
+
+	#[test]
+	fn option_match_takes_clearly_closest_typo() {
+		let Some((0, MatchQuality::Fuzzy(similarity))) = best_option_match(&["Polymorphism", "Encapsulation"], "Polymorphysm") else {
+			panic!("expected a fuzzy match on Polymorphism");
+		};
+		assert!(similarity >= FUZZY_MATCH_MIN);
+	}
+
+	#[test]
+	fn option_match_rejects_ambiguous_and_distant_answers() {
+		// Equal once normalized
+		assert_eq!(best_option_match(&["Vrai", "vrai."], "VRAI"), None);
+		// Equally close to two options
+		assert_eq!(best_option_match(&["option 1", "option 2"], "option 3"), None);
+		assert_eq!(best_option_match(&["alpha", "beta"], "gamma"), None);
+	}
 }
@@ -0,0 +1,336 @@
+//! Where LLM calls go, selected by `llm_provider`: the `ask_llm` client (Anthropic, the default), or any server with
+//! an OpenAI-style `/chat/completions` endpoint (Ollama, llama.cpp, vLLM...) at `llm_base_url`
+//!
+//! Providers differ in how replies are held to JSON: Anthropic's is enforced by the client ([`JsonMode::Native`]),
+//! while OpenAI-compatible servers only get asked for it in a system message ([`JsonMode::Prompted`]). Either way
+//! replies go through the JSON repair in `llm`, which is what makes the prompted mode workable.
+
+use color_eyre::{
+	Result,
+	eyre::{WrapErr, bail, eyre},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+	config::{AppConfig, LlmProvider},
+	llm::parse_model,
+};
+
+/// Base URL used for `openai_compatible` without `llm_base_url`: a local Ollama
+const DEFAULT_OPENAI_BASE_URL: &str = "http://localhost:11434/v1";
+
+/// System message standing in for a native JSON mode
+const JSON_ONLY_INSTRUCTION: &str = "Reply with a single JSON object and nothing else: no prose before or after it, no Markdown code fences.";
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+	User,
+	Assistant,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Message {
+	pub role: Role,
+	pub content: String,
+}
+
+/// Messages of an LLM conversation, in order
+#[derive(Clone, Debug, Default, Serialize)]
+#[serde(transparent)]
+pub struct Conversation(Vec<Message>);
+
+impl Conversation {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn add(&mut self, role: Role, content: impl Into<String>) {
+		self.0.push(Message { role, content: content.into() });
+	}
+
+	pub fn messages(&self) -> &[Message] {
+		&self.0
+	}
+}
+
+/// An image sent along with a prompt
+pub struct Attachment {
+	/// Image URL, or what else the image is
+	pub source: String,
+	pub base64: String,
+	pub media_type: String,
+}
+
+/// Per-call settings
+pub struct CompletionOptions<'a> {
+	/// `quiz_model`/`code_model` tier: "fast", "medium" or "slow"
+	pub model: &'a str,
+	pub max_tokens: usize,
+	/// Hold the reply to a JSON object, natively or by asking (see [`ChatBackend::json_mode`])
+	pub force_json: bool,
+	pub files: &'a [Attachment],
+}
+
+pub struct ChatResponse {
+	pub text: String,
+	/// (input, output) tokens the provider reported, if it did
+	pub tokens: Option<(u64, u64)>,
+	/// Cost in US cents the provider reported, if it did
+	pub cost_cents: Option<f64>,
+}
+
+/// How a backend gets JSON replies out of its model
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum JsonMode {
+	/// The provider enforces it
+	Native,
+	/// Asked for in a system message; the model may still wrap it in prose or fences
+	Prompted,
+}
+
+impl JsonMode {
+	pub fn as_str(self) -> &'static str {
+		match self {
+			JsonMode::Native => "native",
+			JsonMode::Prompted => "prompted",
+		}
+	}
+}
+
+pub trait ChatBackend {
+	fn complete(&self, conv: &Conversation, options: &CompletionOptions<'_>) -> impl Future<Output = Result<ChatResponse>> + Send;
+
+	fn json_mode(&self) -> JsonMode;
+
+	/// Whether the model can be sent images; questions with some fail when it can't
+	fn supports_images(&self) -> bool;
+
+	/// What to fix when the provider rejects the credentials
+	fn credential_hint(&self) -> String;
+}
+
+/// The backend `llm_provider` selects
+pub enum LlmBackend {
+	Anthropic(AskLlmBackend),
+	OpenAiCompatible(OpenAiBackend),
+}
+
+impl LlmBackend {
+	pub fn new(config: &AppConfig) -> Result<Self> {
+		Ok(match config.llm_provider {
+			LlmProvider::Anthropic => LlmBackend::Anthropic(AskLlmBackend { client: ask_llm::Client::new() }),
+			LlmProvider::OpenaiCompatible => LlmBackend::OpenAiCompatible(OpenAiBackend::new(config)?),
+		})
+	}
+}
+
+impl ChatBackend for LlmBackend {
+	async fn complete(&self, conv: &Conversation, options: &CompletionOptions<'_>) -> Result<ChatResponse> {
+		match self {
+			LlmBackend::Anthropic(backend) => backend.complete(conv, options).await,
+			LlmBackend::OpenAiCompatible(backend) => backend.complete(conv, options).await,
+		}
+	}
+
+	fn json_mode(&self) -> JsonMode {
+		match self {
+			LlmBackend::Anthropic(backend) => backend.json_mode(),
+			LlmBackend::OpenAiCompatible(backend) => backend.json_mode(),
+		}
+	}
+
+	fn supports_images(&self) -> bool {
+		match self {
+			LlmBackend::Anthropic(backend) => backend.supports_images(),
+			LlmBackend::OpenAiCompatible(backend) => backend.supports_images(),
+		}
+	}
+
+	fn credential_hint(&self) -> String {
+		match self {
+			LlmBackend::Anthropic(backend) => backend.credential_hint(),
+			LlmBackend::OpenAiCompatible(backend) => backend.credential_hint(),
+		}
+	}
+}
+
+/// Anthropic through the `ask_llm` client, which reads `CLAUDE_TOKEN`
+pub struct AskLlmBackend {
+	client: ask_llm::Client,
+}
+
+impl ChatBackend for AskLlmBackend {
+	async fn complete(&self, conv: &Conversation, options: &CompletionOptions<'_>) -> Result<ChatResponse> {
+		let mut client = self.client.clone().model(parse_model(options.model)?).max_tokens(options.max_tokens);
+		if options.force_json {
+			client = client.force_json();
+		}
+		for file in options.files {
+			client = client.append_file(file.base64.clone(), file.media_type.clone());
+		}
+		let mut ask_conv = ask_llm::Conversation::new();
+		for message in conv.messages() {
+			let role = match message.role {
+				Role::User => ask_llm::Role::User,
+				Role::Assistant => ask_llm::Role::Assistant,
+			};
+			ask_conv.add(role, &message.content);
+		}
+		let response = client.conversation(&ask_conv).await?;
+		// ask_llm's response carries the call's cost but no token usage, so this default provider only feeds call
+		// counts and cost into the usage summary; token totals come from providers that report them
+		Ok(ChatResponse {
+			text: response.text,
+			tokens: None,
+			cost_cents: Some(response.cost_cents as f64),
+		})
+	}
+
+	fn json_mode(&self) -> JsonMode {
+		JsonMode::Native
+	}
+
+	fn supports_images(&self) -> bool {
+		true
+	}
+
+	fn credential_hint(&self) -> String {
+		"set CLAUDE_TOKEN to a valid API token".to_string()
+	}
+}
+
+/// An OpenAI-style `/chat/completions` endpoint, running `llm_model_name` for every model tier
+pub struct OpenAiBackend {
+	http: reqwest::Client,
+	/// Up to and including the API version, e.g. `http://localhost:11434/v1`
+	base_url: String,
+	model_name: String,
+	/// `llm_api_key_env` and its value; local servers usually take no key
+	api_key: Option<(String, String)>,
+	supports_images: bool,
+}
+
+impl OpenAiBackend {
+	fn new(config: &AppConfig) -> Result<Self> {
+		let Some(model_name) = config.llm_model_name.clone() else {
+			bail!("llm_provider = \"openai_compatible\" needs llm_model_name, the model the endpoint runs (e.g. \"qwen2.5vl:7b\")");
+		};
+		let api_key = match &config.llm_api_key_env {
+			Some(var) => {
+				let key = std::env::var(var).map_err(|_| eyre!("llm_api_key_env is {var}, but {var} isn't set"))?;
+				Some((var.clone(), key))
+			}
+			None => None,
+		};
+		Ok(Self {
+			http: reqwest::Client::new(),
+			base_url: config.llm_base_url.as_deref().unwrap_or(DEFAULT_OPENAI_BASE_URL).trim_end_matches('/').to_string(),
+			model_name,
+			api_key,
+			supports_images: config.llm_supports_images,
+		})
+	}
+
+	/// `conv` as chat messages; images go with the first user message, which holds the question
+	fn messages(conv: &Conversation, options: &CompletionOptions<'_>) -> Vec<serde_json::Value> {
+		let mut messages = Vec::new();
+		if options.force_json {
+			messages.push(serde_json::json!({ "role": "system", "content": JSON_ONLY_INSTRUCTION }));
+		}
+		let mut files = Some(options.files).filter(|files| !files.is_empty());
+		for message in conv.messages() {
+			let content = match files.take_if(|_| message.role == Role::User) {
+				Some(files) => {
+					let mut parts = vec![serde_json::json!({ "type": "text", "text": message.content })];
+					parts.extend(
+						files
+							.iter()
+							.map(|file| serde_json::json!({ "type": "image_url", "image_url": { "url": format!("data:{};base64,{}", file.media_type, file.base64) } })),
+					);
+					serde_json::Value::Array(parts)
+				}
+				None => message.content.clone().into(),
+			};
+			messages.push(serde_json::json!({ "role": message.role, "content": content }));
+		}
+		messages
+	}
+}
+
+impl ChatBackend for OpenAiBackend {
+	async fn complete(&self, conv: &Conversation, options: &CompletionOptions<'_>) -> Result<ChatResponse> {
+		let body = serde_json::json!({
+			"model": self.model_name,
+			"max_tokens": options.max_tokens,
+			"messages": Self::messages(conv, options),
+		});
+		let mut request = self.http.post(format!("{}/chat/completions", self.base_url)).json(&body);
+		if let Some((_, key)) = &self.api_key {
+			request = request.bearer_auth(key);
+		}
+		let response = request.send().await.wrap_err_with(|| format!("Failed to reach {}", self.base_url))?;
+		let status = response.status();
+		let text = response.text().await?;
+		if !status.is_success() {
+			// Worded for `LlmErrorKind::of`, which reads the status back
+			let error = eyre!("status {}: {text}", status.as_u16());
+			return Err(match options.files.len() {
+				n if n > 0 && status.is_client_error() => error.wrap_err(format!(
+					"{} rejected a request with {n} image(s); if it can't take images, set llm_supports_images = false",
+					self.model_name
+				)),
+				_ => error,
+			});
+		}
+		let reply: OpenAiReply = serde_json::from_str(&text).wrap_err_with(|| format!("Unexpected reply from {}: '{text}'", self.base_url))?;
+		let Some(choice) = reply.choices.into_iter().next() else {
+			bail!("No choices in the reply from {}: '{text}'", self.base_url);
+		};
+		Ok(ChatResponse {
+			text: choice.message.content.unwrap_or_default(),
+			tokens: reply.usage.map(|usage| (usage.prompt_tokens, usage.completion_tokens)),
+			cost_cents: None,
+		})
+	}
+
+	fn json_mode(&self) -> JsonMode {
+		// `response_format` isn't understood by every OpenAI-compatible server
+		JsonMode::Prompted
+	}
+
+	fn supports_images(&self) -> bool {
+		self.supports_images
+	}
+
+	fn credential_hint(&self) -> String {
+		match &self.api_key {
+			Some((var, _)) => format!("check the API key in {var} (llm_api_key_env) for {}", self.base_url),
+			None => format!("{} wants an API key: put it in an environment variable named by llm_api_key_env", self.base_url),
+		}
+	}
+}
+
+#[derive(Deserialize)]
+struct OpenAiReply {
+	choices: Vec<OpenAiChoice>,
+	#[serde(default)]
+	usage: Option<OpenAiUsage>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiChoice {
+	message: OpenAiMessage,
+}
+
+#[derive(Deserialize)]
+struct OpenAiMessage {
+	#[serde(default)]
+	content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct OpenAiUsage {
+	prompt_tokens: u64,
+	completion_tokens: u64,
+}
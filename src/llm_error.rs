@@ -0,0 +1,206 @@
+//! What went wrong with an LLM API call, to retry only the failures that can go away by themselves
+//!
+//! `ask_llm` reports failures as plain [`Report`]s, so [`LlmErrorKind::of`] looks at what is inside: the HTTP
+//! client's error (status, timeout), our own [`LlmTimeout`], the type of the API's error body (`rate_limit_error`,
+//! `overloaded_error`...), else an HTTP status mentioned in the message. A final auth failure is tagged with
+//! `.wrap_err(LlmErrorKind::AuthFailed)`, which [`LlmErrorKind::of`] reads back first.
+
+use std::{fmt, sync::LazyLock, time::Duration};
+
+use color_eyre::Report;
+use regex::Regex;
+
+/// `"type": "overloaded_error"` in an API error body
+static API_ERROR_TYPE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#""type"\s*:\s*"(\w+)""#).expect("valid regex"));
+/// `status: 529`, `HTTP 429`, `status code 401`...
+static HTTP_STATUS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)\b(?:status(?: code)?|http)\s*[:=]?\s*(\d{3})\b").expect("valid regex"));
+/// `retry-after: 20`, `"retry_after": 1.5`...
+static RETRY_AFTER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r#"(?i)retry[-_ ]after"?\s*[:=]?\s*"?(\d+(?:\.\d+)?)"#).expect("valid regex"));
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LlmErrorKind {
+	/// 429, `rate_limit_error`; `retry_after` is how long the provider asked to wait, when it said
+	RateLimited { retry_after: Option<Duration> },
+	/// 5xx (529 overloaded included), `api_error`, `overloaded_error`
+	ServerError,
+	/// No reply within the timeout, or the connection couldn't be made
+	Timeout,
+	/// 401, 403, `authentication_error`, `permission_error`
+	AuthFailed,
+	/// Any other 4xx: refused as sent, and would be again
+	InvalidRequest,
+	/// A reply that isn't the response the client expected
+	ParseError,
+	/// None of the above
+	Other,
+}
+
+impl LlmErrorKind {
+	/// Classify `err` (see the module docs)
+	pub fn of(err: &Report) -> Self {
+		if let Some(kind) = err.downcast_ref::<LlmErrorKind>() {
+			return *kind;
+		}
+		if err.downcast_ref::<LlmTimeout>().is_some() {
+			return LlmErrorKind::Timeout;
+		}
+		let mut parse_error = false;
+		for cause in err.chain() {
+			if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+				if e.is_timeout() || e.is_connect() {
+					return LlmErrorKind::Timeout;
+				}
+				if let Some(status) = e.status() {
+					return Self::from_status(status.as_u16(), None);
+				}
+				parse_error |= e.is_decode();
+			}
+			parse_error |= cause.is::<serde_json::Error>();
+		}
+
+		// The API's error body, when the client put it in the message, says more than a failed decode of it
+		let text = format!("{err:#}");
+		let retry_after = RETRY_AFTER.captures(&text).and_then(|c| c[1].parse::<f64>().ok()).map(Duration::from_secs_f64);
+		if let Some(kind) = API_ERROR_TYPE.captures_iter(&text).find_map(|c| Self::from_api_error_type(&c[1], retry_after)) {
+			return kind;
+		}
+		if let Some(status) = HTTP_STATUS.captures(&text).and_then(|c| c[1].parse().ok()) {
+			return Self::from_status(status, retry_after);
+		}
+		match parse_error {
+			true => LlmErrorKind::ParseError,
+			false => LlmErrorKind::Other,
+		}
+	}
+
+	fn from_status(status: u16, retry_after: Option<Duration>) -> Self {
+		match status {
+			429 => LlmErrorKind::RateLimited { retry_after },
+			401 | 403 => LlmErrorKind::AuthFailed,
+			408 => LlmErrorKind::Timeout,
+			500..=599 => LlmErrorKind::ServerError,
+			400..=499 => LlmErrorKind::InvalidRequest,
+			_ => LlmErrorKind::Other,
+		}
+	}
+
+	/// Anthropic-style error types; None for ones that aren't (`"type": "error"`, `"type": "text"`...)
+	fn from_api_error_type(error_type: &str, retry_after: Option<Duration>) -> Option<Self> {
+		Some(match error_type {
+			"rate_limit_error" => LlmErrorKind::RateLimited { retry_after },
+			"api_error" | "overloaded_error" => LlmErrorKind::ServerError,
+			"timeout_error" => LlmErrorKind::Timeout,
+			"authentication_error" | "permission_error" => LlmErrorKind::AuthFailed,
+			"invalid_request_error" | "not_found_error" | "request_too_large" => LlmErrorKind::InvalidRequest,
+			_ => return None,
+		})
+	}
+
+	/// Worth sending the same request again
+	pub fn is_transient(&self) -> bool {
+		matches!(self, LlmErrorKind::RateLimited { .. } | LlmErrorKind::ServerError | LlmErrorKind::Timeout)
+	}
+}
+
+impl fmt::Display for LlmErrorKind {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LlmErrorKind::RateLimited { retry_after: Some(wait) } => write!(f, "rate limited (retry after {:.1}s)", wait.as_secs_f64()),
+			LlmErrorKind::RateLimited { retry_after: None } => write!(f, "rate limited"),
+			LlmErrorKind::ServerError => write!(f, "server error"),
+			LlmErrorKind::Timeout => write!(f, "timeout"),
+			LlmErrorKind::AuthFailed => write!(f, "LLM API rejected the credentials"),
+			LlmErrorKind::InvalidRequest => write!(f, "invalid request"),
+			LlmErrorKind::ParseError => write!(f, "unreadable response"),
+			LlmErrorKind::Other => write!(f, "unknown error"),
+		}
+	}
+}
+
+/// An LLM call abandoned after waiting this long for its reply
+#[derive(Debug)]
+pub struct LlmTimeout(pub Duration);
+
+impl fmt::Display for LlmTimeout {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "LLM call timed out after {}s", self.0.as_secs())
+	}
+}
+
+impl std::error::Error for LlmTimeout {}
+
+#[cfg(test)]
+mod tests {
+	use color_eyre::eyre::eyre;
+
+	use super::*;
+
+	#[test]
+	fn rate_limited_with_retry_after() {
+		let err = eyre!(
+			"{}",
+			r#"Anthropic API error (status 429): {"type":"error","error":{"type":"rate_limit_error","message":"Number of request tokens has exceeded your per-minute rate limit"}} retry-after: 20"#
+		);
+		let kind = LlmErrorKind::of(&err);
+		assert_eq!(
+			kind,
+			LlmErrorKind::RateLimited {
+				retry_after: Some(Duration::from_secs(20))
+			}
+		);
+		assert!(kind.is_transient());
+		assert_eq!(kind.to_string(), "rate limited (retry after 20.0s)");
+
+		let err = eyre!("{}", r#"HTTP 429 Too Many Requests: {"error": {"message": "slow down", "retry_after": 1.5}}"#);
+		assert_eq!(
+			LlmErrorKind::of(&err),
+			LlmErrorKind::RateLimited {
+				retry_after: Some(Duration::from_millis(1500))
+			}
+		);
+		assert_eq!(LlmErrorKind::of(&eyre!("status: 429")), LlmErrorKind::RateLimited { retry_after: None });
+	}
+
+	#[test]
+	fn overloaded() {
+		let body = r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#;
+		assert_eq!(LlmErrorKind::of(&eyre!("API returned status code 529: {body}")), LlmErrorKind::ServerError);
+		assert_eq!(LlmErrorKind::of(&eyre!("request failed with status 529")), LlmErrorKind::ServerError);
+		assert!(LlmErrorKind::ServerError.is_transient());
+	}
+
+	#[test]
+	fn auth_failed() {
+		let err = eyre!("{}", r#"status 401: {"type":"error","error":{"type":"authentication_error","message":"invalid x-api-key"}}"#);
+		assert_eq!(LlmErrorKind::of(&err), LlmErrorKind::AuthFailed);
+		assert!(!LlmErrorKind::AuthFailed.is_transient());
+		// The tag put on a final auth failure wins over what the message says
+		let tagged = eyre!("status 500").wrap_err(LlmErrorKind::AuthFailed);
+		assert_eq!(LlmErrorKind::of(&tagged), LlmErrorKind::AuthFailed);
+	}
+
+	#[test]
+	fn timeout() {
+		let err: Report = LlmTimeout(Duration::from_secs(90)).into();
+		assert_eq!(LlmErrorKind::of(&err), LlmErrorKind::Timeout);
+		assert_eq!(LlmErrorKind::of(&err.wrap_err("Quiz answer")), LlmErrorKind::Timeout);
+		assert_eq!(LlmErrorKind::of(&eyre!("HTTP 408 Request Timeout")), LlmErrorKind::Timeout);
+	}
+
+	#[tokio::test]
+	async fn refused_connection_counts_as_timeout() {
+		let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		drop(listener);
+		let err = reqwest::get(format!("http://{addr}/v1/messages")).await.unwrap_err();
+		assert_eq!(LlmErrorKind::of(&Report::new(err).wrap_err("LLM request failed")), LlmErrorKind::Timeout);
+	}
+
+	#[test]
+	fn other_failures() {
+		assert_eq!(LlmErrorKind::of(&eyre!("status 400: max_tokens too large")), LlmErrorKind::InvalidRequest);
+		let decode = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+		assert_eq!(LlmErrorKind::of(&Report::new(decode)), LlmErrorKind::ParseError);
+		assert_eq!(LlmErrorKind::of(&eyre!("something broke")), LlmErrorKind::Other);
+	}
+}
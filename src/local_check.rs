@@ -0,0 +1,94 @@
+//! Local pre-flight check for generated VPL code - compile/lint it with a user-configured command before pasting
+
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+	sync::atomic::{AtomicUsize, Ordering},
+	time::Duration,
+};
+
+use color_eyre::{Result, eyre::eyre};
+
+/// How long a single check command may run
+const CHECK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Language name for a filename's extension, as used for `local_check_cmd` keys
+fn language_of(filename: &str) -> Option<&'static str> {
+	let ext = Path::new(filename).extension()?.to_str()?.to_ascii_lowercase();
+	Some(match ext.as_str() {
+		"py" => "python",
+		"c" | "h" => "c",
+		"cpp" | "cc" | "cxx" | "hpp" | "hh" => "cpp",
+		"java" => "java",
+		"rs" => "rust",
+		"js" | "mjs" => "javascript",
+		"ts" => "typescript",
+		"sh" => "shell",
+		"hs" => "haskell",
+		"ml" => "ocaml",
+		"go" => "go",
+		_ => return None,
+	})
+}
+
+/// Command configured for `filename`: by language name first, then by bare extension
+fn command_for<'a>(filename: &str, commands: &'a HashMap<String, String>) -> Option<&'a String> {
+	language_of(filename)
+		.and_then(|lang| commands.get(lang))
+		.or_else(|| Path::new(filename).extension().and_then(|ext| ext.to_str()).and_then(|ext| commands.get(ext)))
+}
+
+/// Quote a path for `sh -c`
+fn shell_quote(path: &Path) -> String {
+	format!("'{}'", path.display().to_string().replace('\'', r"'\''"))
+}
+
+fn fresh_check_dir() -> PathBuf {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+	std::env::temp_dir().join(format!("uni_headless_check_{}_{n}", std::process::id()))
+}
+
+/// Write `files` to a temporary directory and run the configured check command for each of them
+/// Returns the combined output of the failing commands, or None if everything passed (or nothing had a command)
+pub async fn run_local_check(files: &[(String, String)], commands: &HashMap<String, String>) -> Result<Option<String>> {
+	let checks: Vec<(&String, &String)> = files.iter().filter_map(|(name, _)| command_for(name, commands).map(|cmd| (name, cmd))).collect();
+	if checks.is_empty() {
+		return Ok(None);
+	}
+
+	// All files go in the same directory, so headers and modules next to each other resolve
+	let dir = fresh_check_dir();
+	std::fs::create_dir_all(&dir).map_err(|e| eyre!("Failed to create {}: {e}", dir.display()))?;
+	for (name, content) in files {
+		let path = dir.join(name);
+		if let Some(parent) = path.parent() {
+			std::fs::create_dir_all(parent).map_err(|e| eyre!("Failed to create {}: {e}", parent.display()))?;
+		}
+		std::fs::write(&path, content).map_err(|e| eyre!("Failed to write {}: {e}", path.display()))?;
+	}
+
+	let mut failures = Vec::new();
+	for (name, cmd) in checks {
+		let cmd = cmd.replace("{file}", &shell_quote(&dir.join(name)));
+		let output = tokio::time::timeout(CHECK_TIMEOUT, tokio::process::Command::new("sh").arg("-c").arg(&cmd).current_dir(&dir).output()).await;
+		match output {
+			Err(_) => failures.push(format!("{name}: `{cmd}` timed out after {}s", CHECK_TIMEOUT.as_secs())),
+			Ok(Err(e)) => {
+				let _ = std::fs::remove_dir_all(&dir);
+				return Err(eyre!("Failed to run local check `{cmd}`: {e}"));
+			}
+			Ok(Ok(output)) if output.status.success() => {}
+			Ok(Ok(output)) => {
+				let mut text = String::from_utf8_lossy(&output.stderr).into_owned();
+				text.push_str(&String::from_utf8_lossy(&output.stdout));
+				// Paths of the temp dir mean nothing to the LLM
+				let text = text.replace(&format!("{}/", dir.display()), "");
+				failures.push(format!("{name}:\n{}", text.trim_end()));
+			}
+		}
+	}
+
+	let _ = std::fs::remove_dir_all(&dir);
+	Ok((!failures.is_empty()).then(|| failures.join("\n\n")))
+}
@@ -1,54 +1,191 @@
+use std::fmt;
+
 use chromiumoxide::Page;
 use color_eyre::{
 	Result,
 	eyre::{bail, eyre},
 };
-use v_utils::log;
+use v_utils::{elog, log};
 
-use crate::config::AppConfig;
+use crate::{
+	config::{AppConfig, Credentials, LoginFlow},
+	js_string, shutdown,
+	store::SessionStore,
+	timing::{action_delay, timings, wait},
+	totp,
+};
 
 /// Detected site type
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Site {
 	Caseine,
 	UcaMoodle,
+	/// Any other Moodle, logged into through its own login form
+	GenericMoodle,
 }
 
 impl Site {
 	pub fn detect(url: &str) -> Self {
-		if url.contains("caseine.org") { Site::Caseine } else { Site::UcaMoodle }
+		let host = url_host(url);
+		if host.ends_with("caseine.org") {
+			Site::Caseine
+		} else if host.ends_with("uca.fr") {
+			Site::UcaMoodle
+		} else {
+			Site::GenericMoodle
+		}
+	}
+
+	/// Like `detect`, but honoring a `sites` override from the config (`manual` is handled by the caller)
+	pub fn resolve(url: &str, config: &AppConfig) -> Self {
+		match config.login_flow(url) {
+			Some(LoginFlow::Cas) => Site::UcaMoodle,
+			Some(LoginFlow::Direct) => Site::GenericMoodle,
+			Some(LoginFlow::Manual) | None => Site::detect(url),
+		}
+	}
+
+	/// Dashboard to log in to when there is no particular page to open; None for a Moodle we don't know the URL of
+	pub fn home_url(&self) -> Option<&'static str> {
+		match self {
+			Site::Caseine => Some("https://moodle.caseine.org/my/"),
+			Site::UcaMoodle => Some("https://moodle2025.uca.fr/my/"),
+			Site::GenericMoodle => None,
+		}
 	}
 
 	pub fn name(&self) -> &'static str {
 		match self {
 			Site::Caseine => "caseine.org",
 			Site::UcaMoodle => "moodle2025.uca.fr",
+			Site::GenericMoodle => "Moodle (direct login)",
 		}
 	}
 }
 
-/// Perform login for the detected site and navigate to target URL
-pub async fn login_and_navigate(page: &Page, site: Site, target_url: &str, config: &AppConfig) -> Result<()> {
+/// A login failure that retrying can't fix: the credentials were turned down, or the config lacks something
+#[derive(Debug)]
+pub enum LoginError {
+	/// Wrong username or password
+	BadCredentials { host: String, message: String },
+	/// Locked after too many failed attempts
+	AccountLocked { host: String, message: String },
+	/// Missing or wrong login settings (credentials, `totp_secret`, `institution`)
+	Config(String),
+}
+
+impl fmt::Display for LoginError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LoginError::BadCredentials { host, message } => {
+				write!(f, "Wrong username or password for {host} ({message}) - fix the credentials in the config")
+			}
+			LoginError::AccountLocked { host, message } => write!(
+				f,
+				"Account locked on {host} after too many failed logins ({message}) - fix the credentials in the config and wait before retrying"
+			),
+			LoginError::Config(message) => write!(f, "{message}"),
+		}
+	}
+}
+
+impl std::error::Error for LoginError {}
+
+/// Host part of a URL ("https://moodle.example.edu:8443/mod/..." -> "moodle.example.edu")
+pub fn url_host(url: &str) -> &str {
+	let without_scheme = url.split_once("://").map_or(url, |(_, rest)| rest);
+	let authority = without_scheme.split(['/', '?', '#']).next().unwrap_or(without_scheme);
+	let authority = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+	authority.split(':').next().unwrap_or(authority)
+}
+
+/// Root of a Moodle installation, which may live under a path prefix ("https://x.edu/moodle/mod/quiz/..." -> "https://x.edu/moodle")
+pub fn moodle_base_url(url: &str) -> &str {
+	const MOODLE_PATHS: &[&str] = &["/mod/", "/course/", "/login/", "/my/", "/user/", "/enrol/"];
+	if let Some(idx) = MOODLE_PATHS.iter().filter_map(|p| url.find(p)).min() {
+		return &url[..idx];
+	}
+	let scheme_end = url.find("://").map_or(0, |i| i + 3);
+	url[scheme_end..].find('/').map_or(url, |i| &url[..scheme_end + i])
+}
+
+/// Whether a URL belongs to one of the login flows (Moodle login page, federation discovery, CAS, SAML IdP),
+/// meaning the session has expired if we land there mid-run
+pub fn is_login_url(url: &str) -> bool {
+	const LOGIN_MARKERS: &[&str] = &["/login/index.php", "discovery.renater.fr", "wayf", "ent.uca.fr/cas", "idp.uca.fr"];
+	LOGIN_MARKERS.iter().any(|marker| url.contains(marker))
+}
+
+/// Log in again after the session expired mid-run, then return to `resume_url`
+pub async fn relogin(page: &Page, resume_url: &str, config: &AppConfig, store: &SessionStore) -> Result<()> {
+	let site = Site::resolve(resume_url, config);
+	log!("Session expired, logging in to {} again...", site.name());
+
+	// Start from the resume URL so every site's flow sees the same redirects as at startup
+	page.goto(resume_url).await.map_err(|e| eyre!("Failed to navigate to {resume_url}: {e}"))?;
+	page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for {resume_url}: {e}"))?;
+	login_and_navigate(page, site, resume_url, config, store).await?;
+
+	log!("Logged in again, resuming at {resume_url}");
+	Ok(())
+}
+
+/// Perform login for the detected site and navigate to target URL. A failure other than a [`LoginError`] reruns
+/// the whole flow from `target_url` up to `login_retries` times, saving each failing page to the session dir.
+pub async fn login_and_navigate(page: &Page, site: Site, target_url: &str, config: &AppConfig, store: &SessionStore) -> Result<()> {
+	let mut attempt = 0;
+	loop {
+		let error = match login_once(page, site, target_url, config).await {
+			Ok(()) => return Ok(()),
+			Err(e) => e,
+		};
+
+		if let Err(save_err) = store.save_error_snapshot(page).await {
+			elog!("Failed to save login page HTML: {save_err}");
+		}
+
+		if attempt >= config.login_retries || error.downcast_ref::<LoginError>().is_some() {
+			return Err(error);
+		}
+		attempt += 1;
+		match server_error_title(page).await {
+			Some(title) => elog!("Login failed on a server error page ({title}): {error}"),
+			None => elog!("Login failed: {error}"),
+		}
+		log!("Retrying login in {}s ({attempt}/{})...", config.login_retry_delay_secs, config.login_retries);
+		shutdown::sleep(std::time::Duration::from_secs(config.login_retry_delay_secs)).await?;
+
+		// From the target again, so the redirect chain starts over
+		page.goto(target_url).await.map_err(|e| eyre!("Failed to navigate to {target_url}: {e}"))?;
+		page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for {target_url}: {e}"))?;
+	}
+}
+
+/// One pass of the site's login flow
+async fn login_once(page: &Page, site: Site, target_url: &str, config: &AppConfig) -> Result<()> {
+	let host = url_host(target_url);
+	let creds = config
+		.credentials_for(target_url)
+		.ok_or_else(|| LoginError::Config(format!("No credentials for {host}: set username/password or [credentials.\"{host}\"] in the config")))?;
 	match site {
-		Site::Caseine => login_caseine(page, target_url, config).await,
-		Site::UcaMoodle => login_uca_moodle(page, target_url, config).await,
+		Site::Caseine => login_caseine(page, target_url, &creds, config).await,
+		Site::UcaMoodle => login_uca_moodle(page, target_url, &creds, config).await,
+		Site::GenericMoodle => login_generic_moodle(page, target_url, &creds).await,
 	}
 }
 
 /// Login flow for caseine.org
 /// Goes directly to target URL, handles enrollment redirect, then OAuth login
-async fn login_caseine(page: &Page, target_url: &str, config: &AppConfig) -> Result<()> {
-	let current_url = page.url().await.ok().flatten().unwrap_or_default();
-
-	// Check if already logged in (landed on target or VPL page)
-	if current_url.contains("/mod/vpl/") && !current_url.contains("login") && !current_url.contains("enrol") {
-		log!("Already logged in, at target page");
+async fn login_caseine(page: &Page, target_url: &str, creds: &Credentials, config: &AppConfig) -> Result<()> {
+	if finish_if_logged_in(page, target_url).await? {
 		return Ok(());
 	}
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
 
 	// Step 1: If on enrollment page, click Continue
 	if current_url.contains("enrol/index.php") {
 		log!("On enrollment page, clicking Continue...");
+		action_delay().await;
 		page.evaluate(
 			r#"
 			(function() {
@@ -66,42 +203,56 @@ async fn login_caseine(page: &Page, target_url: &str, config: &AppConfig) -> Res
 		)
 		.await
 		.map_err(|e| eyre!("Failed to click Continue: {e}"))?;
-		tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+		wait(timings().post_navigation_wait_ms).await;
 	}
 
 	// Step 2: If on login page, click the federation login button
 	let current_url = page.url().await.ok().flatten().unwrap_or_default();
 	if current_url.contains("moodle.caseine.org/login/index.php") {
 		log!("On login page, clicking login button...");
+		action_delay().await;
 		page.evaluate(r#"document.querySelector('a.btn:nth-child(3)').click()"#)
 			.await
 			.map_err(|e| eyre!("Failed to click login button: {e}"))?;
-		tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+		wait(timings().post_navigation_wait_ms).await;
 	}
 
-	// Step 3: Select university from dropdown (if on federation page)
+	// Step 3: Select university from dropdown (if on federation page), once more if the page didn't move on
+	let is_discovery = |url: &str| url.contains("discovery.renater.fr") || url.contains("wayf");
 	let current_url = page.url().await.ok().flatten().unwrap_or_default();
-	if current_url.contains("discovery.renater.fr") || current_url.contains("wayf") {
-		log!("Selecting university from dropdown...");
+	if is_discovery(&current_url) {
+		log!("Selecting {} from dropdown...", config.institution());
 		page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for federation page: {e}"))?;
-		tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-		select_university_from_dropdown(page).await?;
+		wait(timings().step_wait_ms).await;
+		select_university_from_dropdown(page, config.institution()).await?;
+
+		if is_discovery(&page.url().await.ok().flatten().unwrap_or_default()) {
+			log!("Still on the federation page, selecting again...");
+			select_university_from_dropdown(page, config.institution()).await?;
+			let current_url = page.url().await.ok().flatten().unwrap_or_default();
+			if is_discovery(&current_url) {
+				bail!("Selecting {} did not leave the federation page ({current_url})", config.institution());
+			}
+		}
 	}
 
 	// Step 4: Fill UCA CAS login form (if on CAS page)
 	let current_url = page.url().await.ok().flatten().unwrap_or_default();
 	if current_url.contains("ent.uca.fr/cas") {
 		log!("Filling CAS login form...");
-		tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-		fill_and_submit_login_form(page, config).await?;
-		tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+		wait(timings().page_settle_wait_ms).await;
+		fill_and_submit_login_form(page, creds).await?;
+		wait(timings().post_navigation_wait_ms).await;
+		check_login_rejected(page, target_url).await?;
+		submit_totp_if_requested(page, config).await?;
 	}
 
 	// Step 5: Click "Accept" button on SAML consent page (if present)
 	let current_url = page.url().await.ok().flatten().unwrap_or_default();
 	if current_url.contains("idp.uca.fr") {
 		log!("On SAML consent page, clicking Accept...");
-		tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+		wait(timings().page_settle_wait_ms).await;
+		action_delay().await;
 		page.evaluate(
 			r#"
 			(function() {
@@ -112,74 +263,170 @@ async fn login_caseine(page: &Page, target_url: &str, config: &AppConfig) -> Res
 		)
 		.await
 		.ok();
-		tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+		wait(timings().post_navigation_wait_ms).await;
 	}
 
 	let final_url = page.url().await.ok().flatten().unwrap_or_default();
 	log!("Login complete, now at: {final_url}");
 
 	// If not at the target, navigate there (login may have landed on a different page like the homepage)
-	let target_base = target_url.split('?').next().unwrap_or(target_url);
-	let final_base = final_url.split('?').next().unwrap_or(&final_url);
-	if final_base != target_base {
+	if !same_moodle_page(target_url, &final_url) {
 		log!("Not at target yet ({final_url}), navigating to {target_url}...");
 		page.goto(target_url).await.map_err(|e| eyre!("Failed to navigate to target: {e}"))?;
 		page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for target page: {e}"))?;
-
-		let final_url = page.url().await.ok().flatten().unwrap_or_default();
-		let final_base = final_url.split('?').next().unwrap_or(&final_url);
-		if final_base != target_base {
-			bail!("Login failed: expected to be at {target_url}, but at {final_url}");
-		}
 	}
 
-	Ok(())
+	ensure_logged_in_at(page, target_url).await
 }
 
 /// Login flow for moodle2025.uca.fr
 /// Navigated to target URL, gets redirected to CAS login, fills form, gets redirected back to target
-async fn login_uca_moodle(page: &Page, target_url: &str, config: &AppConfig) -> Result<()> {
-	let current_url = page.url().await.ok().flatten().unwrap_or_default();
-
-	// Check if already at target (already logged in)
-	let target_base = target_url.split('?').next().unwrap_or(target_url);
-	let current_base = current_url.split('?').next().unwrap_or(&current_url);
-	if current_base == target_base {
-		log!("Already logged in, at target page");
+async fn login_uca_moodle(page: &Page, target_url: &str, creds: &Credentials, config: &AppConfig) -> Result<()> {
+	if finish_if_logged_in(page, target_url).await? {
 		return Ok(());
 	}
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
 
 	// Handle CAS login (ent.uca.fr/cas)
 	if current_url.contains("ent.uca.fr/cas") {
 		log!("On CAS login page, filling form...");
-		tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
-		fill_and_submit_login_form(page, config).await?;
-		tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+		wait(timings().page_settle_wait_ms).await;
+		fill_and_submit_login_form(page, creds).await?;
+		wait(timings().post_navigation_wait_ms).await;
+		check_login_rejected(page, target_url).await?;
+		submit_totp_if_requested(page, config).await?;
 	}
 
 	// After login, should be redirected back to target
 	let final_url = page.url().await.ok().flatten().unwrap_or_default();
-	let final_base = final_url.split('?').next().unwrap_or(&final_url);
-
-	if final_base == target_base {
-		log!("Login successful, at target page");
-	} else {
+	if !same_moodle_page(target_url, &final_url) {
 		log!("Not at target yet ({final_url}), navigating to {target_url}...");
 		page.goto(target_url).await.map_err(|e| eyre!("Failed to navigate to target: {e}"))?;
 		page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for target page: {e}"))?;
+	}
 
-		let final_url = page.url().await.ok().flatten().unwrap_or_default();
-		let final_base = final_url.split('?').next().unwrap_or(&final_url);
-		if final_base != target_base {
-			bail!("Login failed: expected to be at {target_url}, but at {final_url}");
-		}
+	ensure_logged_in_at(page, target_url).await?;
+	log!("Login successful, at target page");
+	Ok(())
+}
+
+/// Login flow for any Moodle with its own login form
+/// Goes to `<base>/login/index.php`, fills #username/#password, submits, then navigates to the target
+async fn login_generic_moodle(page: &Page, target_url: &str, creds: &Credentials) -> Result<()> {
+	if finish_if_logged_in(page, target_url).await? {
+		return Ok(());
+	}
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
+	if !current_url.contains("/login/index.php") {
+		let login_url = format!("{}/login/index.php", moodle_base_url(target_url));
+		log!("Navigating to login page {login_url}...");
+		page.goto(login_url.as_str()).await.map_err(|e| eyre!("Failed to navigate to login page: {e}"))?;
+		page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for login page: {e}"))?;
 	}
 
+	log!("Filling Moodle login form...");
+	fill_and_submit_login_form(page, creds).await?;
+	wait(timings().post_navigation_wait_ms).await;
+	check_login_rejected(page, target_url).await?;
+
+	let after_login = page.url().await.ok().flatten().unwrap_or_default();
+	if after_login.contains("/login/index.php") {
+		bail!("Login failed: still on the login page ({after_login}) - check username/password");
+	}
+
+	log!("Navigating to {target_url}...");
+	page.goto(target_url).await.map_err(|e| eyre!("Failed to navigate to target: {e}"))?;
+	page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for target page: {e}"))?;
+
+	ensure_logged_in_at(page, target_url).await
+}
+
+/// Whether `current` shows the same Moodle page as `target`, allowing for the redirects Moodle makes on the way
+/// (view.php to the running attempt, added or dropped parameters, a trailing slash): on activity pages the
+/// course module id decides, elsewhere the path
+pub fn same_moodle_page(target: &str, current: &str) -> bool {
+	if url_host(target) != url_host(current) {
+		return false;
+	}
+	match (module_id(target), module_id(current)) {
+		(Some(target_id), Some(current_id)) => target_id == current_id,
+		_ => url_path(target) == url_path(current),
+	}
+}
+
+/// URL without query, fragment and trailing slash
+fn url_path(url: &str) -> &str {
+	let path = url.split(['?', '#']).next().unwrap_or(url);
+	path.strip_suffix('/').unwrap_or(path)
+}
+
+fn query_param<'a>(url: &'a str, name: &str) -> Option<&'a str> {
+	let query = url.split_once('?')?.1.split('#').next()?;
+	query.split('&').find_map(|pair| pair.split_once('=').filter(|(key, _)| *key == name).map(|(_, value)| value))
+}
+
+/// Course module id of an activity URL: `cmid=` on attempt/summary/review pages, `id=` on view.php (and on
+/// every VPL page)
+fn module_id(url: &str) -> Option<&str> {
+	if !url.contains("/mod/") {
+		return None;
+	}
+	query_param(url, "cmid").or_else(|| {
+		let by_id = url_path(url).ends_with("/view.php") || url.contains("/mod/vpl/");
+		by_id.then(|| query_param(url, "id")).flatten()
+	})
+}
+
+/// Whether the page shows Moodle's user menu, i.e. the session is logged in (logged-out pages only have a
+/// "Log in" link there, and so do guests)
+pub async fn is_logged_in(page: &Page) -> bool {
+	let script = r#"
+		(function() {
+			const text = document.body ? document.body.innerText : '';
+			if (/currently using guest access|accès anonyme/i.test(text)) return false;
+			const userId = document.body && document.body.dataset.userid;
+			if (userId && userId !== '0') return true;
+			const menu = document.querySelector('.usermenu, #usermenu, [data-region="usermenu"]');
+			if (!menu || menu.querySelector('.login')) return false;
+			return !!menu.querySelector('#user-menu-toggle, .userbutton, .dropdown-toggle, a[href*="/user/profile.php"]');
+		})()
+	"#;
+	page.evaluate(script).await.ok().and_then(|result| result.value().and_then(|v| v.as_bool())).unwrap_or(false)
+}
+
+/// Fast path for a session that is still valid (persisted profile, restored cookies, connected browser): if the
+/// page is logged in, go on to the target if not there yet. False when a login is needed.
+async fn finish_if_logged_in(page: &Page, target_url: &str) -> Result<bool> {
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
+	// Logged in on the enrollment page, but the flow still has to get past it
+	if is_login_url(&current_url) || current_url.contains("enrol/index.php") || !is_logged_in(page).await {
+		return Ok(false);
+	}
+
+	if same_moodle_page(target_url, &current_url) {
+		log!("Already logged in, at target page");
+		return Ok(true);
+	}
+	log!("Already logged in, navigating to {target_url}...");
+	page.goto(target_url).await.map_err(|e| eyre!("Failed to navigate to target: {e}"))?;
+	page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for target page: {e}"))?;
+	Ok(true)
+}
+
+/// Err unless the page is `target_url` (or where Moodle redirected it) and logged in rather than a guest
+async fn ensure_logged_in_at(page: &Page, target_url: &str) -> Result<()> {
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
+	if !same_moodle_page(target_url, &current_url) {
+		bail!("Login failed: expected to be at {target_url}, but at {current_url}");
+	}
+	if !is_logged_in(page).await {
+		bail!("Login failed: at {current_url}, but not logged in (guest access?)");
+	}
 	Ok(())
 }
 
-/// Select "Université Clermont Auvergne" from the federation dropdown
-async fn select_university_from_dropdown(page: &Page) -> Result<()> {
+/// Select `institution` in the federation dropdown, checking it is the option that actually got selected
+async fn select_university_from_dropdown(page: &Page, institution: &str) -> Result<()> {
 	// Open the select2 dropdown using jQuery API
 	let open_script = r#"
 		(function() {
@@ -191,31 +438,68 @@ async fn select_university_from_dropdown(page: &Page) -> Result<()> {
 		})()
 	"#;
 	page.evaluate(open_script).await.map_err(|e| eyre!("Failed to open dropdown: {e}"))?;
-	tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+	wait(timings().step_wait_ms).await;
 
 	// Type in the search field
-	let type_script = r#"
-		(function() {
+	let type_script = format!(
+		r#"
+		(function() {{
 			const searchInput = document.querySelector('input.select2-search__field');
-			if (searchInput) {
+			if (searchInput) {{
 				searchInput.focus();
-				searchInput.value = "Université Clermont Auvergne";
-				searchInput.dispatchEvent(new Event('input', { bubbles: true }));
+				searchInput.value = {};
+				searchInput.dispatchEvent(new Event('input', {{ bubbles: true }}));
 				return 'typed';
-			}
+			}}
 			return 'search field not found';
+		}})()
+	"#,
+		js_string(institution)
+	);
+	page.evaluate(type_script).await.map_err(|e| eyre!("Failed to type: {e}"))?;
+	wait(timings().page_settle_wait_ms).await;
+
+	// Enter picks the highlighted option, which is only the first match for the search text
+	let options_script = r#"
+		(function() {
+			const highlighted = document.querySelector('.select2-results__option--highlighted');
+			const options = Array.from(document.querySelectorAll('.select2-results__option')).map(o => o.textContent.trim());
+			return JSON.stringify({ highlighted: highlighted ? highlighted.textContent.trim() : null, options: options.slice(0, 5) });
 		})()
 	"#;
-	page.evaluate(type_script).await.map_err(|e| eyre!("Failed to type: {e}"))?;
-	tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+	let result = page.evaluate(options_script).await.map_err(|e| eyre!("Failed to read dropdown options: {e}"))?;
+	let options: serde_json::Value = result.value().and_then(|v| v.as_str()).and_then(|s| serde_json::from_str(s).ok()).unwrap_or_default();
+	let highlighted = options["highlighted"].as_str().unwrap_or_default();
+	if !is_institution(highlighted, institution) {
+		let listed: Vec<&str> = options["options"].as_array().into_iter().flatten().filter_map(|o| o.as_str()).collect();
+		return Err(LoginError::Config(format!(
+			"No \"{institution}\" in the federation dropdown (it offers: {}) - check `institution` in the config",
+			listed.join(", ")
+		))
+		.into());
+	}
 
 	// Press Enter to select the option
 	page.evaluate(r#"document.querySelector('input.select2-search__field').dispatchEvent(new KeyboardEvent('keydown', {key: 'Enter', keyCode: 13, bubbles: true}))"#)
 		.await
 		.map_err(|e| eyre!("Failed to press Enter: {e}"))?;
-	tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+	wait(timings().page_settle_wait_ms).await;
+
+	let selected_script = r#"
+		(function() {
+			const select = document.querySelector('select');
+			if (!select || select.selectedIndex < 0) return '';
+			return select.options[select.selectedIndex].text.trim();
+		})()
+	"#;
+	let result = page.evaluate(selected_script).await.map_err(|e| eyre!("Failed to read the selected institution: {e}"))?;
+	let selected = result.value().and_then(|v| v.as_str()).unwrap_or_default();
+	if !is_institution(selected, institution) {
+		bail!("Federation dropdown selected \"{selected}\" instead of \"{institution}\"");
+	}
 
 	// Click the "Select" button
+	action_delay().await;
 	let btn_result = page
 		.evaluate(
 			r#"
@@ -228,11 +512,6 @@ async fn select_university_from_dropdown(page: &Page) -> Result<()> {
 					return 'clicked: ' + text;
 				}
 			}
-			// Fallback: click any button
-			if (btns.length > 0) {
-				btns[0].click();
-				return 'clicked first button';
-			}
 			return 'no button found';
 		})()
 	"#,
@@ -240,29 +519,73 @@ async fn select_university_from_dropdown(page: &Page) -> Result<()> {
 		.await
 		.map_err(|e| eyre!("Failed to click Select button: {e}"))?;
 	log!("Select button result: {:?}", btn_result.value());
-	tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+	if btn_result.value().and_then(|v| v.as_str()) == Some("no button found") {
+		bail!("No Select button on the federation page");
+	}
+	wait(timings().post_navigation_wait_ms).await;
 
 	Ok(())
 }
 
+/// Whether a dropdown entry is the configured institution (case and surrounding whitespace aside)
+fn is_institution(option: &str, institution: &str) -> bool {
+	option.trim().to_lowercase() == institution.trim().to_lowercase()
+}
+
+/// After submitting credentials: a [`LoginError`] if the login page came back with an error banner (CAS
+/// `#msg.errors`, Moodle's `#loginerrormessage`, ...), so the caller stops instead of submitting them again
+async fn check_login_rejected(page: &Page, target_url: &str) -> Result<()> {
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
+	if !is_login_url(&current_url) {
+		return Ok(());
+	}
+	let script = r#"
+		(function() {
+			const banner = document.querySelector('#msg.errors, #loginerrormessage, .loginerrors, .alert-danger, .alert-error');
+			const bannerText = banner ? banner.textContent.replace(/\s+/g, ' ').trim() : '';
+			const text = (bannerText || (document.body ? document.body.innerText : '')).toLowerCase();
+			if (/verrouill|bloqu|locked|too many|trop de tentatives/.test(text)) return JSON.stringify({ kind: 'locked', message: bannerText });
+			if (bannerText || /mot de passe incorrect|invalid login|invalid credentials|bad credentials/.test(text)) {
+				return JSON.stringify({ kind: 'bad', message: bannerText });
+			}
+			return JSON.stringify({ kind: 'none', message: '' });
+		})()
+	"#;
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to check the login result: {e}"))?;
+	let verdict: serde_json::Value = result.value().and_then(|v| v.as_str()).and_then(|s| serde_json::from_str(s).ok()).unwrap_or_default();
+	let host = url_host(target_url).to_string();
+	let message = match verdict["message"].as_str() {
+		Some(m) if !m.is_empty() => m.to_string(),
+		_ => "login page shown again".to_string(),
+	};
+	match verdict["kind"].as_str() {
+		Some("locked") => Err(LoginError::AccountLocked { host, message }.into()),
+		Some("bad") => Err(LoginError::BadCredentials { host, message }.into()),
+		_ => Ok(()),
+	}
+}
+
 /// Fill username/password and submit the login form
-async fn fill_and_submit_login_form(page: &Page, config: &AppConfig) -> Result<()> {
+/// The fill script embeds the credentials, so it's never logged and its errors are reported without details
+async fn fill_and_submit_login_form(page: &Page, creds: &Credentials) -> Result<()> {
+	action_delay().await;
 	let fill_script = format!(
 		r#"
 		(function() {{
 			const usernameField = document.querySelector('input[name="username"], input[id="username"]');
 			const passwordField = document.querySelector('input[name="password"], input[id="password"], input[type="password"]');
 			if (usernameField && passwordField) {{
-				usernameField.value = "{}";
-				passwordField.value = "{}";
+				usernameField.value = {};
+				passwordField.value = {};
 				return true;
 			}}
 			return false;
 		}})()
 		"#,
-		config.username, config.password
+		js_string(&creds.username),
+		js_string(&creds.password)
 	);
-	page.evaluate(fill_script).await.map_err(|e| eyre!("Failed to fill login form: {e}"))?;
+	page.evaluate(fill_script).await.map_err(|_| eyre!("Failed to fill login form"))?;
 
 	// Submit
 	let submit_script = r#"
@@ -284,3 +607,121 @@ async fn fill_and_submit_login_form(page: &Page, config: &AppConfig) -> Result<(
 
 	Ok(())
 }
+
+/// JS expression finding the one-time-code input of a second-factor page (null if there is none)
+const OTP_INPUT_JS: &str =
+	r#"document.querySelector('input[name="token"], input[autocomplete="one-time-code"], input[name="otp"], input[name="j_otp"], input[name="code"][inputmode="numeric"]')"#;
+
+/// If the page after the credentials is a TOTP prompt, fill in the current code from `totp_secret` and submit.
+/// A rejected code is retried once with the next time window (clock skew).
+async fn submit_totp_if_requested(page: &Page, config: &AppConfig) -> Result<()> {
+	if !otp_input_present(page).await? {
+		return Ok(());
+	}
+	let Some(secret) = config.totp_secret.as_deref() else {
+		return Err(LoginError::Config("Login asks for a one-time code (2FA), but no totp_secret is configured. Set totp_secret in the config or use --manual-login".to_string()).into());
+	};
+
+	let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
+	for (attempt, time) in [now, now + totp::TOTP_STEP_SECS].into_iter().enumerate() {
+		if attempt > 0 {
+			log!("One-time code rejected, retrying with the next time window...");
+		}
+		log!("Submitting one-time code...");
+		let code = js_string(&totp::totp_code(secret, time)?);
+		let script = format!(
+			r#"
+			(function() {{
+				const input = {OTP_INPUT_JS};
+				if (!input) return false;
+				input.value = {code};
+				input.dispatchEvent(new Event('input', {{ bubbles: true }}));
+				const form = input.closest('form');
+				const submit = form ? form.querySelector('button[type="submit"], input[type="submit"]') : null;
+				if (submit) submit.click(); else if (form) form.submit(); else return false;
+				return true;
+			}})()
+			"#
+		);
+		action_delay().await;
+		page.evaluate(script).await.map_err(|e| eyre!("Failed to submit one-time code: {e}"))?;
+		wait(timings().post_navigation_wait_ms).await;
+
+		if !otp_input_present(page).await? {
+			return Ok(());
+		}
+	}
+
+	Err(LoginError::Config("One-time code was rejected twice - check totp_secret and the system clock".to_string()).into())
+}
+
+/// Title of an HTTP 5xx error page ("502 Bad Gateway", ...), which the SSO servers show when overloaded
+async fn server_error_title(page: &Page) -> Option<String> {
+	let title = page.get_title().await.ok().flatten()?;
+	let lower = title.to_lowercase();
+	let is_server_error = ["500", "502", "503", "504", "bad gateway", "service unavailable", "internal server error", "gateway time"]
+		.iter()
+		.any(|marker| lower.contains(marker));
+	is_server_error.then_some(title)
+}
+
+async fn otp_input_present(page: &Page) -> Result<bool> {
+	let result = page
+		.evaluate(format!("!!({OTP_INPUT_JS})"))
+		.await
+		.map_err(|e| eyre!("Failed to check for a one-time code prompt: {e}"))?;
+	Ok(result.value().and_then(|v| v.as_bool()).unwrap_or(false))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn module_id_by_page() {
+		let cases = [
+			("https://m.example.fr/mod/quiz/view.php?id=42", Some("42")),
+			("https://m.example.fr/mod/quiz/view.php?id=42#region-main", Some("42")),
+			("https://m.example.fr/mod/quiz/attempt.php?attempt=9&cmid=42&page=2", Some("42")),
+			("https://m.example.fr/mod/quiz/summary.php?attempt=9&cmid=42", Some("42")),
+			// Attempt pages are only identified by cmid
+			("https://m.example.fr/mod/quiz/attempt.php?attempt=9&id=5", None),
+			("https://m.example.fr/mod/vpl/forms/edit.php?id=7&userid=3", Some("7")),
+			("https://m.example.fr/mod/vpl/view.php?id=7", Some("7")),
+			("https://m.example.fr/course/view.php?id=3", None),
+		];
+		for (url, expected) in cases {
+			assert_eq!(module_id(url), expected, "{url}");
+		}
+	}
+
+	#[test]
+	fn same_moodle_page_table() {
+		let cases = [
+			(
+				"https://m.example.fr/mod/quiz/view.php?id=42",
+				"https://m.example.fr/mod/quiz/attempt.php?attempt=9&cmid=42",
+				true,
+			),
+			(
+				"https://m.example.fr/mod/quiz/view.php?id=42",
+				"https://m.example.fr/mod/quiz/attempt.php?attempt=9&cmid=43",
+				false,
+			),
+			(
+				"https://m.example.fr/mod/quiz/view.php?id=42",
+				"https://m.example.fr/mod/quiz/summary.php?attempt=9&cmid=42#top",
+				true,
+			),
+			("https://m.example.fr/mod/quiz/view.php?id=42", "https://other.example.fr/mod/quiz/view.php?id=42", false),
+			("https://m.example.fr/mod/vpl/view.php?id=7", "https://m.example.fr/mod/vpl/forms/edit.php?id=7&userid=3", true),
+			("https://m.example.fr/mod/vpl/view.php?id=7", "https://m.example.fr/mod/vpl/forms/edit.php?id=8", false),
+			("https://m.example.fr/my/", "https://m.example.fr/my", true),
+			("https://m.example.fr/course/view.php?id=3", "https://m.example.fr/course/view.php?id=3&section=2", true),
+			("https://m.example.fr/my/", "https://m.example.fr/login/index.php", false),
+		];
+		for (target, current, expected) in cases {
+			assert_eq!(same_moodle_page(target, current), expected, "{target} -> {current}");
+		}
+	}
+}
@@ -1,212 +1,697 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
-
-use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::{
+	Page,
+	browser::{Browser, BrowserConfig},
+};
 use chrono::Local;
-use clap::Parser;
-use color_eyre::{Result, eyre::eyre};
+use clap::{Args, Parser, Subcommand};
+use color_eyre::{
+	Result,
+	eyre::{WrapErr, bail, eyre},
+};
 use futures::StreamExt;
-#[cfg(feature = "xdg")]
-use uni_headless::runner::save_page_html;
+use regex::Regex;
 use uni_headless::{
-	config::{AppConfig, SettingsFlags},
+	Question, QuestionKind, UrlTask,
+	answers::{AnswerBook, AnswersFile},
+	api::MoodleWs,
+	config::{AppConfig, ConfirmMode, LoginFlow, SettingsFlags},
+	course::{ActivityFilter, discover_activities, is_course_url},
+	display,
+	emit::{Emitter, Event},
+	export::write_questions,
+	failure::{Failure, FailureKind},
+	images::ImageCache,
 	is_vpl_url,
-	login::{Site, login_and_navigate},
-	runner::{handle_quiz_page, handle_vpl_page},
+	llm::{QuizLlm, validate_model_settings},
+	llm_error::LlmErrorKind,
+	login::{LoginError, Site, is_logged_in, is_login_url, login_and_navigate, same_moodle_page, url_host},
+	parse::{parse_questions_from_html, parse_vpl_from_html},
+	prompts,
+	proxy::Proxy,
+	report::{AnswerSource, ExitStatus, RunReport, UrlKind, UrlOutcome},
+	runner::{collect_quiz_questions, handle_quiz_page, handle_quiz_via_webservice, handle_vpl_page, parse_vpl_page, run_stop_hook},
+	shutdown, stealth,
+	store::{SessionStore, SessionSummary, cleanup_old_sessions, list_sessions, read_index, snapshot_files},
+	terminal_image, timing,
+};
+#[cfg(feature = "xdg")]
+use uni_headless::{
+	answer_cache::AnswerCache,
+	session::{clear_cookies, restore_cookies, save_cookies},
 };
 #[cfg(feature = "xdg")]
 use v_utils::xdg_state_dir;
 use v_utils::{clientside, elog, log};
 
+const EXIT_CODES_HELP: &str = "Exit codes: 0 success, 1 other error, 2 login failed, 3 page could not be parsed, 4 LLM failed after all retries, \
+5 below target (VPL short of full marks, quiz not submitted), 6 browser failure, \
+7 Moodle refused the page (attempt already submitted, not enrolled, error page), 130 interrupted";
+
 #[derive(Debug, Parser)]
 #[command(name = "uni_headless")]
 #[command(about = "Automated Moodle login and navigation", long_about = None)]
-struct Args {
+#[command(after_help = EXIT_CODES_HELP)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+struct Cli {
+	#[command(subcommand)]
+	command: Option<Command>,
+
+	/// Write the built-in LLM prompt templates to DIR, as a starting point for `prompts_dir`, and exit
+	#[arg(long, value_name = "DIR", exclusive = true)]
+	dump_prompts: Option<std::path::PathBuf>,
+
+	/// Without a subcommand, the arguments of `run`: `uni_headless <URL> ...` is `uni_headless run <URL> ...`
+	#[command(flatten)]
+	run: RunArgs,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+	/// Log in and process the URLs (the default)
+	#[command(after_help = EXIT_CODES_HELP)]
+	Run(Box<RunArgs>),
+	/// Log in and print the questions of a page without answering anything, or parse saved HTML
+	#[command(after_help = EXIT_CODES_HELP)]
+	Parse(Box<ParseArgs>),
+	/// Log in and write the questions of the pages to a file without answering anything
+	#[command(after_help = EXIT_CODES_HELP)]
+	Export(Box<ExportArgs>),
+	/// Only log in, reporting whether it worked and how long it took; checks the credentials and login config
+	#[command(after_help = EXIT_CODES_HELP)]
+	LoginCheck(Box<LoginCheckArgs>),
+	/// Inspect the session directories saved by previous runs
+	Sessions {
+		/// Session directories root (default: persist_htmls in the state dir)
+		#[arg(long, value_name = "DIR", global = true)]
+		root: Option<std::path::PathBuf>,
+
+		#[command(subcommand)]
+		command: SessionsCommand,
+	},
+}
+
+#[derive(Debug, Subcommand)]
+enum SessionsCommand {
+	/// Each session with its creation time, the URLs it touched and how they went
+	List,
+	/// The files saved in a session, with the URL of each page snapshot
+	Show { id: String },
+	/// Remove old sessions (what every run does at startup, with 12h)
+	Clean {
+		/// Age past which a session is removed, e.g. `30m`, `12h`, `2d`
+		#[arg(long, value_parser = parse_age, default_value = "12h")]
+		older_than: u64,
+	},
+	/// Run the HTML question parser over every page saved in a session and print the questions found
+	Parse { id: String },
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
 	/// Target URL to navigate to after login
-	target_url: String,
+	#[arg(required = true)]
+	target_url: Option<String>,
 
-	/// Additional URLs to process after the first one succeeds (for VPL: only if 100% grade)
+	/// Additional URLs to process after the first one succeeds (for VPL: only if 100% grade). Prefix one with
+	/// `optional:` (e.g. `optional:https://...`) to go on with the rest even if it fails.
 	#[arg(short = 'd', long = "do-after")]
 	do_after: Vec<String>,
 
+	/// Process every URL even after one fails; the exit code is still nonzero if any did
+	#[arg(long)]
+	continue_on_failure: bool,
+
 	/// Use LLM to answer multi-choice questions
 	#[arg(short, long)]
 	ask_llm: bool,
 
-	/// Debug mode: interpret target_url as path to local HTML file (skips browser)
+	/// Don't check the LLM credentials with a test call at startup (offline --debug-from-html runs)
+	#[arg(long)]
+	skip_llm_check: bool,
+
+	/// Debug mode: interpret target_url as path to local HTML file. On a directory, runs the HTML parser over every
+	/// `.html` file in it (no browser) and prints what it found.
 	#[arg(long)]
 	debug_from_html: bool,
 
+	/// Answer quizzes through the Moodle webservice API (needs `ws_token` in config); falls back to the browser
+	/// when the token lacks the required capabilities
+	#[arg(long)]
+	webservice: bool,
+
+	/// LLM model for both quizzes and VPL code ("fast", "medium" or "slow"), overriding quiz_model/code_model
+	#[arg(long)]
+	model: Option<String>,
+
+	/// Answer quiz questions from this JSON file (see `answers` module docs for the schema).
+	/// Questions missing from it are only sent to the LLM if --ask-llm is also passed.
+	#[arg(long)]
+	answers_file: Option<std::path::PathBuf>,
+
+	/// Write all answers given during this run to this JSON file, in the --answers-file format
+	#[arg(long)]
+	export_answers: Option<std::path::PathBuf>,
+
+	/// Scrape only: parse all questions (every quiz page, or the VPL description and file templates) and
+	/// write them to this file without answering anything. Markdown if the extension is `.md`, JSON otherwise.
+	#[arg(long)]
+	export: Option<std::path::PathBuf>,
+
+	/// Don't reuse LLM answers cached by previous runs (new answers aren't cached either)
+	#[arg(long)]
+	no_cache: bool,
+
+	/// Where to write the JSON run report (default: report.json in the session directory)
+	#[arg(long)]
+	report: Option<std::path::PathBuf>,
+
+	/// Progress output: "human", or "json" for one JSON event per line on stdout; overrides `output` in config
+	#[arg(long)]
+	output: Option<Emitter>,
+
+	/// File whose contents go before every LLM conversation (lecture notes, a glossary); repeat for several, given
+	/// in order. Replaces `context_file` in config.
+	#[arg(long, value_name = "PATH")]
+	context_file: Vec<String>,
+
+	/// Plain text in the terminal, without colors (also when `NO_COLOR` is set)
+	#[arg(long)]
+	no_color: bool,
+
+	/// Confirm quiz answers per page, per question, or per question with editing; overrides `confirm_mode` in config
+	#[arg(long)]
+	confirm_mode: Option<ConfirmMode>,
+
+	/// Only answer these kinds of questions, e.g. `single,multi,match,fill` (`vpl` for VPL code); the others are
+	/// displayed and left to you. Overrides `question_types` in config.
+	#[arg(long, value_delimiter = ',')]
+	question_types: Option<Vec<QuestionKind>>,
+
+	/// Course pages: only run activities whose name matches this regex
+	#[arg(long)]
+	include: Option<Regex>,
+
+	/// Course pages: skip activities whose name matches this regex
+	#[arg(long)]
+	exclude: Option<Regex>,
+
+	/// Course pages: also run activities already marked as done
+	#[arg(long)]
+	include_completed: bool,
+
+	/// Course pages: list the quiz and VPL activities found, without running any
+	#[arg(long)]
+	discover_only: bool,
+
+	/// Print the questions found instead of answering them (`parse`)
+	#[arg(skip)]
+	print_questions: bool,
+
+	#[command(flatten)]
+	browser: BrowserArgs,
+
+	#[command(flatten)]
+	settings: SettingsFlags,
+}
+
+impl RunArgs {
+	/// `run` arguments for scraping `target_url` (and `do_after`) without answering anything: into `export`, or
+	/// printed with `print_questions`
+	fn scrape(target_url: String, do_after: Vec<String>, export: Option<std::path::PathBuf>, print_questions: bool, browser: BrowserArgs, settings: SettingsFlags) -> Self {
+		Self {
+			target_url: Some(target_url),
+			do_after,
+			continue_on_failure: false,
+			ask_llm: false,
+			skip_llm_check: false,
+			debug_from_html: false,
+			webservice: false,
+			model: None,
+			answers_file: None,
+			export_answers: None,
+			export,
+			no_cache: false,
+			report: None,
+			output: None,
+			context_file: Vec::new(),
+			no_color: false,
+			confirm_mode: None,
+			question_types: None,
+			include: None,
+			exclude: None,
+			include_completed: false,
+			discover_only: false,
+			print_questions,
+			browser,
+			settings,
+		}
+	}
+}
+
+#[derive(Args, Debug)]
+struct ParseArgs {
+	/// Page URL, or a saved HTML file or directory of them (parsed without a browser)
+	target: String,
+
+	#[command(flatten)]
+	browser: BrowserArgs,
+
+	#[command(flatten)]
+	settings: SettingsFlags,
+}
+
+#[derive(Args, Debug)]
+struct ExportArgs {
+	/// Page URL (a course page stands for its activities)
+	target_url: String,
+
+	/// More URLs to export the questions of
+	#[arg(short = 'd', long = "do-after")]
+	do_after: Vec<String>,
+
+	/// File to write the questions to: Markdown if the extension is `.md`, JSON otherwise
+	#[arg(short, long)]
+	out: std::path::PathBuf,
+
+	#[command(flatten)]
+	browser: BrowserArgs,
+
+	#[command(flatten)]
+	settings: SettingsFlags,
+}
+
+#[derive(Args, Debug)]
+struct LoginCheckArgs {
+	/// Page to log in to; defaults to the dashboard of `--site`
+	#[arg(required_unless_present = "site")]
+	url: Option<String>,
+
+	/// Site whose login flow to check, when no URL is given
+	#[arg(long, value_enum, conflicts_with = "url")]
+	site: Option<SiteArg>,
+
+	#[command(flatten)]
+	browser: BrowserArgs,
+
+	#[command(flatten)]
+	settings: SettingsFlags,
+}
+
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+enum SiteArg {
+	Caseine,
+	Uca,
+}
+
+impl From<SiteArg> for Site {
+	fn from(site: SiteArg) -> Self {
+		match site {
+			SiteArg::Caseine => Site::Caseine,
+			SiteArg::Uca => Site::UcaMoodle,
+		}
+	}
+}
+
+/// Browser and login options shared by the subcommands that open pages
+#[derive(Args, Debug)]
+struct BrowserArgs {
 	/// Manual login: skip automatic login, wait for user to manually navigate to target URL.
 	/// Requires --visible to be set.
 	#[arg(long)]
 	manual_login: bool,
 
-	#[command(flatten)]
-	settings: SettingsFlags,
+	/// Ignore the saved browser session (cookies from a previous run) and log in from scratch
+	#[arg(long)]
+	fresh_login: bool,
+
+	/// Start with an empty browser profile: clears the `persist_profile` one (a configured `user_data_dir` is left
+	/// alone and just not used this run)
+	#[arg(long)]
+	fresh_profile: bool,
+
+	/// Use an already running Chrome instead of launching one: its DevTools websocket URL, `host:port` or just the
+	/// port of `--remote-debugging-port`. Overrides `browser_ws` from config.
+	#[arg(long)]
+	connect: Option<String>,
+
+	/// Save page HTML snapshots (and meta.json, and the run report unless --report is given) to this directory
+	/// instead of the session directory in the state dir
+	#[arg(long, value_name = "DIR", conflicts_with = "no_save_html")]
+	save_html: Option<std::path::PathBuf>,
+
+	/// Don't save page HTML snapshots or a session directory at all
+	#[arg(long)]
+	no_save_html: bool,
 }
 #[tokio::main]
 async fn main() -> Result<()> {
 	clientside!();
-	let args = Args::parse();
-	let mut config = AppConfig::try_build(args.settings)?;
-	if args.manual_login && !config.visible {
-		panic!("--manual-login requires --visible to be set");
+	let cli = Cli::parse();
+	if let Some(dir) = &cli.dump_prompts {
+		let written = prompts::dump(dir)?;
+		log!("Wrote {} prompt template(s) to {}", written.len(), dir.display());
+		return Ok(());
 	}
-	if config.allow_skip && (config.visible || config.continuation_prompts) {
-		panic!("--allow-skip conflicts with --visible and continuation_prompts=true");
+	match cli.command {
+		Some(Command::Run(args)) => run(*args).await,
+		Some(Command::Parse(args)) => parse(*args).await,
+		Some(Command::Export(args)) => {
+			let ExportArgs {
+				target_url,
+				do_after,
+				out,
+				browser,
+				settings,
+			} = *args;
+			run(RunArgs::scrape(target_url, do_after, Some(out), false, browser, settings)).await
+		}
+		Some(Command::LoginCheck(args)) => login_check(*args).await,
+		Some(Command::Sessions { root, command }) => {
+			if let Err(e) = sessions(root, command) {
+				exit_with(e);
+			}
+			Ok(())
+		}
+		None => run(cli.run).await,
 	}
+}
 
-	// Session ID is just the current time HH:MM:SS
-	let session_id = Local::now().format("%H:%M:%S").to_string();
+async fn run(args: RunArgs) -> Result<()> {
+	let target_url = args.target_url.clone().expect("required by clap");
+	let (mut config, proxy) = load_config(args.settings, &args.browser)?;
+	if let Some(model) = &args.model {
+		config.quiz_model = Some(model.clone());
+		config.code_model = Some(model.clone());
+	}
+	validate_model_settings(&config)?;
+	if config.allow_skip && config.visible {
+		panic!("--allow-skip conflicts with --visible");
+	}
+	if config.dry_run && config.auto_submit {
+		panic!("--dry-run conflicts with --auto-submit");
+	}
+	if let Some(output) = args.output {
+		config.output = output;
+	}
+	if let Some(mode) = args.confirm_mode {
+		config.confirm_mode = mode;
+	}
+	display::init(args.no_color);
+	if !args.context_file.is_empty() {
+		config.context_file = args.context_file.clone();
+	}
+	if let Some(kinds) = args.question_types.clone() {
+		config.question_types = Some(kinds);
+	}
+	if args.discover_only && !is_course_url(&target_url) {
+		bail!("--discover-only needs a course page URL (course/view.php)");
+	}
+	if config.output.is_json() && !(config.auto_submit || config.dry_run) {
+		bail!("JSON output can't answer confirmation prompts: set auto_submit (or dry_run)");
+	}
 
-	log!("Starting Moodle login automation... [session: {session_id}]");
-	log!("Visible mode: {}", config.visible);
+	// A directory of saved pages: parse them all without a browser
+	if args.debug_from_html && std::path::Path::new(&target_url).is_dir() {
+		return parse_saved(std::path::Path::new(&target_url));
+	}
 
-	// Create session-specific HTML directory and cleanup old sessions
-	#[cfg(feature = "xdg")]
-	if !args.debug_from_html {
-		let html_base = xdg_state_dir!("persist_htmls");
-		let session_dir = html_base.join(&session_id);
-		if let Err(e) = std::fs::create_dir_all(&session_dir) {
-			elog!("Failed to create session HTML dir: {}", e);
+	// URL queue: first the target, then do_after URLs
+	let mut urls: Vec<UrlTask> = std::iter::once(&target_url).chain(&args.do_after).map(|arg| UrlTask::parse(arg)).collect();
+
+	// Every URL we log in to needs a username/password, either per-domain or the top-level pair
+	if !args.debug_from_html && !args.browser.manual_login {
+		for UrlTask { url, .. } in &urls {
+			if config.login_flow(url) != Some(LoginFlow::Manual) && config.credentials_for(url).is_none() {
+				let host = url_host(url);
+				bail!("No credentials for {host}: set username/password or [credentials.\"{host}\"] in the config");
+			}
 		}
+	}
 
-		// Write meta.json with creation timestamp
-		let meta = serde_json::json!({
-			"created_at": std::time::SystemTime::now()
-				.duration_since(std::time::UNIX_EPOCH)
-				.unwrap_or_default()
-				.as_secs()
-		});
-		let meta_path = session_dir.join("meta.json");
-		if let Err(e) = std::fs::write(&meta_path, serde_json::to_string_pretty(&meta).unwrap_or_default()) {
-			elog!("Failed to write meta.json: {}", e);
+	let mut answers = AnswerBook {
+		replay: args.answers_file.as_deref().map(AnswersFile::load).transpose()?,
+		export: args.export_answers.as_ref().map(|_| AnswersFile::default()),
+		cache: None,
+	};
+	if let Some(replay) = &answers.replay {
+		log!("Loaded {} answer(s) from answers file", replay.len());
+	}
+	#[cfg(feature = "xdg")]
+	if args.ask_llm && !args.no_cache {
+		let path = xdg_state_dir!("answer_cache").join("answers.json");
+		let cache = AnswerCache::load(path, config.answer_cache_ttl_days * 24 * 60 * 60);
+		if !cache.is_empty() {
+			log!("Loaded {} cached answer(s) from previous runs", cache.len());
+		}
+		answers.cache = Some(cache);
+	}
+	let images = ImageCache::new(&config)?;
+	// One LLM client for the whole run
+	let llm = args.ask_llm.then(|| QuizLlm::new(&config)).transpose()?;
+	// A missing or expired token would otherwise only show once a (possibly timed) quiz attempt is under way
+	if let Some(llm) = &llm
+		&& !args.skip_llm_check
+	{
+		log!("Checking the LLM credentials...");
+		if let Err(e) = llm.check_credentials().await {
+			let kind = match LlmErrorKind::of(&e) {
+				LlmErrorKind::AuthFailed => FailureKind::LlmAuth,
+				_ => FailureKind::Llm,
+			};
+			return Err(e.wrap_err(format!("LLM check failed: {}, or pass --skip-llm-check", llm.credential_hint())).wrap_err(kind));
 		}
+	}
+
+	// Session ID is just the current time HH:MM:SS
+	let session_id = Local::now().format("%H:%M:%S").to_string();
+
+	let store = args.browser.session_store(&session_id, &config, args.debug_from_html);
+	let llm = llm.map(|llm| llm.with_exchange_log(store.exchange_log(&config)));
 
-		// Cleanup old sessions (older than 12 hours)
-		cleanup_old_sessions(&html_base);
+	// Run report: --report, else report.json in the session directory
+	let report_path = args.report.clone().or_else(|| store.report_path());
+	let mut report = RunReport::new(&session_id, report_path);
+
+	log!("Starting Moodle login automation... [session: {session_id}]");
+	log!("Visible mode: {}", config.visible);
+	if let Some(proxy) = &proxy {
+		log!("Proxy: {proxy}");
 	}
 
-	// Configure browser based on visibility flag
-	let browser_config = if config.visible {
-		BrowserConfig::builder().with_head().build().map_err(|e| eyre!("Failed to build browser config: {e}"))?
-	} else {
-		BrowserConfig::builder().build().map_err(|e| eyre!("Failed to build browser config: {e}"))?
-	};
+	// Create the session directory and clean up old sessions
+	store.init();
 
-	// Launch browser
-	let (mut browser, mut handler) = Browser::launch(browser_config).await.map_err(|e| eyre!("Failed to launch browser: {e}"))?;
+	let (mut pages, handle) = start_browser(&config, &args.browser, proxy).await?;
 
-	// Spawn a task to handle browser events
-	let handle = tokio::spawn(async move {
-		while let Some(_event) = handler.next().await {
-			// Silently consume events
-		}
-	});
+	// A course page stands for its quiz and VPL activities, in page order
+	if is_course_url(&urls[0].url) {
+		let page = match open_page(
+			&mut pages,
+			&urls[0].url,
+			&config,
+			args.debug_from_html,
+			args.browser.manual_login,
+			args.browser.fresh_login,
+			&store,
+		)
+		.await
+		{
+			Ok(page) => page,
+			Err(e) => exit_with(e),
+		};
+		let activities = discover_activities(&page).await.unwrap_or_else(|e| exit_with(e.wrap_err(FailureKind::Parse)));
 
-	// Build URL queue: first the target, then do_after URLs
-	// Normalize URLs: add https:// if no scheme is present
-	let normalize_url = |url: String| -> String {
-		if url.starts_with("http://") || url.starts_with("https://") {
-			url
-		} else {
-			format!("https://{url}")
+		log!("Found {} activities on the course page:", activities.len());
+		for activity in &activities {
+			log!("  {activity}");
 		}
-	};
-	let mut urls: Vec<String> = vec![normalize_url(args.target_url.clone())];
-	urls.extend(args.do_after.iter().cloned().map(normalize_url));
+		if args.discover_only {
+			handle.abort();
+			pages.close().await;
+			return Ok(());
+		}
+
+		let filter = ActivityFilter {
+			include: args.include.clone(),
+			exclude: args.exclude.clone(),
+			include_completed: args.include_completed,
+		};
+		let todo: Vec<UrlTask> = activities
+			.iter()
+			.filter(|activity| filter.accepts(activity))
+			.map(|activity| UrlTask {
+				url: activity.url.clone(),
+				optional: false,
+			})
+			.collect();
+		log!("{} of them to do", todo.len());
+		urls.splice(0..1, todo);
+	}
 
 	// Process URLs
+	let mut exported: Option<Vec<Question>> = (args.export.is_some() || args.print_questions).then(Vec::new);
 	let mut processing_error: Option<color_eyre::Report> = None;
 
-	let mut any_failure = false;
-	for (idx, target_url) in urls.iter().enumerate() {
+	// Decides the exit code
+	let mut first_failure: Option<FailureKind> = None;
+	// Set once a URL that isn't optional fails without --continue-on-failure; the rest are skipped
+	let mut blocked = false;
+	for (idx, task) in urls.iter().enumerate() {
+		let target_url = &task.url;
+		let kind = if is_vpl_url(target_url) { UrlKind::Vpl } else { UrlKind::Quiz };
+		if blocked || shutdown::token().is_cancelled() {
+			report.skip_url(target_url, kind);
+			continue;
+		}
+		let may_continue = task.optional || args.continue_on_failure;
 		if idx > 0 {
 			log!("\n========== Processing next URL ({}/{}) ==========", idx + 1, urls.len());
 		}
+		report.start_url(target_url, kind);
+		if let Some(llm) = &llm {
+			llm.select_context_files(target_url);
+		}
 
-		match process_url(&mut browser, target_url, &mut config, args.ask_llm, args.debug_from_html, args.manual_login, &session_id).await {
-			Ok((success, _page)) =>
-				if !success {
-					any_failure = true;
-					if is_vpl_url(target_url) {
-						log!("Stopping - did not get perfect grade on VPL");
-					} else {
-						log!("Stopping - failed to submit answers for quiz");
-					}
-					break;
-				},
-			Err(e) => {
+		let processing = process_url(
+			&mut pages,
+			target_url,
+			&mut config,
+			llm.as_ref(),
+			args.debug_from_html,
+			args.browser.manual_login,
+			args.browser.fresh_login,
+			args.webservice,
+			&mut answers,
+			&images,
+			exported.as_mut(),
+			&mut report,
+			&store,
+		);
+		let result = tokio::select! {
+			biased;
+			result = processing => result,
+			// Whatever it was waiting on (an LLM call, a page load) is dropped
+			_ = shutdown::token().cancelled() => Err(Failure::from(shutdown::interrupted())),
+		};
+		match result {
+			Ok(_page) => report.finish_url(UrlOutcome::Success),
+			Err(failure) if failure.kind == FailureKind::BelowTarget => {
+				report.finish_url(UrlOutcome::Failure);
+				first_failure.get_or_insert(failure.kind);
+				if may_continue {
+					log!("{failure}, continuing with the next URL");
+				} else {
+					log!("Stopping - {failure}");
+					blocked = true;
+				}
+			}
+			Err(failure) => {
 				// Error HTML is saved in process_url
-				processing_error = Some(e);
-				break;
+				let message = failure.to_string();
+				report.finish_url(UrlOutcome::Error(message.clone()));
+				config.output.emit(&Event::Error { message: message.clone() });
+				let login_error = failure.error.downcast_ref::<LoginError>();
+				if let Some(login_error) = login_error {
+					run_stop_hook(&config, llm.as_ref(), &mut report, &format!("Login failed: {login_error}"));
+				}
+				// Going on after rejected credentials would only submit them again
+				if may_continue && failure.kind != FailureKind::Interrupted && login_error.is_none() {
+					elog!("Error on {target_url}: {message}, continuing with the next URL");
+				} else {
+					blocked = true;
+				}
+				first_failure.get_or_insert(failure.kind);
+				// The first error is the one printed at the end
+				processing_error.get_or_insert(failure.error);
 			}
 		}
 	}
 
-	// If there was an error and visible mode, keep browser open for debugging
-	if let Some(ref err) = processing_error {
-		if config.visible {
-			elog!("Error occurred: {err}");
-			log!("Keeping browser open for debugging. Press Ctrl+C to exit...");
+	let exit_code = first_failure.map_or(0, FailureKind::exit_code);
+	report.exit_status = Some(match (&processing_error, first_failure) {
+		(Some(_), _) => ExitStatus::Error,
+		(None, Some(_)) => ExitStatus::Failure,
+		(None, None) => ExitStatus::Success,
+	});
+	report.exit_code = Some(exit_code);
+	if urls.len() > 1 || exit_code != 0 {
+		let table = report.outcome_table();
+		log!("URL outcomes:\n{table}");
+		run_stop_hook(&config, llm.as_ref(), &mut report, &format!("Run finished (exit code {exit_code}):\n{table}"));
+	}
 
-			static SIGINT_COUNT: AtomicUsize = AtomicUsize::new(0);
+	if let (Some(path), Some(questions)) = (&args.export, &exported) {
+		match write_questions(path, questions) {
+			Ok(()) => log!("Exported {} question(s) to {}", questions.len(), path.display()),
+			Err(e) => elog!("{e}"),
+		}
+	}
+	if let (true, Some(questions)) = (args.print_questions, &exported) {
+		println!("{} question(s)", questions.len());
+		for question in questions {
+			println!("\n{question}");
+		}
+	}
+	if let (Some(path), Some(export)) = (&args.export_answers, &answers.export) {
+		match export.save(path) {
+			Ok(()) => log!("Exported {} answer(s) to {}", export.len(), path.display()),
+			Err(e) => elog!("{e}"),
+		}
+	}
 
-			//SAFETY: no
-			unsafe {
-				libc::signal(libc::SIGINT, sigint_handler_err as *const () as libc::sighandler_t);
-			}
+	if let Some(llm) = llm.as_ref().filter(|llm| llm.usage_totals().calls > 0) {
+		log!("LLM usage:\n{}", llm.usage_summary());
+		store.record_llm_usage(&llm.usage_totals());
+	}
 
-			extern "C" fn sigint_handler_err(_: libc::c_int) {
-				std::process::exit(130);
-			}
+	let unsupported = report.count_from(AnswerSource::Unsupported);
+	if unsupported > 0 {
+		elog!("{unsupported} question(s) of unsupported types were left unanswered");
+	}
+	if let Some(summary) = report.question_summary() {
+		log!("Questions: {summary}");
+	}
 
-			while SIGINT_COUNT.load(Ordering::SeqCst) == 0 {
-				tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-			}
+	report.llm_usage = llm.as_ref().map(|llm| llm.usage_totals());
+	if let Some(path) = report.path() {
+		match report.save() {
+			Ok(()) => log!("Run report written to {}", path.display()),
+			Err(e) => elog!("{e}"),
+		}
+	}
+
+	// If there was an error and visible mode, keep browser open for debugging
+	if let Some(ref err) = processing_error {
+		if config.visible {
+			elog!("Error occurred: {err:#}");
+			log!("Keeping browser open for debugging. Press Ctrl+C to exit...");
+			shutdown::token().cancelled().await;
 
+			pages.close().await;
 			handle.abort();
-			let _ = tokio::time::timeout(std::time::Duration::from_secs(2), browser.close()).await;
 		} else {
+			pages.close().await;
 			handle.abort();
-			let _ = tokio::time::timeout(std::time::Duration::from_secs(2), browser.close()).await;
 		}
 
-		return Err(processing_error.unwrap());
+		eprintln!("Error: {:?}", processing_error.unwrap());
+		std::process::exit(exit_code);
 	}
 
 	// Keep browser open in visible mode
 	if config.visible {
 		log!("Browser is visible. Press Ctrl+C to exit...");
+		shutdown::token().cancelled().await;
 
-		static SIGINT_COUNT: AtomicUsize = AtomicUsize::new(0);
-
-		//SAFETY: no
-		unsafe {
-			libc::signal(libc::SIGINT, sigint_handler as *const () as libc::sighandler_t);
-		}
-
-		extern "C" fn sigint_handler(_: libc::c_int) {
-			let count = SIGINT_COUNT.fetch_add(1, Ordering::SeqCst);
-			if count >= 1 {
-				std::process::exit(130);
-			}
-		}
-
-		while SIGINT_COUNT.load(Ordering::SeqCst) == 0 {
-			tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-		}
-
-		log!("Shutting down... (press Ctrl+C again to force exit)");
+		pages.close().await;
 		handle.abort();
-		let _ = tokio::time::timeout(std::time::Duration::from_secs(2), browser.close()).await;
 	} else {
 		tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
+		pages.close().await;
 		handle.abort();
-		let _ = tokio::time::timeout(std::time::Duration::from_secs(2), browser.close()).await;
 
-		if any_failure {
-			std::process::exit(1);
+		if exit_code != 0 {
+			std::process::exit(exit_code);
 		}
 		log!("Task completed successfully!");
 	}
@@ -214,34 +699,316 @@ async fn main() -> Result<()> {
 	Ok(())
 }
 
-/// Process a single URL - returns (success, page) where success indicates if VPL got 100%
+/// Config from the file and flags with the [`BrowserArgs`] overrides applied, and the proxy it sets
+fn load_config(settings: SettingsFlags, browser: &BrowserArgs) -> Result<(AppConfig, Option<Proxy>)> {
+	let mut config = AppConfig::try_build(settings)?;
+	config.resolve_secret_commands()?;
+	let proxy = Proxy::from_config(&config)?;
+	config.timings.validate()?;
+	timing::init(config.timings.clone());
+	terminal_image::init(config.image_renderer);
+	if browser.manual_login && !config.visible {
+		panic!("--manual-login requires --visible to be set");
+	}
+	Ok((config, proxy))
+}
+
+impl BrowserArgs {
+	/// Page HTML snapshots: --save-html DIR, else the state-dir session (not for `local` files)
+	fn session_store(&self, session_id: &str, config: &AppConfig, local: bool) -> SessionStore {
+		if let Some(dir) = &self.save_html {
+			SessionStore::at(dir, session_id)
+		} else if self.no_save_html || local {
+			SessionStore::disabled(session_id)
+		} else {
+			SessionStore::state_dir(session_id)
+		}
+		.with_screenshots(config.save_screenshots)
+	}
+}
+
+/// Launch Chrome, or connect to the running one of `--connect`/`browser_ws`, along with the task consuming its
+/// events (to abort once done)
+async fn start_browser(config: &AppConfig, browser: &BrowserArgs, proxy: Option<Proxy>) -> Result<(PageManager, tokio::task::JoinHandle<()>)> {
+	// From here on Ctrl+C closes the browser instead of leaving it running
+	shutdown::listen();
+
+	let connect = browser.connect.clone().or_else(|| config.browser_ws.clone());
+	let (chrome, mut handler) = match &connect {
+		Some(endpoint) => {
+			if config.visible {
+				elog!("--visible has no effect with --connect: pages open in the connected browser");
+			}
+			if proxy.is_some() {
+				elog!("The proxy only applies to webservice requests with --connect: the connected browser keeps its own settings");
+			}
+			log!("Connecting to the running browser at {endpoint}");
+			match Browser::connect(devtools_url(endpoint)).await {
+				Ok(connected) => connected,
+				Err(e) => exit_with(eyre!("Failed to connect to the browser at {endpoint}: {e}").wrap_err(FailureKind::Browser)),
+			}
+		}
+		None => {
+			// Configure browser based on visibility flag
+			let mut builder = BrowserConfig::builder();
+			if config.visible {
+				builder = builder.with_head();
+			}
+			if let Some(path) = &config.chrome_executable {
+				builder = builder.chrome_executable(path);
+			}
+			if let Some(proxy) = &proxy {
+				builder = builder.args(proxy.chrome_args());
+			}
+			if let Some(dir) = profile_dir(config, browser.fresh_profile) {
+				log!("Browser profile: {}", dir.display());
+				builder = builder.user_data_dir(dir);
+			}
+			let browser_config = builder.args(&config.chrome_args).build().map_err(|e| eyre!("Failed to build browser config: {e}"))?;
+
+			match Browser::launch(browser_config).await {
+				Ok(launched) => launched,
+				Err(e) => exit_with(eyre!("Failed to launch browser: {e}").wrap_err(FailureKind::Browser)),
+			}
+		}
+	};
+
+	// Spawn a task to handle browser events
+	let handle = tokio::spawn(async move {
+		while let Some(_event) = handler.next().await {
+			// Silently consume events
+		}
+	});
+
+	// A connected browser is the user's own, not headless
+	let stealth_language = (config.stealth && !config.visible && connect.is_none()).then(|| config.browser_language().to_string());
+	let pages = PageManager::new(chrome, connect.is_some(), stealth_language, proxy.filter(|_| connect.is_none()));
+	Ok((pages, handle))
+}
+
+/// Process a single URL. Not getting 100% on a VPL or not submitting the quiz comes back as a
+/// [`FailureKind::BelowTarget`] failure, everything else by the kind its error was tagged with.
+#[allow(clippy::too_many_arguments)]
 async fn process_url(
-	browser: &mut Browser,
+	pages: &mut PageManager,
 	target_url: &str,
 	config: &mut AppConfig,
-	ask_llm: bool,
+	llm: Option<&QuizLlm>,
 	debug_from_html: bool,
 	manual_login: bool,
-	session_id: &str,
-) -> Result<(bool, chromiumoxide::Page)> {
-	// Create/navigate to page
+	fresh_login: bool,
+	webservice: bool,
+	answers: &mut AnswerBook,
+	images: &ImageCache,
+	exported: Option<&mut Vec<Question>>,
+	report: &mut RunReport,
+	store: &SessionStore,
+) -> Result<Page, Failure> {
+	if webservice && !debug_from_html && exported.is_none() && !is_vpl_url(target_url) {
+		match MoodleWs::from_config(config, target_url) {
+			Some(ws) => {
+				// Questions are parsed from the webservice HTML; the page is only used to fetch their images
+				let page = pages.open("about:blank").await?;
+				match handle_quiz_via_webservice(&page, &ws, target_url, llm, answers, images, config, report).await? {
+					Some(true) => return Ok(page),
+					Some(false) => return Err(Failure::below_target("failed to submit answers for quiz")),
+					None => {
+						log!("Webservice unavailable for this quiz, falling back to the browser");
+					}
+				}
+			}
+			None => log!("--webservice needs ws_token in config, using the browser"),
+		}
+	}
+
+	let page = open_page(pages, target_url, config, debug_from_html, manual_login, fresh_login, store).await?;
+
+	// Save the page HTML for debugging
+	if let Err(e) = store.save_page_snapshot(&page).await {
+		elog!("Failed to save page HTML: {}", e);
+	}
+
+	// Check if this is a VPL page
+	let is_vpl = if debug_from_html {
+		target_url.contains("vpl") || target_url.contains("VPL")
+	} else {
+		is_vpl_url(target_url)
+	};
+
+	// Export mode: collect questions instead of answering them
+	if let Some(exported) = exported {
+		let questions = if is_vpl {
+			parse_vpl_page(&page, config).await?.into_iter().collect()
+		} else {
+			collect_quiz_questions(&page, config).await?
+		};
+		log!("Parsed {} question(s)", questions.len());
+		exported.extend(questions);
+		return Ok(page);
+	}
+
+	let result = if is_vpl {
+		log!("Detected VPL (Virtual Programming Lab) page");
+		handle_vpl_page(&page, llm, images, config, report, store).await
+	} else {
+		handle_quiz_page(&page, llm, answers, images, config, report, store).await
+	};
+
+	match result {
+		Ok(true) => Ok(page),
+		Ok(false) => Err(Failure::below_target(if is_vpl {
+			"did not get perfect grade on VPL"
+		} else {
+			"failed to submit answers for quiz"
+		})),
+		Err(e) => {
+			// Save error page HTML before returning error
+			if let Err(save_err) = store.save_error_snapshot(&page).await {
+				elog!("Failed to save error page HTML: {save_err}");
+			}
+			Err(e.into())
+		}
+	}
+}
+
+/// The one tab the run works in. Each URL is navigated to in it rather than in a new tab, so a long `--do-after`
+/// queue doesn't pile up tabs holding whole quiz DOMs.
+struct PageManager {
+	browser: Browser,
+	page: Option<Page>,
+	/// The browser was already running (`--connect`): it's left open on shutdown, only our tab is closed
+	connected: bool,
+	/// `Accept-Language` to set up stealth with on the tab, None to not
+	stealth_language: Option<String>,
+	/// Proxy the launched browser goes through, whose auth challenges the tab answers
+	proxy: Option<Proxy>,
+}
+
+impl PageManager {
+	fn new(browser: Browser, connected: bool, stealth_language: Option<String>, proxy: Option<Proxy>) -> Self {
+		Self {
+			browser,
+			page: None,
+			connected,
+			stealth_language,
+			proxy,
+		}
+	}
+
+	/// Navigate the tab to `url`, opening it first if there is none yet (or the old one stopped responding)
+	async fn open(&mut self, url: &str) -> Result<Page> {
+		if let Some(page) = self.page.take() {
+			match page.goto(url).await {
+				Ok(_) => {
+					self.page = Some(page.clone());
+					return Ok(page);
+				}
+				Err(e) => {
+					elog!("Could not reuse the open tab ({e}), opening a new one");
+					let _ = page.close().await;
+				}
+			}
+		}
+		// Stealth and proxy auth have to be set up before the first navigation, for the login pages to see them
+		let page = self
+			.browser
+			.new_page("about:blank")
+			.await
+			.map_err(|e| eyre!("Failed to create new page: {e}").wrap_err(FailureKind::Browser))?;
+		if let Some(language) = &self.stealth_language
+			&& let Err(e) = stealth::apply(&page, language).await
+		{
+			elog!("Failed to set up stealth mode: {e}");
+		}
+		if let Some(proxy) = &self.proxy {
+			proxy.handle_auth(&page).await.map_err(|e| e.wrap_err(FailureKind::Browser))?;
+		}
+		if url != "about:blank" {
+			page.goto(url).await.map_err(|e| eyre!("Failed to navigate to {url}: {e}").wrap_err(FailureKind::Browser))?;
+		}
+		self.page = Some(page.clone());
+		Ok(page)
+	}
+
+	/// Close the browser, and with it the tab. A connected browser is only detached from, after closing our tab.
+	async fn close(&mut self) {
+		let page = self.page.take();
+		if self.connected {
+			if let Some(page) = page {
+				let _ = tokio::time::timeout(std::time::Duration::from_secs(2), page.close()).await;
+			}
+			return;
+		}
+		let _ = tokio::time::timeout(std::time::Duration::from_secs(2), self.browser.close()).await;
+	}
+}
+
+/// Profile directory to launch Chrome with: `user_data_dir`, else the state-dir one if `persist_profile` is set
+fn profile_dir(config: &AppConfig, fresh: bool) -> Option<std::path::PathBuf> {
+	if let Some(dir) = &config.user_data_dir {
+		if fresh {
+			log!("--fresh-profile: not using user_data_dir {dir} this run");
+			return None;
+		}
+		return Some(std::path::PathBuf::from(dir));
+	}
+	if !config.persist_profile {
+		return None;
+	}
+	#[cfg(feature = "xdg")]
+	{
+		let dir = xdg_state_dir!("profile");
+		if fresh {
+			match std::fs::remove_dir_all(&dir) {
+				Ok(()) => log!("Cleared the saved browser profile"),
+				Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+				Err(e) => elog!("Failed to clear the saved browser profile: {e}"),
+			}
+		}
+		Some(dir)
+	}
+	#[cfg(not(feature = "xdg"))]
+	{
+		let _ = fresh;
+		elog!("persist_profile needs the xdg feature, set user_data_dir instead");
+		None
+	}
+}
+
+/// `--connect` endpoint as `Browser::connect` takes it: websocket and http URLs as they are, `host:port` and bare
+/// ports as the http endpoint it reads the websocket URL from
+fn devtools_url(endpoint: &str) -> String {
+	if endpoint.contains("://") {
+		endpoint.to_string()
+	} else if endpoint.parse::<u16>().is_ok() {
+		format!("http://127.0.0.1:{endpoint}")
+	} else {
+		format!("http://{endpoint}")
+	}
+}
+
+/// Open a page on `target_url`, logging in first if needed (or opening the local file in debug mode)
+async fn open_page(pages: &mut PageManager, target_url: &str, config: &AppConfig, debug_from_html: bool, manual_login: bool, fresh_login: bool, store: &SessionStore) -> Result<Page> {
 	let page = if debug_from_html {
-		let file_url = format!("file://{target_url}");
+		// A relative path would resolve against nothing and leave the tab on about:blank
+		let path = std::fs::canonicalize(target_url).map_err(|e| eyre!("Failed to open {target_url}: {e}"))?;
+		let file_url = format!("file://{}", path.display());
 		log!("Debug mode: opening local file {file_url}");
-		let page = browser.new_page(&file_url).await.map_err(|e| eyre!("Failed to open file: {e}"))?;
+		let page = pages.open(&file_url).await.map_err(|e| e.wrap_err("Failed to open file"))?;
 		tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
 		page
-	} else if manual_login {
+	} else if manual_login || config.login_flow(target_url) == Some(LoginFlow::Manual) {
+		if !config.visible {
+			bail!("Manual login for {} requires --visible", url_host(target_url));
+		}
 		log!("Manual login mode: waiting for you to navigate to target URL...");
 		log!("Target: {target_url}");
 
-		let page = browser.new_page(target_url).await.map_err(|e| eyre!("Failed to create new page: {e}"))?;
+		let page = pages.open(target_url).await?;
 
-		let target_base = target_url.split('?').next().unwrap_or(target_url);
 		loop {
 			let current_url = page.url().await.ok().flatten().unwrap_or_default();
-			let current_base = current_url.split('?').next().unwrap_or(&current_url);
-			if current_base == target_base {
+			if same_moodle_page(target_url, &current_url) {
 				log!("Target URL reached");
 				break;
 			}
@@ -249,95 +1016,269 @@ async fn process_url(
 		}
 		page
 	} else {
-		let site = Site::detect(target_url);
+		let site = Site::resolve(target_url, config);
 		log!("Detected site: {}", site.name());
 
-		let start_url = target_url.to_string();
+		let page = pages.open("about:blank").await?;
 
-		let page = browser.new_page(&start_url).await.map_err(|e| eyre!("Failed to create new page: {e}"))?;
-		page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for initial page load: {e}"))?;
+		// Restore the saved session before the first navigation, so the target may load without any login
+		#[cfg(feature = "xdg")]
+		let restored = if fresh_login {
+			0
+		} else {
+			restore_cookies(&page, url_host(target_url)).await.unwrap_or_else(|e| {
+				elog!("Failed to restore saved session: {e}");
+				0
+			})
+		};
+		#[cfg(not(feature = "xdg"))]
+		let restored = {
+			let _ = fresh_login;
+			0
+		};
+
+		page.goto(target_url)
+			.await
+			.map_err(|e| eyre!("Failed to navigate to target: {e}").wrap_err(FailureKind::Browser))?;
+		page.wait_for_navigation()
+			.await
+			.map_err(|e| eyre!("Failed waiting for initial page load: {e}").wrap_err(FailureKind::Browser))?;
+
+		let current_url = page.url().await.ok().flatten().unwrap_or_default();
+		// Already logged in: the restored session, an earlier URL of this run, or a browser we connected to
+		let logged_in = !is_login_url(&current_url) && same_moodle_page(target_url, &current_url) && is_logged_in(&page).await;
+		if logged_in && restored > 0 {
+			log!("Restored saved session ({restored} cookies), skipping login");
+		} else if logged_in {
+			log!("Already logged in, skipping login");
+		} else {
+			if restored > 0 {
+				log!("Saved session is no longer valid, logging in normally...");
+				#[cfg(feature = "xdg")]
+				clear_cookies(url_host(target_url));
+			}
+			login_and_navigate(&page, site, target_url, config, store).await.wrap_err(FailureKind::Login)?;
 
-		login_and_navigate(&page, site, target_url, config).await?;
+			#[cfg(feature = "xdg")]
+			match save_cookies(&page, url_host(target_url)).await {
+				Ok(count) => log!("Saved session ({count} cookies) for next run"),
+				Err(e) => elog!("Failed to save session: {e}"),
+			}
+		}
 		page
 	};
 
 	let final_url = page.url().await.map_err(|e| eyre!("Failed to get final URL: {e}"))?;
 	log!("Successfully navigated to: {final_url:?}");
+	Ok(page)
+}
 
-	// Save the page HTML for debugging
-	#[cfg(feature = "xdg")]
-	if let Err(e) = save_page_html(&page, session_id).await {
-		elog!("Failed to save page HTML: {}", e);
-	}
+/// Print `error` and exit with the code of the failure class it was tagged with
+fn exit_with(error: color_eyre::Report) -> ! {
+	eprintln!("Error: {error:?}");
+	std::process::exit(FailureKind::of(&error).exit_code())
+}
 
-	// Check if this is a VPL page
-	let is_vpl = if debug_from_html {
-		target_url.contains("vpl") || target_url.contains("VPL")
-	} else {
-		is_vpl_url(target_url)
+/// `sessions` subcommand
+fn sessions(root: Option<std::path::PathBuf>, command: SessionsCommand) -> Result<()> {
+	let Some(root) = root.or_else(SessionStore::state_root) else {
+		bail!("No session directory without the xdg feature: pass --root");
 	};
-
-	let result = if is_vpl {
-		log!("Detected VPL (Virtual Programming Lab) page");
-		handle_vpl_page(&page, ask_llm, config, session_id).await
-	} else {
-		handle_quiz_page(&page, ask_llm, config, session_id).await
+	let session_dir = |id: &str| {
+		let dir = root.join(id);
+		if dir.is_dir() { Ok(dir) } else { Err(eyre!("No session {id} in {}", root.display())) }
 	};
 
-	match result {
-		Ok(success) => Ok((success, page)),
-		Err(e) => {
-			// Save error page HTML before returning error
-			#[cfg(feature = "xdg")]
-			if let Err(save_err) = save_page_html(&page, session_id).await {
-				elog!("Failed to save error page HTML: {save_err}");
+	match command {
+		SessionsCommand::List => {
+			let sessions = list_sessions(&root);
+			if sessions.is_empty() {
+				log!("No sessions in {}", root.display());
+			}
+			for session in sessions {
+				print_session(&session);
 			}
-			Err(e)
+		}
+		SessionsCommand::Show { id } => {
+			let dir = session_dir(&id)?;
+			print_session(&SessionSummary::read(&dir));
+			let index = read_index(&dir);
+			let mut files: Vec<_> = std::fs::read_dir(&dir)?.flatten().map(|e| e.path()).collect();
+			files.sort();
+			for file in files {
+				let name = file.file_name().unwrap_or_default().to_string_lossy().into_owned();
+				let size = file.metadata().map(|m| m.len()).unwrap_or_default();
+				let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+				match index.iter().find(|(s, _)| *s == stem) {
+					Some((_, url)) => println!("  {name} ({size} B)  {url}"),
+					None => println!("  {name} ({size} B)"),
+				}
+			}
+		}
+		SessionsCommand::Clean { older_than } => {
+			let removed = cleanup_old_sessions(&root, older_than);
+			log!("Removed {removed} session(s)");
+		}
+		SessionsCommand::Parse { id } => {
+			let dir = session_dir(&id)?;
+			let index = read_index(&dir);
+			let pages = snapshot_files(&dir)?
+				.into_iter()
+				.map(|file| {
+					let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+					let url = index.iter().find(|(s, _)| *s == stem).map(|(_, url)| url.clone());
+					(file, url)
+				})
+				.collect::<Vec<_>>();
+			parse_html_files(&pages)?;
 		}
 	}
+	Ok(())
 }
 
-/// Cleanup session directories older than 12 hours
-#[cfg(feature = "xdg")]
-fn cleanup_old_sessions(html_base: &std::path::Path) {
-	let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
-	let max_age_secs = 12 * 60 * 60; // 12 hours
+/// `parse` subcommand: saved HTML goes through the HTML parser, a URL through the browser
+async fn parse(args: ParseArgs) -> Result<()> {
+	let path = std::path::Path::new(&args.target);
+	if path.exists() {
+		return parse_saved(path);
+	}
+	run(RunArgs::scrape(args.target, Vec::new(), None, true, args.browser, args.settings)).await
+}
 
-	let Ok(entries) = std::fs::read_dir(html_base) else {
-		return;
+/// `login-check` subcommand
+async fn login_check(args: LoginCheckArgs) -> Result<()> {
+	let url = match (args.url, args.site) {
+		(Some(url), _) => url,
+		(None, Some(site)) => Site::from(site).home_url().expect("--site only offers sites with a known dashboard").to_string(),
+		(None, None) => unreachable!("clap requires a URL or --site"),
 	};
+	let (config, proxy) = load_config(args.settings, &args.browser)?;
+	let session_id = Local::now().format("%H:%M:%S").to_string();
+	let store = args.browser.session_store(&session_id, &config, false);
+	store.init();
+	let (mut pages, handle) = start_browser(&config, &args.browser, proxy).await?;
 
-	for entry in entries.flatten() {
-		let path = entry.path();
-		if !path.is_dir() {
-			continue;
+	log!("Checking the login to {url} ({})", Site::resolve(&url, &config).name());
+	let started = std::time::Instant::now();
+	// A saved session would skip the very flow being checked
+	let result = open_page(&mut pages, &url, &config, false, args.browser.manual_login, true, &store).await;
+	let elapsed = started.elapsed().as_secs_f64();
+	pages.close().await;
+	handle.abort();
+
+	match result {
+		Ok(_) => {
+			log!("Login OK ({elapsed:.1}s)");
+			Ok(())
 		}
+		Err(e) => {
+			elog!("Login failed ({elapsed:.1}s)");
+			exit_with(e)
+		}
+	}
+}
+
+/// Run the HTML parser over a saved page, or every `.html` file of a directory, exiting with the parse failure code
+/// if any of them fails
+fn parse_saved(path: &std::path::Path) -> Result<()> {
+	let files = if path.is_dir() { snapshot_files(path)? } else { vec![path.to_path_buf()] };
+	if files.is_empty() {
+		bail!("No .html files in {}", path.display());
+	}
+	let pages: Vec<_> = files.into_iter().map(|file| (file, None)).collect();
+	if let Err(e) = parse_html_files(&pages) {
+		exit_with(e);
+	}
+	Ok(())
+}
 
-		let meta_path = path.join("meta.json");
-		let created_at = if meta_path.exists() {
-			// Read created_at from meta.json
-			std::fs::read_to_string(&meta_path)
-				.ok()
-				.and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
-				.and_then(|v| v["created_at"].as_u64())
+/// Run the HTML question parser over saved pages, given with the URL they were saved from if known, printing the
+/// questions of each and then a summary. Fails if any page couldn't be parsed.
+fn parse_html_files(pages: &[(std::path::PathBuf, Option<String>)]) -> Result<()> {
+	let mut by_kind: Vec<(QuestionKind, usize)> = QuestionKind::ALL.into_iter().map(|kind| (kind, 0)).collect();
+	let mut empty = Vec::new();
+	let mut failed = 0;
+	for (file, url) in pages {
+		let name = file.file_name().unwrap_or_default().to_string_lossy();
+		println!("==================== {name} ====================");
+		if let Some(url) = url {
+			println!("{url}");
+		}
+		let html = std::fs::read_to_string(file).map_err(|e| eyre!("Failed to read {}: {e}", file.display()))?;
+		let is_vpl = url.as_deref().map_or_else(|| name.to_lowercase().contains("vpl"), is_vpl_url);
+		let parsed = if is_vpl {
+			parse_vpl_from_html(&html).map(|q| q.into_iter().collect::<Vec<_>>())
 		} else {
-			// Fallback: use directory modification time
-			entry
-				.metadata()
-				.ok()
-				.and_then(|m| m.modified().ok())
-				.and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
-				.map(|d| d.as_secs())
+			parse_questions_from_html(&html)
 		};
-
-		if let Some(created_at) = created_at
-			&& now.saturating_sub(created_at) > max_age_secs
-		{
-			if let Err(e) = std::fs::remove_dir_all(&path) {
-				elog!("Failed to cleanup old session {}: {}", path.display(), e);
-			} else {
-				log!("Cleaned up old session: {}", path.file_name().unwrap_or_default().to_string_lossy());
+		match parsed {
+			Ok(questions) => {
+				println!("{} question(s)", questions.len());
+				if questions.is_empty() {
+					empty.push(name.into_owned());
+				}
+				for question in questions {
+					if let Some((_, count)) = by_kind.iter_mut().find(|(kind, _)| *kind == question.kind()) {
+						*count += 1;
+					}
+					println!("\n{question}");
+				}
+			}
+			Err(e) => {
+				elog!("Failed to parse {}: {e}", file.display());
+				failed += 1;
 			}
 		}
+		println!();
+	}
+
+	let total: usize = by_kind.iter().map(|(_, count)| count).sum();
+	println!("==================== Summary ====================");
+	println!("Files processed: {}", pages.len());
+	println!("Questions: {total}");
+	for (kind, count) in by_kind.iter().filter(|(_, count)| *count > 0) {
+		println!("  {kind:<12} {count}");
 	}
+	if !empty.is_empty() {
+		println!("No questions found in {} file(s):", empty.len());
+		for name in &empty {
+			println!("  {name}");
+		}
+	}
+	if failed > 0 {
+		return Err(eyre!("{failed} file(s) failed to parse").wrap_err(FailureKind::Parse));
+	}
+	Ok(())
+}
+
+/// One `sessions list` entry: id, creation time and outcome, then the URLs
+fn print_session(session: &SessionSummary) {
+	let created = session
+		.created_at
+		.and_then(|secs| chrono::DateTime::from_timestamp(secs as i64, 0))
+		.map(|t| t.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S").to_string())
+		.unwrap_or_else(|| "unknown time".to_string());
+	let status = session.exit_status.as_deref().unwrap_or("unfinished");
+	println!("{}  created {created}  {status}", session.id);
+	for (url, outcome) in &session.urls {
+		match outcome {
+			Some(outcome) => println!("    {outcome}: {url}"),
+			None => println!("    {url}"),
+		}
+	}
+}
+
+/// Age like `90s`, `30m`, `12h` or `2d` (plain numbers are seconds), in seconds
+fn parse_age(s: &str) -> Result<u64, String> {
+	let s = s.trim();
+	let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+	let number: u64 = number.parse().map_err(|_| format!("invalid age {s:?}, expected e.g. 30m, 12h or 2d"))?;
+	let multiplier = match unit {
+		"" | "s" => 1,
+		"m" => 60,
+		"h" => 60 * 60,
+		"d" => 24 * 60 * 60,
+		_ => return Err(format!("unknown unit {unit:?} in {s:?}, expected s, m, h or d")),
+	};
+	Ok(number * multiplier)
 }
@@ -0,0 +1,54 @@
+//! What a question is worth, from the grade line of its info block (`.info .grade`): "Marked out of 2.00",
+//! "Mark 1.00 out of 2.00", "Noté sur 2,00", "Note de 1,00 sur 2,00"
+
+use std::fmt;
+
+/// Both None for descriptions and ungraded questions, which have no grade line
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct QuestionMarks {
+	/// What the question is worth
+	pub max: Option<f64>,
+	/// The mark it got, once graded
+	pub current: Option<f64>,
+}
+
+impl QuestionMarks {
+	/// The last number in the text is the maximum, a number before it the current mark; decimal commas are read as
+	/// decimal points
+	pub fn parse(text: &str) -> Self {
+		let numbers: Vec<f64> = text
+			.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+			.map(|word| word.trim_matches(['.', ',']).replace(',', "."))
+			.filter_map(|word| word.parse().ok())
+			.collect();
+		match numbers[..] {
+			[] => Self::default(),
+			[max] => Self { max: Some(max), current: None },
+			[.., current, max] => Self {
+				max: Some(max),
+				current: Some(current),
+			},
+		}
+	}
+
+	/// For ordering: questions without a grade line count as worth nothing
+	pub fn weight(&self) -> f64 {
+		self.max.unwrap_or(0.0)
+	}
+}
+
+/// "2.0 pts", "1.5/2.0 pts"; empty without a maximum
+impl fmt::Display for QuestionMarks {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let Some(max) = self.max else { return Ok(()) };
+		if let Some(current) = self.current {
+			write!(f, "{}/", format_mark(current))?;
+		}
+		write!(f, "{} pts", format_mark(max))
+	}
+}
+
+/// One decimal, two when the second one isn't 0 (0.25)
+fn format_mark(mark: f64) -> String {
+	if (mark * 10.0).fract().abs() < 1e-9 { format!("{mark:.1}") } else { format!("{mark:.2}") }
+}
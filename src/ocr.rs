@@ -0,0 +1,70 @@
+//! OCR fallback for question images: an image that can't be fetched through the page (CORS on `pluginfile.php` after
+//! session quirks) is downloaded outside it with the browser's cookies and run through the configured `ocr_cmd`, so
+//! the LLM gets its text rather than nothing
+
+use std::{
+	path::PathBuf,
+	sync::atomic::{AtomicUsize, Ordering},
+	time::Duration,
+};
+
+use chromiumoxide::{Page, cdp::browser_protocol::network::GetCookiesParams};
+use color_eyre::{Result, eyre::eyre};
+use reqwest::header::{COOKIE, USER_AGENT};
+
+/// How long a single OCR command may run
+const OCR_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Download the image at `url` (absolute) without going through the page, sending the browser's cookies for it
+pub async fn download_image(page: &Page, client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+	let cookies = page
+		.execute(GetCookiesParams::builder().urls(vec![url.to_string()]).build())
+		.await
+		.map_err(|e| eyre!("Failed to read browser cookies: {e}"))?
+		.result
+		.cookies;
+	let cookie_header = cookies.iter().map(|c| format!("{}={}", c.name, c.value)).collect::<Vec<_>>().join("; ");
+	let user_agent = page
+		.user_agent()
+		.await
+		.map_err(|e| eyre!("Failed to read the user agent: {e}"))?
+		.replace("HeadlessChrome", "Chrome");
+
+	let response = client
+		.get(url)
+		.header(COOKIE, cookie_header)
+		.header(USER_AGENT, user_agent)
+		.send()
+		.await
+		.and_then(|r| r.error_for_status())
+		.map_err(|e| eyre!("Failed to download {url}: {e}"))?;
+	let bytes = response.bytes().await.map_err(|e| eyre!("Failed to download {url}: {e}"))?;
+	Ok(bytes.to_vec())
+}
+
+fn fresh_image_path() -> PathBuf {
+	static COUNTER: AtomicUsize = AtomicUsize::new(0);
+	let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+	std::env::temp_dir().join(format!("uni_headless_ocr_{}_{n}", std::process::id()))
+}
+
+/// Run `cmd` on the image, `{file}` replaced with its path, and return what it printed. None if that's blank.
+pub async fn run_ocr(cmd: &str, image: &[u8]) -> Result<Option<String>> {
+	let path = fresh_image_path();
+	std::fs::write(&path, image).map_err(|e| eyre!("Failed to write {}: {e}", path.display()))?;
+	let quoted = format!("'{}'", path.display().to_string().replace('\'', r"'\''"));
+	let cmd = cmd.replace("{file}", &quoted);
+	let output = tokio::time::timeout(OCR_TIMEOUT, tokio::process::Command::new("sh").arg("-c").arg(&cmd).output()).await;
+	let _ = std::fs::remove_file(&path);
+
+	let output = match output {
+		Err(_) => return Err(eyre!("`{cmd}` timed out after {}s", OCR_TIMEOUT.as_secs())),
+		Ok(Err(e)) => return Err(eyre!("Failed to run `{cmd}`: {e}")),
+		Ok(Ok(output)) => output,
+	};
+	if !output.status.success() {
+		return Err(eyre!("`{cmd}` failed: {}", String::from_utf8_lossy(&output.stderr).trim_end()));
+	}
+	let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+	Ok((!text.is_empty()).then_some(text))
+}
@@ -0,0 +1,138 @@
+//! What Moodle actually served: the page asked for, or one of its error pages standing in for it
+//!
+//! A wrong quiz URL, an attempt already submitted or a lapsed enrolment all get a page without questions, which
+//! would otherwise end up as "No questions found". [`classify_page`] recognizes them from the URL and markup, so the
+//! run can stop with what went wrong instead.
+
+use std::fmt;
+
+use chromiumoxide::Page;
+use color_eyre::{Result, eyre::eyre};
+use scraper::{Html, Selector};
+
+use crate::{answers::normalize_whitespace, failure::FailureKind, login::is_login_url};
+
+/// Texts of the error pages that have a state of their own, lowercased, English and French
+const ALREADY_SUBMITTED: &[&str] = &[
+	"this attempt has already been finished",
+	"this attempt has already been submitted",
+	"cette tentative a déjà été envoyée",
+	"cette tentative est déjà terminée",
+];
+const NOT_ENROLLED: &[&str] = &[
+	"you cannot enrol yourself in this course",
+	"you are not enrolled",
+	"not enrolled in this course",
+	"vous ne pouvez pas vous inscrire vous-même à ce cours",
+	"vous n'êtes pas inscrit",
+];
+const GUEST_ACCESS: &[&str] = &["currently using guest access", "accès anonyme"];
+const NO_PERMISSION: &[&str] = &[
+	"you do not currently have permissions to do that",
+	"you are not allowed",
+	"vous n'avez actuellement pas les droits",
+	"vous n'êtes pas autorisé",
+];
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PageState {
+	/// No error: a quiz, VPL or any other regular page
+	Normal,
+	/// Moodle's (or the CAS/IdP) login form
+	LoginPage,
+	/// Browsing as guest, without being logged in
+	GuestAccess,
+	AttemptAlreadySubmitted,
+	NotEnrolled,
+	/// A permission error, with Moodle's message
+	NoPermission(String),
+	/// Any other error page or exception box, with Moodle's message
+	MoodleError(String),
+}
+
+impl PageState {
+	pub fn is_error(&self) -> bool {
+		*self != PageState::Normal
+	}
+
+	/// Failure class for a run that ends up on this page
+	pub fn failure_kind(&self) -> FailureKind {
+		match self {
+			PageState::Normal => FailureKind::Other,
+			PageState::LoginPage | PageState::GuestAccess => FailureKind::Login,
+			PageState::AttemptAlreadySubmitted | PageState::NotEnrolled | PageState::NoPermission(_) | PageState::MoodleError(_) => FailureKind::Access,
+		}
+	}
+}
+
+impl fmt::Display for PageState {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			PageState::Normal => write!(f, "normal page"),
+			PageState::LoginPage => write!(f, "landed on the login page"),
+			PageState::GuestAccess => write!(f, "browsing as guest, not logged in"),
+			PageState::AttemptAlreadySubmitted => write!(f, "attempt already submitted"),
+			PageState::NotEnrolled => write!(f, "not enrolled in the course"),
+			PageState::NoPermission(message) => write!(f, "no permission: {message}"),
+			PageState::MoodleError(message) => write!(f, "Moodle error: {message}"),
+		}
+	}
+}
+
+/// [`classify_page`] on the page as it is now
+pub async fn inspect_page_state(page: &Page) -> Result<PageState> {
+	let url = page.url().await.ok().flatten().unwrap_or_default();
+	let html = page.evaluate("document.documentElement.outerHTML").await.map_err(|e| eyre!("Failed to get page HTML: {e}"))?;
+	Ok(classify_page(&url, html.value().and_then(|v| v.as_str()).unwrap_or_default()))
+}
+
+/// What `html`, served at `url`, is. Only clear error markers count: a top-level `.alert-danger` is ignored on a page
+/// that has questions, which can carry notices of their own.
+pub fn classify_page(url: &str, html: &str) -> PageState {
+	if is_login_url(url) {
+		return PageState::LoginPage;
+	}
+	let document = Html::parse_document(html);
+	let select = |css: &str| Selector::parse(css).expect("static selector");
+	let text_of = |css: &str| -> Option<String> {
+		document
+			.select(&select(css))
+			.map(|el| normalize_whitespace(&el.text().collect::<String>()))
+			.find(|text| !text.is_empty())
+	};
+
+	let has_questions = document.select(&select(".que")).next().is_some();
+	let error_message = text_of(".errorbox .errormessage")
+		.or_else(|| text_of(".errorbox, #page-error, .moodle-exception, [data-rel=\"fatalerror\"]"))
+		.or_else(|| {
+			(!has_questions)
+				.then(|| text_of("#region-main > .alert-danger, #region-main > div > .alert-danger, #user-notifications .alert-danger"))
+				.flatten()
+		});
+	let body_text = text_of("#region-main").unwrap_or_default().to_lowercase();
+	let message_text = error_message.as_deref().unwrap_or_default().to_lowercase();
+	let mentions = |texts: &[&str]| texts.iter().any(|t| message_text.contains(t) || (!has_questions && body_text.contains(t)));
+
+	if mentions(ALREADY_SUBMITTED) {
+		return PageState::AttemptAlreadySubmitted;
+	}
+	if url.contains("/enrol/index.php") || mentions(NOT_ENROLLED) {
+		return PageState::NotEnrolled;
+	}
+	let page_text = text_of("body").unwrap_or_default().to_lowercase();
+	if GUEST_ACCESS.iter().any(|t| page_text.contains(t)) {
+		return PageState::GuestAccess;
+	}
+	if document
+		.select(&select("form#login, form[action*=\"/login/index.php\"] input[name=\"password\"]"))
+		.next()
+		.is_some()
+	{
+		return PageState::LoginPage;
+	}
+	match error_message {
+		Some(message) if NO_PERMISSION.iter().any(|t| message.to_lowercase().contains(t)) => PageState::NoPermission(message),
+		Some(message) => PageState::MoodleError(message),
+		None => PageState::Normal,
+	}
+}
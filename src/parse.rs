@@ -0,0 +1,1114 @@
+//! Question parsing from saved HTML, without a browser
+//!
+//! A port of the in-browser parsers in `runner` (`parse_questions`, `parse_vpl_page`) to `scraper`, giving the same
+//! questions for static content. Live pages still go through the JS versions, since they see state the scripts on the
+//! page have set up after load; these work on the `persist_htmls` snapshots, on anything fetched as outerHTML and on
+//! the question HTML the webservice returns.
+//!
+//! Where the JS relies on the DOM (`img.src`, `select.value`, `input.checked`), the equivalent is read from the
+//! markup: attributes as written, the `selected` option (or the first one), the `checked` attribute.
+
+use std::collections::HashSet;
+
+use color_eyre::{Result, eyre::eyre};
+use ego_tree::NodeRef;
+use scraper::{ElementRef, Html, Node, Selector};
+
+use crate::{
+	Blank, Choice, DragChoice, DragDropIntoText, DropZone, EditorKind, FillInBlanks, FillSegment, Image, MatchItem, MatchOption, OrderingItem, Question, RequiredFile, StackInput,
+	answers::normalize_whitespace, decimal_separator, images::DEFAULT_MIN_IMAGE_SIZE_PX,
+};
+
+/// Parse the questions of a quiz attempt page
+pub fn parse_questions_from_html(html: &str) -> Result<Vec<Question>> {
+	let document = Html::parse_document(html);
+	let mut questions = Vec::new();
+	for formulation in document.select(&sel(".formulation.clearfix")?) {
+		if let Some(question) = parse_formulation(&document, formulation)? {
+			questions.push(question);
+		}
+	}
+	Ok(questions)
+}
+
+/// Name/value pairs of every response field of the questions in `html`, as a browser would submit the form: checked
+/// radios and checkboxes only, the selected option of selects, and the hidden `:sequencecheck` fields Moodle requires
+pub fn parse_response_fields(html: &str) -> Result<Vec<(String, String)>> {
+	let document = Html::parse_document(html);
+	let mut fields = Vec::new();
+	for el in document.select(&sel(".que input[name], .que select[name], .que textarea[name]")?) {
+		if el.value().attr("disabled").is_some() {
+			continue;
+		}
+		let value = match el.value().name() {
+			"select" => select_value(el)?,
+			"textarea" => el.text().collect(),
+			_ => match attr(el, "type").to_ascii_lowercase().as_str() {
+				"submit" | "button" => continue,
+				"radio" | "checkbox" if el.value().attr("checked").is_none() => continue,
+				// A checked box without a value submits "on"
+				"radio" | "checkbox" => el.value().attr("value").unwrap_or("on").to_string(),
+				_ => attr(el, "value").to_string(),
+			},
+		};
+		fields.push((attr(el, "name").to_string(), value));
+	}
+	Ok(fields)
+}
+
+/// Parse a VPL description page into its code submission question. None if the page has neither a description nor
+/// required files.
+///
+/// The module id comes from the `cmid-N` class Moodle puts on `<body>`, as there is no URL to read it from.
+pub fn parse_vpl_from_html(html: &str) -> Result<Option<Question>> {
+	let document = Html::parse_document(html);
+	let removed = sel("script, style, .ace_editor, pre[id^=\"codefile\"]")?;
+
+	let extract_description = |container: ElementRef, min_len: usize| -> Option<(String, Vec<Image>)> {
+		let text = text_content(*container);
+		if text.contains("Work state summary") {
+			return None;
+		}
+		let text = text.trim();
+		if text.chars().count() < min_len || text.contains("Responsable de la matière") {
+			return None;
+		}
+		let mut desc = String::new();
+		for child in container.children() {
+			walk_vpl_description(child, &removed, &mut desc);
+		}
+		let desc = collapse_blank_lines(desc.trim());
+		(desc.chars().count() > 50).then(|| (desc, extract_images(Some(container))))
+	};
+
+	let mut description = None;
+	let no_overflow = sel(".no-overflow")?;
+	for bx in document.select(&sel(".generalbox")?) {
+		if let Some(container) = bx.select(&no_overflow).next()
+			&& let Some(found) = extract_description(container, 50)
+		{
+			description = Some(found);
+			break;
+		}
+	}
+	if description.is_none() {
+		description = document.select(&no_overflow).find_map(|div| extract_description(div, 100));
+	}
+	let (description, images) = description.unwrap_or_default();
+
+	let ace_line = sel(".ace_line")?;
+	let ace_content = |pre: ElementRef| -> Option<String> {
+		let lines: Vec<String> = pre.select(&ace_line).map(|line| text_content(*line)).collect();
+		(!lines.is_empty()).then(|| lines.join("\n"))
+	};
+
+	let mut required_files = Vec::new();
+	for h4 in document.select(&sel("h4[id^=\"fileid\"]")?) {
+		let name = text_content(*h4).trim().to_string();
+		if name.is_empty() {
+			continue;
+		}
+		let pre_id = format!("code{}", h4.value().id().unwrap_or_default());
+		let content = element_by_id(&document, &pre_id).and_then(ace_content).unwrap_or_default();
+		required_files.push(RequiredFile {
+			name,
+			content: content.trim().to_string(),
+		});
+	}
+	if required_files.is_empty() {
+		for pre in document.select(&sel("pre.ace_editor")?) {
+			if let Some(content) = ace_content(pre)
+				&& (content.contains("# Ecrivez") || content.contains("if __name__"))
+			{
+				required_files.push(RequiredFile {
+					name: "student.py".to_string(),
+					content: content.trim().to_string(),
+				});
+				break;
+			}
+		}
+	}
+
+	if description.is_empty() && required_files.is_empty() {
+		return Ok(None);
+	}
+
+	let module_id = document
+		.select(&sel("body")?)
+		.next()
+		.and_then(|body| body.value().classes().find_map(|c| c.strip_prefix("cmid-").map(str::to_string)))
+		.unwrap_or_default();
+
+	Ok(Some(Question::CodeSubmission {
+		description,
+		required_files,
+		module_id,
+		images,
+	}))
+}
+
+/// One `.formulation` block, checked against each question type in the same order as the JS parser
+fn parse_formulation(document: &Html, formulation: ElementRef) -> Result<Option<Question>> {
+	let qtext = formulation.select(&sel(".qtext")?).next();
+	let mut question_text = qtext.map(extract_text_with_latex).unwrap_or_default();
+	if question_text.is_empty()
+		&& let Some(span) = formulation.select(&sel(".filter_mathjaxloader_equation")?).next()
+	{
+		question_text = extract_text_with_latex(span);
+	}
+	let images = extract_images(qtext);
+
+	let wrapper = closest(formulation, &sel(".que")?);
+	let wrapper_is = |class: &str| wrapper.is_some_and(|w| has_class(w, class));
+
+	if wrapper_is("vplquestion")
+		&& let Some(textarea) = formulation.select(&sel("textarea[data-role=\"code-editor\"]")?).next()
+	{
+		let clearfix = formulation.select(&sel(".clearfix")?).next();
+		let question_text = if question_text.is_empty() {
+			clearfix.map(extract_text_with_latex).unwrap_or_default()
+		} else {
+			question_text
+		};
+		let images = if images.is_empty() { extract_images(clearfix) } else { images };
+		return Ok(Some(Question::CodeBlock {
+			question_text,
+			input_name: attr(textarea, "name").to_string(),
+			language: textarea.value().attr("data-templatelang").filter(|l| !l.is_empty()).unwrap_or("text").to_string(),
+			current_code: text_content(*textarea),
+			images,
+		}));
+	}
+
+	if wrapper_is("description") {
+		return Ok(Some(Question::Description { text: question_text, images }));
+	}
+
+	// STACK: a single Maxima input, usually inline in the question text rather than in the answer block
+	if wrapper_is("stack") {
+		let inputs: Vec<ElementRef> = formulation.select(&sel("input[type=\"text\"]")?).collect();
+		if let [input] = inputs[..]
+			&& !attr(input, "name").is_empty()
+		{
+			let syntax_example = sel(".stacksyntaxexample")?;
+			let hints = std::iter::once(attr(input, "placeholder").to_string()).chain(formulation.select(&syntax_example).map(|e| text_content(*e)));
+			let requirements: Vec<String> = hints.map(|h| h.trim().to_string()).filter(|h| !h.is_empty()).collect();
+			return Ok(Some(Question::ShortAnswer {
+				question_text,
+				input_name: attr(input, "name").to_string(),
+				current_answer: attr(input, "value").to_string(),
+				stack: Some(StackInput {
+					requirements: requirements.join("\n"),
+				}),
+				images,
+			}));
+		}
+	}
+
+	if wrapper_is("ddwtos") {
+		let mut place_inputs: Vec<ElementRef> = formulation.select(&sel("input.placeinput")?).collect();
+		if place_inputs.is_empty() {
+			place_inputs = formulation.select(&sel("input[type=\"hidden\"]")?).filter(|i| place_suffix(attr(*i, "name")).is_some()).collect();
+		}
+		let mut drop_zones: Vec<DropZone> = place_inputs
+			.into_iter()
+			.map(|input| DropZone {
+				input_name: attr(input, "name").to_string(),
+				place_number: numbered_class(input, "place").or_else(|| place_suffix(attr(input, "name"))).unwrap_or(0),
+				group: numbered_class(input, "group").unwrap_or(1),
+				current_choice: parse_int_prefix(attr(input, "value")).map_or(0, |n| n.max(0) as usize),
+				position: None,
+			})
+			.collect();
+		let mut choices = drag_choices(formulation, |el| text_content(**el).trim().to_string())?;
+
+		if !drop_zones.is_empty() && !choices.is_empty() {
+			choices.sort_by_key(|c| c.choice_number);
+			drop_zones.sort_by_key(|z| z.place_number);
+			return Ok(Some(Question::DragDropIntoText(DragDropIntoText {
+				question_text,
+				on_image: false,
+				choices,
+				drop_zones,
+				images,
+			})));
+		}
+	}
+
+	if wrapper_is("ddimageortext") {
+		let mut drop_zones = Vec::new();
+		for input in formulation.select(&sel("input[type=\"hidden\"]")?) {
+			let Some(place_number) = place_suffix(attr(input, "name")) else {
+				continue;
+			};
+			// Visual drop zones are 0-indexed (place0 corresponds to _p1)
+			let position = place_number
+				.checked_sub(1)
+				.and_then(|n| Selector::parse(&format!(".dropzone.place{n}")).ok())
+				.and_then(|zone_sel| formulation.select(&zone_sel).next())
+				.and_then(|zone| {
+					let style = zone.value().attr("style").unwrap_or_default();
+					Some((parse_int_prefix(style_property(style, "left")?)?, parse_int_prefix(style_property(style, "top")?)?))
+				});
+			drop_zones.push(DropZone {
+				input_name: attr(input, "name").to_string(),
+				place_number,
+				group: numbered_class(input, "group").unwrap_or(1),
+				current_choice: parse_int_prefix(attr(input, "value")).map_or(0, |n| n.max(0) as usize),
+				position,
+			});
+		}
+		let img = sel("img")?;
+		// Drag items may be images; fall back to their alt text
+		let mut choices = drag_choices(formulation, |el| {
+			let text = text_content(**el).trim().to_string();
+			if !text.is_empty() {
+				return text;
+			}
+			el.value()
+				.attr("alt")
+				.filter(|a| !a.is_empty())
+				.or_else(|| el.select(&img).next().and_then(|i| i.value().attr("alt")))
+				.unwrap_or_default()
+				.to_string()
+		})?;
+
+		if !drop_zones.is_empty() && !choices.is_empty() {
+			choices.sort_by_key(|c| c.choice_number);
+			drop_zones.sort_by_key(|z| z.place_number);
+
+			// The background image carries the spatial layout, so it must go first
+			let mut all_images = Vec::new();
+			if let Some(background) = formulation.select(&sel("img.dropbackground")?).next()
+				&& let Some(src) = background.value().attr("src").filter(|s| !s.is_empty())
+			{
+				all_images.push(Image {
+					url: src.to_string(),
+					alt: background.value().attr("alt").filter(|a| !a.is_empty()).map(str::to_string),
+				});
+			}
+			for image in images {
+				if !all_images.iter().any(|i| i.url == image.url) {
+					all_images.push(image);
+				}
+			}
+
+			return Ok(Some(Question::DragDropIntoText(DragDropIntoText {
+				question_text,
+				on_image: true,
+				choices,
+				drop_zones,
+				images: all_images,
+			})));
+		}
+	}
+
+	if wrapper_is("ordering") {
+		let response = formulation
+			.select(&sel("input[type=\"hidden\"][name$=\"_response\"], input[type=\"hidden\"][name*=\"_response_\"]")?)
+			.next();
+		let items: Vec<OrderingItem> = formulation
+			.select(&sel("ul.sortablelist > li, li.sortableitem")?)
+			.filter_map(|li| {
+				let id = li.value().attr("data-id").filter(|id| !id.is_empty()).or_else(|| li.value().id()).unwrap_or_default();
+				(!id.is_empty()).then(|| OrderingItem {
+					id: id.to_string(),
+					text: extract_text_with_latex(li),
+				})
+			})
+			.collect();
+
+		if let Some(response) = response
+			&& !items.is_empty()
+		{
+			return Ok(Some(Question::Ordering {
+				question_text,
+				items,
+				input_name: attr(response, "name").to_string(),
+				images,
+			}));
+		}
+	}
+
+	if wrapper_is("essay")
+		&& let Some(textarea) = formulation.select(&sel("textarea[name$=\"_answer\"]")?).next()
+	{
+		let id = attr(textarea, "id");
+		let editor_kind = match id {
+			"" => EditorKind::Plain,
+			_ if element_by_id(document, &format!("{id}editable")).is_some() => EditorKind::Atto,
+			_ if element_by_id(document, &format!("{id}_ifr")).is_some() => EditorKind::TinyMce,
+			_ => EditorKind::Plain,
+		};
+		return Ok(Some(Question::Essay {
+			question_text,
+			input_name: attr(textarea, "name").to_string(),
+			editor_kind,
+			current_html: text_content(*textarea),
+			accepts_attachments: formulation.select(&sel(".attachments, input[name$=\"_attachments\"]")?).next().is_some(),
+			images,
+		}));
+	}
+
+	// Must come before fill-in-blanks: a unit dropdown next to the input looks like a cloze
+	if wrapper_is("numerical")
+		&& let Some(answer_input) = formulation.select(&sel(".ablock input[type=\"text\"]")?).next()
+		&& !attr(answer_input, "name").is_empty()
+	{
+		let unit_select = formulation.select(&sel(".ablock select")?).next();
+		let unit_radios: Vec<ElementRef> = formulation.select(&sel(".ablock input[type=radio][name$=\"unit\"]")?).collect();
+		let (unit_control, units) = match (unit_select, unit_radios.first()) {
+			(Some(select), _) => (Some(select), select_options(select)?.into_iter().filter(|o| !o.value.is_empty()).collect()),
+			(None, Some(&radio)) => {
+				let labels: Vec<ElementRef> = formulation.select(&sel("label[for]")?).collect();
+				let units = unit_radios
+					.iter()
+					.map(|&radio| {
+						let label = labels.iter().find(|label| radio.value().id().is_some_and(|id| attr(**label, "for") == id));
+						MatchOption {
+							value: attr(radio, "value").to_string(),
+							text: label
+								.map(|label| normalize_whitespace(&text_content(**label)))
+								.unwrap_or_else(|| attr(radio, "value").to_string()),
+						}
+					})
+					.collect();
+				(Some(radio), units)
+			}
+			(None, None) => (None, Vec::new()),
+		};
+
+		return Ok(Some(Question::Numerical {
+			question_text,
+			input_name: attr(answer_input, "name").to_string(),
+			current_answer: attr(answer_input, "value").to_string(),
+			unit_select_name: unit_control.map(|control| attr(control, "name").to_string()),
+			units,
+			unit_radios: unit_select.is_none() && unit_control.is_some(),
+			unit_in_input: unit_control.is_none(),
+			decimal_separator: decimal_separator(document.root_element().value().attr("lang").unwrap_or_default()).to_string(),
+			images,
+		}));
+	}
+
+	// Fill-in-the-blanks (multianswer / cloze): several inline inputs, a mix of text inputs and selects, or any
+	// subquestion input (a single one too, to keep its context)
+	let ablock = formulation.select(&sel(".ablock")?).next();
+	let inline_inputs = formulation
+		.select(&sel(
+			".qtext input[type=\"text\"], .ablock input[type=\"text\"], .qtext select, .ablock select, .subquestion input[type=\"text\"], .subquestion select",
+		)?)
+		.count();
+	let has_multiple_inline_inputs = inline_inputs > 1;
+	let has_inline_select = formulation.select(&sel(".qtext select, .ablock select, .subquestion select")?).next().is_some();
+	let has_inline_text_input = formulation
+		.select(&sel(".qtext input[type=\"text\"], .ablock input[type=\"text\"], .subquestion input[type=\"text\"]")?)
+		.next()
+		.is_some();
+	let has_subquestion_inputs = formulation.select(&sel(".subquestion input[type=\"text\"], .subquestion select")?).next().is_some();
+
+	if has_multiple_inline_inputs || (has_inline_select && has_inline_text_input) || has_subquestion_inputs {
+		let mut segments = Vec::new();
+		let mut blanks = Vec::new();
+		walk_for_segments(*formulation, &mut segments, &mut blanks)?;
+		if !blanks.is_empty() {
+			return Ok(Some(Question::FillInBlanks(FillInBlanks {
+				question_text,
+				segments,
+				blanks,
+				images,
+			})));
+		}
+	}
+
+	if let Some(text_input) = ablock.and_then(|a| a.select(&sel("input[type=\"text\"]").ok()?).next())
+		&& !attr(text_input, "name").is_empty()
+		&& !has_multiple_inline_inputs
+	{
+		return Ok(Some(Question::ShortAnswer {
+			question_text,
+			input_name: attr(text_input, "name").to_string(),
+			current_answer: attr(text_input, "value").to_string(),
+			stack: None,
+			images,
+		}));
+	}
+
+	// Matching: dropdowns in a table
+	if let Some(table) = formulation.select(&sel(".ablock table.answer")?).next() {
+		let text_cell = sel(".text")?;
+		let row = sel("tr")?;
+		let mut items = Vec::new();
+		for select in table.select(&sel("select")?) {
+			let prompt = closest(select, &row).and_then(|r| r.select(&text_cell).next()).map(extract_text_with_latex).unwrap_or_default();
+			let selected = select_value(select)?;
+			items.push(MatchItem {
+				prompt,
+				select_name: attr(select, "name").to_string(),
+				options: select_options(select)?,
+				selected_value: if selected.is_empty() { "0".to_string() } else { selected },
+			});
+		}
+		if !items.is_empty() {
+			return Ok(Some(Question::Matching { question_text, items, images }));
+		}
+	}
+
+	// Inline dropdowns embedded in the question text; their context is in the question text, so no prompt
+	let mut items = Vec::new();
+	for select in formulation.select(&sel(".subquestion select, .qtext select")?) {
+		items.push(MatchItem {
+			prompt: String::new(),
+			select_name: attr(select, "name").to_string(),
+			options: select_options(select)?.into_iter().filter(|o| !o.value.is_empty()).collect(),
+			selected_value: select_value(select)?,
+		});
+	}
+	if !items.is_empty() {
+		return Ok(Some(Question::Matching { question_text, items, images }));
+	}
+
+	let Some(answer_div) = formulation.select(&sel(".answer")?).next() else {
+		return unsupported_question(formulation, wrapper, question_text);
+	};
+	let radios: Vec<ElementRef> = answer_div.select(&sel("input[type=\"radio\"]")?).collect();
+	let checkboxes: Vec<ElementRef> = answer_div.select(&sel("input[type=\"checkbox\"]")?).collect();
+	let multi = radios.is_empty();
+	let label = sel("label, .ml-1, .flex-fill")?;
+	let div = sel("div")?;
+	let inputs = if multi { checkboxes } else { radios };
+	let choices: Vec<Choice> = inputs
+		.into_iter()
+		.map(|input| {
+			let label_el = closest(input, &div).and_then(|d| d.select(&label).next());
+			Choice {
+				input_name: attr(input, "name").to_string(),
+				input_value: attr(input, "value").to_string(),
+				text: label_el.map(extract_text_with_latex).unwrap_or_default(),
+				selected: input.value().attr("checked").is_some(),
+				images: extract_images(label_el),
+			}
+		})
+		.collect();
+
+	if choices.is_empty() {
+		return unsupported_question(formulation, wrapper, question_text);
+	}
+	Ok(Some(if multi {
+		Question::MultiChoice { question_text, choices, images }
+	} else {
+		Question::SingleChoice { question_text, choices, images }
+	}))
+}
+
+/// A formulation none of the branches recognized: reported only if it has form fields to fill
+fn unsupported_question(formulation: ElementRef, wrapper: Option<ElementRef>, question_text: String) -> Result<Option<Question>> {
+	let mut input_names: Vec<String> = Vec::new();
+	for el in formulation.select(&sel("input[name], select[name], textarea[name]")?) {
+		let name = attr(el, "name");
+		let input_type = el.value().attr("type").unwrap_or_default().to_ascii_lowercase();
+		// Moodle's own bookkeeping fields (":sequencecheck", ":flagged") and buttons aren't answers
+		if name.contains("_:") || (el.value().name() == "input" && matches!(input_type.as_str(), "submit" | "button")) {
+			continue;
+		}
+		if !input_names.iter().any(|n| n == name) {
+			input_names.push(name.to_string());
+		}
+	}
+	if input_names.is_empty() {
+		return Ok(None);
+	}
+	// The class attribute, not `classes()`: that one is sorted, and the question type is the class right after "que"
+	let qtype_class = wrapper.and_then(|w| attr(w, "class").split_whitespace().find(|c| *c != "que")).unwrap_or("unknown").to_string();
+	Ok(Some(Question::Unknown {
+		question_text,
+		qtype_class,
+		raw_html_excerpt: formulation.html().chars().take(500).collect(),
+		input_names,
+	}))
+}
+
+fn sel(css: &str) -> Result<Selector> {
+	Selector::parse(css).map_err(|e| eyre!("Invalid selector `{css}`: {e}"))
+}
+
+fn attr<'a>(el: ElementRef<'a>, name: &str) -> &'a str {
+	el.value().attr(name).unwrap_or_default()
+}
+
+fn has_class(el: ElementRef, class: &str) -> bool {
+	el.value().classes().any(|c| c == class)
+}
+
+/// The element itself or its nearest ancestor matching `selector`, like `Element.closest`
+fn closest<'a>(el: ElementRef<'a>, selector: &Selector) -> Option<ElementRef<'a>> {
+	std::iter::once(*el).chain(el.ancestors()).filter_map(ElementRef::wrap).find(|e| selector.matches(e))
+}
+
+fn element_by_id<'a>(document: &'a Html, id: &str) -> Option<ElementRef<'a>> {
+	document.tree.nodes().filter_map(ElementRef::wrap).find(|e| e.value().id() == Some(id))
+}
+
+/// `Node.textContent`: every descendant text node, script and style contents included
+fn text_content(node: NodeRef<Node>) -> String {
+	node.descendants().filter_map(|n| n.value().as_text()).map(|t| &**t).collect()
+}
+
+/// The number N of a `{prefix}N` class (e.g. `place3`, `group2`, `choice1`)
+fn numbered_class(el: ElementRef, prefix: &str) -> Option<usize> {
+	el.value()
+		.classes()
+		.find_map(|c| c.strip_prefix(prefix).filter(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())))
+		.and_then(|n| n.parse().ok())
+}
+
+/// N of a drop zone input named `..._pN`
+fn place_suffix(name: &str) -> Option<usize> {
+	let (_, n) = name.rsplit_once("_p")?;
+	(!n.is_empty() && n.bytes().all(|b| b.is_ascii_digit())).then(|| n.parse().ok())?
+}
+
+/// JS `parseInt(s, 10)`: leading whitespace, an optional sign, then as many digits as there are
+fn parse_int_prefix(s: &str) -> Option<i64> {
+	let s = s.trim_start();
+	let (sign, rest) = match s.strip_prefix('-') {
+		Some(rest) => (-1, rest),
+		None => (1, s.strip_prefix('+').unwrap_or(s)),
+	};
+	let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+	digits.parse::<i64>().ok().map(|n| sign * n)
+}
+
+/// Value of a declaration in an inline `style` attribute
+fn style_property<'a>(style: &'a str, property: &str) -> Option<&'a str> {
+	style.split(';').find_map(|decl| {
+		let (name, value) = decl.split_once(':')?;
+		name.trim().eq_ignore_ascii_case(property).then(|| value.trim())
+	})
+}
+
+/// Draggable choices of a drag-drop question, unique by group and choice number
+fn drag_choices(formulation: ElementRef, text: impl Fn(&ElementRef) -> String) -> Result<Vec<DragChoice>> {
+	let mut seen = HashSet::new();
+	let mut choices = Vec::new();
+	for el in formulation.select(&sel(".draghome:not(.dragplaceholder)")?) {
+		let choice_number = numbered_class(el, "choice").unwrap_or(0);
+		let group = numbered_class(el, "group").unwrap_or(1);
+		// Choice numbers repeat across groups
+		if choice_number > 0 && seen.insert((group, choice_number)) {
+			choices.push(DragChoice {
+				choice_number,
+				group,
+				text: text(&el),
+			});
+		}
+	}
+	Ok(choices)
+}
+
+/// `HTMLOptionElement.value`: the value attribute, or the whitespace-collapsed text
+fn option_value(option: ElementRef) -> String {
+	match option.value().attr("value") {
+		Some(value) => value.to_string(),
+		None => normalize_whitespace(&text_content(*option)),
+	}
+}
+
+fn select_options(select: ElementRef) -> Result<Vec<MatchOption>> {
+	Ok(select
+		.select(&sel("option")?)
+		.map(|option| MatchOption {
+			value: option_value(option),
+			text: text_content(*option).trim().to_string(),
+		})
+		.collect())
+}
+
+/// `HTMLSelectElement.value` before any script ran: the last option marked `selected`, else the first one
+fn select_value(select: ElementRef) -> Result<String> {
+	let options: Vec<ElementRef> = select.select(&sel("option")?).collect();
+	let chosen = options.iter().rev().find(|o| o.value().attr("selected").is_some()).or(options.first());
+	Ok(chosen.map(|o| option_value(*o)).unwrap_or_default())
+}
+
+fn extract_images(element: Option<ElementRef>) -> Vec<Image> {
+	let Some(element) = element else {
+		return Vec::new();
+	};
+	let Ok(img) = sel("img") else {
+		return Vec::new();
+	};
+	element
+		.select(&img)
+		.filter_map(|img| {
+			let url = image_url(img)?;
+			if is_decorative(img, url) {
+				return None;
+			}
+			Some(Image {
+				url: url.to_string(),
+				alt: img.value().attr("alt").filter(|a| !a.is_empty()).map(str::to_string),
+			})
+		})
+		.collect()
+}
+
+/// The lazy-loading `data-src`/`data-original` over the placeholder `src`, then the largest `srcset` candidate
+fn image_url(img: ElementRef<'_>) -> Option<&str> {
+	let attr = |name: &str| img.value().attr(name).filter(|s| !s.trim().is_empty());
+	attr("data-src")
+		.or_else(|| attr("data-original"))
+		.or_else(|| attr("srcset").or_else(|| attr("data-srcset")).and_then(largest_srcset_candidate))
+		.or_else(|| attr("src"))
+}
+
+/// URL of the candidate with the largest width or density descriptor in a `srcset`
+fn largest_srcset_candidate(srcset: &str) -> Option<&str> {
+	srcset
+		.split(", ")
+		.filter_map(|candidate| {
+			let mut parts = candidate.split_whitespace();
+			let url = parts.next()?;
+			let size = parts.next().map_or(1., |d| d.trim_end_matches(['w', 'x']).parse::<f64>().unwrap_or(0.));
+			Some((url, size))
+		})
+		.max_by(|a, b| a.1.total_cmp(&b.1))
+		.map(|(url, _)| url)
+}
+
+/// Icons, spacer gifs, and images whose `width` and `height` are both under [`DEFAULT_MIN_IMAGE_SIZE_PX`] (saved pages
+/// are parsed without a config)
+fn is_decorative(img: ElementRef, url: &str) -> bool {
+	if img.value().classes().any(|c| c == "icon") {
+		return true;
+	}
+	let file = url.split(['?', '#']).next().unwrap_or(url).rsplit('/').next().unwrap_or_default().to_lowercase();
+	if ["spacer.gif", "blank.gif", "pixel.gif", "transparent.gif"].contains(&file.as_str()) {
+		return true;
+	}
+	let dimension = |name: &str| img.value().attr(name).and_then(|v| v.trim().trim_end_matches("px").parse::<u32>().ok()).unwrap_or(0);
+	let (width, height) = (dimension("width"), dimension("height"));
+	width > 0 && height > 0 && width < DEFAULT_MIN_IMAGE_SIZE_PX && height < DEFAULT_MIN_IMAGE_SIZE_PX
+}
+
+/// Text of an element with MathJax rendering replaced by its LaTeX source, and its layout kept
+///
+/// MathJax 3 `mjx-container`s give their TeX annotation (or `data-latex`, or an inner `math/tex` script), falling back
+/// to the assistive MathML text. MathJax 2 rendering spans give the `math/tex` script that follows them, and are
+/// dropped otherwise. Display math is wrapped in `\[...\]`, inline math in `\(...\)`.
+///
+/// Paragraphs and other blocks are separated by a blank line and `<br>` breaks the line; list items start with "• ",
+/// code and monospace spans are wrapped in backticks, `<pre>` in a fenced block, and tables with several columns come
+/// as markdown tables (see [`markdown_table`]). Other whitespace is collapsed as the browser would.
+fn extract_text_with_latex(element: ElementRef) -> String {
+	let mut out = LaidOutText::default();
+	for child in element.children() {
+		walk_latex_text(child, &mut out);
+	}
+	out.finish()
+}
+
+/// Text being built by [`walk_latex_text`]
+#[derive(Default)]
+struct LaidOutText {
+	text: String,
+	/// After an answer number ("a. ") or a bullet: block breaks are a space until the next text, so choice labels and
+	/// list items stay on one line
+	glue: bool,
+	/// Inside a table cell, where a nested table is flattened to "a, b; c, d"
+	in_table_cell: bool,
+}
+
+impl LaidOutText {
+	/// Text from the page source: whitespace runs become one space, none at the start of a line
+	fn push(&mut self, text: &str) {
+		for c in text.chars() {
+			if c.is_whitespace() {
+				if !self.text.is_empty() && !self.text.ends_with(char::is_whitespace) {
+					self.text.push(' ');
+				}
+			} else {
+				self.text.push(c);
+				self.glue = false;
+			}
+		}
+	}
+
+	/// Text kept as it is (code, LaTeX)
+	fn push_raw(&mut self, text: &str) {
+		self.text.push_str(text);
+		if !text.trim().is_empty() {
+			self.glue = false;
+		}
+	}
+
+	/// End the line so that at least `newlines` newlines separate it from what comes next
+	fn line_break(&mut self, newlines: usize) {
+		if self.glue {
+			self.push(" ");
+			return;
+		}
+		self.trim_end_spaces();
+		if self.text.is_empty() {
+			return;
+		}
+		let have = self.text.len() - self.text.trim_end_matches('\n').len();
+		self.text.push_str(&"\n".repeat(newlines.saturating_sub(have)));
+	}
+
+	/// `<br>`: a newline each, so that two make a blank line
+	fn hard_break(&mut self) {
+		if self.glue {
+			return;
+		}
+		self.trim_end_spaces();
+		if !self.text.is_empty() {
+			self.text.push('\n');
+		}
+	}
+
+	fn trim_end_spaces(&mut self) {
+		self.text.truncate(self.text.trim_end_matches(' ').len());
+	}
+
+	fn finish(self) -> String {
+		collapse_blank_lines(self.text.trim())
+	}
+}
+
+fn walk_latex_text(node: NodeRef<Node>, out: &mut LaidOutText) {
+	match node.value() {
+		Node::Text(text) => out.push(text),
+		Node::Element(_) => {
+			let Some(el) = ElementRef::wrap(node) else { return };
+			let name = el.value().name();
+			if name == "mjx-container" {
+				if let Some(text) = mjx_container_text(el) {
+					out.push_raw(&text);
+					return;
+				}
+			} else if is_mathjax2_span(el) {
+				if let Some(script) = next_element_sibling(el).filter(|s| math_script_type(*s).is_some()) {
+					out.push_raw(&wrap_latex(&text_content(*script), math_script_type(script).is_some_and(|t| t.contains("mode=display"))));
+				}
+				return;
+			} else if let Some(script_type) = math_script_type(el) {
+				// Already taken by the rendering span before it
+				if !previous_element_sibling(el).is_some_and(is_mathjax2_span) {
+					out.push_raw(&wrap_latex(&text_content(*el), script_type.contains("mode=display")));
+				}
+				return;
+			}
+			let children = |out: &mut LaidOutText| {
+				for child in node.children() {
+					walk_latex_text(child, out);
+				}
+			};
+			match name {
+				"script" | "style" => {}
+				"br" => out.hard_break(),
+				"pre" => {
+					out.line_break(2);
+					out.push_raw(&format!("```\n{}\n```", text_content(*el).trim_end()));
+					out.line_break(2);
+				}
+				"code" | "kbd" | "samp" | "tt" => out.push_raw(&format!("`{}`", text_content(*el))),
+				"span" if attr(el, "style").contains("courier") || attr(el, "style").contains("monospace") => out.push_raw(&format!("`{}`", text_content(*el))),
+				"table" => {
+					let rows = table_rows(el);
+					if out.in_table_cell {
+						let flattened: Vec<String> = rows
+							.iter()
+							.map(|row| row_cells(*row).map(cell_text).filter(|text| !text.is_empty()).collect::<Vec<_>>().join(", "))
+							.collect();
+						out.push(&flattened.join("; "));
+					} else if rows.iter().all(|row| row_cells(*row).count() <= 1) {
+						// Layout table: only its content matters
+						out.line_break(2);
+						children(out);
+						out.line_break(2);
+					} else {
+						out.line_break(2);
+						out.push_raw(&markdown_table(&rows));
+						out.line_break(2);
+					}
+				}
+				"tr" => {
+					out.line_break(1);
+					children(out);
+					out.line_break(1);
+				}
+				"li" => {
+					out.line_break(1);
+					out.push_raw("• ");
+					// `<li><p>...</p></li>`: the paragraph goes on the bullet's line
+					out.glue = true;
+					children(out);
+					// ...and doesn't leave a blank line before the next item
+					out.glue = false;
+					out.text.truncate(out.text.trim_end().len());
+					out.line_break(1);
+				}
+				"p" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "blockquote" | "ul" | "ol" | "section" | "article" | "header" | "footer" | "figure" => {
+					out.line_break(2);
+					children(out);
+					out.line_break(2);
+				}
+				_ => {
+					children(out);
+					if has_class(el, "answernumber") {
+						out.glue = true;
+					}
+				}
+			}
+		}
+		_ => {}
+	}
+}
+
+/// The rows of `table` itself, not of tables nested in it
+fn table_rows(table: ElementRef) -> Vec<ElementRef> {
+	table
+		.child_elements()
+		.flat_map(|child| match child.value().name() {
+			"tr" => vec![child],
+			"thead" | "tbody" | "tfoot" => child.child_elements().filter(|row| row.value().name() == "tr").collect(),
+			_ => Vec::new(),
+		})
+		.collect()
+}
+
+fn row_cells(row: ElementRef) -> impl Iterator<Item = ElementRef> {
+	row.child_elements().filter(|cell| matches!(cell.value().name(), "td" | "th"))
+}
+
+/// A cell's text on one line
+fn cell_text(cell: ElementRef) -> String {
+	let mut out = LaidOutText {
+		in_table_cell: true,
+		..Default::default()
+	};
+	for child in cell.children() {
+		walk_latex_text(child, &mut out);
+	}
+	normalize_whitespace(&out.finish())
+}
+
+/// Rows as a markdown table with padded columns, the first row as the header. A cell spanning several columns is
+/// followed by empty ones, and short rows are padded; nothing is cut, however wide.
+fn markdown_table(rows: &[ElementRef]) -> String {
+	let mut grid: Vec<Vec<String>> = rows
+		.iter()
+		.map(|row| {
+			let mut cells = Vec::new();
+			for cell in row_cells(*row) {
+				cells.push(cell_text(cell).replace('|', "\\|"));
+				let span: usize = cell.value().attr("colspan").and_then(|s| s.trim().parse().ok()).unwrap_or(1);
+				cells.extend(std::iter::repeat_n(String::new(), span.clamp(1, 50) - 1));
+			}
+			cells
+		})
+		.collect();
+	let columns = grid.iter().map(Vec::len).max().unwrap_or(0);
+	let mut widths = vec![3; columns];
+	for row in &mut grid {
+		row.resize(columns, String::new());
+		for (width, cell) in widths.iter_mut().zip(row.iter()) {
+			*width = (*width).max(cell.chars().count());
+		}
+	}
+	let line = |cells: &[String]| {
+		let padded: Vec<String> = cells.iter().zip(&widths).map(|(cell, &width)| format!("{cell:<width$}")).collect();
+		format!("| {} |", padded.join(" | "))
+	};
+	let separator: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+	let mut lines = vec![line(&grid[0]), line(&separator)];
+	lines.extend(grid[1..].iter().map(|row| line(row)));
+	lines.join("\n")
+}
+
+/// What an `mjx-container` stands for; None to keep its rendered text
+fn mjx_container_text(container: ElementRef) -> Option<String> {
+	let first_text = |css: &str| sel(css).ok().and_then(|s| container.select(&s).next()).map(|e| text_content(*e));
+	let latex = first_text("annotation[encoding=\"application/x-tex\"]")
+		.filter(|l| !l.is_empty())
+		.or_else(|| container.value().attr("data-latex").filter(|l| !l.is_empty()).map(str::to_string))
+		.or_else(|| first_text("script[type=\"math/tex\"]"))
+		.filter(|l| !l.is_empty());
+	match latex {
+		Some(latex) => {
+			let display = container.value().attr("display") == Some("true") || has_class(container, "MJXc-display");
+			Some(wrap_latex(&latex, display))
+		}
+		None => first_text(".MJX_Assistive_MathML, mjx-assistive-mml"),
+	}
+}
+
+fn is_mathjax2_span(el: ElementRef) -> bool {
+	el.value().classes().any(|c| matches!(c, "MathJax" | "MathJax_Preview" | "MathJax_Display"))
+}
+
+/// The `type` of a `<script type="math/tex...">`
+fn math_script_type(el: ElementRef<'_>) -> Option<&str> {
+	(el.value().name() == "script").then(|| el.value().attr("type")).flatten().filter(|t| t.contains("math/tex"))
+}
+
+fn next_element_sibling(el: ElementRef) -> Option<ElementRef> {
+	el.next_siblings().find_map(ElementRef::wrap)
+}
+
+fn previous_element_sibling(el: ElementRef) -> Option<ElementRef> {
+	el.prev_siblings().find_map(ElementRef::wrap)
+}
+
+fn wrap_latex(latex: &str, display: bool) -> String {
+	match display {
+		true => format!("\\[{latex}\\]"),
+		false => format!("\\({latex}\\)"),
+	}
+}
+
+/// Text and blanks of a cloze question in document order
+fn walk_for_segments(node: NodeRef<Node>, segments: &mut Vec<FillSegment>, blanks: &mut Vec<Blank>) -> Result<()> {
+	let el = match node.value() {
+		Node::Text(text) => {
+			if !text.trim().is_empty() {
+				segments.push(FillSegment::Text(text.to_string()));
+			}
+			return Ok(());
+		}
+		Node::Element(_) => match ElementRef::wrap(node) {
+			Some(el) => el,
+			None => return Ok(()),
+		},
+		_ => return Ok(()),
+	};
+	let tag = el.value().name();
+	let input_type = el.value().attr("type").unwrap_or("text").to_ascii_lowercase();
+
+	// Hidden inputs, accessibility labels and info headers
+	if (tag == "input" && input_type == "hidden") || (matches!(tag, "label" | "h4") && has_class(el, "accesshide")) {
+		return Ok(());
+	}
+
+	match tag {
+		"input" if input_type == "text" => {
+			segments.push(FillSegment::Blank(blanks.len()));
+			blanks.push(Blank::Text {
+				input_name: attr(el, "name").to_string(),
+				current_value: attr(el, "value").to_string(),
+			});
+		}
+		"select" => {
+			segments.push(FillSegment::Blank(blanks.len()));
+			blanks.push(Blank::Select {
+				select_name: attr(el, "name").to_string(),
+				options: select_options(el)?.into_iter().filter(|o| !o.value.is_empty()).collect(),
+				selected_value: select_value(el)?,
+			});
+		}
+		"br" => segments.push(FillSegment::Text("\n".to_string())),
+		"p" => {
+			segments.push(FillSegment::Text("\n".to_string()));
+			for child in node.children() {
+				walk_for_segments(child, segments, blanks)?;
+			}
+			segments.push(FillSegment::Text("\n".to_string()));
+		}
+		"script" | "style" | "mjx-container" | "img" => {}
+		_ =>
+			for child in node.children() {
+				walk_for_segments(child, segments, blanks)?;
+			},
+	}
+	Ok(())
+}
+
+/// VPL description as markdown-ish text, skipping elements matching `removed`
+fn walk_vpl_description(node: NodeRef<Node>, removed: &Selector, desc: &mut String) {
+	let el = match node.value() {
+		Node::Text(text) => {
+			desc.push_str(text);
+			return;
+		}
+		Node::Element(_) => match ElementRef::wrap(node) {
+			Some(el) => el,
+			None => return,
+		},
+		_ => return,
+	};
+	if removed.matches(&el) {
+		return;
+	}
+	let children = |desc: &mut String| {
+		for child in node.children() {
+			walk_vpl_description(child, removed, desc);
+		}
+	};
+	match el.value().name() {
+		"p" => {
+			desc.push_str("\n\n");
+			children(desc);
+		}
+		"br" => desc.push('\n'),
+		"li" => {
+			desc.push_str("\n• ");
+			children(desc);
+		}
+		"code" => desc.push_str(&format!("`{}`", text_without(node, removed))),
+		"span" => {
+			let style = attr(el, "style");
+			if style.contains("courier") || style.contains("monospace") {
+				desc.push_str(&format!("`{}`", text_without(node, removed)));
+			} else {
+				children(desc);
+			}
+		}
+		"em" | "i" => {
+			desc.push('_');
+			children(desc);
+			desc.push('_');
+		}
+		"strong" | "b" => {
+			desc.push_str("**");
+			children(desc);
+			desc.push_str("**");
+		}
+		"div" if has_class(el, "editor-indent") => {
+			desc.push('\n');
+			children(desc);
+		}
+		_ => children(desc),
+	}
+}
+
+/// `textContent` of a node whose descendants matching `removed` were taken out
+fn text_without(node: NodeRef<Node>, removed: &Selector) -> String {
+	let mut text = String::new();
+	for child in node.children() {
+		match child.value() {
+			Node::Text(t) => text.push_str(t),
+			Node::Element(_) if !ElementRef::wrap(child).is_some_and(|e| removed.matches(&e)) => text.push_str(&text_without(child, removed)),
+			_ => {}
+		}
+	}
+	text
+}
+
+/// Runs of 3+ newlines down to a blank line
+fn collapse_blank_lines(text: &str) -> String {
+	let mut out = String::with_capacity(text.len());
+	let mut newlines = 0;
+	for c in text.chars() {
+		if c == '\n' {
+			newlines += 1;
+			if newlines <= 2 {
+				out.push(c);
+			}
+		} else {
+			newlines = 0;
+			out.push(c);
+		}
+	}
+	out
+}
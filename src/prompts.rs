@@ -0,0 +1,324 @@
+//! Prompt templates for the LLM, overridable by files in the config directory
+//!
+//! Each kind of question (and VPL code) has a built-in template; a file named after it in `prompts_dir`
+//! (`single_choice.txt`, `code_submission.txt`, ...) replaces it. Templates are plain text with `{name}` placeholders,
+//! the ones [`PromptKind::placeholders`] lists for their kind. `{format_instructions}` (the JSON the reply has to be
+//! in) must be in every template, or replies couldn't be parsed. `--dump-prompts` writes the built-in ones out as a
+//! starting point.
+
+use std::{
+	collections::HashMap,
+	path::{Path, PathBuf},
+};
+
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+
+/// Placeholders every template can use
+const COMMON_PLACEHOLDERS: [&str; 3] = ["context", "question", "format_instructions"];
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PromptKind {
+	SingleChoice,
+	MultiChoice,
+	ShortAnswer,
+	Matching,
+	FillInBlanks,
+	CodeBlock,
+	DragDrop,
+	Numerical,
+	Essay,
+	Ordering,
+	CodeSubmission,
+}
+
+impl PromptKind {
+	pub const ALL: [PromptKind; 11] = [
+		PromptKind::SingleChoice,
+		PromptKind::MultiChoice,
+		PromptKind::ShortAnswer,
+		PromptKind::Matching,
+		PromptKind::FillInBlanks,
+		PromptKind::CodeBlock,
+		PromptKind::DragDrop,
+		PromptKind::Numerical,
+		PromptKind::Essay,
+		PromptKind::Ordering,
+		PromptKind::CodeSubmission,
+	];
+
+	/// Template file name in `prompts_dir`
+	pub fn file_name(self) -> &'static str {
+		match self {
+			PromptKind::SingleChoice => "single_choice.txt",
+			PromptKind::MultiChoice => "multi_choice.txt",
+			PromptKind::ShortAnswer => "short_answer.txt",
+			PromptKind::Matching => "matching.txt",
+			PromptKind::FillInBlanks => "fill_in_blanks.txt",
+			PromptKind::CodeBlock => "code_block.txt",
+			PromptKind::DragDrop => "drag_drop.txt",
+			PromptKind::Numerical => "numerical.txt",
+			PromptKind::Essay => "essay.txt",
+			PromptKind::Ordering => "ordering.txt",
+			PromptKind::CodeSubmission => "code_submission.txt",
+		}
+	}
+
+	/// Placeholders its template can use besides [`COMMON_PLACEHOLDERS`]
+	fn placeholders(self) -> &'static [&'static str] {
+		match self {
+			// The STACK syntax rules, or the plain short answer instruction. `answer_language` is the sentence saying
+			// which language to write in, empty when there's none to say.
+			PromptKind::ShortAnswer => &["instructions", "answer_language"],
+			PromptKind::Essay | PromptKind::FillInBlanks => &["answer_language"],
+			PromptKind::CodeBlock => &["language", "answer_language"],
+			// Says the drop zones are on the attached image, for drag-and-drop onto an image
+			PromptKind::DragDrop => &["image_note"],
+			PromptKind::Numerical => &["decimal_separator", "unit_instructions"],
+			PromptKind::CodeSubmission => &["files_list", "previous_submission", "answer_language"],
+			_ => &[],
+		}
+	}
+
+	/// The JSON the reply has to be in, for `{format_instructions}`
+	pub fn format_instructions(self) -> &'static str {
+		match self {
+			PromptKind::SingleChoice =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"response": "<the text of the correct answer>", "response_number": <the number of the correct answer>}"#,
+			PromptKind::MultiChoice =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"responses": ["<text of first correct answer>", "<text of second correct answer>", ...], "response_numbers": [<number of first correct answer>, <number of second correct answer>, ...]}"#,
+			PromptKind::ShortAnswer =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"answer": "<your concise answer>"}"#,
+			PromptKind::Matching =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"matches": [{"item": <item number>, "prompt": "<item prompt text or slot number like '[1]'>", "answer": "<chosen option text>"}]}"#,
+			PromptKind::FillInBlanks =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"blanks": [{"blank_number": <number>, "answer": "<the answer for this blank>"}]}"#,
+			PromptKind::CodeBlock =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"code": "<your complete solution code>"}"#,
+			PromptKind::DragDrop =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"placements": [{"place_number": <drop zone number>, "choice": "<the exact text of the choice to place there>"}]}"#,
+			PromptKind::Numerical =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"answer": "<the number>", "unit": "<unit option text>" or null}"#,
+			PromptKind::Essay =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"answer": "<your answer, with paragraphs separated by blank lines>"}"#,
+			PromptKind::Ordering =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"order": [<item number that comes first>, <item number that comes second>, ...]}"#,
+			PromptKind::CodeSubmission =>
+				r#"Respond with JSON only, no markdown, in this exact format:
+{"files": [{"filename": "<filename>", "content": "<complete file content>"}]}"#,
+		}
+	}
+
+	fn built_in(self) -> &'static str {
+		match self {
+			PromptKind::SingleChoice =>
+				"{context}You are answering a single-choice question. Pick the ONE correct answer.
+
+{question}
+{format_instructions}",
+			PromptKind::MultiChoice =>
+				"{context}You are answering a multiple-choice question where MULTIPLE answers may be correct. Select ALL correct answers.
+
+{question}
+{format_instructions}",
+			PromptKind::ShortAnswer =>
+				"{context}{instructions}
+
+{question}
+{format_instructions}
+{answer_language}",
+			PromptKind::Matching =>
+				"{context}You are answering a matching question. For each item, select the correct option from its available choices.
+
+{question}
+{format_instructions}
+
+\"item\" is the 1-based number the item is listed with above.",
+			PromptKind::FillInBlanks =>
+				"{context}You are answering a fill-in-the-blanks question. Fill in each numbered blank with the correct answer.
+
+{question}
+{format_instructions}
+
+For text input blanks, provide the exact text to enter.
+For dropdown blanks, provide the exact text of the option to select (one of the listed choices).
+{answer_language}",
+			PromptKind::CodeBlock =>
+				"{context}You are solving a programming problem. Write the complete solution code.
+Think in English.
+
+{question}
+
+The programming language is: {language}
+
+IMPORTANT: {format_instructions}
+
+Write correct, working code. Do not include docstrings or comments.
+{answer_language}",
+			PromptKind::DragDrop =>
+				"{context}You are answering a drag-and-drop question. Place each choice into the correct drop zone.
+{image_note}
+{question}
+{format_instructions}
+
+Each place_number corresponds to a drop zone (1, 2, 3, etc.). Choose the correct option for each zone from the available choices.
+IMPORTANT: Each drop zone can only accept choices from its group. Match the groups correctly.",
+			PromptKind::Numerical =>
+				"{context}You are answering a numerical question. The answer must be a bare number, without words or explanation.
+
+{question}
+{format_instructions}
+
+Use '{decimal_separator}' as the decimal separator and no thousands separators.
+{unit_instructions}",
+			PromptKind::Essay =>
+				"{context}You are answering an essay question. Write a clear, well-structured answer in plain prose.
+
+{question}
+{format_instructions}
+
+Do not use markdown, HTML or bullet points - plain paragraphs only.
+{answer_language}",
+			PromptKind::Ordering =>
+				"{context}You are answering an ordering question. Arrange the numbered items into the correct sequence.
+
+{question}
+{format_instructions}
+
+Use every item number exactly once.",
+			PromptKind::CodeSubmission =>
+				"{context}You are solving a programming assignment. Write the complete solution code.
+Think in English.
+
+Problem Description:
+{question}
+
+Required Files:
+{files_list}
+{previous_submission}
+IMPORTANT: {format_instructions}
+
+Make sure the code is correct and ready to submit. Do not include docstrings or comments.
+{answer_language}",
+		}
+	}
+}
+
+/// The template of each kind: the file from `prompts_dir` where there is one, else the built-in
+#[derive(Clone, Debug, Default)]
+pub struct Prompts {
+	overrides: HashMap<PromptKind, String>,
+}
+
+impl Prompts {
+	/// Read and check the templates in `dir`; a missing directory or file keeps the built-in template
+	pub fn load(dir: Option<&Path>) -> Result<Self> {
+		let mut overrides = HashMap::new();
+		let Some(dir) = dir else {
+			return Ok(Self { overrides });
+		};
+		for kind in PromptKind::ALL {
+			let path = dir.join(kind.file_name());
+			let Ok(template) = std::fs::read_to_string(&path) else {
+				continue;
+			};
+			let template = template.trim_end_matches('\n').to_string();
+			validate(kind, &template).map_err(|e| eyre!("{}: {e}", path.display()))?;
+			tracing::debug!("Using prompt template {}", path.display());
+			overrides.insert(kind, template);
+		}
+		Ok(Self { overrides })
+	}
+
+	/// The template of `kind` with its placeholders filled: `{format_instructions}` by the kind's, the others from
+	/// `values` (empty when not given). Trailing whitespace left by empty ones is dropped.
+	pub fn render(&self, kind: PromptKind, values: &[(&str, &str)]) -> String {
+		let template = self.overrides.get(&kind).map_or(kind.built_in(), String::as_str);
+		let mut out = String::with_capacity(template.len());
+		let mut rest = template;
+		while let Some(start) = rest.find('{') {
+			out.push_str(&rest[..start]);
+			match placeholder_at(&rest[start..]) {
+				Some(name) => {
+					let value = match name {
+						"format_instructions" => kind.format_instructions(),
+						_ => values.iter().find(|(key, _)| *key == name).map_or("", |(_, value)| *value),
+					};
+					out.push_str(value);
+					rest = &rest[start + name.len() + 2..];
+				}
+				None => {
+					out.push('{');
+					rest = &rest[start + 1..];
+				}
+			}
+		}
+		out.push_str(rest);
+		out.truncate(out.trim_end().len());
+		out
+	}
+}
+
+/// The name of the placeholder `s` starts with: `{` then lowercase letters and underscores then `}`. Anything else
+/// (JSON, code) is plain text.
+fn placeholder_at(s: &str) -> Option<&str> {
+	let end = s.find('}')?;
+	let name = &s[1..end];
+	(!name.is_empty() && name.chars().all(|c| c.is_ascii_lowercase() || c == '_')).then_some(name)
+}
+
+fn placeholders_in(template: &str) -> impl Iterator<Item = &str> {
+	template.match_indices('{').filter_map(|(i, _)| placeholder_at(&template[i..]))
+}
+
+/// Every placeholder is one `kind` knows, and `{format_instructions}` is there
+fn validate(kind: PromptKind, template: &str) -> Result<()> {
+	for name in placeholders_in(template) {
+		if !COMMON_PLACEHOLDERS.contains(&name) && !kind.placeholders().contains(&name) {
+			let known: Vec<String> = COMMON_PLACEHOLDERS.iter().chain(kind.placeholders()).map(|p| format!("{{{p}}}")).collect();
+			bail!("unknown placeholder {{{name}}} (known here: {})", known.join(", "));
+		}
+	}
+	if !placeholders_in(template).any(|name| name == "format_instructions") {
+		bail!("no {{format_instructions}}: without the JSON format the reply can't be parsed");
+	}
+	Ok(())
+}
+
+/// `prompts` in the config directory (`$XDG_CONFIG_HOME/uni_headless`, `~/.config/uni_headless`)
+pub fn default_prompts_dir() -> Option<PathBuf> {
+	let config_home = std::env::var_os("XDG_CONFIG_HOME")
+		.filter(|dir| !dir.is_empty())
+		.map(PathBuf::from)
+		.or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+	Some(config_home.join(env!("CARGO_PKG_NAME")).join("prompts"))
+}
+
+/// Write the built-in templates to `dir`, to start overriding them from. Files already there are left alone.
+/// Returns the paths written.
+pub fn dump(dir: &Path) -> Result<Vec<PathBuf>> {
+	std::fs::create_dir_all(dir).map_err(|e| eyre!("Failed to create {}: {e}", dir.display()))?;
+	let mut written = Vec::new();
+	for kind in PromptKind::ALL {
+		let path = dir.join(kind.file_name());
+		if path.exists() {
+			tracing::info!("Not overwriting {}", path.display());
+			continue;
+		}
+		std::fs::write(&path, format!("{}\n", kind.built_in())).map_err(|e| eyre!("Failed to write {}: {e}", path.display()))?;
+		written.push(path);
+	}
+	Ok(written)
+}
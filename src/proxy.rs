@@ -0,0 +1,146 @@
+//! Proxy for the launched browser and the HTTP clients (`proxy` in the config, or `--proxy`)
+//!
+//! Chrome takes the proxy as `--proxy-server` but has no flag for its credentials, so when the proxy needs them
+//! each page answers the auth challenges itself through the CDP `Fetch` domain.
+
+use std::fmt;
+
+use chromiumoxide::{
+	Page,
+	cdp::browser_protocol::fetch::{
+		AuthChallengeResponse, AuthChallengeResponseResponse, ContinueRequestParams, ContinueWithAuthParams, EnableParams, EventAuthRequired, EventRequestPaused,
+	},
+};
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+use futures::StreamExt;
+use reqwest::Url;
+use v_utils::elog;
+
+use crate::config::AppConfig;
+
+#[derive(Clone, Debug)]
+pub struct Proxy {
+	/// Without credentials
+	url: Url,
+	/// Hosts to reach directly, comma-separated (Chrome's `--proxy-bypass-list` syntax)
+	bypass: Option<String>,
+	username: Option<String>,
+	password: Option<String>,
+}
+
+impl Proxy {
+	/// The configured proxy, None if there is none. Errors on a malformed URL or an unsupported scheme. Credentials
+	/// may come from `proxy_username`/`proxy_password` or the URL itself.
+	pub fn from_config(config: &AppConfig) -> Result<Option<Self>> {
+		let Some(raw) = config.proxy.as_deref().filter(|p| !p.trim().is_empty()) else {
+			return Ok(None);
+		};
+		let mut url = Url::parse(raw.trim()).map_err(|e| eyre!("Invalid proxy URL: {e} (expected e.g. http://host:3128 or socks5://host:1080)"))?;
+		if !matches!(url.scheme(), "http" | "https" | "socks4" | "socks5") {
+			bail!("Unsupported proxy scheme {:?}: use http, https, socks4 or socks5", url.scheme());
+		}
+		if url.host_str().is_none_or(str::is_empty) {
+			bail!("Proxy URL has no host");
+		}
+
+		let username = config.proxy_username.clone().or_else(|| (!url.username().is_empty()).then(|| url.username().to_string()));
+		let password = config.proxy_password.clone().or_else(|| url.password().map(str::to_string));
+		let _ = url.set_username("");
+		let _ = url.set_password(None);
+		Ok(Some(Self {
+			url,
+			bypass: config.proxy_bypass.clone(),
+			username,
+			password,
+		}))
+	}
+
+	/// Command-line arguments for the launched Chrome
+	pub fn chrome_args(&self) -> Vec<String> {
+		let mut args = vec![format!("--proxy-server={self}")];
+		if let Some(bypass) = &self.bypass {
+			args.push(format!("--proxy-bypass-list={bypass}"));
+		}
+		args
+	}
+
+	/// Answer the proxy's auth challenges on `page`, if it has credentials. Call before the page's first navigation.
+	pub async fn handle_auth(&self, page: &Page) -> Result<()> {
+		let Some(username) = self.username.clone() else {
+			return Ok(());
+		};
+		let password = self.password.clone().unwrap_or_default();
+
+		let mut challenges = page.event_listener::<EventAuthRequired>().await.map_err(|e| eyre!("Failed to listen for proxy auth: {e}"))?;
+		// With auth handling on, Fetch pauses every request until it's let through
+		let mut paused = page
+			.event_listener::<EventRequestPaused>()
+			.await
+			.map_err(|e| eyre!("Failed to listen for paused requests: {e}"))?;
+		page.execute(EnableParams::builder().handle_auth_requests(true).build())
+			.await
+			.map_err(|e| eyre!("Failed to enable proxy auth handling: {e}"))?;
+
+		let auth_page = page.clone();
+		tokio::spawn(async move {
+			while let Some(event) = challenges.next().await {
+				let response = AuthChallengeResponse::builder()
+					.response(AuthChallengeResponseResponse::ProvideCredentials)
+					.username(username.clone())
+					.password(password.clone())
+					.build();
+				let result = match response {
+					Ok(response) => auth_page.execute(ContinueWithAuthParams::new(event.request_id.clone(), response)).await.map(|_| ()),
+					Err(e) => {
+						elog!("Failed to build the proxy auth response: {e}");
+						break;
+					}
+				};
+				if let Err(e) = result {
+					elog!("Failed to answer the proxy auth challenge: {e}");
+				}
+			}
+		});
+		let paused_page = page.clone();
+		tokio::spawn(async move {
+			while let Some(event) = paused.next().await {
+				let _ = paused_page.execute(ContinueRequestParams::new(event.request_id.clone())).await;
+			}
+		});
+		Ok(())
+	}
+
+	fn reqwest(&self) -> Result<reqwest::Proxy> {
+		let mut proxy = reqwest::Proxy::all(self.url.as_str()).map_err(|e| eyre!("Invalid proxy for HTTP requests: {e}"))?;
+		if let Some(username) = &self.username {
+			proxy = proxy.basic_auth(username, self.password.as_deref().unwrap_or_default());
+		}
+		if let Some(bypass) = &self.bypass {
+			proxy = proxy.no_proxy(reqwest::NoProxy::from_string(bypass));
+		}
+		Ok(proxy)
+	}
+}
+
+/// `scheme://host:port`, safe to log
+impl fmt::Display for Proxy {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}://{}", self.url.scheme(), self.url.host_str().unwrap_or_default())?;
+		if let Some(port) = self.url.port() {
+			write!(f, ":{port}")?;
+		}
+		Ok(())
+	}
+}
+
+/// HTTP client going through the configured proxy, if any
+pub fn http_client(config: &AppConfig) -> Result<reqwest::Client> {
+	let mut builder = reqwest::Client::builder();
+	if let Some(proxy) = Proxy::from_config(config)? {
+		builder = builder.proxy(proxy.reqwest()?);
+	}
+	builder.build().map_err(|e| eyre!("Failed to build the HTTP client: {e}"))
+}
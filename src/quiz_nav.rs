@@ -0,0 +1,121 @@
+//! Per-question state from the quiz navigation panel (`#mod_quiz_navblock`)
+//!
+//! Each `.qnbutton` carries the state of its question as a class (`answersaved`, `notyetanswered`, ...) and is
+//! numbered by the question's slot, the number after the colon in its field names. On a resumed attempt this is how
+//! answers saved by an earlier run are told apart from blank questions.
+
+use std::{collections::HashMap, fmt};
+
+use chromiumoxide::Page;
+use color_eyre::{Result, eyre::eyre};
+use serde::Deserialize;
+
+use crate::Question;
+
+/// A question's state, as Moodle names it in the button's classes (and `stateclass` in the webservice)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuestionState {
+	NotYetAnswered,
+	AnswerSaved,
+	/// Saved, but Moodle can't grade it as it is (e.g. a number it couldn't parse)
+	InvalidAnswer,
+	/// Saved, for the question types that say "complete" instead (essays)
+	Complete,
+	Correct,
+	PartiallyCorrect,
+	Incorrect,
+	RequiresGrading,
+	/// Anything else (`notanswered` on a finished attempt, a class of a newer Moodle)
+	Other,
+}
+
+impl QuestionState {
+	/// The state among `classes` (a button's `class` attribute, or a single state class)
+	pub fn from_classes(classes: &str) -> Self {
+		classes
+			.split_whitespace()
+			.find_map(|class| match class {
+				"notyetanswered" => Some(QuestionState::NotYetAnswered),
+				"answersaved" => Some(QuestionState::AnswerSaved),
+				"invalidanswer" => Some(QuestionState::InvalidAnswer),
+				"complete" => Some(QuestionState::Complete),
+				"correct" => Some(QuestionState::Correct),
+				"partiallycorrect" => Some(QuestionState::PartiallyCorrect),
+				"incorrect" => Some(QuestionState::Incorrect),
+				"requiresgrading" => Some(QuestionState::RequiresGrading),
+				_ => None,
+			})
+			.unwrap_or(QuestionState::Other)
+	}
+
+	/// Whether an answer is already saved that is worth keeping. Invalid and incorrect answers aren't.
+	pub fn has_saved_answer(self) -> bool {
+		matches!(
+			self,
+			QuestionState::AnswerSaved | QuestionState::Complete | QuestionState::Correct | QuestionState::PartiallyCorrect | QuestionState::RequiresGrading
+		)
+	}
+}
+
+impl fmt::Display for QuestionState {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			QuestionState::NotYetAnswered => "not yet answered",
+			QuestionState::AnswerSaved => "answer saved",
+			QuestionState::InvalidAnswer => "invalid answer",
+			QuestionState::Complete => "complete",
+			QuestionState::Correct => "correct",
+			QuestionState::PartiallyCorrect => "partially correct",
+			QuestionState::Incorrect => "incorrect",
+			QuestionState::RequiresGrading => "requires grading",
+			QuestionState::Other => "unknown state",
+		};
+		f.write_str(s)
+	}
+}
+
+/// One navigation button
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct NavEntry {
+	pub state: QuestionState,
+	pub flagged: bool,
+}
+
+/// Navigation panel entries by question slot; empty when the page has no panel
+pub async fn read_quiz_nav(page: &Page) -> Result<HashMap<u32, NavEntry>> {
+	let script = r#"
+		(function() {
+			const buttons = [];
+			for (const button of document.querySelectorAll('#mod_quiz_navblock .qnbutton')) {
+				const slot = (button.id || '').match(/quiznavbutton(\d+)/);
+				if (slot) buttons.push({ slot: Number(slot[1]), classes: button.className });
+			}
+			return JSON.stringify(buttons);
+		})()
+	"#;
+
+	#[derive(Deserialize)]
+	struct Button {
+		slot: u32,
+		classes: String,
+	}
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to read quiz navigation: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
+	let buttons: Vec<Button> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse quiz navigation JSON: {e}"))?;
+	Ok(buttons
+		.into_iter()
+		.map(|button| {
+			let entry = NavEntry {
+				state: QuestionState::from_classes(&button.classes),
+				flagged: button.classes.split_whitespace().any(|class| class == "flagged"),
+			};
+			(button.slot, entry)
+		})
+		.collect())
+}
+
+/// The question's slot in the attempt, from its field names ("q123:4_answer" is slot 4)
+pub fn question_slot(question: &Question) -> Option<u32> {
+	question.slot_key()?.split_once(':')?.1.parse().ok()
+}
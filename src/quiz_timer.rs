@@ -0,0 +1,109 @@
+//! The countdown of timed quizzes (`#quiz-timer`), and how much time that leaves per question
+//!
+//! Moodle auto-submits the attempt when the countdown runs out, answered or not. The countdown only ticks in the
+//! page's JS, so it is read again on every page and [`QuizClock::remaining_secs`] extrapolates from the last reading.
+
+use std::time::Instant;
+
+use chromiumoxide::Page;
+use color_eyre::{Result, eyre::eyre};
+use serde::Deserialize;
+
+use crate::config::AppConfig;
+
+/// Largest image still attached to LLM requests once time is tight
+pub const TIGHT_MAX_IMAGE_BYTES: usize = 256 * 1024;
+
+/// The countdown as last read from the page
+#[derive(Clone, Copy, Debug)]
+pub struct QuizClock {
+	time_left_secs: u64,
+	read_at: Instant,
+	/// Questions in the attempt, from the navigation panel
+	pub total_questions: Option<usize>,
+}
+
+/// How the rest of the attempt should be run given the time left
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum TimeBudget {
+	/// No countdown, or plenty of time
+	Relaxed,
+	/// Below `tight_question_budget_secs` per question: no terminal images, no large LLM attachments
+	Tight,
+	/// Below `panic_threshold_secs` overall: submit what is answered before Moodle does it with less
+	Panic,
+}
+
+impl QuizClock {
+	/// Seconds left now, counted down from the last reading
+	pub fn remaining_secs(&self) -> u64 {
+		self.time_left_secs.saturating_sub(self.read_at.elapsed().as_secs())
+	}
+
+	/// Under `panic_threshold_secs` left
+	pub fn is_out_of_time(&self, config: &AppConfig) -> bool {
+		self.remaining_secs() < config.panic_threshold_secs
+	}
+
+	/// Budget for the questions after the first `answered` ones, of which at least `on_page` are still to do
+	pub fn budget(&self, answered: usize, on_page: usize, config: &AppConfig) -> TimeBudget {
+		if self.is_out_of_time(config) {
+			return TimeBudget::Panic;
+		}
+		let per_question = self.remaining_secs() / self.questions_left(answered, on_page).max(1) as u64;
+		if per_question < config.tight_question_budget_secs {
+			return TimeBudget::Tight;
+		}
+		TimeBudget::Relaxed
+	}
+
+	/// Questions still to answer; without a navigation panel, only the ones on this page are known of
+	pub fn questions_left(&self, answered: usize, on_page: usize) -> usize {
+		self.total_questions.map(|total| total.saturating_sub(answered)).unwrap_or(0).max(on_page)
+	}
+}
+
+/// Read the countdown, None when the quiz isn't timed (or the timer isn't shown on this page)
+pub async fn read_quiz_clock(page: &Page) -> Result<Option<QuizClock>> {
+	let script = r#"
+		(function() {
+			const timer = document.querySelector('#quiz-time-left') || document.querySelector('#quiz-timer');
+			if (!timer) return null;
+			const questions = document.querySelectorAll('#mod_quiz_navblock .qnbutton').length;
+			return JSON.stringify({ text: timer.textContent, total_questions: questions || null });
+		})()
+	"#;
+
+	#[derive(Deserialize)]
+	struct Timer {
+		text: String,
+		total_questions: Option<usize>,
+	}
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to read quiz timer: {e}"))?;
+	let Some(json_str) = result.value().and_then(|v| v.as_str()) else {
+		return Ok(None);
+	};
+	let timer: Timer = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse quiz timer JSON: {e}"))?;
+	Ok(parse_time_left(&timer.text).map(|time_left_secs| QuizClock {
+		time_left_secs,
+		read_at: Instant::now(),
+		total_questions: timer.total_questions,
+	}))
+}
+
+/// Seconds in a countdown text such as "Time left 0:29:45" or "Temps restant 4:07"; None when there is no
+/// `[[h:]m:]s` group in it
+pub fn parse_time_left(text: &str) -> Option<u64> {
+	let clock = text.split_whitespace().rev().find(|word| word.contains(':'))?;
+	let mut secs = 0;
+	for part in clock.split(':') {
+		secs = secs * 60 + part.parse::<u64>().ok()?;
+	}
+	Some(secs)
+}
+
+/// "1:02:05" for 3725 seconds, the way Moodle shows it
+pub fn format_secs(secs: u64) -> String {
+	format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
@@ -0,0 +1,350 @@
+//! Machine-readable summary of a run, written as `report.json` into the session directory (or to `--report`)
+//!
+//! Filled in as the run goes: one [`UrlReport`] per processed URL, with the questions answered on it or the VPL
+//! evaluations made, then the LLM usage and exit status at the end. The stop hook gets the path of a snapshot.
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::{Result, eyre::eyre};
+use serde::Serialize;
+
+use crate::{
+	Question,
+	answers::normalize_whitespace,
+	llm::{LlmAnswer, LlmAnswerResult},
+	marks::QuestionMarks,
+	usage::UsageTotals,
+};
+
+/// Question text kept in the report, in chars
+const TEXT_EXCERPT_CHARS: usize = 200;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UrlKind {
+	Quiz,
+	Vpl,
+}
+
+/// How processing a URL ended
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "status", content = "error", rename_all = "snake_case")]
+pub enum UrlOutcome {
+	/// All answers submitted, or full marks on VPL
+	Success,
+	/// Nothing submitted, or VPL short of full marks
+	Failure,
+	Error(String),
+	/// Not processed, because an earlier URL that wasn't optional failed
+	Skipped,
+}
+
+impl std::fmt::Display for UrlOutcome {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			UrlOutcome::Success => write!(f, "success"),
+			UrlOutcome::Failure => write!(f, "failed"),
+			UrlOutcome::Error(e) => write!(f, "error: {e}"),
+			UrlOutcome::Skipped => write!(f, "skipped (earlier failure)"),
+		}
+	}
+}
+
+/// Where an answer came from
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerSource {
+	AnswersFile,
+	Cache,
+	Llm,
+	/// None sought: the question type isn't supported, so it was left unanswered
+	Unsupported,
+	/// None sought: the question type isn't in `question_types`, so it was left to the user
+	Skipped,
+	/// None sought: an answer was already saved in the attempt (resumed), and `reanswer` is off
+	AlreadySaved,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QuestionReport {
+	/// Question number in the run's order
+	pub number: usize,
+	/// Question type, as in the `[...]` marker shown in the terminal
+	pub kind: String,
+	/// Start of the question text
+	pub text: String,
+	pub source: AnswerSource,
+	/// None when no answer could be obtained (see `error`)
+	pub answer: Option<LlmAnswerResult>,
+	/// `answer` as displayed in the terminal, with option values resolved to their text
+	pub answer_text: Option<String>,
+	pub confidence: Option<u8>,
+	/// The LLM's one-sentence reasoning for the answer
+	pub reasoning: Option<String>,
+	/// Time the LLM took to answer, retries and escalations included
+	pub llm_latency_ms: Option<u64>,
+	/// What the question is worth, None without a grade line
+	pub max_mark: Option<f64>,
+	/// The mark shown on the page, once graded
+	pub mark: Option<f64>,
+	pub error: Option<String>,
+	/// Whether the answer went into a submitted (or webservice-saved) page
+	pub submitted: bool,
+	/// First form field name, to find the entry again when its page gets submitted
+	#[serde(skip)]
+	field: Option<String>,
+}
+
+impl QuestionReport {
+	pub fn new(number: usize, question: &Question, source: AnswerSource) -> Self {
+		Self {
+			number,
+			kind: question.kind().to_string(),
+			text: normalize_whitespace(question.question_text()).chars().take(TEXT_EXCERPT_CHARS).collect(),
+			source,
+			answer: None,
+			answer_text: None,
+			confidence: None,
+			reasoning: None,
+			llm_latency_ms: None,
+			max_mark: None,
+			mark: None,
+			error: None,
+			submitted: false,
+			field: question.field_names().into_iter().next().map(str::to_string),
+		}
+	}
+
+	/// Set the answer and its displayed form
+	pub fn answered(mut self, question: &Question, answer: &LlmAnswer) -> Self {
+		self.answer_text = Some(answer.result.display_with(question).to_string());
+		self.answer = Some(answer.result.clone());
+		self.confidence = answer.confidence;
+		self.reasoning = answer.reasoning.clone();
+		self
+	}
+}
+
+/// One VPL evaluation
+#[derive(Clone, Debug, Serialize)]
+pub struct VplAttemptReport {
+	/// 1-based
+	pub attempt: usize,
+	/// Proposed grade as shown by VPL (e.g. "75%"); None if it couldn't be read
+	pub grade: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct UrlReport {
+	pub url: String,
+	pub kind: UrlKind,
+	/// None while the URL is still being processed
+	pub outcome: Option<UrlOutcome>,
+	pub questions: Vec<QuestionReport>,
+	pub vpl_attempts: Vec<VplAttemptReport>,
+}
+
+impl UrlReport {
+	/// Entries with this source, once each (a page revisited from the attempt summary is parsed again)
+	fn questions_from(&self, source: AnswerSource) -> Vec<&QuestionReport> {
+		let mut entries: Vec<&QuestionReport> = Vec::new();
+		for entry in self.questions.iter().filter(|e| e.source == source) {
+			if !entries.iter().any(|seen| seen.field == entry.field) {
+				entries.push(entry);
+			}
+		}
+		entries
+	}
+}
+
+/// How the whole run ended
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExitStatus {
+	Success,
+	Failure,
+	Error,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct RunReport {
+	pub session_id: String,
+	pub urls: Vec<UrlReport>,
+	/// LLM calls, tokens and estimated cost; None when the LLM wasn't used
+	pub llm_usage: Option<UsageTotals>,
+	/// None until the run is over (e.g. in the snapshot the stop hook gets)
+	pub exit_status: Option<ExitStatus>,
+	/// Process exit code, see [`crate::failure`]; None until the run is over
+	pub exit_code: Option<i32>,
+	/// Where the report is written; None to not write one
+	#[serde(skip)]
+	path: Option<PathBuf>,
+}
+
+impl RunReport {
+	pub fn new(session_id: impl Into<String>, path: Option<PathBuf>) -> Self {
+		Self {
+			session_id: session_id.into(),
+			path,
+			..Default::default()
+		}
+	}
+
+	pub fn path(&self) -> Option<&Path> {
+		self.path.as_deref()
+	}
+
+	/// Start the entry for the next URL; everything recorded until the next call goes to it
+	pub fn start_url(&mut self, url: &str, kind: UrlKind) {
+		self.urls.push(UrlReport {
+			url: url.to_string(),
+			kind,
+			outcome: None,
+			questions: Vec::new(),
+			vpl_attempts: Vec::new(),
+		});
+	}
+
+	pub fn finish_url(&mut self, outcome: UrlOutcome) {
+		if let Some(url) = self.urls.last_mut() {
+			url.outcome = Some(outcome);
+		}
+	}
+
+	/// Record a URL that won't be processed
+	pub fn skip_url(&mut self, url: &str, kind: UrlKind) {
+		self.start_url(url, kind);
+		self.finish_url(UrlOutcome::Skipped);
+	}
+
+	/// One line per URL with its outcome
+	pub fn outcome_table(&self) -> String {
+		self.urls
+			.iter()
+			.map(|url| match &url.outcome {
+				Some(outcome) => format!("{outcome}: {}", url.url),
+				None => format!("unfinished: {}", url.url),
+			})
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+
+	pub fn question(&mut self, entry: QuestionReport) {
+		if let Some(url) = self.urls.last_mut() {
+			url.questions.push(entry);
+		}
+	}
+
+	/// Replace the answer of the latest entry for `question` (after a retry on an interactive quiz)
+	pub fn update_answer(&mut self, question: &Question, answer: &LlmAnswer) {
+		if let Some(entry) = self.find_question(question) {
+			entry.answer_text = Some(answer.result.display_with(question).to_string());
+			entry.answer = Some(answer.result.clone());
+			entry.confidence = answer.confidence;
+			entry.reasoning = answer.reasoning.clone();
+		}
+	}
+
+	/// Set the marks of the latest entry for `question`
+	pub fn set_marks(&mut self, question: &Question, marks: QuestionMarks) {
+		if let Some(entry) = self.find_question(question) {
+			entry.max_mark = marks.max;
+			entry.mark = marks.current;
+		}
+	}
+
+	/// Mark the latest entries for these questions as submitted
+	pub fn mark_submitted<'a>(&mut self, questions: impl IntoIterator<Item = &'a Question>) {
+		for question in questions {
+			if let Some(entry) = self.find_question(question) {
+				entry.submitted = true;
+			}
+		}
+	}
+
+	/// Questions of the current URL whose answer came from `source` (e.g. the unsupported or skipped ones)
+	pub fn questions_from(&self, source: AnswerSource) -> Vec<&QuestionReport> {
+		self.urls.last().map(|url| url.questions_from(source)).unwrap_or_default()
+	}
+
+	/// Question counts over the whole run, e.g. "18 answered, 2 skipped (already saved), 1 failed"; None without
+	/// questions. A question seen more than once (revisited from the attempt summary) counts once, by its best outcome.
+	pub fn question_summary(&self) -> Option<String> {
+		let (mut answered, mut already_saved, mut skipped, mut unsupported, mut failed) = (0, 0, 0, 0, 0);
+		for url in &self.urls {
+			let mut fields: Vec<Option<&str>> = Vec::new();
+			for entry in &url.questions {
+				if !fields.contains(&entry.field.as_deref()) {
+					fields.push(entry.field.as_deref());
+				}
+			}
+			for field in fields {
+				let entries: Vec<&QuestionReport> = url.questions.iter().filter(|e| e.field.as_deref() == field).collect();
+				let any_from = |source: AnswerSource| entries.iter().any(|e| e.source == source);
+				if entries.iter().any(|e| e.answer.is_some()) {
+					answered += 1;
+				} else if any_from(AnswerSource::AlreadySaved) {
+					already_saved += 1;
+				} else if any_from(AnswerSource::Skipped) {
+					skipped += 1;
+				} else if any_from(AnswerSource::Unsupported) {
+					unsupported += 1;
+				} else {
+					failed += 1;
+				}
+			}
+		}
+		if answered + already_saved + skipped + unsupported + failed == 0 {
+			return None;
+		}
+
+		let mut parts = vec![format!("{answered} answered")];
+		for (count, label) in [
+			(already_saved, "skipped (already saved)"),
+			(skipped, "skipped (not in --question-types)"),
+			(unsupported, "unsupported"),
+			(failed, "failed"),
+		] {
+			if count > 0 {
+				parts.push(format!("{count} {label}"));
+			}
+		}
+		Some(parts.join(", "))
+	}
+
+	/// Questions over the whole run whose answer came from `source`
+	pub fn count_from(&self, source: AnswerSource) -> usize {
+		self.urls.iter().map(|url| url.questions_from(source).len()).sum()
+	}
+
+	pub fn vpl_attempt(&mut self, grade: Option<String>) {
+		if let Some(url) = self.urls.last_mut() {
+			let attempt = url.vpl_attempts.len() + 1;
+			url.vpl_attempts.push(VplAttemptReport { attempt, grade });
+		}
+	}
+
+	/// Write the report to its path, if it has one
+	pub fn save(&self) -> Result<()> {
+		let Some(path) = &self.path else {
+			return Ok(());
+		};
+		let json = serde_json::to_string_pretty(self).map_err(|e| eyre!("Failed to serialize run report: {e}"))?;
+		std::fs::write(path, json).map_err(|e| eyre!("Failed to write run report {}: {e}", path.display()))
+	}
+
+	/// Whether the current URL already has an answer for `question`
+	pub fn has_answer(&self, question: &Question) -> bool {
+		let Some(field) = question.field_names().into_iter().next() else {
+			return false;
+		};
+		self.urls
+			.last()
+			.is_some_and(|url| url.questions.iter().any(|entry| entry.field.as_deref() == Some(field) && entry.answer.is_some()))
+	}
+
+	fn find_question(&mut self, question: &Question) -> Option<&mut QuestionReport> {
+		let field = question.field_names().into_iter().next()?;
+		self.urls.last_mut()?.questions.iter_mut().rev().find(|entry| entry.field.as_deref() == Some(field))
+	}
+}
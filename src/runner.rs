@@ -1,15 +1,11 @@
 //! Page execution logic - handles VPL and quiz pages
 
-#[cfg(feature = "xdg")]
-use std::path::PathBuf;
-
 use chromiumoxide::Page;
 use color_eyre::{
 	Result,
-	eyre::{bail, eyre},
+	eyre::{WrapErr, bail, eyre},
 };
-#[cfg(feature = "xdg")]
-use v_utils::xdg_state_dir;
+use serde::{Deserialize, Serialize};
 use v_utils::{
 	Percent, elog,
 	io::{ConfirmResult, confirmation},
@@ -17,80 +13,175 @@ use v_utils::{
 };
 
 use crate::{
-	Blank, Choice, DragChoice, DragDropIntoText, DropZone, FillInBlanks, FillSegment, Image, MatchItem, MatchOption, Question, RequiredFile,
-	config::AppConfig,
-	llm::{FillInBlanksAnswerItem, LlmAnswerResult, ask_llm_for_answer, ask_llm_for_code, retry_llm_with_test_results},
+	Blank, Choice, DragChoice, DragDropIntoText, DropZone, EditorKind, FillInBlanks, FillSegment, Image, MatchItem, MatchOption, OrderingItem, Question, QuestionKind, RequiredFile,
+	StackInput,
+	answer_edit::{edit_hint, parse_answer_edit},
+	answers::AnswerBook,
+	api::{MoodleWs, WsAttempt, WsError, WsVplResult},
+	config::{AppConfig, ConfirmMode, ImageRenderer},
+	decimal_separator, display,
+	emit::{Emitter, Event},
+	failure::FailureKind,
+	images::{ImageCache, load_lazy_images, question_image_urls, with_image_js},
+	js_string,
+	keyboard::type_text_answer,
+	llm::{AnswerFeedback, FillInBlanksAnswerItem, LlmAnswer, LlmAnswerResult, LlmCodeResult, PreviousSubmission, QuizLlm},
+	llm_backend::Conversation,
+	llm_error::LlmErrorKind,
+	local_check::run_local_check,
+	login::{is_login_url, relogin},
+	marks::QuestionMarks,
+	page_state::inspect_page_state,
+	parse::{parse_questions_from_html, parse_response_fields},
+	quiz_nav::{QuestionState, question_slot, read_quiz_nav},
+	quiz_timer::{QuizClock, TIGHT_MAX_IMAGE_BYTES, TimeBudget, format_secs, read_quiz_clock},
+	report::{AnswerSource, QuestionReport, RunReport},
+	shutdown, stack,
+	store::SessionStore,
+	terminal_image,
+	timing::{action_delay, timings, wait},
 };
 
-/// Shared JS helper to check if text matches confirmation keywords
-const CONFIRMATION_MATCH_JS: &str = r#"
-	function isConfirmationText(text) {
-		const t = text.toLowerCase();
-		return t.includes('envoyer') || t.includes('terminer') || t.includes('submit') || t.includes('finir') || t.includes('confirm') || t.includes('valider');
-	}
-"#;
+/// JS helper `isConfirmationText(text)`, matching `confirmation_keywords`
+fn confirmation_match_js(config: &AppConfig) -> String {
+	let keywords: Vec<String> = config.confirmation_keywords.iter().map(|k| k.to_lowercase()).collect();
+	let keywords_json = serde_json::to_string(&keywords).unwrap_or_else(|_| "[]".to_string());
+	format!(
+		r#"
+	function isConfirmationText(text) {{
+		const t = (text || '').toLowerCase();
+		return {keywords_json}.some(keyword => t.includes(keyword));
+	}}
+"#
+	)
+}
 /// Handle a VPL (Virtual Programming Lab) code submission page
 /// Returns true if got perfect grade (100%)
-pub async fn handle_vpl_page(page: &Page, ask_llm: bool, config: &mut AppConfig, session_id: &str) -> Result<bool> {
-	let question = parse_vpl_page(page).await?;
+pub async fn handle_vpl_page(page: &Page, llm: Option<&QuizLlm>, images: &ImageCache, config: &mut AppConfig, report: &mut RunReport, store: &SessionStore) -> Result<bool> {
+	let question = parse_vpl_page(page, config).await?;
 
 	let Some(question) = question else {
-		log!("No VPL question found on this page.");
-		return Ok(false);
+		return Err(eyre!("No VPL question found on this page").wrap_err(FailureKind::Parse));
 	};
 
 	// Display the question
+	let output = config.output;
 	let header = "--- Code Submission [VPL] ---";
 	tracing::info!("{header}");
-	eprintln!("{header}");
+	output.human(header);
 
 	let text = question.question_text();
 	tracing::info!("{text}");
-	eprintln!("{text}");
+	output.human(text);
+	output.emit(&Event::question(1, &question));
 
 	// Display images
-	for img in question.images() {
-		if let Err(e) = display_image_chafa(page, &img.url, 60).await {
-			elog!("Failed to display image: {}", e);
-			eprintln!("  [Image: {}]", img.alt.as_deref().unwrap_or(&img.url));
+	if !output.is_json() {
+		for img in question.images() {
+			display_image(page, images, output, img, 60, "  ").await;
+			display_ocr_text(page, images, output, &img.url, "  ").await;
 		}
 	}
 
 	// Display required files
 	let required_files = question.required_files();
 	if !required_files.is_empty() {
-		eprintln!("\nRequired files:");
+		output.human("\nRequired files:");
 		for file in required_files {
 			if file.content.is_empty() {
-				eprintln!("  - {}", file.name);
+				output.human(format_args!("  - {}", file.name));
 			} else {
-				eprintln!("  - {} (has template)", file.name);
+				output.human(format_args!("  - {} (has template)", file.name));
 			}
 		}
 	}
-	eprintln!();
+	output.human("");
 
-	if !ask_llm {
+	let Some(llm) = llm else {
 		// If not using LLM, just display the question
 		return Ok(false);
+	};
+	if !config.answers_kind(QuestionKind::CodeSubmission) {
+		log!("VPL code is not in --question-types, leaving it to you");
+		return Ok(false);
+	}
+
+	// Submit through the webservice when a token is configured, skipping the editor entirely
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
+	let ws = MoodleWs::from_config(config, &current_url);
+	let Question::CodeSubmission { module_id, .. } = &question else {
+		unreachable!("parse_vpl_page only returns CodeSubmission questions")
+	};
+	let vpl_id = module_id.parse::<u64>().ok();
+
+	// Whatever is already submitted, so a re-run improves on it instead of starting over.
+	// Without the webservice this means opening the editor now (and staying there for the paste).
+	let mut grade = parse_vpl_work_state_grade(page).await.unwrap_or_else(|e| {
+		elog!("Failed to read the VPL work state: {e}");
+		None
+	});
+	// Evaluation cap, if the activity has one; retries must leave at least one for a human
+	let remaining = parse_vpl_remaining_submissions(page).await.unwrap_or_else(|e| {
+		elog!("Failed to read the VPL submission limit: {e}");
+		None
+	});
+	let mut previous_files = None;
+	if let (Some(ws), Some(vpl_id)) = (&ws, vpl_id) {
+		match ws.vpl_open(vpl_id).await {
+			Ok(submission) => {
+				let ws_grade = submission.grade.trim().trim_start_matches("Proposed grade:").trim();
+				if !ws_grade.is_empty() {
+					grade = Some(ws_grade.to_string());
+				}
+				previous_files = Some(submission.files.into_iter().map(|f| (f.name, f.data)).collect());
+			}
+			Err(e) => elog!("Failed to fetch the current VPL submission through the webservice: {e}"),
+		}
+	}
+
+	// Already solved: don't overwrite a perfect submission unless asked to
+	if let Some(current) = grade.as_deref()
+		&& parse_grade_fraction(current).is_some_and(|g| g >= 1.0)
+	{
+		if !config.force_vpl {
+			log!("Already at full marks ({current}), skipping");
+			return Ok(true);
+		}
+		log!("Already at full marks ({current}), resubmitting anyway (--force-vpl)");
+	}
+
+	let in_editor = previous_files.is_none();
+	let previous_files = match previous_files {
+		Some(files) => files,
+		None => {
+			log!("Navigating to VPL editor...");
+			if !open_vpl_editor(page).await? {
+				elog!("Could not find Edit button on VPL page");
+				return Ok(false);
+			}
+			read_vpl_editor_files(page, question.required_files().first().map(|f| f.name.as_str())).await.unwrap_or_else(|e| {
+				elog!("Failed to read the current VPL submission: {e}");
+				Vec::new()
+			})
+		}
+	};
+	let previous = previous_submission(previous_files, grade.clone(), question.required_files());
+	if let Some(previous) = &previous {
+		let grade = previous.grade.as_deref().unwrap_or("not evaluated");
+		log!(
+			"Found a previous submission ({} file(s), grade: {grade}), giving it to the LLM as a starting point",
+			previous.files.len()
+		);
 	}
 
 	// Ask LLM to generate code
 	log!("Asking LLM to generate code solution...");
-	let code_result = match ask_llm_for_code(&question, config).await {
+	let code_result = match generate_vpl_code(llm, &question, previous.as_ref(), config).await {
 		Ok(result) => {
-			eprintln!("\nGenerated code:");
-			for (filename, content) in &result.files {
-				eprintln!("\n=== {filename} ===");
-				eprintln!("{content}");
-			}
-			eprintln!();
+			show_code(config.output, "Generated code", &result.files);
 			result
 		}
-		Err(e) => {
-			elog!("Failed to generate code: {}", e);
-			return Ok(false);
-		}
+		Err(e) => return Err(e.wrap_err("Failed to generate code").wrap_err(FailureKind::Llm)),
 	};
 
 	if code_result.files.is_empty() {
@@ -99,153 +190,492 @@ pub async fn handle_vpl_page(page: &Page, ask_llm: bool, config: &mut AppConfig,
 	}
 
 	// Ask for confirmation before pasting (skip if auto_submit is enabled)
-	if !config.auto_submit && confirmation("Paste generated code into editor?").flush().await != ConfirmResult::Yes {
+	let confirm_msg = if ws.is_some() && vpl_id.is_some() {
+		"Submit generated code?"
+	} else {
+		"Paste generated code into editor?"
+	};
+	if !config.auto_submit && confirmation(confirm_msg).flush().await != ConfirmResult::Yes {
 		log!("Cancelled by user");
 		return Ok(false);
 	}
 
+	if let (Some(ws), Some(vpl_id)) = (&ws, vpl_id) {
+		log!("Saving code through the webservice...");
+		match ws.vpl_save(vpl_id, &code_result.files).await {
+			Ok(()) => return submit_vpl_via_webservice(llm, ws, vpl_id, code_result.conversation, code_result.files, remaining, grade, config, report).await,
+			Err(e) if is_ws_capability_error(&e) => log!("Webservice unavailable for VPL ({e}), using the editor"),
+			Err(e) => return Err(e),
+		}
+	}
+
 	// Track conversation for retries
 	let mut conversation = code_result.conversation;
 	let mut files = code_result.files;
 
-	// Navigate to the Edit page (only on first attempt)
-	log!("Navigating to VPL editor...");
-	if !click_vpl_edit_button(page).await? {
-		elog!("Could not find Edit button on VPL page");
-		return Ok(false);
+	// Navigate to the Edit page (only on first attempt, unless reading the previous submission already did)
+	if !in_editor {
+		log!("Navigating to VPL editor...");
+		if !open_vpl_editor(page).await? {
+			elog!("Could not find Edit button on VPL page");
+			return Ok(false);
+		}
 	}
 
-	// Wait for editor page to fully load
-	page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for navigation: {e}"))?;
-	tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+	// Remember the editor URL so an expired session can be resumed there
+	let editor_url = page.url().await.ok().flatten().unwrap_or_default();
+
+	// Required files plus whatever the LLM produced, so nothing gets dropped for lack of a tab
+	let mut wanted_files: Vec<String> = question.required_files().iter().map(|f| f.name.clone()).collect();
+	for (filename, _) in &files {
+		if !wanted_files.contains(filename) {
+			wanted_files.push(filename.clone());
+		}
+	}
+	if let Err(e) = ensure_vpl_files(page, &wanted_files).await {
+		elog!("Failed to create missing VPL files: {e}");
+	}
 
 	// Retry loop for test failures
 	let max_retries = config.max_consecutive_failures;
+	// A retry without any diagnostics to go on is only worth trying once
+	let mut generic_retry_used = false;
+	let mut remaining = remaining;
+	let mut current_grade = grade;
+	// Best evaluated attempt, restored if we never reach full marks so Moodle keeps our best code
+	let mut best: Option<(Percent, Vec<(String, String)>)> = None;
+	let mut failure = String::from("Exhausted all retry attempts");
 	for attempt in 0..=max_retries {
+		shutdown::check()?;
 		if attempt > 0 {
 			log!("Retry attempt {attempt}/{max_retries}");
 		}
 
+		// Slow LLM calls can outlive the Moodle session
+		let current_url = page.url().await.ok().flatten().unwrap_or_default();
+		if is_login_url(&current_url) {
+			relogin(page, &editor_url, config, store).await.wrap_err(FailureKind::Login)?;
+			wait(timings().post_navigation_wait_ms).await;
+		}
+
 		// Save the editor page HTML
-		#[cfg(feature = "xdg")]
-		if let Err(e) = save_page_html(page, session_id).await {
+		if let Err(e) = store.save_page_snapshot(page).await {
 			elog!("Failed to save editor page HTML: {e}");
 		}
 
-		log!("Pasting code into editor...");
-		tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-		for (filename, content) in &files {
-			// Prepend empty line - VPL panics without it
-			let content = format!("\n{content}");
-			if let Err(e) = set_vpl_file_content(page, filename, &content).await {
-				elog!("Failed to set content for {filename}: {e}");
-			}
-		}
-		tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
+		paste_and_save_vpl_files(page, llm, report, &files, config).await?;
 
-		log!("Saving code...");
-		tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-		if !click_vpl_button_with_retry(page, "save", config.button_click_retries).await? {
-			run_stop_hook(config, "Could not find Save button");
-			bail!("Could not find Save button - aborting");
+		if config.dry_run {
+			log!("Dry run: code pasted and saved, not running evaluation");
+			return Ok(true);
 		}
 
-		tokio::time::sleep(tokio::time::Duration::from_secs(3)).await;
+		check_vpl_submissions_left(llm, report, remaining, current_grade.as_deref(), config).await?;
+
+		wait(timings().post_save_wait_ms).await;
 		log!("Running evaluation...");
 		if !click_vpl_button_with_retry(page, "evaluate", config.button_click_retries).await? {
-			run_stop_hook(config, "Could not find Evaluate button");
+			run_stop_hook(config, Some(llm), report, "Could not find Evaluate button");
 			bail!("Could not find Evaluate button - aborting");
 		}
 		log!("Waiting for evaluation results...");
-		tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+		shutdown::sleep(std::time::Duration::from_millis(timings().evaluation_wait_ms)).await?;
+		// The console sometimes reports the new count; otherwise assume this evaluation used one up
+		remaining = parse_vpl_remaining_submissions(page).await.ok().flatten().or(remaining.map(|r| r.saturating_sub(1)));
 
 		let eval_result = parse_vpl_evaluation_result(page).await?;
 		if let Some(result) = &eval_result {
-			eprintln!("\n=== Evaluation Result ===");
-			eprintln!("{result}");
+			config.output.human(format_args!("\n=== Evaluation Result ===\n{result}"));
 		} else {
 			log!("No evaluation result found (may still be running)");
 		}
 
 		// Parse proposed grade
 		let grade = parse_vpl_proposed_grade(page).await?;
-		if let Some(grade) = grade {
-			eprintln!("Proposed grade: {grade}");
-			if grade >= 1.0 {
-				log!("Full marks! Evaluation successful.");
-				run_stop_hook(config, "VPL: Full marks!");
-				return Ok(true);
+		report.vpl_attempt(grade.as_ref().map(|g| g.to_string()));
+		let Some(grade) = grade else {
+			run_stop_hook(config, Some(llm), report, "VPL: Could not find proposed grade");
+			bail!("Could not find proposed grade in evaluation results");
+		};
+		config.output.human(format_args!("Proposed grade: {grade}"));
+		config.output.emit(&Event::VplGrade { value: grade.0 });
+		current_grade = Some(grade.to_string());
+		if grade >= 1.0 {
+			log!("Full marks! Evaluation successful.");
+			run_stop_hook(config, Some(llm), report, "VPL: Full marks!");
+			return Ok(true);
+		}
+		if best.as_ref().is_none_or(|(best_grade, _)| grade > *best_grade) {
+			best = Some((grade, files.clone()));
+		}
+
+		// Not perfect - read what went wrong and retry
+		if attempt == max_retries {
+			failure = format!("Failed after {max_retries} retries");
+			break;
+		}
+		if !has_spare_vpl_submission(remaining) {
+			failure = "Stopped to keep the last submission".to_string();
+			break;
+		}
+		let diagnostics = parse_vpl_diagnostics(page).await?;
+		let Some(retry_message) = diagnostics.retry_message(grade, !generic_retry_used) else {
+			elog!("No compiler or test output to retry with");
+			failure = "Could not parse test results".to_string();
+			break;
+		};
+		diagnostics.print(config.output);
+		generic_retry_used |= diagnostics == VplDiagnostics::NoOutput;
+
+		// Ask LLM to fix the code with the diagnostics
+		log!("Asking LLM to fix the code based on {}...", diagnostics.kind());
+		let result = match regenerate_vpl_code(llm, conversation, &retry_message, config).await {
+			Ok(result) => result,
+			Err(e) => {
+				elog!("Failed to regenerate code: {}", e);
+				failure = format!("Failed to regenerate code: {e}");
+				break;
 			}
+		};
+		show_code(config.output, "Regenerated code", &result.files);
 
-			// Not perfect - try to get test results and retry
-			if attempt < max_retries {
-				let test_results = parse_vpl_test_results(page).await?;
-				if let Some(test_results) = test_results {
-					eprintln!("\n=== Test Failure Details ===");
-					eprintln!("{test_results}");
-
-					// Ask LLM to fix the code with test results
-					log!("Asking LLM to fix the code based on test results...");
-					match retry_llm_with_test_results(conversation, &test_results, config).await {
-						Ok(result) => {
-							eprintln!("\nRegenerated code:");
-							for (filename, content) in &result.files {
-								eprintln!("\n=== {filename} ===");
-								eprintln!("{content}");
-							}
-							eprintln!();
+		// Ask for confirmation before pasting regenerated code
+		if !config.auto_submit && confirmation("Paste regenerated code into editor?").flush().await != ConfirmResult::Yes {
+			log!("Cancelled by user");
+			failure = "Cancelled by user".to_string();
+			break;
+		}
 
-							// Ask for confirmation before pasting regenerated code
-							if !config.auto_submit && confirmation("Paste regenerated code into editor?").flush().await != ConfirmResult::Yes {
-								log!("Cancelled by user");
-								run_stop_hook(config, "VPL: Cancelled by user");
-								bail!("Evaluation failed: got {} (expected 100%)", grade * Percent(1.0));
-							}
+		// Update for next iteration
+		conversation = result.conversation;
+		files = result.files;
+	}
 
-							// Update for next iteration
-							conversation = result.conversation;
-							files = result.files;
-							continue;
-						}
-						Err(e) => {
-							elog!("Failed to regenerate code: {}", e);
-							run_stop_hook(config, &format!("VPL: Failed to regenerate code: {e}"));
-							bail!("Evaluation failed: got {} (expected 100%)", grade * Percent(1.0));
-						}
-					}
-				} else {
-					elog!("Could not parse test results for retry");
-					run_stop_hook(config, "VPL: Could not parse test results");
-					bail!("Evaluation failed: got {} (expected 100%)", grade * Percent(1.0));
-				}
-			} else {
-				let msg = format!("VPL: Failed after {} retries ({}%)", max_retries, grade * Percent(1.0));
-				run_stop_hook(config, &msg);
-				bail!("Evaluation failed after {} retries: got {} (expected 100%)", max_retries, grade * Percent(1.0));
+	let Some((best_grade, best_files)) = best else {
+		run_stop_hook(config, Some(llm), report, &format!("VPL: {failure}"));
+		bail!("{failure}");
+	};
+	if best_files != files {
+		log!("Restoring the best attempt ({best_grade})...");
+		paste_and_save_vpl_files(page, llm, report, &best_files, config).await?;
+	}
+	run_stop_hook(config, Some(llm), report, &format!("VPL: {failure} (best: {})", best_grade * Percent(1.0)));
+	Err(eyre!("Evaluation failed ({failure}): best attempt got {} (expected 100%) and was restored", best_grade * Percent(1.0)).wrap_err(FailureKind::BelowTarget))
+}
+
+/// `QuizLlm::generate_code`, then the local pre-flight check
+async fn generate_vpl_code(llm: &QuizLlm, question: &Question, previous: Option<&PreviousSubmission>, config: &AppConfig) -> Result<LlmCodeResult> {
+	let result = llm.generate_code(question, previous).await?;
+	check_code_locally(llm, result, config).await
+}
+
+/// `QuizLlm::retry_code`, then the local pre-flight check
+async fn regenerate_vpl_code(llm: &QuizLlm, conversation: Conversation, feedback: &str, config: &AppConfig) -> Result<LlmCodeResult> {
+	let result = llm.retry_code(conversation, feedback).await?;
+	check_code_locally(llm, result, config).await
+}
+
+/// Run the configured `local_check_cmd`s on generated code and send failures straight back to the LLM, so code that
+/// doesn't even compile never costs a browser round-trip. Gives up after a few local retries and returns the last
+/// version anyway (the real evaluation will report what's wrong).
+async fn check_code_locally(llm: &QuizLlm, mut result: LlmCodeResult, config: &AppConfig) -> Result<LlmCodeResult> {
+	const LOCAL_RETRIES: u32 = 3;
+
+	if config.local_check_cmd.is_empty() {
+		return Ok(result);
+	}
+	for attempt in 0..=LOCAL_RETRIES {
+		let Some(output) = run_local_check(&result.files, &config.local_check_cmd).await? else {
+			if attempt > 0 {
+				log!("Local check passed");
 			}
-		} else {
-			run_stop_hook(config, "VPL: Could not find proposed grade");
-			bail!("Could not find proposed grade in evaluation results");
+			return Ok(result);
+		};
+		config.output.human(format_args!("\n=== Local Check Failed ===\n{output}"));
+		if attempt == LOCAL_RETRIES {
+			elog!("Local check still failing after {LOCAL_RETRIES} retries, submitting anyway");
+			return Ok(result);
+		}
+
+		log!("Asking LLM to fix the code based on the local check ({}/{LOCAL_RETRIES})...", attempt + 1);
+		result = llm.retry_code(result.conversation, &format!("The code failed to compile with:\n{output}")).await?;
+		for (filename, content) in &result.files {
+			tracing::debug!("Locally regenerated {filename}:\n{content}");
+		}
+	}
+	Ok(result)
+}
+
+/// Print generated files under a title, for the user to look over before they're pasted
+fn show_code(output: Emitter, title: &str, files: &[(String, String)]) {
+	output.human(format_args!("\n{title}:"));
+	for (filename, content) in files {
+		output.human(format_args!("\n=== {filename} ===\n{content}"));
+	}
+	output.human("");
+}
+
+/// Paste every file into its editor tab and click Save
+async fn paste_and_save_vpl_files(page: &Page, llm: &QuizLlm, report: &mut RunReport, files: &[(String, String)], config: &AppConfig) -> Result<()> {
+	log!("Pasting code into editor...");
+	wait(timings().step_wait_ms).await;
+	for (filename, content) in files {
+		// Prepend empty line - VPL panics without it
+		let content = format!("\n{content}");
+		if let Err(e) = set_vpl_file_content(page, filename, &content).await {
+			elog!("Failed to set content for {filename}: {e}");
+		}
+	}
+	wait(timings().step_wait_ms).await;
+
+	log!("Saving code...");
+	wait(timings().step_wait_ms).await;
+	if !click_vpl_button_with_retry(page, "save", config.button_click_retries).await? {
+		run_stop_hook(config, Some(llm), report, "Could not find Save button");
+		bail!("Could not find Save button - aborting");
+	}
+	Ok(())
+}
+/// Submit and evaluate VPL code through the webservice (`mod_vpl_*`), retrying with the LLM on failed tests like the
+/// editor path does. Expects the first version of `files` to already be saved.
+#[allow(clippy::too_many_arguments)]
+async fn submit_vpl_via_webservice(
+	llm: &QuizLlm,
+	ws: &MoodleWs,
+	vpl_id: u64,
+	mut conversation: Conversation,
+	mut files: Vec<(String, String)>,
+	mut remaining: Option<u32>,
+	mut current_grade: Option<String>,
+	config: &AppConfig,
+	report: &mut RunReport,
+) -> Result<bool> {
+	let max_retries = config.max_consecutive_failures;
+	let mut generic_retry_used = false;
+	let mut best: Option<(Percent, Vec<(String, String)>)> = None;
+	let mut failure = String::from("Exhausted all retry attempts");
+	for attempt in 0..=max_retries {
+		shutdown::check()?;
+		if attempt > 0 {
+			log!("Retry attempt {attempt}/{max_retries}");
+			log!("Saving code through the webservice...");
+			ws.vpl_save(vpl_id, &files).await?;
+		}
+
+		if config.dry_run {
+			log!("Dry run: code saved, not running evaluation");
+			return Ok(true);
+		}
+
+		check_vpl_submissions_left(llm, report, remaining, current_grade.as_deref(), config).await?;
+		log!("Running evaluation through the webservice...");
+		ws.vpl_evaluate(vpl_id).await?;
+		remaining = remaining.map(|r| r.saturating_sub(1));
+		let result = poll_vpl_result(ws, vpl_id).await?;
+
+		let grade = parse_proposed_grade_text(&result.grade);
+		report.vpl_attempt(grade.as_ref().map(|g| g.to_string()));
+		let Some(grade) = grade else {
+			run_stop_hook(config, Some(llm), report, "VPL: Could not find proposed grade");
+			bail!("Could not find proposed grade in evaluation results ({:?})", result.grade);
+		};
+		config.output.human(format_args!("Proposed grade: {grade}"));
+		config.output.emit(&Event::VplGrade { value: grade.0 });
+		current_grade = Some(grade.to_string());
+		if grade >= 1.0 {
+			log!("Full marks! Evaluation successful.");
+			run_stop_hook(config, Some(llm), report, "VPL: Full marks!");
+			return Ok(true);
+		}
+		if best.as_ref().is_none_or(|(best_grade, _)| grade > *best_grade) {
+			best = Some((grade, files.clone()));
+		}
+
+		if attempt == max_retries {
+			failure = format!("Failed after {max_retries} retries");
+			break;
+		}
+		if !has_spare_vpl_submission(remaining) {
+			failure = "Stopped to keep the last submission".to_string();
+			break;
+		}
+
+		let diagnostics = VplDiagnostics::classify(&result.compilation, &result.evaluation);
+		let Some(retry_message) = diagnostics.retry_message(grade, !generic_retry_used) else {
+			elog!("No compiler or test output to retry with");
+			failure = "Could not parse test results".to_string();
+			break;
+		};
+		diagnostics.print(config.output);
+		generic_retry_used |= diagnostics == VplDiagnostics::NoOutput;
+
+		log!("Asking LLM to fix the code based on {}...", diagnostics.kind());
+		let result = match regenerate_vpl_code(llm, conversation, &retry_message, config).await {
+			Ok(result) => result,
+			Err(e) => {
+				elog!("Failed to regenerate code: {}", e);
+				failure = format!("Failed to regenerate code: {e}");
+				break;
+			}
+		};
+		show_code(config.output, "Regenerated code", &result.files);
+
+		if !config.auto_submit && confirmation("Submit regenerated code?").flush().await != ConfirmResult::Yes {
+			log!("Cancelled by user");
+			failure = "Cancelled by user".to_string();
+			break;
+		}
+		conversation = result.conversation;
+		files = result.files;
+	}
+
+	let Some((best_grade, best_files)) = best else {
+		run_stop_hook(config, Some(llm), report, &format!("VPL: {failure}"));
+		bail!("{failure}");
+	};
+	if best_files != files {
+		log!("Restoring the best attempt ({best_grade}) through the webservice...");
+		ws.vpl_save(vpl_id, &best_files).await?;
+	}
+	run_stop_hook(config, Some(llm), report, &format!("VPL: {failure} (best: {})", best_grade * Percent(1.0)));
+	Err(eyre!("Evaluation failed ({failure}): best attempt got {} (expected 100%) and was restored", best_grade * Percent(1.0)).wrap_err(FailureKind::BelowTarget))
+}
+
+/// Submissions left on a capped VPL ("Submissions left: 2", "Évaluations restantes : 2"), None when there's no cap shown
+async fn parse_vpl_remaining_submissions(page: &Page) -> Result<Option<u32>> {
+	let script = r#"
+		(function() {
+			const text = document.body ? document.body.innerText : '';
+			const patterns = [
+				/(?:submissions|evaluations|attempts)\s+(?:left|remaining)\s*:?\s*(\d+)/i,
+				/(\d+)\s+(?:submissions|evaluations|attempts)\s+(?:left|remaining)/i,
+				/(?:soumissions|évaluations|tentatives)\s+restantes?\s*:?\s*(\d+)/i,
+				/(\d+)\s+(?:soumissions|évaluations|tentatives)\s+restantes?/i,
+			];
+			for (const re of patterns) {
+				const match = text.match(re);
+				if (match) return match[1];
+			}
+			return null;
+		})()
+	"#;
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to parse VPL submission limit: {e}"))?;
+	Ok(result.value().and_then(|v| v.as_str()).and_then(|s| s.parse().ok()))
+}
+
+/// Whether another evaluation would still leave one for a human
+fn has_spare_vpl_submission(remaining: Option<u32>) -> bool {
+	remaining.is_none_or(|r| r > 1)
+}
+
+/// Log the submission cap before an evaluation; bail when nothing is left, and make the user confirm spending the
+/// last one (unless auto_submit)
+async fn check_vpl_submissions_left(llm: &QuizLlm, report: &mut RunReport, remaining: Option<u32>, current_grade: Option<&str>, config: &AppConfig) -> Result<()> {
+	let Some(remaining) = remaining else {
+		return Ok(());
+	};
+	log!("VPL submissions left: {remaining}");
+	if remaining == 0 {
+		run_stop_hook(config, Some(llm), report, "VPL: No submissions left");
+		bail!("No VPL submissions left");
+	}
+	if remaining == 1 && !config.auto_submit {
+		let grade = current_grade.unwrap_or("not evaluated");
+		if confirmation(&format!("Only one VPL submission left (current grade: {grade}). Use it?")).flush().await != ConfirmResult::Yes {
+			run_stop_hook(config, Some(llm), report, "VPL: Kept the last submission");
+			bail!("Stopped before using the last VPL submission");
 		}
 	}
+	Ok(())
+}
+
+/// Stop on a Moodle error page (or the login page) with what it says, rather than finding no questions on it
+async fn check_page_state(page: &Page, config: &AppConfig, llm: Option<&QuizLlm>, report: &mut RunReport) -> Result<()> {
+	let state = inspect_page_state(page).await?;
+	if !state.is_error() {
+		return Ok(());
+	}
+	let url = page.url().await.ok().flatten().unwrap_or_default();
+	run_stop_hook(config, llm, report, &format!("Quiz: {state}"));
+	Err(eyre!("{state} ({url})").wrap_err(state.failure_kind()))
+}
 
-	run_stop_hook(config, "VPL: Exhausted all retry attempts");
-	bail!("Exhausted all retry attempts");
+/// Wait for the evaluation started by `mod_vpl_evaluate` to produce a grade
+async fn poll_vpl_result(ws: &MoodleWs, vpl_id: u64) -> Result<WsVplResult> {
+	const MAX_WAIT_SECS: u64 = 120;
+
+	log!("Waiting for evaluation results...");
+	let deadline = std::time::Instant::now() + std::time::Duration::from_secs(MAX_WAIT_SECS);
+	loop {
+		shutdown::sleep(std::time::Duration::from_millis(timings().evaluation_poll_interval_ms)).await?;
+		let result = ws.vpl_get_result(vpl_id).await?;
+		if !result.grade.trim().is_empty() {
+			return Ok(result);
+		}
+		if std::time::Instant::now() >= deadline {
+			bail!("No evaluation result after {MAX_WAIT_SECS}s");
+		}
+	}
 }
+
 /// Handle a quiz (multi-choice) page
 /// Returns Ok(true) if at least one answer was submitted, Ok(false) if questions existed but none were answered
-pub async fn handle_quiz_page(page: &Page, ask_llm: bool, config: &mut AppConfig, session_id: &str) -> Result<bool> {
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_quiz_page(
+	page: &Page,
+	llm: Option<&QuizLlm>,
+	answers: &mut AnswerBook,
+	images: &ImageCache,
+	config: &mut AppConfig,
+	report: &mut RunReport,
+	store: &SessionStore,
+) -> Result<bool> {
 	use v_utils::io::{ConfirmResult, confirmation};
 
+	// Quiz cover page (view.php): start or continue an attempt first
+	check_page_state(page, config, llm, report).await?;
+	start_quiz_attempt(page, config).await?;
+
 	let mut question_num = 0;
 	let mut consecutive_failures = 0;
 	let mut first_page = true;
 	let mut total_questions_found = 0;
+	// `.que` ids of the questions already counted (and answered), so a page parsed again isn't handled twice
+	let mut seen_questions: std::collections::HashSet<String> = std::collections::HashSet::new();
+	let mut consecutive_repeats = 0;
 	let mut total_answers_submitted = 0;
+	// Questions we already went back to from the summary page, so a question the LLM can't answer doesn't loop forever
+	let mut revisited_from_summary: std::collections::HashSet<u32> = std::collections::HashSet::new();
+	// Where to resume after a re-login: the last attempt page we saw (with its page= parameter), else the entry URL
+	let mut resume_url = page.url().await.ok().flatten().unwrap_or_default();
+	let mut consecutive_relogins = 0;
+	// Low-confidence answers left blank in headless mode, reported at the end
+	let mut left_for_review: Vec<String> = Vec::new();
+	// Timed quiz about to run out: stop answering and submit what there is
+	let mut out_of_time = false;
+	// Questions left unanswered after an LLM failure to get on with the rest (`allow_skip`)
+	let mut skipped_after_failure: Vec<usize> = Vec::new();
 
 	loop {
+		let mut current_url = page.url().await.ok().flatten().unwrap_or_default();
+
+		// Session expired mid-quiz: log in again and go back to where we were
+		if is_login_url(&current_url) {
+			consecutive_relogins += 1;
+			if consecutive_relogins > 2 {
+				run_stop_hook(config, llm, report, "Quiz: re-login keeps landing on the login page");
+				return Err(eyre!("Session expired and re-login keeps landing on the login page ({current_url})").wrap_err(FailureKind::Login));
+			}
+			relogin(page, &resume_url, config, store).await.wrap_err(FailureKind::Login)?;
+			current_url = page.url().await.ok().flatten().unwrap_or_default();
+		} else {
+			consecutive_relogins = 0;
+		}
+		if current_url.contains("/mod/quiz/attempt.php") {
+			resume_url = current_url.clone();
+		}
+
 		// Print page separator
-		let current_url = page.url().await.ok().flatten().unwrap_or_default();
 		let page_num = current_url.split("page=").nth(1).and_then(|s| s.split('&').next()).and_then(|s| s.parse::<u32>().ok());
 
 		if !first_page {
@@ -258,28 +688,127 @@ pub async fn handle_quiz_page(page: &Page, ask_llm: bool, config: &mut AppConfig
 		first_page = false;
 
 		// Save page HTML before parsing for debugging
-		#[cfg(feature = "xdg")]
-		if let Err(e) = save_page_html(page, session_id).await {
+		if let Err(e) = store.save_page_snapshot(page).await {
 			elog!("Failed to save quiz page HTML: {e}");
 		}
 
-		let questions = parse_questions(page).await?;
+		check_page_state(page, config, llm, report).await?;
+		let parsed = parse_questions_with_info(page, config).await?;
+
+		// A page seen again (a submit that didn't navigate, a manual wait ending on the same page) only brings
+		// questions that weren't handled yet
+		let parsed_count = parsed.len();
+		let (descriptions, questions, marks) = split_descriptions(
+			parsed.into_iter().filter(|(_, info)| info.dom_id.as_ref().is_none_or(|id| seen_questions.insert(id.clone()))),
+			llm,
+		);
+		if parsed_count > 0 && questions.is_empty() && descriptions.is_empty() {
+			consecutive_repeats += 1;
+			log!("All {parsed_count} question(s) on this page were handled already");
+			if consecutive_repeats > 2 {
+				run_stop_hook(config, llm, report, "Quiz: stuck on a page that was handled already");
+				return Err(eyre!("Stuck on a page whose questions were all handled already ({current_url})").wrap_err(FailureKind::Parse));
+			}
+			if config.visible {
+				log!("Waiting for manual navigation...");
+				if wait_for_page_change(page, config.page_change_timeout_secs).await? == PageChange::TimedOut {
+					run_stop_hook(config, llm, report, "Timed out waiting for manual intervention");
+					bail!("Timed out after {}s waiting for manual intervention", config.page_change_timeout_secs);
+				}
+			} else if !click_next_page(page).await? {
+				run_stop_hook(config, llm, report, "Quiz: no way off a page that was handled already");
+				return Err(eyre!("No next page button on a page whose questions were all handled already ({current_url})").wrap_err(FailureKind::Parse));
+			}
+			continue;
+		}
+		consecutive_repeats = 0;
+
+		show_descriptions(config.output, &descriptions);
+		if questions.is_empty() && !descriptions.is_empty() && !config.dry_run && click_next_page(page).await? {
+			log!("Only descriptions on this page, moved on");
+			continue;
+		}
+
+		// Timed quiz: the countdown only runs in the page, so read it again on every page
+		let clock = read_quiz_clock(page).await.unwrap_or_else(|e| {
+			elog!("{e}");
+			None
+		});
+		let budget = clock.map(|clock| clock.budget(question_num, questions.len(), config)).unwrap_or(TimeBudget::Relaxed);
+		if let Some(clock) = &clock {
+			log_quiz_clock(clock, question_num, questions.len(), budget);
+		}
+		images.set_attachment_limit((budget >= TimeBudget::Tight).then_some(TIGHT_MAX_IMAGE_BYTES));
+		// Resumed attempt: answers saved before this run are kept, see `keep_saved_answer`
+		let nav = read_quiz_nav(page).await.unwrap_or_else(|e| {
+			elog!("{e}");
+			Default::default()
+		});
+		if budget == TimeBudget::Panic && !out_of_time {
+			elog!("Less than {}s left on the quiz timer: submitting the attempt as it is", config.panic_threshold_secs);
+			out_of_time = true;
+		}
+		if out_of_time && !config.dry_run && !questions.is_empty() && go_to_quiz_summary(page, &current_url).await? {
+			continue;
+		}
 
 		if questions.is_empty() {
+			// Summary page: go back to any question left unanswered before finalizing the attempt
+			if current_url.contains("/mod/quiz/summary.php") {
+				let summary = parse_quiz_summary(page).await?;
+				log!("Attempt summary:");
+				for (number, status) in &summary {
+					log!("  Question {number}: {status}");
+				}
+
+				let unanswered: Vec<u32> = summary.iter().filter(|(_, status)| is_unanswered_status(status)).map(|(number, _)| *number).collect();
+				if !out_of_time && let Some(&number) = unanswered.iter().find(|n| !revisited_from_summary.contains(n)) {
+					log!("{} question(s) not yet answered, returning to question {number}...", unanswered.len());
+					revisited_from_summary.extend(unanswered.iter().copied());
+					if return_to_summary_question(page, number).await? {
+						// Going back on purpose: the answered questions there are kept by `keep_saved_answer`
+						seen_questions.clear();
+						continue;
+					}
+					elog!("Could not navigate back to question {number}, continuing with submission");
+				} else if !unanswered.is_empty() {
+					elog!("Question(s) {unanswered:?} are still unanswered after revisiting them");
+				}
+			}
+
 			// Only check for confirmation prompts when there are no questions to answer
-			let confirmation_buttons = find_confirmation_buttons(page, false).await?;
+			let confirmation_buttons = find_confirmation_buttons(page, config, false).await?;
 			if !confirmation_buttons.is_empty() {
 				log!("Found {} confirmation prompt(s):", confirmation_buttons.len());
 				for btn in &confirmation_buttons {
 					log!("  - {btn}");
 				}
 
-				if config.continuation_prompts {
+				if config.dry_run {
+					log!("Dry run: not clicking confirmation buttons");
+				} else if out_of_time {
+					log!("Out of time, submitting the attempt...");
+					if click_all_confirmations(page, config).await? {
+						report_left_for_review(&left_for_review);
+						report_unanswered(report);
+						run_stop_hook(config, llm, report, "Quiz submitted as the timer ran out");
+						return Ok(quiz_succeeded(total_answers_submitted, total_questions_found, &skipped_after_failure));
+					}
+				} else if config.continuation_prompts && !may_finalize(config, report) {
+					if !config.visible {
+						report_left_for_review(&left_for_review);
+						report_unanswered(report);
+						run_stop_hook(config, llm, report, "Quiz not finalized: unsupported or skipped questions left unanswered");
+						return Ok(false);
+					}
+				} else if config.continuation_prompts {
 					log!("Auto-clicking confirmation buttons...");
-					if click_all_confirmations(page).await? {
+					if click_all_confirmations(page, config).await? {
 						// Modal confirmation clicked = quiz submitted, we're done
-						run_stop_hook(config, "Quiz submitted successfully");
-						return Ok(total_answers_submitted > 0 || total_questions_found == 0);
+						report_left_for_review(&left_for_review);
+						report_unanswered(report);
+						run_stop_hook(config, llm, report, "Quiz submitted successfully");
+						return Ok(quiz_succeeded(total_answers_submitted, total_questions_found, &skipped_after_failure));
 					}
 				} else {
 					log!("(set continuation_prompts = true in config to auto-click)");
@@ -292,308 +821,1610 @@ pub async fn handle_quiz_page(page: &Page, ask_llm: bool, config: &mut AppConfig
 					if click_next_page(page).await? {
 						continue;
 					} else {
-						elog!("Could not find next page button, exiting.");
-						run_stop_hook(config, "No questions found, no next page button");
-						std::process::exit(1);
+						run_stop_hook(config, llm, report, "No questions found, no next page button");
+						return Err(eyre!("No questions found on page and no next page button").wrap_err(FailureKind::Parse));
 					}
 				}
-				elog!("No questions found on page. // Might be a fucky-wucky, but we're in headless, so exiting.");
-				run_stop_hook(config, "No questions found on page");
-				std::process::exit(1);
+				// Might be a fucky-wucky, but we're in headless, so give up on this URL
+				run_stop_hook(config, llm, report, "No questions found on page");
+				return Err(eyre!("No questions found on page").wrap_err(FailureKind::Parse));
 			}
 			log!("No more questions found. Waiting for manual intervention or page change...");
-			run_stop_hook(config, "No more questions found");
-			wait_for_page_change(page).await?;
+			run_stop_hook(config, llm, report, "No more questions found");
+			if wait_for_page_change(page, config.page_change_timeout_secs).await? == PageChange::TimedOut {
+				run_stop_hook(config, llm, report, "Timed out waiting for manual intervention");
+				bail!("Timed out after {}s waiting for manual intervention", config.page_change_timeout_secs);
+			}
 			continue;
 		}
 
 		total_questions_found += questions.len();
 
+		// Fetch every image on the page up front, a few at a time; display and LLM attachments both read from the cache
+		images.prefetch(page, questions.iter().flat_map(question_image_urls)).await;
+
 		// Display all questions on this page
+		let output = config.output;
 		for (i, question) in questions.iter().enumerate() {
-			let header = format!("--- Question {} {} ---", question_num + i + 1, question.type_marker());
+			let header = question_header(question_num + i + 1, question, marks[i]);
 			tracing::info!("{header}");
-			eprintln!("{header}");
+			output.human(display::question_header(question_num + i + 1, question, marks[i]));
 
 			let question_str = question.to_string();
 			tracing::info!("{question_str}");
-			eprint!("{question_str}");
+			output.human(display::question_text(question_str.trim_end_matches('\n')));
+			output.emit(&Event::question(question_num + i + 1, question));
+			if output.is_json() || budget >= TimeBudget::Tight {
+				continue;
+			}
 
 			// Display question images
 			for img in question.images() {
-				if let Err(e) = display_image_chafa(page, &img.url, 60).await {
-					elog!("Failed to display image: {}", e);
-					eprintln!("  [Image: {}]", img.alt.as_deref().unwrap_or(&img.url));
-				}
+				display_image(page, images, output, img, 60, "  ").await;
+				display_ocr_text(page, images, output, &img.url, "  ").await;
 			}
 
 			// Display choice images
 			for choice in question.choices() {
 				for img in &choice.images {
-					if let Err(e) = display_image_chafa(page, &img.url, 40).await {
-						elog!("Failed to display choice image: {}", e);
-						eprintln!("    [Image: {}]", img.alt.as_deref().unwrap_or(&img.url));
-					}
+					display_image(page, images, output, img, 40, "    ").await;
+					display_ocr_text(page, images, output, &img.url, "    ").await;
 				}
 			}
 
-			eprintln!(); // newline between questions
+			output.human(display::ruler()); // between questions
 		}
 
-		if !ask_llm {
-			// If not using LLM, just display questions and exit
+		if llm.is_none() && answers.replay.is_none() {
+			// If not using LLM or an answers file, just display questions and exit
 			break;
 		}
 
 		// Collect answers for all questions on this page
 		let mut answers_to_select: Vec<(&Question, LlmAnswerResult)> = Vec::new();
+		// Low-confidence answers: never auto-submitted, they wait for the confirm prompt (or stay blank headless)
+		let mut held: Vec<(&Question, LlmAnswerResult)> = Vec::new();
+		let mut held_labels: Vec<String> = Vec::new();
 		let mut answer_logs: Vec<String> = Vec::new();
+		let mut failed_on_page: Vec<usize> = Vec::new();
 
-		for question in &questions {
-			question_num += 1;
+		// Short on time: the questions worth the most get answered first (numbers stay in page order)
+		let page_start = question_num;
+		let mut order: Vec<usize> = (0..questions.len()).collect();
+		if budget >= TimeBudget::Tight {
+			order.sort_by(|&a, &b| marks[b].weight().total_cmp(&marks[a].weight()));
+		}
+		question_num += questions.len();
+		for i in order {
+			let question = &questions[i];
+			if !out_of_time && clock.is_some_and(|clock| clock.is_out_of_time(config)) {
+				elog!("Less than {}s left on the quiz timer: submitting the answers so far", config.panic_threshold_secs);
+				out_of_time = true;
+			}
+			if out_of_time {
+				break;
+			}
+			let question_num = page_start + i + 1;
 
-			match ask_llm_for_answer(page, question, config).await {
-				Ok(answer_result) => {
+			let saved_state = question_slot(question).and_then(|slot| nav.get(&slot)).map(|entry| entry.state);
+			if keep_saved_answer(question, question_num, saved_state, config, report) {
+				report.set_marks(question, marks[i]);
+				continue;
+			}
+
+			let answer = obtain_answer(page, question, question_num, llm, answers, images, config, report).await?;
+			report.set_marks(question, marks[i]);
+			let Some(answer) = answer else {
+				continue;
+			};
+
+			match answer {
+				Ok(answer) => {
 					consecutive_failures = 0; // Reset on success
 
 					// Collect answer display for later
-					answer_logs.push(format!("Question {question_num} {} answer:", question.type_marker()));
-					match &answer_result {
-						LlmAnswerResult::Single { idx, text } => {
-							answer_logs.push(format!("  Selected: {}. {}", idx + 1, text));
-						}
-						LlmAnswerResult::Multi { indices, texts } => {
-							answer_logs.push("  Selected:".to_string());
-							for (idx, text) in indices.iter().zip(texts.iter()) {
-								answer_logs.push(format!("    {}. {}", idx + 1, text));
-							}
-						}
-						LlmAnswerResult::Text { answer } => {
-							answer_logs.push(format!("  Answer: {answer}"));
-						}
-						LlmAnswerResult::Matching { selections } => {
-							answer_logs.push("  Matches:".to_string());
-							// Find the answer text for each selection
-							for (select_name, value) in selections {
-								// Find the item and option text
-								for item in question.match_items() {
-									if &item.select_name == select_name {
-										let answer_text = item.options.iter().find(|o| &o.value == value).map(|o| o.text.as_str()).unwrap_or("?");
-										answer_logs.push(format!("    {} -> {answer_text}", item.prompt));
-										break;
-									}
-								}
-							}
-						}
-						LlmAnswerResult::FillInBlanks { answers } => {
-							answer_logs.push("  Blanks:".to_string());
-							if let Some(fill) = question.fill_in_blanks() {
-								for (i, blank) in fill.blanks.iter().enumerate() {
-									// Find the answer for this blank
-									let answer_text = answers
-										.iter()
-										.find(|a| match (a, blank) {
-											(FillInBlanksAnswerItem::Text { input_name, .. }, Blank::Text { input_name: bn, .. }) => input_name == bn,
-											(FillInBlanksAnswerItem::Select { select_name, .. }, Blank::Select { select_name: sn, .. }) => select_name == sn,
-											_ => false,
-										})
-										.map(|a| match a {
-											FillInBlanksAnswerItem::Text { answer, .. } => answer.clone(),
-											FillInBlanksAnswerItem::Select { value, .. } => {
-												// Find the option text for this value
-												if let Blank::Select { options, .. } = blank {
-													options.iter().find(|o| &o.value == value).map(|o| o.text.clone()).unwrap_or_else(|| value.clone())
-												} else {
-													value.clone()
-												}
-											}
-										})
-										.unwrap_or_else(|| "?".to_string());
-									answer_logs.push(format!("    [{}]: {}", i + 1, answer_text));
-								}
-							}
-						}
-						LlmAnswerResult::CodeBlock { code } => {
-							// Show first few lines of code
-							let lines: Vec<&str> = code.lines().take(5).collect();
-							answer_logs.push("  Code:".to_string());
-							for line in lines {
-								answer_logs.push(format!("    {line}"));
-							}
-							if code.lines().count() > 5 {
-								answer_logs.push(format!("    ... ({} more lines)", code.lines().count() - 5));
-							}
-						}
-						LlmAnswerResult::DragDropIntoText { placements } => {
-							answer_logs.push("  Placements:".to_string());
-							if let Some(ddwtos) = question.drag_drop_into_text() {
-								for (input_name, choice_num) in placements {
-									// Find the choice text and zone number
-									let choice_text = ddwtos.choices.iter().find(|c| c.choice_number == *choice_num).map(|c| c.text.as_str()).unwrap_or("?");
-									let place_num = ddwtos.drop_zones.iter().find(|z| &z.input_name == input_name).map(|z| z.place_number).unwrap_or(0);
-									answer_logs.push(format!("    Place {place_num} -> {choice_text}"));
-								}
-							}
-						}
+					let confidence = answer.confidence.map(|c| format!(" (confidence {c}%)")).unwrap_or_default();
+					let low_confidence = answer.is_low_confidence(config);
+					let flag = if low_confidence { " [LOW CONFIDENCE]" } else { "" };
+					answer_logs.push(format!("Question {question_num} {} answer{confidence}{flag}:", question.type_marker()));
+					answer_logs.push(format!("{}", answer.result.display_with(question)));
+					if let Some(reasoning) = &answer.reasoning {
+						answer_logs.push(format!("  Reasoning: {reasoning}"));
 					}
+					output.emit(&Event::Answer {
+						index: question_num,
+						answer: &answer.result,
+						confidence: answer.confidence,
+					});
 
-					answers_to_select.push((question, answer_result));
+					if low_confidence && config.auto_submit {
+						held_labels.push(format!("Question {question_num}{confidence}"));
+						held.push((question, answer.result));
+					} else {
+						answers_to_select.push((question, answer.result));
+					}
 				}
 				Err(e) => {
+					// Every other question would be refused the same way
+					if LlmErrorKind::of(&e) == LlmErrorKind::AuthFailed {
+						run_stop_hook(config, llm, report, &format!("Quiz: {e}"));
+						return Err(e.wrap_err(FailureKind::LlmAuth));
+					}
 					consecutive_failures += 1;
 					elog!(
 						"Failed to get LLM answer for question {question_num}: {e} ({consecutive_failures}/{})",
 						config.max_consecutive_failures
 					);
+					config.output.emit(&Event::Error {
+						message: format!("Failed to get LLM answer for question {question_num}: {e}"),
+					});
 					if consecutive_failures >= config.max_consecutive_failures {
-						run_stop_hook(config, &format!("Quiz: Exceeded {} consecutive LLM failures", config.max_consecutive_failures));
-						bail!("Exceeded {} consecutive LLM failures", config.max_consecutive_failures);
+						run_stop_hook(config, llm, report, &format!("Quiz: Exceeded {} consecutive LLM failures", config.max_consecutive_failures));
+						return Err(eyre!("Exceeded {} consecutive LLM failures", config.max_consecutive_failures).wrap_err(FailureKind::Llm));
 					}
 					// Skip this question but continue with others
+					failed_on_page.push(question_num);
+				}
+			}
+		}
+		if config.allow_skip {
+			skipped_after_failure.extend(&failed_on_page);
+		}
+
+		// Display all answers at once with newlines around
+		if !answer_logs.is_empty() {
+			for line in &answer_logs {
+				tracing::info!("{line}");
+			}
+			let styled: Vec<String> = answer_logs.iter().map(|entry| display::answer_log(entry)).collect();
+			output.human(format_args!("\n{}\n", styled.join("\n")));
+		}
+
+		// Headless: nobody to ask, so low-confidence answers are left blank for a later look
+		if !held.is_empty() && !config.visible && !config.dry_run {
+			log!("Leaving {} low-confidence answer(s) blank for manual review", held.len());
+			left_for_review.append(&mut held_labels);
+			held.clear();
+		}
+
+		if answers_to_select.is_empty() && held.is_empty() && out_of_time && !config.dry_run && go_to_quiz_summary(page, &current_url).await? {
+			continue;
+		}
+		// Nothing to submit on this page because every answer failed: with allow_skip, leave it blank and go on
+		if answers_to_select.is_empty() && held.is_empty() && config.allow_skip && !config.dry_run && !failed_on_page.is_empty() {
+			elog!("No answer for any question on this page, --allow-skip is set: leaving {failed_on_page:?} unanswered and moving on");
+			let button = click_submit(page).await?;
+			log!("Moved on (clicked \"{button}\")");
+			continue;
+		}
+		if answers_to_select.is_empty() && held.is_empty() {
+			// We had questions but couldn't get any answers from LLM
+			if total_questions_found > 0 && total_answers_submitted == 0 {
+				elog!(
+					"No answers to submit. LLM failed to answer all {total_questions_found} question(s).\nThis may be a transient API error. Try running again, or check your CLAUDE_TOKEN."
+				);
+			} else {
+				log!("No answers to submit on this page.");
+			}
+
+			break;
+		}
+
+		if config.dry_run {
+			answers_to_select.append(&mut held);
+			apply_answers(page, &answers_to_select).await?;
+			verify_answers(page, config, &answers_to_select).await?;
+			log!("Dry run: filled {} answer(s), would have submitted:", answers_to_select.len());
+			for line in answer_logs.iter().flat_map(|entry| entry.lines()) {
+				log!("  {line}");
+			}
+			if !config.visible {
+				return Ok(true);
+			}
+			log!("Dry run: waiting for manual navigation to the next page...");
+			if wait_for_page_change(page, config.page_change_timeout_secs).await? == PageChange::TimedOut {
+				log!("Dry run: no page change, stopping");
+				return Ok(true);
+			}
+			continue;
+		}
+
+		// Confident answers go in right away when auto-submitting; whatever is held waits for the prompt below
+		let confident_applied = config.auto_submit && !held.is_empty();
+		if confident_applied {
+			apply_answers(page, &answers_to_select).await?;
+		}
+
+		// Ask for confirmation once for all answers on this page; no time for that when the timer is about to run out
+		let should_submit = if (config.auto_submit && held.is_empty()) || out_of_time {
+			Some(true)
+		} else if config.confirm_mode != ConfirmMode::Page {
+			// One prompt per answer; when auto-submitting, the confident ones are in already and only the held ones are asked about
+			if confident_applied {
+				held = review_answers(held, config.confirm_mode, report).await?;
+			} else {
+				answers_to_select = review_answers(answers_to_select, config.confirm_mode, report).await?;
+			}
+			if answers_to_select.is_empty() && held.is_empty() {
+				log!("No answer accepted.");
+				None
+			} else {
+				Some(true)
+			}
+		} else {
+			// Race between user confirmation and detecting manual submission
+			let confirm_msg = if held.is_empty() {
+				format!("Submit {} answer(s)?", answers_to_select.len())
+			} else {
+				format!("Low confidence: {}. Submit these answer(s) too?", held_labels.join(", "))
+			};
+			let timeout_secs = config.page_change_timeout_secs;
+			tokio::select! {
+				biased;
+				result = confirmation(&confirm_msg).all().flush() => {
+					match result {
+						ConfirmResult::Yes => Some(true),
+						ConfirmResult::All => {
+							// SAFETY: single-threaded, no concurrent reads
+							unsafe { config.set_auto_submit(true) };
+							Some(true)
+						}
+						_ => None, // User will submit manually
+					}
+				}
+				change = wait_for_page_change(page, timeout_secs) => {
+					match change? {
+						PageChange::Changed => {
+							log!("User submitted manually.");
+							Some(false) // Already submitted, don't submit again
+						}
+						PageChange::TimedOut => {
+							run_stop_hook(config, llm, report, "Timed out waiting for submit confirmation");
+							bail!("Timed out after {timeout_secs}s waiting for submit confirmation");
+						}
+					}
+				}
+			}
+		};
+
+		match should_submit {
+			Some(true) => {
+				// Select all answers on this page (the confident ones are already in if some were held)
+				if config.confirm_mode == ConfirmMode::Page {
+					if !confident_applied {
+						apply_answers(page, &answers_to_select).await?;
+					}
+					apply_answers(page, &held).await?;
+				} else {
+					// Answers picked one by one: one that can't be applied doesn't hold up the rest
+					if !confident_applied {
+						answers_to_select = apply_each_answer(page, answers_to_select).await;
+					}
+					held = apply_each_answer(page, held).await;
+				}
+				answers_to_select.append(&mut held);
+				// Make sure every value stuck before submitting (re-rendered inputs, disabled elements, ...)
+				verify_answers(page, config, &answers_to_select).await?;
+				// STACK answers its validation rejected get one correction round
+				if !out_of_time {
+					correct_stack_answers(page, &mut answers_to_select, llm, answers, images, report).await?;
+				}
+				// Interactive quizzes: check each answer and retry wrong ones while tries remain
+				if !out_of_time {
+					check_and_retry_answers(page, &answers_to_select, llm, answers, images, config, report).await?;
+				}
+				// Submit once for all questions on this page
+				let button = click_submit(page).await?;
+				report.mark_submitted(answers_to_select.iter().map(|(question, _)| *question));
+				output.emit(&Event::Submitted { count: answers_to_select.len() });
+				total_answers_submitted += answers_to_select.len();
+				log!("All {} answer(s) submitted (clicked \"{button}\")!", answers_to_select.len());
+			}
+			Some(false) => {
+				// Already submitted by user, count as submitted
+				report.mark_submitted(answers_to_select.iter().chain(&held).map(|(question, _)| *question));
+				output.emit(&Event::Submitted {
+					count: answers_to_select.len() + held.len(),
+				});
+				total_answers_submitted += answers_to_select.len() + held.len();
+			}
+			None => {
+				// User said no, wait for them to submit manually
+				log!("Waiting for manual submission...");
+				if wait_for_page_change(page, config.page_change_timeout_secs).await? == PageChange::TimedOut {
+					run_stop_hook(config, llm, report, "Timed out waiting for manual submission");
+					bail!("Timed out after {}s waiting for manual submission", config.page_change_timeout_secs);
+				}
+				log!("Page changed, continuing...");
+			}
+		}
+	}
+
+	report_left_for_review(&left_for_review);
+	report_unanswered(report);
+
+	Ok(quiz_succeeded(total_answers_submitted, total_questions_found, &skipped_after_failure))
+}
+
+/// Success if we submitted at least one answer, or if there were no questions to answer, and no question was skipped
+/// after failing
+fn quiz_succeeded(total_answers_submitted: usize, total_questions_found: usize, skipped_after_failure: &[usize]) -> bool {
+	if !skipped_after_failure.is_empty() {
+		let labels: Vec<String> = skipped_after_failure.iter().map(|n| format!("Question {n}")).collect();
+		elog!("Skipped after failing to answer (--allow-skip): {}", labels.join(", "));
+		return false;
+	}
+	total_answers_submitted > 0 || total_questions_found == 0
+}
+/// Log the quiz countdown and what it leaves per question
+fn log_quiz_clock(clock: &QuizClock, answered: usize, on_page: usize, budget: TimeBudget) {
+	let remaining = clock.remaining_secs();
+	let left = clock.questions_left(answered, on_page);
+	let note = match budget {
+		TimeBudget::Relaxed => "",
+		TimeBudget::Tight => " - short on time, skipping image display and large attachments",
+		TimeBudget::Panic => " - out of time",
+	};
+	if left == 0 {
+		log!("Quiz timer: {} left{note}", format_secs(remaining));
+	} else {
+		log!("Quiz timer: {} left, ~{left} question(s) to go, {}s each{note}", format_secs(remaining), remaining / left as u64);
+	}
+}
+
+/// Leave the attempt page for its summary page (summary.php), where the attempt is submitted
+/// Returns false when `attempt_url` isn't an attempt page
+async fn go_to_quiz_summary(page: &Page, attempt_url: &str) -> Result<bool> {
+	if !attempt_url.contains("/mod/quiz/attempt.php") {
+		return Ok(false);
+	}
+	let (path, query) = attempt_url.split_once('?').unwrap_or((attempt_url, ""));
+	let query: Vec<&str> = query.split('#').next().unwrap_or_default().split('&').filter(|kv| !kv.starts_with("page=")).collect();
+	let summary_url = format!("{}?{}", path.replace("/mod/quiz/attempt.php", "/mod/quiz/summary.php"), query.join("&"));
+	log!("Going to the attempt summary: {summary_url}");
+	page.goto(summary_url.as_str()).await.map_err(|e| eyre!("Failed to navigate to the quiz summary: {e}"))?;
+	page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for the quiz summary: {e}"))?;
+	Ok(true)
+}
+
+/// Parse every page of a quiz attempt without answering anything (for `--export`)
+/// Pages are visited through the navigation panel links rather than the submit button, so nothing gets recorded
+pub async fn collect_quiz_questions(page: &Page, config: &AppConfig) -> Result<Vec<Question>> {
+	start_quiz_attempt(page, config).await?;
+
+	let nav_script = r#"
+		(function() {
+			const pages = [];
+			const seen = new Set();
+			for (const link of document.querySelectorAll('#mod_quiz_navblock a.qnbutton, .qn_buttons a.qnbutton')) {
+				const href = link.href;
+				if (!href) continue;
+				const pageNum = link.getAttribute('data-quiz-page') ?? (new URL(href).searchParams.get('page') || '0');
+				if (seen.has(pageNum)) continue;
+				seen.add(pageNum);
+				pages.push(href.split('#')[0]);
+			}
+			return JSON.stringify(pages);
+		})()
+	"#;
+	let result = page.evaluate(nav_script).await.map_err(|e| eyre!("Failed to read quiz navigation: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
+	let page_urls: Vec<String> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse quiz navigation JSON: {e}"))?;
+
+	if page_urls.is_empty() {
+		log!("No quiz navigation panel found, exporting the current page only");
+		return parse_questions(page, config).await;
+	}
+
+	let mut questions = Vec::new();
+	for (i, url) in page_urls.iter().enumerate() {
+		log!("Parsing quiz page {}/{}...", i + 1, page_urls.len());
+		page.goto(url.as_str()).await.map_err(|e| eyre!("Failed to navigate to quiz page {url}: {e}"))?;
+		page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for quiz page {url}: {e}"))?;
+		questions.extend(parse_questions(page, config).await?);
+	}
+
+	Ok(questions)
+}
+
+/// Answer a quiz through the Moodle webservice instead of clicking through attempt pages.
+/// Question HTML from `mod_quiz_get_attempt_data` goes through [`crate::parse::parse_questions_from_html`], and the
+/// form fields it holds, filled in with the answers, are posted with `mod_quiz_process_attempt`. `page` is only there
+/// for fetching images for the LLM.
+/// Returns Ok(None) when the token can't be used for this quiz (missing capability, unparseable question types),
+/// in which case the caller should fall back to the browser.
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_quiz_via_webservice(
+	page: &Page,
+	ws: &MoodleWs,
+	target_url: &str,
+	llm: Option<&QuizLlm>,
+	answers: &mut AnswerBook,
+	images: &ImageCache,
+	config: &AppConfig,
+	report: &mut RunReport,
+) -> Result<Option<bool>> {
+	let Some(cmid) = quiz_cmid(target_url) else {
+		elog!("Webservice: no course module id in {target_url}");
+		return Ok(None);
+	};
+
+	let attempt = match ws_start_or_resume_attempt(ws, cmid, config).await {
+		Ok(attempt) => attempt,
+		Err(e) if is_ws_capability_error(&e) => {
+			elog!("Webservice: {e}");
+			return Ok(None);
+		}
+		Err(e) => return Err(e),
+	};
+	log!("Webservice: using attempt {} ({})", attempt.id, attempt.state);
+
+	let mut question_num = 0;
+	let mut consecutive_failures = 0;
+	let mut total_answers_submitted = 0;
+	let mut left_for_review: Vec<String> = Vec::new();
+	let mut ws_page: u32 = 0;
+	loop {
+		let data = match ws.get_attempt_data(attempt.id, ws_page).await {
+			Ok(data) => data,
+			Err(e) if is_ws_capability_error(&e) && total_answers_submitted == 0 => {
+				elog!("Webservice: {e}");
+				return Ok(None);
+			}
+			Err(e) => return Err(e),
+		};
+
+		let mut parsed = Vec::new();
+		for ws_question in &data.questions {
+			let info = QuestionInfo {
+				marks: ws_question.marks(),
+				..Default::default()
+			};
+			parsed.extend(parse_questions_from_html(&ws_question.html)?.into_iter().map(|question| (question, info.clone())));
+		}
+		let (descriptions, questions, marks) = split_descriptions(parsed.into_iter(), llm);
+		show_descriptions(config.output, &descriptions);
+		let expected = data.questions.iter().filter(|q| q.qtype != "description").count();
+		if questions.len() < expected {
+			elog!("Webservice: parsed {}/{expected} question(s) on page {ws_page}", questions.len());
+			if total_answers_submitted == 0 {
+				return Ok(None);
+			}
+			bail!("Webservice: could not parse all questions on page {ws_page}, attempt {} left in progress", attempt.id);
+		}
+
+		let mut answered: Vec<(&Question, LlmAnswerResult)> = Vec::new();
+		for (question, &question_marks) in questions.iter().zip(&marks) {
+			question_num += 1;
+			log!("{}", question_header(question_num, question, question_marks));
+			config.output.human(display::question_text(question.to_string().trim_end_matches('\n')));
+			config.output.emit(&Event::question(question_num, question));
+
+			let saved_state = question_slot(question)
+				.and_then(|slot| data.questions.iter().find(|q| q.slot == slot))
+				.map(|q| QuestionState::from_classes(&q.stateclass));
+			if keep_saved_answer(question, question_num, saved_state, config, report) {
+				report.set_marks(question, question_marks);
+				continue;
+			}
+
+			let answer = obtain_answer(page, question, question_num, llm, answers, images, config, report).await?;
+			report.set_marks(question, question_marks);
+			let Some(answer) = answer else {
+				continue;
+			};
+			match answer {
+				Ok(answer) => {
+					consecutive_failures = 0;
+					if let Some(confidence) = answer.confidence {
+						log!("Confidence: {confidence}%");
+					}
+					if let Some(reasoning) = &answer.reasoning {
+						log!("Reasoning: {reasoning}");
+					}
+					log!("{}", answer.result.display_with(question));
+					config.output.emit(&Event::Answer {
+						index: question_num,
+						answer: &answer.result,
+						confidence: answer.confidence,
+					});
+					if answer.is_low_confidence(config) && config.auto_submit {
+						log!("Question {question_num}: low confidence, leaving it blank for manual review");
+						left_for_review.push(format!("Question {question_num}"));
+						continue;
+					}
+					answered.push((question, answer.result));
+				}
+				Err(e) => {
+					// Every other question would be refused the same way
+					if LlmErrorKind::of(&e) == LlmErrorKind::AuthFailed {
+						run_stop_hook(config, llm, report, &format!("Quiz: {e}"));
+						return Err(e.wrap_err(FailureKind::LlmAuth));
+					}
+					consecutive_failures += 1;
+					elog!(
+						"Failed to get LLM answer for question {question_num}: {e} ({consecutive_failures}/{})",
+						config.max_consecutive_failures
+					);
+					config.output.emit(&Event::Error {
+						message: format!("Failed to get LLM answer for question {question_num}: {e}"),
+					});
+					if consecutive_failures >= config.max_consecutive_failures {
+						run_stop_hook(config, llm, report, &format!("Quiz: Exceeded {} consecutive LLM failures", config.max_consecutive_failures));
+						return Err(eyre!("Exceeded {} consecutive LLM failures", config.max_consecutive_failures).wrap_err(FailureKind::Llm));
+					}
+				}
+			}
+		}
+
+		if !answered.is_empty() {
+			if config.dry_run {
+				log!("Dry run: would have saved {} answer(s) through the webservice", answered.len());
+			} else {
+				let submit = config.auto_submit
+					|| matches!(
+						confirmation(&format!("Save {} answer(s)?", answered.len())).flush().await,
+						ConfirmResult::Yes | ConfirmResult::All
+					);
+				if submit {
+					let html: String = data.questions.iter().map(|q| q.html.as_str()).collect();
+					let fields = fill_response_fields(parse_response_fields(&html)?, &answered);
+					let state = ws.process_attempt(attempt.id, &fields, false).await?;
+					report.mark_submitted(answered.iter().map(|(question, _)| *question));
+					config.output.emit(&Event::Submitted { count: answered.len() });
+					total_answers_submitted += answered.len();
+					log!("Webservice: saved {} answer(s) (attempt {state})", answered.len());
+				} else {
+					log!("Webservice: answers on page {ws_page} not saved");
+				}
+			}
+		}
+
+		if data.nextpage < 0 {
+			break;
+		}
+		ws_page = data.nextpage as u32;
+	}
+
+	report_left_for_review(&left_for_review);
+	report_unanswered(report);
+	if config.dry_run {
+		return Ok(Some(true));
+	}
+	if config.continuation_prompts && !may_finalize(config, report) {
+		log!("Webservice: attempt {} left open", attempt.id);
+		run_stop_hook(config, llm, report, "Quiz not finalized: unsupported or skipped questions left unanswered");
+		return Ok(Some(false));
+	}
+	if config.continuation_prompts {
+		let state = ws.process_attempt(attempt.id, &[], true).await?;
+		log!("Webservice: attempt {} finished ({state})", attempt.id);
+		run_stop_hook(config, llm, report, "Quiz submitted successfully");
+	} else {
+		log!("Webservice: attempt {} left open (set continuation_prompts = true in config to finish it)", attempt.id);
+	}
+
+	Ok(Some(total_answers_submitted > 0 || question_num == 0))
+}
+
+/// Course module id of a quiz URL (`view.php?id=`, `attempt.php?cmid=`)
+fn quiz_cmid(url: &str) -> Option<u64> {
+	let query = url.split_once('?')?.1.split('#').next()?;
+	let param = |key: &str| query.split('&').find_map(|kv| kv.strip_prefix(key)?.strip_prefix('=')).and_then(|v| v.parse().ok());
+	param("cmid").or_else(|| if url.contains("/mod/quiz/view.php") { param("id") } else { None })
+}
+
+fn is_ws_capability_error(e: &color_eyre::Report) -> bool {
+	e.downcast_ref::<WsError>().is_some_and(WsError::is_capability_error)
+}
+
+async fn ws_start_or_resume_attempt(ws: &MoodleWs, cmid: u64, config: &AppConfig) -> Result<WsAttempt> {
+	let quiz_id = ws.quiz_instance_id(cmid).await?;
+	if let Some(attempt) = ws.unfinished_attempt(quiz_id).await? {
+		return Ok(attempt);
+	}
+	log!("Webservice: starting a new attempt on quiz {quiz_id}");
+	ws.start_attempt(quiz_id, config.quiz_password.as_deref()).await
+}
+
+/// `fields` as parsed from the question HTML, with the fields the answers set changed to their new values
+fn fill_response_fields(mut fields: Vec<(String, String)>, answered: &[(&Question, LlmAnswerResult)]) -> Vec<(String, String)> {
+	for (question, result) in answered {
+		for expectation in field_expectations(question, result) {
+			match expectation {
+				FieldExpectation::Checked { name, value, checked: true } => {
+					// Radios share a name, so whatever was checked before goes; the hidden "0" of a checkbox goes too
+					fields.retain(|(n, _)| *n != name);
+					fields.push((name, value));
+				}
+				FieldExpectation::Checked { name, value, checked: false } => fields.retain(|(n, v)| *n != name || *v != value),
+				FieldExpectation::Value { name, value } => {
+					fields.retain(|(n, _)| *n != name);
+					fields.push((name, value));
+				}
+			}
+		}
+	}
+	fields
+}
+
+/// Final summary line for answers held back because of low confidence
+fn report_left_for_review(left_for_review: &[String]) {
+	if !left_for_review.is_empty() {
+		elog!("Left blank for manual review (low confidence): {}", left_for_review.join(", "));
+	}
+}
+
+/// Final summary lines for questions left blank on purpose: unsupported types, and types not in `question_types`
+fn report_unanswered(report: &RunReport) {
+	let unsupported = report.questions_from(AnswerSource::Unsupported);
+	if !unsupported.is_empty() {
+		let labels: Vec<String> = unsupported
+			.iter()
+			.map(|entry| format!("Question {} ({})", entry.number, entry.error.as_deref().unwrap_or_default()))
+			.collect();
+		elog!("Left unanswered (unsupported): {}", labels.join(", "));
+	}
+	let skipped = report.questions_from(AnswerSource::Skipped);
+	if !skipped.is_empty() {
+		let labels: Vec<String> = skipped.iter().map(|entry| format!("Question {} [{}]", entry.number, entry.kind)).collect();
+		log!("Skipped (not in --question-types): {}", labels.join(", "));
+	}
+}
+
+/// Keep an answer saved before this run (a resumed attempt) instead of asking for another one that may well differ,
+/// unless `reanswer` is set. Returns true when the question is to be skipped.
+fn keep_saved_answer(question: &Question, question_num: usize, state: Option<QuestionState>, config: &AppConfig, report: &mut RunReport) -> bool {
+	let Some(state) = state.filter(|state| state.has_saved_answer() && !config.reanswer) else {
+		return false;
+	};
+	// Answered earlier in this run (a page revisited from the summary): nothing new to record
+	if !report.has_answer(question) {
+		report.question(QuestionReport::new(question_num, question, AnswerSource::AlreadySaved));
+	}
+	log!("Question {question_num}: {state} already, keeping it (pass --reanswer to answer it again)");
+	true
+}
+
+/// Whether the attempt may be finalized: not while unsupported or skipped questions are left blank, unless
+/// `allow_skip` is set
+fn may_finalize(config: &AppConfig, report: &RunReport) -> bool {
+	let blank = report.questions_from(AnswerSource::Unsupported).len() + report.questions_from(AnswerSource::Skipped).len();
+	if blank == 0 || config.allow_skip {
+		return true;
+	}
+	elog!("{blank} unsupported or skipped question(s) left unanswered, not finalizing the attempt (pass --allow-skip to finalize anyway)");
+	false
+}
+
+/// Get the answer for one question: from the answers file if it has an entry, else from the LLM (when enabled).
+/// Ok(None) means the question is skipped. LLM failures come back as Ok(Some(Err(..))) so callers can count
+/// consecutive failures; the outer Err is reserved for fatal problems like a bad answers-file entry.
+#[allow(clippy::too_many_arguments)]
+async fn obtain_answer(
+	page: &Page,
+	question: &Question,
+	question_num: usize,
+	llm: Option<&QuizLlm>,
+	answers: &mut AnswerBook,
+	images: &ImageCache,
+	config: &AppConfig,
+	report: &mut RunReport,
+) -> Result<Option<Result<LlmAnswer>>> {
+	if let Question::Unknown { qtype_class, .. } = question {
+		elog!("Question {question_num}: unsupported question type qtype_{qtype_class}, leaving it unanswered");
+		let mut entry = QuestionReport::new(question_num, question, AnswerSource::Unsupported);
+		entry.error = Some(format!("qtype_{qtype_class}"));
+		report.question(entry);
+		return Ok(None);
+	}
+
+	let mut llm_latency = None;
+	let (answer, source) = match answers.replay.as_ref().and_then(|replay| replay.lookup(question)) {
+		Some(from_file) => {
+			// A bad entry is a mistake in the file, not a transient failure - don't fall back to the LLM
+			let answer_result = from_file?;
+			log!("Question {question_num}: using answer from answers file");
+			let answer = LlmAnswer {
+				result: answer_result,
+				confidence: None,
+				reasoning: None,
+			};
+			(Ok(answer), AnswerSource::AnswersFile)
+		}
+		None => match llm {
+			Some(_) if !config.answers_kind(question.kind()) => {
+				log!("Question {question_num}: {} not in --question-types, leaving it to you", question.type_marker());
+				report.question(QuestionReport::new(question_num, question, AnswerSource::Skipped));
+				return Ok(None);
+			}
+			Some(llm) => match answers.cache.as_ref().and_then(|cache| cache.lookup(question)) {
+				Some(cached) => {
+					log!("Question {question_num}: using answer cached by a previous run");
+					(Ok(cached), AnswerSource::Cache)
+				}
+				None => {
+					let started = std::time::Instant::now();
+					let answer = llm.answer_question(page, question, question_num, &[], images).await;
+					llm_latency = Some(started.elapsed());
+					if let (Ok(answer), Some(cache)) = (&answer, answers.cache.as_mut()) {
+						cache.record(question, answer);
+					}
+					(answer, AnswerSource::Llm)
+				}
+			},
+			None => {
+				log!("Question {question_num}: not in answers file, skipping (pass --ask-llm to fall back to the LLM)");
+				return Ok(None);
+			}
+		},
+	};
+
+	let mut entry = QuestionReport::new(question_num, question, source);
+	match &answer {
+		Ok(answer) => entry = entry.answered(question, answer),
+		Err(e) => entry.error = Some(e.to_string()),
+	}
+	entry.llm_latency_ms = llm_latency.map(|latency| latency.as_millis() as u64);
+	report.question(entry);
+
+	if let (Ok(answer), Some(export)) = (&answer, answers.export.as_mut()) {
+		export.record(question, &answer.result);
+	}
+	Ok(Some(answer))
+}
+
+/// `confirm_mode = "question"`/`"edit"`: a y/n prompt per answer. Rejected answers are left out, or in edit mode
+/// replaced by one typed in (an empty line leaves it out).
+async fn review_answers<'q>(answers: Vec<(&'q Question, LlmAnswerResult)>, mode: ConfirmMode, report: &mut RunReport) -> Result<Vec<(&'q Question, LlmAnswerResult)>> {
+	let mut accepted = Vec::new();
+	for (question, answer) in answers {
+		let excerpt: String = crate::answers::normalize_whitespace(question.question_text()).chars().take(60).collect();
+		let prompt = format!(
+			"{} {excerpt}
+  -> {}
+Use this answer?",
+			question.type_marker(),
+			answer.display_with(question)
+		);
+		if confirmation(&prompt).flush().await == ConfirmResult::Yes {
+			accepted.push((question, answer));
+			continue;
+		}
+		let Some(hint) = edit_hint(question).filter(|_| mode == ConfirmMode::Edit) else {
+			continue;
+		};
+		loop {
+			log!("Your answer ({hint}), empty to leave it out:");
+			let input = read_stdin_line().await?;
+			if input.trim().is_empty() {
+				break;
+			}
+			match parse_answer_edit(&input, question, &answer) {
+				Ok(edited) => {
+					log!("  -> {}", edited.display_with(question));
+					let edited = LlmAnswer {
+						result: edited,
+						confidence: None,
+						reasoning: None,
+					};
+					report.update_answer(question, &edited);
+					accepted.push((question, edited.result));
+					break;
+				}
+				Err(e) => elog!("{e}"),
+			}
+		}
+	}
+	Ok(accepted)
+}
+
+async fn read_stdin_line() -> Result<String> {
+	use tokio::io::AsyncBufReadExt;
+	let mut line = String::new();
+	tokio::io::BufReader::new(tokio::io::stdin())
+		.read_line(&mut line)
+		.await
+		.map_err(|e| eyre!("Failed to read from stdin: {e}"))?;
+	Ok(line)
+}
+
+/// Apply answers one at a time, returning those that went in; failures are logged and left out
+async fn apply_each_answer<'q>(page: &Page, answers: Vec<(&'q Question, LlmAnswerResult)>) -> Vec<(&'q Question, LlmAnswerResult)> {
+	let mut applied = Vec::new();
+	for (question, answer) in answers {
+		match apply_answer(page, question, &answer).await {
+			Ok(()) => applied.push((question, answer)),
+			Err(e) => elog!("Could not apply the answer to {} {}: {e}", question.type_marker(), question.slot_key().unwrap_or_default()),
+		}
+	}
+	applied
+}
+
+/// Apply a single LLM answer to the page (select choices, fill inputs, set editors)
+async fn apply_answer(page: &Page, question: &Question, answer_result: &LlmAnswerResult) -> Result<()> {
+	match answer_result {
+		LlmAnswerResult::Single { idx, .. } => {
+			let choices = question.choices();
+			let choice = &choices[*idx];
+			// Only click if not already selected
+			if !choice.selected {
+				set_choice(page, &choice.input_name, &choice.input_value, true).await?;
+			}
+		}
+		LlmAnswerResult::Multi { indices, .. } => {
+			let choices = question.choices();
+			let should_select: std::collections::HashSet<usize> = indices.iter().copied().collect();
+			for (i, choice) in choices.iter().enumerate() {
+				let want_selected = should_select.contains(&i);
+				if want_selected != choice.selected {
+					set_choice(page, &choice.input_name, &choice.input_value, want_selected).await?;
+				}
+			}
+		}
+		LlmAnswerResult::Text { answer } =>
+			if let Some(input_name) = question.short_answer_input_name() {
+				set_input_value(page, "input", input_name, answer).await?;
+			},
+		LlmAnswerResult::Matching { selections } =>
+			for (select_name, value) in selections {
+				set_input_value(page, "select", select_name, value).await?;
+			},
+		LlmAnswerResult::FillInBlanks { answers } =>
+			for item in answers {
+				match item {
+					FillInBlanksAnswerItem::Text { input_name, answer } => {
+						set_input_value(page, "input", input_name, answer).await?;
+					}
+					FillInBlanksAnswerItem::Select { select_name, value } => {
+						set_input_value(page, "select", select_name, value).await?;
+					}
+				}
+			},
+		LlmAnswerResult::CodeBlock { code } =>
+			if let Some(input_name) = question.code_block_input_name() {
+				set_code_editor_content(page, input_name, code).await?;
+			},
+		LlmAnswerResult::DragDropIntoText { placements } =>
+			for (input_name, choice_num) in placements {
+				set_hidden_input_value(page, input_name, &choice_num.to_string()).await?;
+			},
+		LlmAnswerResult::Numerical { answer, unit } => {
+			if let Some(input_name) = question.numerical_input_name() {
+				set_input_value(page, "input", input_name, answer).await?;
+			}
+			match unit {
+				Some((radio_name, value)) if question.numerical_unit_radios() => set_choice(page, radio_name, value, true).await?,
+				Some((select_name, value)) => set_input_value(page, "select", select_name, value).await?,
+				None => {}
+			}
+		}
+		LlmAnswerResult::Essay { html } =>
+			if let Some((input_name, editor_kind)) = question.essay_editor() {
+				set_essay_content(page, input_name, editor_kind, html).await?;
+			},
+		LlmAnswerResult::Ordering { order } =>
+			if let Some(input_name) = question.ordering_input_name() {
+				set_ordering_response(page, input_name, &question.ordering_item_ids(order)).await?;
+			},
+	}
+	Ok(())
+}
+
+/// Kind of form field a batched write targets
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FieldOpKind {
+	Radio,
+	Checkbox,
+	Text,
+	Select,
+	Hidden,
+}
+
+/// One form field write of a batched answer application
+#[derive(Debug, Serialize)]
+struct FieldOp {
+	kind: FieldOpKind,
+	name: String,
+	/// Value to set; for radios and checkboxes, the `value` attribute of the input to (un)check
+	value: String,
+	/// Whether the checkbox should end up checked (radios are always checked)
+	#[serde(skip_serializing_if = "Option::is_none")]
+	checked: Option<bool>,
+}
+
+impl FieldOp {
+	fn new(kind: FieldOpKind, name: &str, value: &str) -> Self {
+		Self {
+			kind,
+			name: name.to_string(),
+			value: value.to_string(),
+			checked: None,
+		}
+	}
+}
+
+/// Outcome of one [`FieldOp`], as reported by the page
+#[derive(Debug, Deserialize)]
+struct FieldOpStatus {
+	ok: bool,
+	#[serde(default)]
+	error: Option<String>,
+}
+
+/// Form field writes that apply an answer, or None for answers that need their own helper (code editors, essays,
+/// ordering lists)
+fn field_ops(question: &Question, answer_result: &LlmAnswerResult) -> Option<Vec<FieldOp>> {
+	use FieldOpKind::*;
+	let ops = match answer_result {
+		LlmAnswerResult::Single { idx, .. } => {
+			let choice = &question.choices()[*idx];
+			vec![FieldOp::new(Radio, &choice.input_name, &choice.input_value)]
+		}
+		LlmAnswerResult::Multi { indices, .. } => question
+			.choices()
+			.iter()
+			.enumerate()
+			.map(|(i, choice)| FieldOp {
+				checked: Some(indices.contains(&i)),
+				..FieldOp::new(Checkbox, &choice.input_name, &choice.input_value)
+			})
+			.collect(),
+		LlmAnswerResult::Text { answer } => question.short_answer_input_name().map(|name| FieldOp::new(Text, name, answer)).into_iter().collect(),
+		LlmAnswerResult::Matching { selections } => selections.iter().map(|(name, value)| FieldOp::new(Select, name, value)).collect(),
+		LlmAnswerResult::FillInBlanks { answers } => answers
+			.iter()
+			.map(|item| match item {
+				FillInBlanksAnswerItem::Text { input_name, answer } => FieldOp::new(Text, input_name, answer),
+				FillInBlanksAnswerItem::Select { select_name, value } => FieldOp::new(Select, select_name, value),
+			})
+			.collect(),
+		LlmAnswerResult::DragDropIntoText { placements } => placements.iter().map(|(name, choice_num)| FieldOp::new(Hidden, name, &choice_num.to_string())).collect(),
+		LlmAnswerResult::Numerical { answer, unit } => question
+			.numerical_input_name()
+			.map(|name| FieldOp::new(Text, name, answer))
+			.into_iter()
+			.chain(
+				unit.iter()
+					.map(|(name, value)| FieldOp::new(if question.numerical_unit_radios() { Radio } else { Select }, name, value)),
+			)
+			.collect(),
+		LlmAnswerResult::CodeBlock { .. } | LlmAnswerResult::Essay { .. } | LlmAnswerResult::Ordering { .. } => return None,
+	};
+	Some(ops)
+}
+
+/// Apply the answers for a whole page: plain form fields in a single `page.evaluate`, code editors, essays and
+/// ordering lists through their own helpers
+async fn apply_answers(page: &Page, answers: &[(&Question, LlmAnswerResult)]) -> Result<()> {
+	let mut ops = Vec::new();
+	for (question, answer_result) in answers {
+		match field_ops(question, answer_result) {
+			Some(question_ops) => ops.extend(question_ops),
+			None => apply_answer(page, question, answer_result).await?,
+		}
+	}
+	apply_field_ops(page, &ops).await
+}
+
+/// Write all `ops` in one evaluate, dispatching the same events as the single-field helpers.
+/// Errors list every field that couldn't be written.
+async fn apply_field_ops(page: &Page, ops: &[FieldOp]) -> Result<()> {
+	if ops.is_empty() {
+		return Ok(());
+	}
+	let ops_json = serde_json::to_string(ops).map_err(|e| eyre!("Failed to serialize field writes: {e}"))?;
+	let script = format!(
+		r#"
+		(function() {{
+			const ops = {ops_json};
+			const fire = (el, events) => events.forEach(name => el.dispatchEvent(new Event(name, {{ bubbles: true }})));
+			return JSON.stringify(ops.map(op => {{
+				try {{
+					const els = Array.from(document.getElementsByName(op.name));
+					switch (op.kind) {{
+						case 'radio':
+						case 'checkbox': {{
+							const input = els.find(el => el.tagName === 'INPUT' && el.type === op.kind && el.value === op.value);
+							if (!input) return {{ ok: false, error: 'no ' + op.kind + ' with value "' + op.value + '"' }};
+							if (input.disabled) return {{ ok: false, error: 'disabled' }};
+							const want = op.kind === 'radio' ? true : op.checked;
+							if (input.checked !== want) input.click();
+							return input.checked === want ? {{ ok: true }} : {{ ok: false, error: 'click did not change its state' }};
+						}}
+						case 'text':
+						case 'select': {{
+							const tag = op.kind === 'text' ? 'input' : 'select';
+							const el = els.find(e => e.tagName.toLowerCase() === tag);
+							if (!el) return {{ ok: false, error: 'not found' }};
+							if (el.disabled) return {{ ok: false, error: 'disabled' }};
+							el.value = op.value;
+							fire(el, ['input', 'change']);
+							return el.value === op.value ? {{ ok: true }} : {{ ok: false, error: 'value did not stick' }};
+						}}
+						case 'hidden': {{
+							const el = els.find(e => e.type === 'hidden');
+							if (!el) return {{ ok: false, error: 'not found' }};
+							el.value = op.value;
+							fire(el, ['change']);
+							return {{ ok: true }};
+						}}
+					}}
+					return {{ ok: false, error: 'unknown kind ' + op.kind }};
+				}} catch (e) {{
+					return {{ ok: false, error: String(e) }};
+				}}
+			}}));
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to fill in answers: {e}"))?;
+	check_field_op_statuses(ops, result.value().and_then(|v| v.as_str()).unwrap_or("[]"))
+}
+
+/// Check the per-field results the fill script reported, one per op and in the same order
+fn check_field_op_statuses(ops: &[FieldOp], json_str: &str) -> Result<()> {
+	let statuses: Vec<FieldOpStatus> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse answer fill results: {e}"))?;
+	if statuses.len() != ops.len() {
+		bail!("Answer fill reported {} result(s) for {} field(s)", statuses.len(), ops.len());
+	}
+
+	let failed: Vec<String> = ops
+		.iter()
+		.zip(&statuses)
+		.filter(|(_, status)| !status.ok)
+		.map(|(op, status)| format!("{:?} {}=\"{}\": {}", op.kind, op.name, op.value, status.error.as_deref().unwrap_or("failed")))
+		.collect();
+	if !failed.is_empty() {
+		bail!("Failed to fill {} of {} field(s):\n  {}", failed.len(), ops.len(), failed.join("\n  "));
+	}
+	Ok(())
+}
+/// If the page is a quiz cover page (mod/quiz/view.php), click "Attempt quiz" / "Continue your attempt" /
+/// "Re-attempt quiz", handle the preflight form (password, timed-quiz confirmation), and wait for attempt.php.
+/// Does nothing on any other page.
+async fn start_quiz_attempt(page: &Page, config: &AppConfig) -> Result<()> {
+	let current_url = page.url().await.ok().flatten().unwrap_or_default();
+	if !current_url.contains("/mod/quiz/view.php") {
+		return Ok(());
+	}
+
+	let click_script = r#"
+		(function() {
+			const btn = document.querySelector('.quizstartbuttondiv button, .quizstartbuttondiv input[type="submit"], form[action*="startattempt.php"] button[type="submit"]');
+			if (btn) {
+				const label = (btn.textContent || btn.value || '').trim();
+				btn.click();
+				return JSON.stringify({ clicked: true, label: label });
+			}
+			// No start button: report why (closed quiz, no attempts left, ...)
+			const info = document.querySelector('.quizattempt, .quizinfo, #region-main .box');
+			return JSON.stringify({ clicked: false, label: info ? info.textContent.replace(/\s+/g, ' ').trim() : '' });
+		})()
+	"#;
+	action_delay().await;
+	let result = page.evaluate(click_script).await.map_err(|e| eyre!("Failed to click start attempt button: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("{}");
+	let parsed: serde_json::Value = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse start attempt result: {e}"))?;
+	let label = parsed["label"].as_str().unwrap_or("");
+
+	if parsed["clicked"].as_bool() != Some(true) {
+		if label.is_empty() {
+			bail!("Quiz cover page has no button to start an attempt (quiz closed or no attempts remaining?)");
+		}
+		bail!("Cannot start quiz attempt: {label}");
+	}
+	log!("On quiz cover page, clicked \"{label}\"");
+	wait(timings().post_submit_wait_ms).await;
+
+	// Preflight form: appears as a modal (timed quiz confirmation / password) or as its own startattempt.php page
+	let password_json = serde_json::to_string(&config.quiz_password).map_err(|e| eyre!("Failed to serialize quiz password: {e}"))?;
+	let preflight_script = format!(
+		r#"
+		(function() {{
+			const password = {password_json};
+			const form = document.querySelector('.modal form[action*="startattempt.php"], form#mod_quiz_preflight_form, form[action*="startattempt.php"]');
+			if (!form) return 'none';
+			const passwordField = form.querySelector('input[name="quizpassword"]');
+			if (passwordField) {{
+				if (password === null) return 'password_required';
+				passwordField.value = password;
+			}}
+			const submit = form.querySelector('#id_submitbutton, button[type="submit"], input[type="submit"]');
+			if (!submit) return 'none';
+			submit.click();
+			return 'submitted';
+		}})()
+		"#
+	);
+	let result = page.evaluate(preflight_script).await.map_err(|e| eyre!("Failed to handle quiz preflight form: {e}"))?;
+	match result.value().and_then(|v| v.as_str()) {
+		Some("password_required") => bail!("Quiz requires a password - set quiz_password in the config"),
+		Some("submitted") => log!("Confirmed quiz preflight form"),
+		_ => {}
+	}
+
+	// Wait for the attempt page
+	for _ in 0..60 {
+		let url = page.url().await.ok().flatten().unwrap_or_default();
+		if url.contains("/mod/quiz/attempt.php") {
+			log!("Quiz attempt started");
+			return Ok(());
+		}
+		wait(timings().poll_interval_ms).await;
+	}
+
+	// Still not on attempt.php: a wrong password re-renders the preflight form with an error
+	let error = page
+		.evaluate(r#"(function() { const e = document.querySelector('.error, .alert-danger, .form-control-feedback'); return e ? e.textContent.trim() : ''; })()"#)
+		.await
+		.ok()
+		.and_then(|r| r.value().and_then(|v| v.as_str()).map(|s| s.to_string()))
+		.unwrap_or_default();
+	if error.is_empty() {
+		bail!("Clicked \"{label}\" but the quiz attempt page did not load");
+	}
+	bail!("Could not start quiz attempt: {error}");
+}
+
+/// What a form field should read back as after an answer was applied
+#[derive(Clone, Debug)]
+enum FieldExpectation {
+	/// A radio button or checkbox, identified by name and value, and whether it should be checked
+	Checked { name: String, value: String, checked: bool },
+	/// An input, select or textarea whose value should equal `value`
+	Value { name: String, value: String },
+}
+
+impl FieldExpectation {
+	fn name(&self) -> &str {
+		match self {
+			FieldExpectation::Checked { name, .. } | FieldExpectation::Value { name, .. } => name,
+		}
+	}
+
+	fn expected(&self) -> String {
+		match self {
+			FieldExpectation::Checked { value, checked, .. } => format!("value {value} {}", if *checked { "checked" } else { "unchecked" }),
+			FieldExpectation::Value { value, .. } => format!("{value:?}"),
+		}
+	}
+
+	/// Compare against what was read back from the page (None = element not found)
+	fn matches(&self, actual: Option<&str>) -> bool {
+		let Some(actual) = actual else { return false };
+		let normalize = |s: &str| s.replace("\r\n", "\n").trim().to_string();
+		match self {
+			FieldExpectation::Checked { checked, .. } => actual == checked.to_string(),
+			FieldExpectation::Value { value, .. } => normalize(actual) == normalize(value),
+		}
+	}
+}
+
+/// Form field values an applied answer should have produced
+fn field_expectations(question: &Question, answer_result: &LlmAnswerResult) -> Vec<FieldExpectation> {
+	let value = |name: &str, value: &str| FieldExpectation::Value {
+		name: name.to_string(),
+		value: value.to_string(),
+	};
+	match answer_result {
+		LlmAnswerResult::Single { idx, .. } => question
+			.choices()
+			.get(*idx)
+			.map(|c| FieldExpectation::Checked {
+				name: c.input_name.clone(),
+				value: c.input_value.clone(),
+				checked: true,
+			})
+			.into_iter()
+			.collect(),
+		LlmAnswerResult::Multi { indices, .. } => question
+			.choices()
+			.iter()
+			.enumerate()
+			.map(|(i, c)| FieldExpectation::Checked {
+				name: c.input_name.clone(),
+				value: c.input_value.clone(),
+				checked: indices.contains(&i),
+			})
+			.collect(),
+		LlmAnswerResult::Text { answer } => question.short_answer_input_name().map(|name| value(name, answer)).into_iter().collect(),
+		LlmAnswerResult::Matching { selections } => selections.iter().map(|(name, v)| value(name, v)).collect(),
+		LlmAnswerResult::FillInBlanks { answers } => answers
+			.iter()
+			.map(|item| match item {
+				FillInBlanksAnswerItem::Text { input_name, answer } => value(input_name, answer),
+				FillInBlanksAnswerItem::Select { select_name, value: v } => value(select_name, v),
+			})
+			.collect(),
+		LlmAnswerResult::CodeBlock { code } => question.code_block_input_name().map(|name| value(name, code)).into_iter().collect(),
+		LlmAnswerResult::DragDropIntoText { placements } => placements.iter().map(|(name, choice)| value(name, &choice.to_string())).collect(),
+		LlmAnswerResult::Numerical { answer, unit } => question
+			.numerical_input_name()
+			.map(|name| value(name, answer))
+			.into_iter()
+			.chain(unit.as_ref().map(|(name, v)| {
+				if question.numerical_unit_radios() {
+					FieldExpectation::Checked {
+						name: name.clone(),
+						value: v.clone(),
+						checked: true,
+					}
+				} else {
+					value(name, v)
+				}
+			}))
+			.collect(),
+		LlmAnswerResult::Essay { html } => question.essay_editor().map(|(name, _)| value(name, html)).into_iter().collect(),
+		LlmAnswerResult::Ordering { order } => question
+			.ordering_input_name()
+			.map(|name| value(name, &question.ordering_item_ids(order).join(",")))
+			.into_iter()
+			.collect(),
+	}
+}
+
+/// Names of the text fields in questions whose wrapper has one of `typed_question_classes`
+async fn fields_needing_keys(page: &Page, config: &AppConfig) -> Result<Vec<String>> {
+	if config.typed_question_classes.is_empty() {
+		return Ok(Vec::new());
+	}
+	let classes = serde_json::to_string(&config.typed_question_classes).expect("serializing strings is infallible");
+	let script = format!(
+		r#"
+		(function() {{
+			const classes = {classes};
+			const names = [];
+			for (const que of document.querySelectorAll('.que')) {{
+				if (!classes.some(c => que.classList.contains(c))) continue;
+				for (const field of que.querySelectorAll('input[type=text], input:not([type]), textarea')) {{
+					if (field.name) names.push(field.name);
+				}}
+			}}
+			return JSON.stringify(names);
+		}})()
+		"#
+	);
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to look for fields that need typing: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
+	serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse field names JSON: {e}"))
+}
+
+/// Read back the current state of each expected field: "true"/"false" for checkables, the value otherwise
+async fn read_back_fields(page: &Page, expectations: &[FieldExpectation]) -> Result<Vec<Option<String>>> {
+	let fields: Vec<serde_json::Value> = expectations
+		.iter()
+		.map(|e| match e {
+			FieldExpectation::Checked { name, value, .. } => serde_json::json!({ "name": name, "value": value, "checkable": true }),
+			FieldExpectation::Value { name, .. } => serde_json::json!({ "name": name, "checkable": false }),
+		})
+		.collect();
+	let fields_json = serde_json::to_string(&fields).map_err(|e| eyre!("Failed to serialize fields: {e}"))?;
+
+	let script = format!(
+		r#"
+		(function() {{
+			const fields = {fields_json};
+			return JSON.stringify(fields.map(f => {{
+				const els = Array.from(document.getElementsByName(f.name));
+				if (f.checkable) {{
+					const el = els.find(e => (e.type === 'radio' || e.type === 'checkbox') && e.value === f.value);
+					return el ? String(el.checked) : null;
+				}}
+				const el = els.find(e => e.type !== 'hidden') || els[0];
+				return el ? el.value : null;
+			}}));
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to read back field values: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
+	serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse field values JSON: {e}"))
+}
+
+/// Read STACK's validation of each STACK answer; one it rejects goes back to the LLM with the error, and the
+/// correction is typed in. An answer still rejected after that is left as it is.
+async fn correct_stack_answers(
+	page: &Page,
+	answered: &mut [(&Question, LlmAnswerResult)],
+	llm: Option<&QuizLlm>,
+	answers: &mut AnswerBook,
+	images: &ImageCache,
+	report: &mut RunReport,
+) -> Result<()> {
+	for (question, answer_result) in answered.iter_mut() {
+		let (Some(input_name), LlmAnswerResult::Text { answer }) = (question.short_answer_input_name().filter(|_| question.stack_input().is_some()), &*answer_result) else {
+			continue;
+		};
+		let Some(error) = stack::validation_error(page, input_name).await? else {
+			continue;
+		};
+		let Some(llm) = llm else {
+			elog!("STACK rejected \"{answer}\": {error} (not correcting without the LLM)");
+			continue;
+		};
+		log!("STACK rejected \"{answer}\": {error}. Asking for a correction...");
+		if let Some(cache) = answers.cache.as_mut() {
+			cache.forget(question);
+		}
+		let question_num = question_slot(question).unwrap_or(0) as usize;
+		let feedback = [AnswerFeedback {
+			previous_answer: answer.clone(),
+			feedback: format!("STACK could not validate this expression: {error}"),
+		}];
+		let corrected = llm.answer_question(page, question, question_num, &feedback, images).await?;
+		let LlmAnswerResult::Text { answer: corrected_text } = &corrected.result else {
+			continue;
+		};
+		type_text_answer(page, input_name, corrected_text).await?;
+		match stack::validation_error(page, input_name).await? {
+			Some(error) => elog!("STACK rejected the correction \"{corrected_text}\" too: {error}"),
+			None => {
+				log!("STACK accepted \"{corrected_text}\"");
+				if let Some(cache) = answers.cache.as_mut() {
+					cache.record(question, &corrected);
 				}
 			}
 		}
+		report.update_answer(question, &corrected);
+		*answer_result = corrected.result;
+	}
+	Ok(())
+}
 
-		// Display all answers at once with newlines around
-		if !answer_logs.is_empty() {
-			let mut output = String::from("\n");
-			for line in &answer_logs {
-				tracing::info!("{line}");
-				output.push_str(line);
-				output.push('\n');
-			}
-			output.push('\n');
-			print!("{output}");
+/// Check that every field touched by the applied answers holds the intended value.
+/// Text fields of `typed_question_classes` questions are typed into first. Mismatched fields get their setter retried
+/// once (text fields are typed into); if they still don't match, errors with a per-field diff.
+async fn verify_answers(page: &Page, config: &AppConfig, answered: &[(&Question, LlmAnswerResult)]) -> Result<()> {
+	let text_fields: std::collections::HashMap<String, String> = answered
+		.iter()
+		.flat_map(|(question, answer_result)| field_ops(question, answer_result).unwrap_or_default())
+		.filter(|op| matches!(op.kind, FieldOpKind::Text))
+		.map(|op| (op.name, op.value))
+		.collect();
+	for name in fields_needing_keys(page, config).await? {
+		if let Some(value) = text_fields.get(&name) {
+			log!("Typing the answer into {name}");
+			type_text_answer(page, &name, value).await?;
 		}
+	}
 
-		if answers_to_select.is_empty() {
-			// We had questions but couldn't get any answers from LLM
-			if total_questions_found > 0 && total_answers_submitted == 0 {
-				elog!(
-					"No answers to submit. LLM failed to answer all {total_questions_found} question(s).\nThis may be a transient API error. Try running again, or check your CLAUDE_TOKEN."
-				);
-			} else {
-				log!("No answers to submit on this page.");
+	let expectations: Vec<(usize, FieldExpectation)> = answered
+		.iter()
+		.enumerate()
+		.flat_map(|(i, (question, answer_result))| field_expectations(question, answer_result).into_iter().map(move |e| (i, e)))
+		.collect();
+	if expectations.is_empty() {
+		return Ok(());
+	}
+	let fields: Vec<FieldExpectation> = expectations.iter().map(|(_, e)| e.clone()).collect();
+
+	let actual = read_back_fields(page, &fields).await?;
+	let mismatched: Vec<usize> = (0..fields.len()).filter(|&i| !fields[i].matches(actual[i].as_deref())).collect();
+	if mismatched.is_empty() {
+		log!("Verified {}/{} fields", fields.len(), fields.len());
+		return Ok(());
+	}
+
+	// Retry once: click mismatched checkables individually, re-apply questions with mismatched values
+	log!("{} field(s) did not take the intended value, retrying...", mismatched.len());
+	let mut reapply: Vec<usize> = Vec::new();
+	for &i in &mismatched {
+		match &fields[i] {
+			FieldExpectation::Checked { name, value, checked } =>
+				if let Err(e) = set_choice(page, name, value, *checked).await {
+					elog!("Retry failed for {name}: {e}");
+				},
+			FieldExpectation::Value { name, value } if text_fields.contains_key(name) => {
+				log!("{name} did not keep its value, typing it in");
+				if let Err(e) = type_text_answer(page, name, value).await {
+					elog!("Typing into {name} failed: {e}");
+				}
 			}
+			FieldExpectation::Value { .. } => reapply.push(expectations[i].0),
+		}
+	}
+	reapply.dedup();
+	for question_idx in reapply {
+		let (question, answer_result) = &answered[question_idx];
+		if let Err(e) = apply_answer(page, question, answer_result).await {
+			elog!("Retry failed for question {}: {e}", question_idx + 1);
+		}
+	}
 
-			break;
+	let actual = read_back_fields(page, &fields).await?;
+	let still_mismatched: Vec<String> = (0..fields.len())
+		.filter(|&i| !fields[i].matches(actual[i].as_deref()))
+		.map(|i| {
+			let got = match &actual[i] {
+				Some(v) if matches!(fields[i], FieldExpectation::Checked { .. }) =>
+					if v == "true" {
+						"checked".to_string()
+					} else {
+						"unchecked".to_string()
+					},
+				Some(v) => format!("{v:?}"),
+				None => "field not found".to_string(),
+			};
+			format!("  {}: expected {}, got {got}", fields[i].name(), fields[i].expected())
+		})
+		.collect();
+	log!("Verified {}/{} fields", fields.len() - still_mismatched.len(), fields.len());
+
+	if !still_mismatched.is_empty() {
+		bail!("{} field(s) did not keep their answer after retrying:\n{}", still_mismatched.len(), still_mismatched.join("\n"));
+	}
+	Ok(())
+}
+
+/// Grading state of a question after its "Check" button was pressed (interactive quizzes)
+#[derive(Debug)]
+struct QuestionFeedback {
+	/// State class of the `.que` element ("correct", "incorrect", "partiallycorrect", ...)
+	state: String,
+	/// Feedback and hint text shown in the question's outcome block
+	feedback: String,
+	/// Whether a "Try again" button is available
+	can_try_again: bool,
+}
+
+/// In interactive quizzes ("Check" button per question), check each applied answer and, while Moodle offers
+/// "Try again", re-ask the LLM with the feedback it showed. Questions without a Check button are left alone.
+async fn check_and_retry_answers(
+	page: &Page,
+	answered: &[(&Question, LlmAnswerResult)],
+	llm: Option<&QuizLlm>,
+	answers: &mut AnswerBook,
+	images: &ImageCache,
+	config: &AppConfig,
+	report: &mut RunReport,
+) -> Result<()> {
+	for (question, answer_result) in answered {
+		let Some(slot) = question.slot_key() else { continue };
+		// Moodle numbers slots in quiz order, so the slot stands in for the question number in the usage report
+		let question_num = slot.rsplit_once(':').and_then(|(_, n)| n.parse().ok()).unwrap_or(0);
+		if !click_question_button(page, slot, "-submit").await? {
+			continue;
 		}
+		wait(timings().post_submit_wait_ms).await;
 
-		// Ask for confirmation once for all answers on this page
-		let should_submit = if config.auto_submit {
-			Some(true)
-		} else {
-			// Race between user confirmation and detecting manual submission
-			let confirm_msg = format!("Submit {} answer(s)?", answers_to_select.len());
-			tokio::select! {
-				biased;
-				result = confirmation(&confirm_msg).all().flush() => {
-					match result {
-						ConfirmResult::Yes => Some(true),
-						ConfirmResult::All => {
-							// SAFETY: single-threaded, no concurrent reads
-							unsafe { config.set_auto_submit(true) };
-							Some(true)
-						}
-						_ => None, // User will submit manually
-					}
-				}
-				_ = wait_for_page_change(page) => {
-					log!("User submitted manually.");
-					Some(false) // Already submitted, don't submit again
-				}
+		let mut history: Vec<AnswerFeedback> = Vec::new();
+		let mut last_answer = answer_result.display_with(question).to_string();
+		for attempt in 1..=config.max_consecutive_failures {
+			let Some(feedback) = parse_question_feedback(page, slot).await? else {
+				break;
+			};
+			if feedback.state == "correct" {
+				log!("Question {slot}: correct");
+				break;
 			}
-		};
+			// Don't hand a wrong answer to the next run
+			if attempt == 1
+				&& let Some(cache) = answers.cache.as_mut()
+			{
+				cache.forget(question);
+			}
+			if !feedback.can_try_again {
+				log!("Question {slot}: {} and no tries left", feedback.state);
+				break;
+			}
+			let Some(llm) = llm else {
+				log!("Question {slot}: {} (not retrying without the LLM)", feedback.state);
+				break;
+			};
+			log!(
+				"Question {slot}: {} - retrying ({attempt}/{}). Feedback: {}",
+				feedback.state,
+				config.max_consecutive_failures,
+				feedback.feedback
+			);
+			history.push(AnswerFeedback {
+				previous_answer: last_answer.clone(),
+				feedback: feedback.feedback,
+			});
+
+			click_question_button(page, slot, "-tryagain").await?;
+			wait(timings().post_submit_wait_ms).await;
+
+			// Inputs are re-rendered after "Try again", so re-parse to get fresh field state
+			let questions = parse_questions(page, config).await?;
+			let Some(fresh) = questions.iter().find(|q| q.slot_key() == Some(slot)) else {
+				elog!("Question {slot} not found on the page after \"Try again\"");
+				break;
+			};
 
-		match should_submit {
-			Some(true) => {
-				// Select all answers on this page
-				for (question, answer_result) in &answers_to_select {
-					match answer_result {
-						LlmAnswerResult::Single { idx, .. } => {
-							let choices = question.choices();
-							let choice = &choices[*idx];
-							// Only click if not already selected
-							if !choice.selected {
-								toggle_answer(page, &choice.input_name, &choice.input_value).await?;
-							}
-						}
-						LlmAnswerResult::Multi { indices, .. } => {
-							let choices = question.choices();
-							let should_select: std::collections::HashSet<usize> = indices.iter().copied().collect();
-							for (i, choice) in choices.iter().enumerate() {
-								let want_selected = should_select.contains(&i);
-								if want_selected != choice.selected {
-									// Need to toggle this choice
-									toggle_answer(page, &choice.input_name, &choice.input_value).await?;
-								}
-							}
-						}
-						LlmAnswerResult::Text { answer } =>
-							if let Some(input_name) = question.short_answer_input_name() {
-								set_input_value(page, "input", input_name, answer).await?;
-							},
-						LlmAnswerResult::Matching { selections } =>
-							for (select_name, value) in selections {
-								set_input_value(page, "select", select_name, value).await?;
-							},
-						LlmAnswerResult::FillInBlanks { answers } =>
-							for item in answers {
-								match item {
-									FillInBlanksAnswerItem::Text { input_name, answer } => {
-										set_input_value(page, "input", input_name, answer).await?;
-									}
-									FillInBlanksAnswerItem::Select { select_name, value } => {
-										set_input_value(page, "select", select_name, value).await?;
-									}
-								}
-							},
-						LlmAnswerResult::CodeBlock { code } =>
-							if let Some(input_name) = question.code_block_input_name() {
-								set_code_editor_content(page, input_name, code).await?;
-							},
-						LlmAnswerResult::DragDropIntoText { placements } =>
-							for (input_name, choice_num) in placements {
-								set_input_value(page, "input", input_name, &choice_num.to_string()).await?;
-							},
+			match llm.answer_question(page, fresh, question_num, &history, images).await {
+				Ok(new_answer) => {
+					if let Some(cache) = answers.cache.as_mut() {
+						cache.record(fresh, &new_answer);
 					}
+					report.update_answer(fresh, &new_answer);
+					let new_answer = new_answer.result;
+					last_answer = new_answer.display_with(fresh).to_string();
+					log!("{last_answer}");
+					apply_answer(page, fresh, &new_answer).await?;
+					if !click_question_button(page, slot, "-submit").await? {
+						break;
+					}
+					wait(timings().post_submit_wait_ms).await;
+				}
+				Err(e) => {
+					elog!("Failed to get LLM answer for retry of question {slot}: {e}");
+					break;
 				}
-				// Submit once for all questions on this page
-				click_submit(page).await?;
-				total_answers_submitted += answers_to_select.len();
-				log!("All {} answer(s) submitted!", answers_to_select.len());
-			}
-			Some(false) => {
-				// Already submitted by user, count as submitted
-				total_answers_submitted += answers_to_select.len();
-			}
-			None => {
-				// User said no, wait for them to submit manually
-				log!("Waiting for manual submission...");
-				wait_for_page_change(page).await?;
-				log!("Page changed, continuing...");
 			}
 		}
 	}
+	Ok(())
+}
+
+/// Click a per-question behaviour button ("-submit" is Check, "-tryagain" is Try again)
+/// Returns false if the question has no such button
+async fn click_question_button(page: &Page, slot: &str, action: &str) -> Result<bool> {
+	action_delay().await;
+	let button_name = js_string(&format!("{slot}_{action}"));
+	let script = format!(
+		r#"
+		(function() {{
+			const btn = document.getElementsByName({button_name})[0];
+			if (!btn || btn.disabled) return false;
+			btn.click();
+			return true;
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to click {action} button: {e}"))?;
+	Ok(result.value().and_then(|v| v.as_bool()).unwrap_or(false))
+}
+
+/// Read the graded state and feedback of a question identified by its slot key
+async fn parse_question_feedback(page: &Page, slot: &str) -> Result<Option<QuestionFeedback>> {
+	let slot_js = js_string(slot);
+	let script = format!(
+		r#"
+		(function() {{
+			const slot = {slot_js};
+			const field = document.querySelector('[name^="' + CSS.escape(slot + '_') + '"]');
+			const que = field ? field.closest('.que') : null;
+			if (!que) return null;
+
+			const states = ['correct', 'partiallycorrect', 'incorrect', 'notanswered', 'invalidanswer', 'answersaved', 'notyetanswered', 'complete'];
+			const state = states.find(s => que.classList.contains(s)) || 'unknown';
+			const outcome = que.querySelector('.outcome, .im-feedback');
+			const feedback = outcome ? outcome.textContent.replace(/\s+/g, ' ').trim() : '';
+			const tryAgain = document.getElementsByName(slot + '_-tryagain')[0];
+
+			return JSON.stringify({{ state: state, feedback: feedback, can_try_again: !!tryAgain && !tryAgain.disabled }});
+		}})()
+		"#
+	);
 
-	// Return success if we submitted at least one answer, or if there were no questions to answer
-	Ok(total_answers_submitted > 0 || total_questions_found == 0)
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to parse question feedback: {e}"))?;
+	let Some(json_str) = result.value().and_then(|v| v.as_str()) else {
+		return Ok(None);
+	};
+	let parsed: serde_json::Value = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse feedback JSON: {e}"))?;
+
+	Ok(Some(QuestionFeedback {
+		state: parsed["state"].as_str().unwrap_or("unknown").to_string(),
+		feedback: parsed["feedback"].as_str().unwrap_or("").to_string(),
+		can_try_again: parsed["can_try_again"].as_bool().unwrap_or(false),
+	}))
 }
+
 /// Parse a VPL page to extract the code submission question
-pub async fn parse_vpl_page(page: &Page) -> Result<Option<Question>> {
+///
+/// Mirrored for saved HTML by [`crate::parse::parse_vpl_from_html`]; changes to one belong in the other.
+pub async fn parse_vpl_page(page: &Page, config: &AppConfig) -> Result<Option<Question>> {
+	if let Err(e) = load_lazy_images(page).await {
+		log!("{e}");
+	}
 	let parse_script = r#"
 		(function() {
-			function extractImages(element) {
-				if (!element) return [];
-				const images = [];
-				const imgElements = element.querySelectorAll('img');
-				for (const img of imgElements) {
-					const url = img.src || '';
-					if (url) images.push({ url: url, alt: img.alt || null });
-				}
-				return images;
-			}
-
 			const urlParams = new URLSearchParams(window.location.search);
 			const moduleId = urlParams.get('id') || '';
 
@@ -708,6 +2539,7 @@ pub async fn parse_vpl_page(page: &Page) -> Result<Option<Question>> {
 		})()
 	"#;
 
+	let parse_script = with_image_js(parse_script, config.min_image_size_px);
 	let result = page.evaluate(parse_script).await.map_err(|e| eyre!("Failed to parse VPL page: {e}"))?;
 
 	let json_str = match result.value().and_then(|v| v.as_str()) {
@@ -751,42 +2583,33 @@ pub async fn parse_vpl_page(page: &Page) -> Result<Option<Question>> {
 		images,
 	}))
 }
-/// Save the current page's HTML to disk for debugging
-/// Uses the page URL as the filename label
-#[cfg(feature = "xdg")]
-pub async fn save_page_html(page: &Page, session_id: &str) -> Result<PathBuf> {
-	let html_dir = xdg_state_dir!("persist_htmls").join(session_id);
-	std::fs::create_dir_all(&html_dir).map_err(|e| eyre!("Failed to create HTML dir: {e}"))?;
-
-	let url = page.url().await.ok().flatten().unwrap_or_default();
-	let label = url.replace("https://", "").replace("http://", "");
-
-	let html = page.evaluate("document.documentElement.outerHTML").await.map_err(|e| eyre!("Failed to get page HTML: {e}"))?;
-	let html_str = html.value().and_then(|v| v.as_str()).unwrap_or("<html></html>");
-
-	let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs();
-	let safe_label: String = label.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
-
-	let filename = format!("{timestamp}_{safe_label}.html");
-	let filepath = html_dir.join(&filename);
-
-	std::fs::write(&filepath, html_str).map_err(|e| eyre!("Failed to write HTML file: {e}"))?;
-
-	log!("Saved page HTML to: {}", filepath.display());
-	Ok(filepath)
-}
-/// Run the stop hook with a message if configured
-fn run_stop_hook(config: &AppConfig, message: &str) {
+/// Run the stop hook with a message if configured, followed by the run's LLM usage when there was any. The path of
+/// a snapshot of the run report is passed as the second argument, when the report has a path.
+pub fn run_stop_hook(config: &AppConfig, llm: Option<&QuizLlm>, report: &mut RunReport, message: &str) {
 	if let Some(ref hook) = config.stop_hook {
+		let totals = llm.map(|llm| llm.usage_totals());
+		let message = match totals.filter(|totals| totals.calls > 0) {
+			Some(totals) => format!("{message} ({totals})"),
+			None => message.to_string(),
+		};
+		report.llm_usage = totals;
+		if let Err(e) = report.save() {
+			elog!("{e}");
+		}
 		log!("Running stop hook: {hook} {message:?}");
 		// Escape single quotes for shell: replace ' with '\''
-		let escaped = message.replace('\'', "'\\''");
-		let _ = tokio::process::Command::new("sh").arg("-c").arg(format!("{hook} '{escaped}'")).spawn();
+		let escape = |arg: &str| arg.replace('\'', "'\\''");
+		let mut command = format!("{hook} '{}'", escape(&message));
+		if let Some(path) = report.path() {
+			command.push_str(&format!(" '{}'", escape(&path.display().to_string())));
+		}
+		let _ = tokio::process::Command::new("sh").arg("-c").arg(command).spawn();
 	}
 }
 
 /// Click the Edit button on a VPL page to open the editor
 async fn click_vpl_edit_button(page: &Page) -> Result<bool> {
+	action_delay().await;
 	let script = r#"
 		(function() {
 			// Look for nav-link with title "Edit"
@@ -815,6 +2638,7 @@ async fn click_vpl_edit_button(page: &Page) -> Result<bool> {
 /// Uses chromiumoxide's native click to emulate a real mouse click
 /// Returns Ok(true) if clicked, Ok(false) if button not found, Err if click failed
 async fn click_vpl_button(page: &Page, action: &str) -> Result<bool> {
+	action_delay().await;
 	// First, try to find by exact ID
 	let button_id = format!("vpl_ide_{action}");
 	let selector = format!("#{button_id}");
@@ -847,7 +2671,7 @@ async fn click_vpl_button_with_retry(page: &Page, action: &str, max_retries: u32
 			Err(e) =>
 				if attempt < max_retries {
 					elog!("Click on '{action}' failed (attempt {attempt}/{max_retries}): {e}");
-					tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+					wait(timings().post_click_wait_ms).await;
 				} else {
 					return Err(e);
 				},
@@ -856,74 +2680,352 @@ async fn click_vpl_button_with_retry(page: &Page, action: &str, max_retries: u32
 	Ok(false)
 }
 
-/// Escape a string for embedding in a JS template literal (backtick string).
-fn escape_for_js_template(s: &str) -> String {
-	s.replace('\\', "\\\\")
-		.replace('`', "\\`")
-		.replace('$', "\\$")
-		.replace('\n', "\\n")
-		.replace('\r', "\\r")
-		.replace('\t', "\\t")
+/// Set the content of one file in the VPL editor
+/// Activates the file's tab first (creating the file if the IDE has no tab for it), so each file lands in its own editor
+async fn set_vpl_file_content(page: &Page, filename: &str, content: &str) -> Result<()> {
+	let target = match select_vpl_file_tab(page, filename).await? {
+		VplTab::Selected(panel) => Some(panel),
+		// Old single-editor layout: there is only one editor to write into
+		VplTab::NoTabs => None,
+		VplTab::Missing => {
+			log!("No editor tab for {filename}, creating it...");
+			create_vpl_file(page, filename).await?;
+			match select_vpl_file_tab(page, filename).await? {
+				VplTab::Selected(panel) => Some(panel),
+				VplTab::Missing | VplTab::NoTabs => bail!("Created {filename} in the VPL editor but no tab showed up for it"),
+			}
+		}
+	};
+	let panel = js_string(target.flatten().as_deref().unwrap_or(""));
+	let content = js_string(content);
+
+	let script = format!(
+		r#"
+		(function() {{
+			const panelId = {panel};
+			const content = {content};
+
+			// VPL uses ACE editor - write into the one belonging to the selected tab
+			if (typeof ace !== 'undefined') {{
+				const panel = panelId ? document.getElementById(panelId) : null;
+				const editors = panel
+					? panel.querySelectorAll('.ace_editor')
+					: Array.from(document.querySelectorAll('.ace_editor')).filter(el => el.offsetParent !== null);
+				for (const editorEl of editors) {{
+					const editor = ace.edit(editorEl);
+					if (editor) {{
+						editor.setValue(content, -1);
+						return true;
+					}}
+				}}
+			}}
+
+			// Try VPL's own editor API
+			if (typeof VPL !== 'undefined' && VPL.editor) {{
+				VPL.editor.setContent(content);
+				return true;
+			}}
+
+			// Fallback: find textarea and set value
+			const textareas = document.querySelectorAll('textarea');
+			for (const ta of textareas) {{
+				if (ta.name && ta.name.includes('file') || ta.id && ta.id.includes('file')) {{
+					ta.value = content;
+					ta.dispatchEvent(new Event('input', {{ bubbles: true }}));
+					return true;
+				}}
+			}}
+
+			// Last resort: find any visible textarea
+			for (const ta of textareas) {{
+				if (ta.offsetParent !== null) {{
+					ta.value = content;
+					ta.dispatchEvent(new Event('input', {{ bubbles: true }}));
+					return true;
+				}}
+			}}
+
+			return false;
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to set file content: {e}"))?;
+
+	if result.value().and_then(|v| v.as_bool()) != Some(true) {
+		bail!("Could not find editor to set content");
+	}
+
+	Ok(())
+}
+
+/// Click Edit on the VPL description page and wait for the editor to load
+/// Returns false if there is no Edit button
+async fn open_vpl_editor(page: &Page) -> Result<bool> {
+	if !click_vpl_edit_button(page).await? {
+		return Ok(false);
+	}
+	page.wait_for_navigation().await.map_err(|e| eyre!("Failed waiting for navigation: {e}"))?;
+	wait(timings().post_navigation_wait_ms).await;
+	Ok(true)
+}
+
+/// Read every file currently in the VPL editor (filename -> content)
+/// `single_file_name` names the content of the single-editor layout, which has no tabs to read names from
+async fn read_vpl_editor_files(page: &Page, single_file_name: Option<&str>) -> Result<Vec<(String, String)>> {
+	let tabs = list_vpl_file_tabs(page).await?;
+	if tabs.is_empty() {
+		let Some(name) = single_file_name else {
+			return Ok(Vec::new());
+		};
+		return Ok(read_vpl_editor_content(page, None).await?.map(|content| vec![(name.to_string(), content)]).unwrap_or_default());
+	}
+
+	let mut files = Vec::new();
+	for name in tabs {
+		if let VplTab::Selected(panel) = select_vpl_file_tab(page, &name).await?
+			&& let Some(content) = read_vpl_editor_content(page, panel.as_deref()).await?
+		{
+			files.push((name, content));
+		}
+	}
+	Ok(files)
+}
+
+/// Content of the ACE editor in `panel` (or the visible one)
+async fn read_vpl_editor_content(page: &Page, panel: Option<&str>) -> Result<Option<String>> {
+	let panel = js_string(panel.unwrap_or(""));
+	let script = format!(
+		r#"
+		(function() {{
+			if (typeof ace === 'undefined') return null;
+			const panel = {panel} ? document.getElementById({panel}) : null;
+			const editors = panel
+				? panel.querySelectorAll('.ace_editor')
+				: Array.from(document.querySelectorAll('.ace_editor')).filter(el => el.offsetParent !== null);
+			for (const editorEl of editors) {{
+				const editor = ace.edit(editorEl);
+				if (editor) return editor.getValue();
+			}}
+			return null;
+		}})()
+		"#
+	);
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to read editor content: {e}"))?;
+	Ok(result.value().and_then(|v| v.as_str()).map(|s| s.to_string()))
+}
+
+/// Grade shown in the "Work state summary" box of the VPL description page (e.g. "6 / 10", "10,00 / 10,00", "60 %")
+async fn parse_vpl_work_state_grade(page: &Page) -> Result<Option<String>> {
+	let script = r#"
+		(function() {
+			const gradeRe = /(?:Proposed grade|Grade|Note proposée|Note)\s*:\s*([\d.,]+\s*(?:\/\s*[\d.,]+|%))/i;
+			// Prefer the work state box, so grades mentioned in the description don't match
+			for (const el of document.querySelectorAll('h2, h3, h4, legend, .card-title, th')) {
+				const heading = el.textContent.toLowerCase();
+				if (!heading.includes('work state') && !heading.includes('état')) continue;
+				const container = el.closest('.card, fieldset, table, section, div') || el.parentElement;
+				const match = container && container.innerText.match(gradeRe);
+				if (match) return match[1];
+			}
+			return null;
+		})()
+	"#;
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to parse VPL work state: {e}"))?;
+	Ok(result.value().and_then(|v| v.as_str()).map(|s| s.split_whitespace().collect::<Vec<_>>().join(" ")))
+}
+
+/// The previous submission worth showing the LLM: files that differ from their template, if any
+fn previous_submission(files: Vec<(String, String)>, grade: Option<String>, templates: &[RequiredFile]) -> Option<PreviousSubmission> {
+	let files: Vec<(String, String)> = files
+		.into_iter()
+		.filter(|(name, content)| {
+			let template = templates.iter().find(|t| &t.name == name).map_or("", |t| t.content.as_str());
+			!content.trim().is_empty() && content.trim() != template.trim()
+		})
+		.collect();
+	if files.is_empty() {
+		return None;
+	}
+	Some(PreviousSubmission { files, grade })
+}
+
+/// Outcome of looking for a file's tab in the VPL IDE
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum VplTab {
+	/// Tab clicked and its editor is showing; holds the editor panel's element id when the tab links to one
+	Selected(Option<String>),
+	/// The IDE has file tabs, but none for this file
+	Missing,
+	/// No file tabs at all (single-editor layout)
+	NoTabs,
+}
+
+/// JS helper listing the VPL IDE file tabs as `{ el, name, panel }`
+const VPL_TABS_JS: &str = r#"
+	function vplFileTabs() {
+		const links = document.querySelectorAll('#vpl_tabs li a, .vpl_ide_tabs li a, #vpl_ide_tabs li a, #vpl_tabs [role="tab"]');
+		return Array.from(links).map(el => {
+			// Modified files get a leading '*' in their label
+			const label = (el.querySelector('.vpl_ide_filename') || el).textContent.trim().replace(/^\*\s*/, '');
+			const href = el.getAttribute('href') || '';
+			const panel = el.getAttribute('aria-controls') || (href.startsWith('#') ? href.slice(1) : null);
+			return { el: el, name: label, panel: panel };
+		}).filter(tab => tab.name);
+	}
+"#;
+
+/// Click the tab of `filename` in the VPL IDE and wait for its editor to become the visible one
+async fn select_vpl_file_tab(page: &Page, filename: &str) -> Result<VplTab> {
+	let filename = js_string(filename);
+	let script = format!(
+		r#"
+		(function() {{
+			{VPL_TABS_JS}
+			const tabs = vplFileTabs();
+			if (tabs.length === 0) return JSON.stringify({{ status: 'no_tabs' }});
+			const tab = tabs.find(t => t.name === {filename});
+			if (!tab) return JSON.stringify({{ status: 'missing' }});
+			tab.el.click();
+			return JSON.stringify({{ status: 'selected', panel: tab.panel }});
+		}})()
+		"#
+	);
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to select VPL file tab: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("{}");
+	let parsed: serde_json::Value = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse VPL tab JSON: {e}"))?;
+
+	match parsed["status"].as_str() {
+		Some("selected") => {}
+		Some("missing") => return Ok(VplTab::Missing),
+		_ => return Ok(VplTab::NoTabs),
+	}
+	let Some(panel) = parsed["panel"].as_str().map(str::to_string) else {
+		wait(timings().poll_interval_ms).await;
+		return Ok(VplTab::Selected(None));
+	};
+
+	// The tab switch is animated; wait for the panel to be shown before touching its editor
+	let visible_script = format!(
+		"(function() {{ const el = document.getElementById({}); return !!el && el.offsetParent !== null; }})()",
+		js_string(&panel)
+	);
+	for _ in 0..20 {
+		let visible = page
+			.evaluate(visible_script.as_str())
+			.await
+			.ok()
+			.and_then(|r| r.value().and_then(|v| v.as_bool()))
+			.unwrap_or(false);
+		if visible {
+			return Ok(VplTab::Selected(Some(panel)));
+		}
+		wait(timings().editor_poll_interval_ms).await;
+	}
+	elog!("Editor for the selected VPL tab never became visible, writing into it anyway");
+	Ok(VplTab::Selected(Some(panel)))
+}
+
+/// Make sure the VPL IDE has a tab for every file we're going to write
+/// When the IDE only has one default-named tab and exactly one file is wanted, that tab is renamed instead
+async fn ensure_vpl_files(page: &Page, wanted: &[String]) -> Result<()> {
+	let existing = list_vpl_file_tabs(page).await?;
+	if existing.is_empty() {
+		// Single-editor layout, nothing to create
+		return Ok(());
+	}
+	let missing: Vec<&String> = wanted.iter().filter(|name| !existing.contains(name)).collect();
+	if missing.is_empty() {
+		return Ok(());
+	}
+
+	if let ([only_tab], [only_wanted]) = (existing.as_slice(), wanted)
+		&& is_default_vpl_filename(only_tab)
+	{
+		log!("Renaming VPL file {only_tab} -> {only_wanted}");
+		return rename_vpl_file(page, only_tab, only_wanted).await;
+	}
+
+	for name in missing {
+		log!("Creating missing VPL file {name}");
+		create_vpl_file(page, name).await?;
+	}
+	Ok(())
+}
+
+/// Names of the files that have a tab in the VPL IDE (empty for the single-editor layout)
+async fn list_vpl_file_tabs(page: &Page) -> Result<Vec<String>> {
+	let script = format!("(function() {{ {VPL_TABS_JS} return JSON.stringify(vplFileTabs().map(t => t.name)); }})()");
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to list VPL file tabs: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
+	serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse VPL tabs JSON: {e}"))
+}
+
+/// Names the IDE gives files nobody named yet (`file1.txt`, `new_file.c`, ...)
+fn is_default_vpl_filename(name: &str) -> bool {
+	let stem = name.split('.').next().unwrap_or(name).to_lowercase();
+	let stem = stem.trim_end_matches(|c: char| c.is_ascii_digit());
+	matches!(stem, "file" | "new_file" | "newfile" | "untitled")
 }
 
-/// Set the content of a file in the VPL editor
-async fn set_vpl_file_content(page: &Page, filename: &str, content: &str) -> Result<()> {
-	let escaped_content = escape_for_js_template(content);
+/// Create a new file in the VPL IDE through its "New file" action and name it `filename`
+async fn create_vpl_file(page: &Page, filename: &str) -> Result<()> {
+	click_vpl_ide_action(page, r#"#vpl_ide_new, [title="New file"], [title="Nouveau fichier"]"#, "New file").await?;
+	fill_vpl_filename_dialog(page, filename).await
+}
+
+/// Rename a file in the VPL IDE through its "Rename" action
+async fn rename_vpl_file(page: &Page, from: &str, to: &str) -> Result<()> {
+	if !matches!(select_vpl_file_tab(page, from).await?, VplTab::Selected(_)) {
+		bail!("Could not select VPL file {from} to rename it");
+	}
+	click_vpl_ide_action(page, r#"#vpl_ide_rename, [title="Rename file"], [title="Renommer le fichier"]"#, "Rename file").await?;
+	fill_vpl_filename_dialog(page, to).await
+}
+
+/// Click a VPL IDE menu button (`selector` is a CSS selector list) and give its dialog time to open
+async fn click_vpl_ide_action(page: &Page, selector: &str, label: &str) -> Result<()> {
+	action_delay().await;
+	let script = format!(
+		"(function() {{ const button = document.querySelector({}); if (!button) return false; button.click(); return true; }})()",
+		js_string(selector)
+	);
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to click the VPL \"{label}\" button: {e}"))?;
+	if result.value().and_then(|v| v.as_bool()) != Some(true) {
+		bail!("Could not find the \"{label}\" button in the VPL editor");
+	}
+	wait(timings().post_click_wait_ms).await;
+	Ok(())
+}
 
+/// Type `filename` into the open VPL filename prompt (new file / rename) and confirm it
+async fn fill_vpl_filename_dialog(page: &Page, filename: &str) -> Result<()> {
+	let filename = js_string(filename);
 	let script = format!(
 		r#"
 		(function() {{
-			const filename = "{filename}";
-			const content = `{escaped_content}`;
-
-			// VPL uses ACE editor - find and set content
-			if (typeof ace !== 'undefined') {{
-				const editors = document.querySelectorAll('.ace_editor');
-				for (const editorEl of editors) {{
-					const editor = ace.edit(editorEl);
-					if (editor) {{
-						editor.setValue(content, -1);
-						return true;
-					}}
-				}}
-			}}
-
-			// Try VPL's own editor API
-			if (typeof VPL !== 'undefined' && VPL.editor) {{
-				VPL.editor.setContent(content);
+			const dialogs = Array.from(document.querySelectorAll('.ui-dialog, [role="dialog"]')).filter(d => d.offsetParent !== null);
+			for (const dialog of dialogs) {{
+				const input = dialog.querySelector('input[type="text"], input:not([type])');
+				if (!input) continue;
+				input.value = {filename};
+				input.dispatchEvent(new Event('input', {{ bubbles: true }}));
+				input.dispatchEvent(new Event('change', {{ bubbles: true }}));
+				// First dialog button is the confirming one (OK / Create)
+				const ok = dialog.querySelector('.ui-dialog-buttonpane button, button.btn-primary, button');
+				if (!ok) return false;
+				ok.click();
 				return true;
 			}}
-
-			// Fallback: find textarea and set value
-			const textareas = document.querySelectorAll('textarea');
-			for (const ta of textareas) {{
-				if (ta.name && ta.name.includes('file') || ta.id && ta.id.includes('file')) {{
-					ta.value = content;
-					ta.dispatchEvent(new Event('input', {{ bubbles: true }}));
-					return true;
-				}}
-			}}
-
-			// Last resort: find any visible textarea
-			for (const ta of textareas) {{
-				if (ta.offsetParent !== null) {{
-					ta.value = content;
-					ta.dispatchEvent(new Event('input', {{ bubbles: true }}));
-					return true;
-				}}
-			}}
-
 			return false;
 		}})()
 		"#
 	);
-
-	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to set file content: {e}"))?;
-
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to fill the VPL filename prompt: {e}"))?;
 	if result.value().and_then(|v| v.as_bool()) != Some(true) {
-		bail!("Could not find editor to set content");
+		bail!("Could not find the filename prompt in the VPL editor");
 	}
-
+	wait(timings().post_click_wait_ms).await;
 	Ok(())
 }
 
@@ -971,18 +3073,80 @@ async fn parse_vpl_evaluation_result(page: &Page) -> Result<Option<String>> {
 	Ok(result.value().and_then(|v| v.as_str()).map(|s| s.to_string()))
 }
 
-/// Parse test results from the VPL comments section
-/// Returns the test failure messages if found
-async fn parse_vpl_test_results(page: &Page) -> Result<Option<String>> {
+/// What a failed VPL evaluation reported, which decides what the retry prompt says
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum VplDiagnostics {
+	/// The code didn't compile; compiler output
+	CompilationError(String),
+	/// The code compiled but tests failed; the evaluation comments
+	TestFailures(String),
+	/// Neither compiler output nor test comments
+	NoOutput,
+}
+
+impl VplDiagnostics {
+	/// Classify raw compiler output and test comments (either may be empty)
+	fn classify(compilation: &str, comments: &str) -> Self {
+		let compilation = compilation.trim();
+		let comments = comments.trim();
+		// Warnings also land in the compilation panel, so it only wins over test comments when it reports an error
+		if !compilation.is_empty() && (comments.is_empty() || compilation.to_lowercase().contains("error")) {
+			Self::CompilationError(compilation.to_string())
+		} else if !comments.is_empty() {
+			Self::TestFailures(comments.to_string())
+		} else {
+			Self::NoOutput
+		}
+	}
+
+	fn kind(&self) -> &'static str {
+		match self {
+			Self::CompilationError(_) => "compilation errors",
+			Self::TestFailures(_) => "test results",
+			Self::NoOutput => "the grade alone",
+		}
+	}
+
+	/// Message to send the LLM; None for `NoOutput` unless a generic retry is still allowed
+	fn retry_message(&self, grade: Percent, allow_generic: bool) -> Option<String> {
+		match self {
+			Self::CompilationError(output) => Some(format!("The code failed to compile with:\n{output}")),
+			Self::TestFailures(comments) => Some(comments.clone()),
+			Self::NoOutput if allow_generic => Some(format!(
+				"The grade was {grade}, and the evaluation produced no compiler or test output. Reconsider the solution against the problem statement."
+			)),
+			Self::NoOutput => None,
+		}
+	}
+
+	fn print(&self, output: Emitter) {
+		match self {
+			Self::CompilationError(compiler_output) => output.human(format_args!("\n=== Compilation Errors ===\n{compiler_output}")),
+			Self::TestFailures(comments) => output.human(format_args!("\n=== Test Failure Details ===\n{comments}")),
+			Self::NoOutput => log!("No compiler or test output, retrying with the grade alone"),
+		}
+	}
+}
+
+/// Read the compilation panel (or execution console) and the test comments of the VPL editor after an evaluation
+async fn parse_vpl_diagnostics(page: &Page) -> Result<VplDiagnostics> {
 	let script = r#"
 		(function() {
+			function panelText(selectors) {
+				for (const selector of selectors) {
+					const el = document.querySelector(selector);
+					if (el && el.innerText.trim()) return el.innerText.trim();
+				}
+				return '';
+			}
+			const compilation = panelText(['.vpl_ide_accordion_c_compilation', '.vpl_ide_accordion_c_execution']);
+
 			// Find comments section by class
 			const comments = document.querySelector('.vpl_ide_accordion_c_comments');
-			if (!comments) return null;
+			if (!comments) return JSON.stringify({ compilation: compilation, comments: '' });
 
 			// Get all text content, preserving structure
 			const parts = [];
-			let inTestResult = false;
 
 			function walkNode(node) {
 				if (node.nodeType === Node.TEXT_NODE) {
@@ -999,8 +3163,7 @@ async fn parse_vpl_test_results(page: &Page) -> Result<Option<String>> {
 					if (tag === 'br') {
 						parts.push('\n');
 					} else if (tag === 'b') {
-						// Bold = test header, start collecting
-						inTestResult = true;
+						// Bold = test header
 						parts.push('\n[TEST] ');
 						for (const child of node.childNodes) {
 							if (walkNode(child) === false) return false;
@@ -1016,17 +3179,20 @@ async fn parse_vpl_test_results(page: &Page) -> Result<Option<String>> {
 
 			walkNode(comments);
 
-			// Clean up and return
-			const result = parts.join('').trim();
-			if (!result || result.length < 10) return null;
+			// Too short to be real test output (e.g. an empty accordion header)
+			let result = parts.join('').trim();
+			if (result.length < 10) result = '';
 
-			return result;
+			return JSON.stringify({ compilation: compilation, comments: result });
 		})()
 	"#;
 
-	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to parse test results: {e}"))?;
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to parse VPL diagnostics: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("{}");
+	let parsed: serde_json::Value = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse VPL diagnostics JSON: {e}"))?;
 
-	Ok(result.value().and_then(|v| v.as_str()).map(|s| s.to_string()))
+	let diagnostics = VplDiagnostics::classify(parsed["compilation"].as_str().unwrap_or(""), parsed["comments"].as_str().unwrap_or(""));
+	Ok(diagnostics)
 }
 
 /// Parse the proposed grade from VPL evaluation results
@@ -1052,102 +3218,128 @@ async fn parse_vpl_proposed_grade(page: &Page) -> Result<Option<Percent>> {
 
 	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to parse proposed grade: {e}"))?;
 
-	let Some(text) = result.value().and_then(|v| v.as_str()) else {
-		return Ok(None);
-	};
+	Ok(result.value().and_then(|v| v.as_str()).and_then(parse_proposed_grade_text))
+}
 
-	let re = regex::Regex::new(r"Proposed grade:\s*([\d.]+)\s*/\s*([\d.]+)").map_err(|e| eyre!("Regex error: {e}"))?;
-	let Some(caps) = re.captures(text) else {
-		return Ok(None);
-	};
+/// Grade out of a "Proposed grade: x / y" text
+fn parse_proposed_grade_text(text: &str) -> Option<Percent> {
+	let (_, grade) = text.split_once("Proposed grade:")?;
+	parse_grade_fraction(grade)
+}
+
+/// Grade out of "x / y" or "x %", with either decimal separator ("10,00 / 10,00" on French Moodles)
+fn parse_grade_fraction(text: &str) -> Option<Percent> {
+	let text = text.replace(',', ".");
+	let fraction = regex::Regex::new(r"([\d.]+)\s*/\s*([\d.]+)").expect("valid regex");
+	if let Some(caps) = fraction.captures(&text) {
+		let score: f64 = caps.get(1).and_then(|m| m.as_str().parse::<f64>().ok()).unwrap_or(0.0);
+		let total: f64 = caps.get(2).and_then(|m| m.as_str().parse::<f64>().ok()).unwrap_or(1.0);
+
+		let percent = if total > 0.0 { score / total } else { 0.0 };
+		return Some(Percent(percent));
+	}
+
+	let percentage = regex::Regex::new(r"([\d.]+)\s*%").expect("valid regex");
+	let caps = percentage.captures(&text)?;
+	let value: f64 = caps.get(1)?.as_str().parse().ok()?;
+	Some(Percent(value / 100.0))
+}
 
-	let score: f64 = caps.get(1).and_then(|m| m.as_str().parse::<f64>().ok()).unwrap_or(0.0);
-	let total: f64 = caps.get(2).and_then(|m| m.as_str().parse::<f64>().ok()).unwrap_or(1.0);
+/// A confirmation button found on the page, and the part of the page it is in
+#[derive(Clone, Debug, Deserialize)]
+struct ConfirmationButton {
+	label: String,
+	container: String,
+}
 
-	let percent = if total > 0.0 { score / total } else { 0.0 };
-	Ok(Some(Percent(percent)))
+impl std::fmt::Display for ConfirmationButton {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "{} (in the {})", self.label, self.container)
+	}
 }
 
 /// Find confirmation buttons on the page and optionally click them
-/// Returns a list of button names found
-async fn find_confirmation_buttons(page: &Page, click: bool) -> Result<Vec<String>> {
+///
+/// Only the quiz form (attempt and summary pages) and the quiz navigation block are searched: the same words label
+/// messaging, forum and completion buttons elsewhere on course pages. "Mark as done" toggles are only included with
+/// `auto_mark_done`.
+async fn find_confirmation_buttons(page: &Page, config: &AppConfig, click: bool) -> Result<Vec<ConfirmationButton>> {
+	if click {
+		action_delay().await;
+	}
+	let match_js = confirmation_match_js(config);
+	let mark_done = config.auto_mark_done;
 	let script = format!(
 		r#"
 		(function() {{
-			{CONFIRMATION_MATCH_JS}
+			{match_js}
 			const shouldClick = {click};
-			const names = [];
-
-			// Mark as done buttons
-			const markDoneButtons = document.querySelectorAll(
-				'button[data-action="toggle-manual-completion"], button[data-toggletype="manual:mark-done"]'
-			);
-			for (const btn of markDoneButtons) {{
-				const name = btn.getAttribute('data-activityname') || btn.textContent.trim();
-				names.push(name);
+			const found = [];
+			const seen = new Set();
+			const take = (btn, label, container) => {{
+				if (seen.has(btn)) return;
+				seen.add(btn);
+				found.push({{ label: label, container: container }});
 				if (shouldClick) btn.click();
-			}}
-
-			// Submit all and finish buttons (quiz summary page)
-			const submitAllBtns = document.querySelectorAll('button[type="submit"].btn-primary');
-			for (const btn of submitAllBtns) {{
-				if (isConfirmationText(btn.textContent)) {{
-					names.push(btn.textContent.trim());
-					if (shouldClick) btn.click();
-				}}
-			}}
+			}};
 
-			// Finish attempt button/link on quiz attempt pages (navigates to summary)
-			const finishAttemptBtns = document.querySelectorAll('.mod_quiz-next-nav, button[name="next"], input[name="next"][type="submit"]');
-			for (const btn of finishAttemptBtns) {{
-				const text = btn.textContent?.trim() || btn.value || '';
-				if (isConfirmationText(text)) {{
-					names.push(text || 'Finish attempt');
-					if (shouldClick) btn.click();
+			if ({mark_done}) {{
+				const markDoneButtons = document.querySelectorAll(
+					'button[data-action="toggle-manual-completion"], button[data-toggletype="manual:mark-done"]'
+				);
+				for (const btn of markDoneButtons) {{
+					take(btn, btn.getAttribute('data-activityname') || btn.textContent.trim(), 'activity completion');
 				}}
 			}}
 
-			// Also check for "Finish attempt..." links in the quiz navigation
-			const finishLinks = document.querySelectorAll('a.endtestlink, a[href*="summary"]');
-			for (const link of finishLinks) {{
-				if (isConfirmationText(link.textContent)) {{
-					names.push(link.textContent.trim());
-					if (shouldClick) link.click();
+			// "Submit all and finish" (summary page), "Finish attempt..." (last attempt page, navigation block)
+			const containers = [
+				['form[action*="/mod/quiz/processattempt.php"]', 'quiz form'],
+				['#mod_quiz_navblock', 'quiz navigation'],
+			];
+			for (const [selector, container] of containers) {{
+				for (const root of document.querySelectorAll(selector)) {{
+					for (const btn of root.querySelectorAll('button[type="submit"], input[type="submit"], .mod_quiz-next-nav, a.endtestlink')) {{
+						const text = (btn.textContent || '').trim() || btn.value || '';
+						if (isConfirmationText(text)) take(btn, text, container);
+					}}
 				}}
 			}}
 
-			return JSON.stringify(names);
+			return JSON.stringify(found);
 		}})()
 	"#
 	);
 
 	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to find confirmation buttons: {e}"))?;
 	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
-	let names: Vec<String> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse JSON: {e}"))?;
+	let buttons: Vec<ConfirmationButton> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse JSON: {e}"))?;
 
-	if click && !names.is_empty() {
-		log!("Clicked {} confirmation button(s)", names.len());
+	if click && !buttons.is_empty() {
+		log!("Clicked {} confirmation button(s)", buttons.len());
 	}
 
-	Ok(names)
+	Ok(buttons)
 }
 
 /// Click all confirmation buttons, then wait and handle any modal that appears
 /// Returns true if a modal confirmation was clicked (quiz is done)
-async fn click_all_confirmations(page: &Page) -> Result<bool> {
-	find_confirmation_buttons(page, true).await?;
+async fn click_all_confirmations(page: &Page, config: &AppConfig) -> Result<bool> {
+	find_confirmation_buttons(page, config, true).await?;
 	// Wait for potential modal to appear
-	tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-	click_modal_confirmation(page).await
+	wait(timings().post_click_wait_ms).await;
+	click_modal_confirmation(page, config).await
 }
 
 /// Click confirmation button in modal dialogs (e.g., "Tout envoyer et terminer" popup)
 /// Returns true if a modal confirmation was clicked
-async fn click_modal_confirmation(page: &Page) -> Result<bool> {
+async fn click_modal_confirmation(page: &Page, config: &AppConfig) -> Result<bool> {
+	action_delay().await;
+	let match_js = confirmation_match_js(config);
 	let script = format!(
 		r#"
 		(function() {{
-			{CONFIRMATION_MATCH_JS}
+			{match_js}
 			// Look for modal confirmation buttons - try multiple selectors for different Moodle versions
 			const modalBtns = document.querySelectorAll(
 				'.modal button.btn-primary, .modal-dialog button.btn-primary, [role="dialog"] button.btn-primary, ' +
@@ -1173,23 +3365,67 @@ async fn click_modal_confirmation(page: &Page) -> Result<bool> {
 	Ok(clicked)
 }
 
+/// Set the descriptions (qtype_description) apart from the questions to answer. A question's context text (or else
+/// the latest description before it) is given to the LLM along with it.
+fn split_descriptions(parsed: impl Iterator<Item = (Question, QuestionInfo)>, llm: Option<&QuizLlm>) -> (Vec<Question>, Vec<Question>, Vec<QuestionMarks>) {
+	let (mut descriptions, mut questions, mut marks) = (Vec::new(), Vec::new(), Vec::new());
+	for (question, info) in parsed {
+		if question.is_description() {
+			descriptions.push(question);
+			continue;
+		}
+		let context = info.context_text.as_deref().or(descriptions.last().map(Question::question_text));
+		if let (Some(llm), Some(context)) = (llm, context) {
+			llm.set_shared_context(&question, context);
+		}
+		questions.push(question);
+		marks.push(info.marks);
+	}
+	(descriptions, questions, marks)
+}
+
+fn show_descriptions(output: Emitter, descriptions: &[Question]) {
+	for description in descriptions {
+		log!("--- Description ---");
+		output.human(description.to_string().trim_end_matches('\n'));
+		output.human("");
+	}
+}
+
+/// "--- Question 3 [multi] (2.0 pts) ---"
+fn question_header(number: usize, question: &Question, marks: QuestionMarks) -> String {
+	match marks.max {
+		Some(_) => format!("--- Question {number} {} ({marks}) ---", question.type_marker()),
+		None => format!("--- Question {number} {} ---", question.type_marker()),
+	}
+}
+
+/// What the page says about a question besides the question itself
+#[derive(Clone, Debug, Default)]
+struct QuestionInfo {
+	/// Id of its `.que` element (`question-<usage>-<slot>`), which stays the same when the page is parsed again
+	dom_id: Option<String>,
+	/// From the info block next to it
+	marks: QuestionMarks,
+	/// Nearest description or section heading above it, for questions that only make sense with the passage they
+	/// follow
+	context_text: Option<String>,
+}
+
 /// Parse questions from the quiz page
-async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
+///
+/// Mirrored for saved HTML by [`crate::parse::parse_questions_from_html`]; changes to one belong in the other.
+async fn parse_questions(page: &Page, config: &AppConfig) -> Result<Vec<Question>> {
+	Ok(parse_questions_with_info(page, config).await?.into_iter().map(|(question, _)| question).collect())
+}
+
+/// [`parse_questions`], each with its [`QuestionInfo`]
+async fn parse_questions_with_info(page: &Page, config: &AppConfig) -> Result<Vec<(Question, QuestionInfo)>> {
+	if let Err(e) = load_lazy_images(page).await {
+		log!("{e}");
+	}
 	let parse_script = r#"
 		(function() {
-			function extractImages(element) {
-				if (!element) return [];
-				const images = [];
-				const imgElements = element.querySelectorAll('img');
-				for (const img of imgElements) {
-					const url = img.src || '';
-					if (url) {
-						images.push({ url: url, alt: img.alt || null });
-					}
-				}
-				return images;
-			}
-
 			function extractTextWithLatex(element) {
 				if (!element) return '';
 				const clone = element.cloneNode(true);
@@ -1235,13 +3471,167 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 					script.replaceWith(document.createTextNode(wrapper[0] + latex + wrapper[1]));
 				}
 
-				return clone.textContent.replace(/\s+/g, ' ').trim();
+				return layoutText(clone);
+			}
+
+			// Text of a node with its layout kept, like `extract_text_with_latex` in parse.rs: blocks separated by a blank
+			// line, <br> breaking the line, "• " list items, `code`, fenced <pre> blocks, markdown tables. In a table
+			// cell (`inCell`), nested tables are flattened to "a, b; c, d".
+			function layoutText(root, inCell = false) {
+				let out = '';
+				// After an answer number ("a. ") or a bullet: block breaks are a space until the next text
+				let glue = false;
+				const push = (text) => {
+					let collapsed = text.replace(/\s+/g, ' ');
+					if (!out || /\s$/.test(out)) collapsed = collapsed.replace(/^ /, '');
+					if (collapsed.trim()) glue = false;
+					out += collapsed;
+				};
+				const pushRaw = (text) => {
+					out += text;
+					if (text.trim()) glue = false;
+				};
+				const lineBreak = (newlines) => {
+					if (glue) return push(' ');
+					out = out.replace(/ +$/, '');
+					if (!out) return;
+					const have = out.length - out.replace(/\n+$/, '').length;
+					out += '\n'.repeat(Math.max(0, newlines - have));
+				};
+				const blocks = ['P', 'DIV', 'H1', 'H2', 'H3', 'H4', 'H5', 'H6', 'BLOCKQUOTE', 'UL', 'OL', 'SECTION', 'ARTICLE', 'HEADER', 'FOOTER', 'FIGURE'];
+				function walk(node) {
+					if (node.nodeType === Node.TEXT_NODE) return push(node.textContent);
+					if (node.nodeType !== Node.ELEMENT_NODE) return;
+					const tag = node.tagName;
+					const style = node.getAttribute('style') || '';
+					if (tag === 'SCRIPT' || tag === 'STYLE') return;
+					if (tag === 'BR') {
+						if (glue) return;
+						out = out.replace(/ +$/, '');
+						if (out) out += '\n';
+					} else if (tag === 'PRE') {
+						lineBreak(2);
+						pushRaw('```\n' + node.textContent.replace(/\s+$/, '') + '\n```');
+						lineBreak(2);
+					} else if (['CODE', 'KBD', 'SAMP', 'TT'].includes(tag) || (tag === 'SPAN' && /courier|monospace/.test(style))) {
+						pushRaw('`' + node.textContent + '`');
+					} else if (tag === 'TABLE') {
+						const rows = tableRows(node);
+						if (inCell) {
+							push(rows.map(row => rowCells(row).map(cellText).filter(text => text).join(', ')).join('; '));
+						} else if (rows.every(row => rowCells(row).length <= 1)) {
+							// Layout table: only its content matters
+							lineBreak(2);
+							node.childNodes.forEach(walk);
+							lineBreak(2);
+						} else {
+							lineBreak(2);
+							pushRaw(markdownTable(rows));
+							lineBreak(2);
+						}
+					} else if (tag === 'TR') {
+						lineBreak(1);
+						node.childNodes.forEach(walk);
+						lineBreak(1);
+					} else if (tag === 'LI') {
+						lineBreak(1);
+						pushRaw('• ');
+						glue = true;
+						node.childNodes.forEach(walk);
+						glue = false;
+						out = out.trimEnd();
+						lineBreak(1);
+					} else if (blocks.includes(tag)) {
+						lineBreak(2);
+						node.childNodes.forEach(walk);
+						lineBreak(2);
+					} else {
+						node.childNodes.forEach(walk);
+						if (node.classList.contains('answernumber')) glue = true;
+					}
+				}
+				root.childNodes.forEach(walk);
+				return out.trim().replace(/\n{3,}/g, '\n\n');
+			}
+
+			// Rows of the table itself, not of tables nested in it
+			function tableRows(table) {
+				return Array.from(table.children).flatMap(child => {
+					if (child.tagName === 'TR') return [child];
+					if (['THEAD', 'TBODY', 'TFOOT'].includes(child.tagName)) return Array.from(child.children).filter(row => row.tagName === 'TR');
+					return [];
+				});
+			}
+
+			function rowCells(row) {
+				return Array.from(row.children).filter(cell => cell.tagName === 'TD' || cell.tagName === 'TH');
+			}
+
+			// A cell's text on one line
+			function cellText(cell) {
+				return layoutText(cell, true).replace(/\s+/g, ' ').trim();
+			}
+
+			// Rows as a markdown table with padded columns, the first row as the header; a cell spanning several columns
+			// is followed by empty ones
+			function markdownTable(rows) {
+				const grid = rows.map(row => rowCells(row).flatMap(cell => {
+					const span = Math.min(Math.max(parseInt(cell.getAttribute('colspan') || '1', 10) || 1, 1), 50);
+					return [cellText(cell).replace(/\|/g, '\\|'), ...Array(span - 1).fill('')];
+				}));
+				const columns = Math.max(...grid.map(row => row.length));
+				const widths = Array(columns).fill(3);
+				for (const row of grid) {
+					while (row.length < columns) row.push('');
+					row.forEach((cell, i) => { widths[i] = Math.max(widths[i], cell.length); });
+				}
+				const line = cells => '| ' + cells.map((cell, i) => cell.padEnd(widths[i])).join(' | ') + ' |';
+				return [line(grid[0]), line(widths.map(width => '-'.repeat(width))), ...grid.slice(1).map(line)].join('\n');
+			}
+
+			// A formulation none of the branches recognized: reported only if it has form fields to fill
+			function unsupportedQuestion(formulation, questionWrapper, questionText) {
+				const inputNames = [];
+				for (const el of formulation.querySelectorAll('input[name], select[name], textarea[name]')) {
+					// Moodle's own bookkeeping fields (":sequencecheck", ":flagged") and buttons aren't answers
+					if (el.name.includes('_:') || el.type === 'submit' || el.type === 'button') continue;
+					if (!inputNames.includes(el.name)) inputNames.push(el.name);
+				}
+				if (inputNames.length === 0) return null;
+				const qtypeClass = (questionWrapper && Array.from(questionWrapper.classList).find(c => c !== 'que')) || 'unknown';
+				return {
+					type: 'Unknown',
+					question_text: questionText,
+					qtype_class: qtypeClass,
+					raw_html_excerpt: formulation.outerHTML.slice(0, 500),
+					input_names: inputNames
+				};
+			}
+
+			// Text of the nearest description or heading before the question, among its siblings in the form
+			function precedingContext(que) {
+				for (let el = que?.previousElementSibling; el; el = el.previousElementSibling) {
+					if (el.matches('.que.description')) return extractTextWithLatex(el.querySelector('.qtext')) || null;
+					if (el.matches('h1, h2, h3, h4, h5, h6')) return el.textContent.trim() || null;
+				}
+				return null;
 			}
 
 			const questions = [];
+			// `.que` id, grade line and preceding context of each question pushed, filled in as the loop moves on to the
+			// next formulation
+			const infos = [];
+			let info = null;
 			const formulations = document.querySelectorAll('.formulation.clearfix');
 
 			for (const formulation of formulations) {
+				while (infos.length < questions.length) infos.push(info);
+				const que = formulation.closest('.que');
+				info = {
+					dom_id: que?.id || null,
+					grade: que?.querySelector('.info .grade')?.textContent || null,
+					context_text: precedingContext(que)
+				};
 				const qtextEl = formulation.querySelector('.qtext');
 				// For multianswer questions, qtext may not exist - question is directly in formulation
 				// In that case, extract text from the filter_mathjaxloader_equation span
@@ -1256,6 +3646,17 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 
 				// Check for code block questions (vplquestion with code-editor textarea)
 				const questionWrapper = formulation.closest('.que');
+
+				// Descriptions (qtype_description): text between the questions, nothing to answer
+				if (questionWrapper && questionWrapper.classList.contains('description')) {
+					questions.push({
+						type: 'Description',
+						question_text: questionText,
+						images: questionImages
+					});
+					continue;
+				}
+
 				if (questionWrapper && questionWrapper.classList.contains('vplquestion')) {
 					const codeTextarea = formulation.querySelector('textarea[data-role="code-editor"]');
 					if (codeTextarea) {
@@ -1279,17 +3680,40 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 					}
 				}
 
+				// STACK (maths) questions: a single Maxima input, usually inline in the question text
+				if (questionWrapper && questionWrapper.classList.contains('stack')) {
+					const stackInputs = formulation.querySelectorAll('input[type="text"]');
+					if (stackInputs.length === 1 && stackInputs[0].name) {
+						const stackInput = stackInputs[0];
+						const hints = [stackInput.placeholder, ...Array.from(formulation.querySelectorAll('.stacksyntaxexample')).map(e => e.textContent)];
+						questions.push({
+							type: 'ShortAnswer',
+							question_text: questionText,
+							input_name: stackInput.name,
+							current_answer: stackInput.value || '',
+							stack_requirements: hints.map(h => (h || '').trim()).filter(h => h).join('\n'),
+							images: questionImages
+						});
+						continue;
+					}
+				}
+
 				// Check for drag-drop-into-text questions (ddwtos)
 				if (questionWrapper && questionWrapper.classList.contains('ddwtos')) {
 					const dropZones = [];
 					const choices = [];
 
 					// Find all drop zones (place inputs)
-					const placeInputs = formulation.querySelectorAll('input.placeinput');
+					// Older themes don't put the "placeinput" class on them, so fall back to hidden inputs named "*_p1", "*_p2", ...
+					let placeInputs = Array.from(formulation.querySelectorAll('input.placeinput'));
+					if (placeInputs.length === 0) {
+						placeInputs = Array.from(formulation.querySelectorAll('input[type="hidden"]')).filter(i => /_p\d+$/.test(i.name || ''));
+					}
 					for (const input of placeInputs) {
-						// Extract place number from class (e.g., "place1", "place2")
+						// Extract place number from class (e.g., "place1", "place2"), or from the "_pN" name suffix
 						const placeMatch = Array.from(input.classList).find(c => c.match(/^place(\d+)$/));
-						const placeNum = placeMatch ? parseInt(placeMatch.replace('place', ''), 10) : 0;
+						const nameMatch = (input.name || '').match(/_p(\d+)$/);
+						const placeNum = placeMatch ? parseInt(placeMatch.replace('place', ''), 10) : nameMatch ? parseInt(nameMatch[1], 10) : 0;
 						// Extract group number from class (e.g., "group1", "group2")
 						const groupMatch = Array.from(input.classList).find(c => c.match(/^group(\d+)$/));
 						const groupNum = groupMatch ? parseInt(groupMatch.replace('group', ''), 10) : 1;
@@ -1301,37 +3725,190 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 						});
 					}
 
-					// Find all draggable choices
-					const choiceElements = formulation.querySelectorAll('.draghome:not(.dragplaceholder)');
-					for (const choiceEl of choiceElements) {
-						// Extract choice number from class (e.g., "choice1", "choice2")
-						const choiceMatch = Array.from(choiceEl.classList).find(c => c.match(/^choice(\d+)$/));
-						const choiceNum = choiceMatch ? parseInt(choiceMatch.replace('choice', ''), 10) : 0;
-						// Extract group number from class (e.g., "group1", "group2")
-						const groupMatch = Array.from(choiceEl.classList).find(c => c.match(/^group(\d+)$/));
-						const groupNum = groupMatch ? parseInt(groupMatch.replace('group', ''), 10) : 1;
-						// Use combo of choice+group as unique key since choice numbers can repeat across groups
-						const uniqueKey = `${groupNum}-${choiceNum}`;
-						if (choiceNum > 0 && !choices.some(c => `${c.group}-${c.choice_number}` === uniqueKey)) {
-							choices.push({
-								choice_number: choiceNum,
-								group: groupNum,
-								text: choiceEl.textContent.trim()
-							});
+					// Find all draggable choices
+					const choiceElements = formulation.querySelectorAll('.draghome:not(.dragplaceholder)');
+					for (const choiceEl of choiceElements) {
+						// Extract choice number from class (e.g., "choice1", "choice2")
+						const choiceMatch = Array.from(choiceEl.classList).find(c => c.match(/^choice(\d+)$/));
+						const choiceNum = choiceMatch ? parseInt(choiceMatch.replace('choice', ''), 10) : 0;
+						// Extract group number from class (e.g., "group1", "group2")
+						const groupMatch = Array.from(choiceEl.classList).find(c => c.match(/^group(\d+)$/));
+						const groupNum = groupMatch ? parseInt(groupMatch.replace('group', ''), 10) : 1;
+						// Use combo of choice+group as unique key since choice numbers can repeat across groups
+						const uniqueKey = `${groupNum}-${choiceNum}`;
+						if (choiceNum > 0 && !choices.some(c => `${c.group}-${c.choice_number}` === uniqueKey)) {
+							choices.push({
+								choice_number: choiceNum,
+								group: groupNum,
+								text: choiceEl.textContent.trim()
+							});
+						}
+					}
+
+					if (dropZones.length > 0 && choices.length > 0) {
+						// Sort choices by number
+						choices.sort((a, b) => a.choice_number - b.choice_number);
+						// Sort drop zones by place number
+						dropZones.sort((a, b) => a.place_number - b.place_number);
+
+						questions.push({
+							type: 'DragDropIntoText',
+							question_text: questionText,
+							on_image: false,
+							choices: choices,
+							drop_zones: dropZones,
+							images: questionImages
+						});
+						continue;
+					}
+				}
+
+				// Check for drag-drop-onto-image questions (ddimageortext)
+				// Placements live in hidden inputs named "q123:1_p1".."_pN", positioned over a background image
+				if (questionWrapper && questionWrapper.classList.contains('ddimageortext')) {
+					const dropZones = [];
+					const choices = [];
+
+					const hiddenInputs = formulation.querySelectorAll('input[type="hidden"]');
+					for (const input of hiddenInputs) {
+						const placeMatch = (input.name || '').match(/_p(\d+)$/);
+						if (!placeMatch) continue;
+						const placeNum = parseInt(placeMatch[1], 10);
+						const groupMatch = Array.from(input.classList).find(c => c.match(/^group(\d+)$/));
+						const groupNum = groupMatch ? parseInt(groupMatch.replace('group', ''), 10) : 1;
+
+						// Visual drop zones are 0-indexed (place0 corresponds to _p1)
+						let position = null;
+						const zoneEl = formulation.querySelector('.dropzone.place' + (placeNum - 1));
+						if (zoneEl) {
+							const left = parseInt(zoneEl.style.left, 10);
+							const top = parseInt(zoneEl.style.top, 10);
+							if (!isNaN(left) && !isNaN(top)) position = [left, top];
+						}
+
+						dropZones.push({
+							input_name: input.name,
+							place_number: placeNum,
+							group: groupNum,
+							current_choice: parseInt(input.value, 10) || 0,
+							position: position
+						});
+					}
+
+					const choiceElements = formulation.querySelectorAll('.draghome:not(.dragplaceholder)');
+					for (const choiceEl of choiceElements) {
+						const choiceMatch = Array.from(choiceEl.classList).find(c => c.match(/^choice(\d+)$/));
+						const choiceNum = choiceMatch ? parseInt(choiceMatch.replace('choice', ''), 10) : 0;
+						const groupMatch = Array.from(choiceEl.classList).find(c => c.match(/^group(\d+)$/));
+						const groupNum = groupMatch ? parseInt(groupMatch.replace('group', ''), 10) : 1;
+						// Drag items may be images; fall back to their alt text
+						const text = choiceEl.textContent.trim() || choiceEl.getAttribute('alt') || choiceEl.querySelector('img')?.alt || '';
+						const uniqueKey = `${groupNum}-${choiceNum}`;
+						if (choiceNum > 0 && !choices.some(c => `${c.group}-${c.choice_number}` === uniqueKey)) {
+							choices.push({ choice_number: choiceNum, group: groupNum, text: text });
+						}
+					}
+
+					if (dropZones.length > 0 && choices.length > 0) {
+						choices.sort((a, b) => a.choice_number - b.choice_number);
+						dropZones.sort((a, b) => a.place_number - b.place_number);
+
+						// The background image carries the spatial layout, so it must go first
+						const images = [];
+						const background = formulation.querySelector('img.dropbackground');
+						if (background && background.src) images.push({ url: background.src, alt: background.alt || null });
+						for (const img of questionImages) {
+							if (!images.some(i => i.url === img.url)) images.push(img);
+						}
+
+						questions.push({
+							type: 'DragDropIntoText',
+							question_text: questionText,
+							on_image: true,
+							choices: choices,
+							drop_zones: dropZones,
+							images: images
+						});
+						continue;
+					}
+				}
+
+				// Check for ordering questions (qtype_ordering)
+				if (questionWrapper && questionWrapper.classList.contains('ordering')) {
+					// Moodle stores the current order as comma-separated item ids in a hidden "..._response_..." input
+					const responseInput = formulation.querySelector('input[type="hidden"][name$="_response"], input[type="hidden"][name*="_response_"]');
+					const itemElements = formulation.querySelectorAll('ul.sortablelist > li, li.sortableitem');
+					const items = [];
+					for (const li of itemElements) {
+						const id = li.dataset.id || li.id || '';
+						if (!id) continue;
+						items.push({ id: id, text: extractTextWithLatex(li) });
+					}
+
+					if (responseInput && items.length > 0) {
+						questions.push({
+							type: 'Ordering',
+							question_text: questionText,
+							items: items,
+							input_name: responseInput.name,
+							images: questionImages
+						});
+						continue;
+					}
+				}
+
+				// Check for essay questions (Atto/TinyMCE editor over a textarea named "..._answer")
+				if (questionWrapper && questionWrapper.classList.contains('essay')) {
+					const textarea = formulation.querySelector('textarea[name$="_answer"]');
+					if (textarea) {
+						let editorKind = 'plain';
+						if (textarea.id && document.getElementById(textarea.id + 'editable')) editorKind = 'atto';
+						else if (textarea.id && document.getElementById(textarea.id + '_ifr')) editorKind = 'tinymce';
+						const acceptsAttachments = formulation.querySelector('.attachments, input[name$="_attachments"]') !== null;
+
+						questions.push({
+							type: 'Essay',
+							question_text: questionText,
+							input_name: textarea.name,
+							editor_kind: editorKind,
+							current_html: textarea.value || '',
+							accepts_attachments: acceptsAttachments,
+							images: questionImages
+						});
+						continue;
+					}
+				}
+
+				// Check for numerical questions (must come before fill-in-blanks: a unit dropdown next to the input looks like a cloze)
+				if (questionWrapper && questionWrapper.classList.contains('numerical')) {
+					const answerInput = formulation.querySelector('.ablock input[type="text"]');
+					if (answerInput && answerInput.name) {
+						const unitSelect = formulation.querySelector('.ablock select');
+						const unitRadios = Array.from(formulation.querySelectorAll('.ablock input[type=radio][name$="unit"]'));
+						const unitControl = unitSelect || unitRadios[0] || null;
+						const units = [];
+						if (unitSelect) {
+							for (const opt of unitSelect.options) {
+								if (opt.value !== '') units.push({ value: opt.value, text: opt.textContent.trim() });
+							}
+						} else {
+							for (const radio of unitRadios) {
+								const label = radio.id ? formulation.querySelector(`label[for="${CSS.escape(radio.id)}"]`) : null;
+								const text = label ? label.textContent.replace(/\s+/g, ' ').trim() : '';
+								units.push({ value: radio.value, text: text || radio.value });
+							}
 						}
-					}
-
-					if (dropZones.length > 0 && choices.length > 0) {
-						// Sort choices by number
-						choices.sort((a, b) => a.choice_number - b.choice_number);
-						// Sort drop zones by place number
-						dropZones.sort((a, b) => a.place_number - b.place_number);
 
 						questions.push({
-							type: 'DragDropIntoText',
+							type: 'Numerical',
 							question_text: questionText,
-							choices: choices,
-							drop_zones: dropZones,
+							input_name: answerInput.name,
+							current_answer: answerInput.value || '',
+							unit_select_name: unitControl ? unitControl.name : null,
+							units: units,
+							unit_radios: !unitSelect && unitControl !== null,
+							unit_in_input: unitControl === null,
+							lang: document.documentElement.lang || '',
 							images: questionImages
 						});
 						continue;
@@ -1533,7 +4110,11 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 				}
 
 				const answerDiv = formulation.querySelector('.answer');
-				if (!answerDiv) continue;
+				if (!answerDiv) {
+					const unsupported = unsupportedQuestion(formulation, questionWrapper, questionText);
+					if (unsupported) questions.push(unsupported);
+					continue;
+				}
 
 				const radioInputs = answerDiv.querySelectorAll('input[type="radio"]');
 				const checkboxInputs = answerDiv.querySelectorAll('input[type="checkbox"]');
@@ -1569,20 +4150,33 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 
 				if (choices.length > 0) {
 					questions.push({ type: questionType, question_text: questionText, choices: choices, images: questionImages });
+				} else {
+					const unsupported = unsupportedQuestion(formulation, questionWrapper, questionText);
+					if (unsupported) questions.push(unsupported);
 				}
 			}
+			while (infos.length < questions.length) infos.push(info);
+			questions.forEach((question, i) => Object.assign(question, infos[i]));
 
 			return JSON.stringify(questions);
 		})()
 	"#;
 
+	let parse_script = with_image_js(parse_script, config.min_image_size_px);
 	let result = page.evaluate(parse_script).await.map_err(|e| eyre!("Failed to parse questions: {e}"))?;
 	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
 	let parsed: Vec<serde_json::Value> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse JSON: {e}"))?;
 
 	let mut questions = Vec::new();
+	let mut infos: Vec<QuestionInfo> = Vec::new();
 
 	for item in parsed {
+		infos.resize(questions.len(), QuestionInfo::default());
+		infos.push(QuestionInfo {
+			dom_id: item["dom_id"].as_str().map(str::to_string),
+			marks: item["grade"].as_str().map(QuestionMarks::parse).unwrap_or_default(),
+			context_text: item["context_text"].as_str().map(str::to_string),
+		});
 		let question_text = item["question_text"].as_str().unwrap_or("").to_string();
 		let question_type = item["type"].as_str().unwrap_or("SingleChoice");
 		let images_json = item["images"].as_array();
@@ -1659,10 +4253,14 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 			"ShortAnswer" => {
 				let input_name = item["input_name"].as_str().unwrap_or("").to_string();
 				let current_answer = item["current_answer"].as_str().unwrap_or("").to_string();
+				let stack = item["stack_requirements"].as_str().map(|requirements| StackInput {
+					requirements: requirements.to_string(),
+				});
 				questions.push(Question::ShortAnswer {
 					question_text,
 					input_name,
 					current_answer,
+					stack,
 					images,
 				});
 			}
@@ -1729,17 +4327,86 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 							place_number: z["place_number"].as_u64().unwrap_or(0) as usize,
 							group: z["group"].as_u64().unwrap_or(1) as usize,
 							current_choice: z["current_choice"].as_u64().unwrap_or(0) as usize,
+							position: z["position"].as_array().and_then(|xy| Some((xy.first()?.as_i64()?, xy.get(1)?.as_i64()?))),
 						})
 						.collect();
 
 					questions.push(Question::DragDropIntoText(DragDropIntoText {
 						question_text,
+						on_image: item["on_image"].as_bool().unwrap_or(false),
 						choices,
 						drop_zones,
 						images,
 					}));
 				}
 			}
+			"Numerical" => {
+				let units: Vec<MatchOption> = item["units"]
+					.as_array()
+					.map(|arr| {
+						arr.iter()
+							.map(|opt| MatchOption {
+								value: opt["value"].as_str().unwrap_or("").to_string(),
+								text: opt["text"].as_str().unwrap_or("").to_string(),
+							})
+							.collect()
+					})
+					.unwrap_or_default();
+				questions.push(Question::Numerical {
+					question_text,
+					input_name: item["input_name"].as_str().unwrap_or("").to_string(),
+					current_answer: item["current_answer"].as_str().unwrap_or("").to_string(),
+					unit_select_name: item["unit_select_name"].as_str().map(|s| s.to_string()),
+					units,
+					unit_radios: item["unit_radios"].as_bool().unwrap_or(false),
+					unit_in_input: item["unit_in_input"].as_bool().unwrap_or(false),
+					decimal_separator: decimal_separator(item["lang"].as_str().unwrap_or_default()).to_string(),
+					images,
+				});
+			}
+			"Essay" => {
+				let editor_kind = match item["editor_kind"].as_str() {
+					Some("atto") => EditorKind::Atto,
+					Some("tinymce") => EditorKind::TinyMce,
+					_ => EditorKind::Plain,
+				};
+				questions.push(Question::Essay {
+					question_text,
+					input_name: item["input_name"].as_str().unwrap_or("").to_string(),
+					editor_kind,
+					current_html: item["current_html"].as_str().unwrap_or("").to_string(),
+					accepts_attachments: item["accepts_attachments"].as_bool().unwrap_or(false),
+					images,
+				});
+			}
+			"Description" => questions.push(Question::Description { text: question_text, images }),
+			"Unknown" => questions.push(Question::Unknown {
+				question_text,
+				qtype_class: item["qtype_class"].as_str().unwrap_or("unknown").to_string(),
+				raw_html_excerpt: item["raw_html_excerpt"].as_str().unwrap_or("").to_string(),
+				input_names: item["input_names"]
+					.as_array()
+					.map(|arr| arr.iter().filter_map(|n| n.as_str().map(|s| s.to_string())).collect())
+					.unwrap_or_default(),
+			}),
+			"Ordering" =>
+				if let Some(items_arr) = item["items"].as_array() {
+					let items: Vec<OrderingItem> = items_arr
+						.iter()
+						.map(|it| OrderingItem {
+							id: it["id"].as_str().unwrap_or("").to_string(),
+							text: it["text"].as_str().unwrap_or("").to_string(),
+						})
+						.collect();
+					let input_name = item["input_name"].as_str().unwrap_or("").to_string();
+
+					questions.push(Question::Ordering {
+						question_text,
+						items,
+						input_name,
+						images,
+					});
+				},
 			_ => {
 				let choices_json = item["choices"].as_array();
 				if let Some(choices_arr) = choices_json {
@@ -1777,18 +4444,24 @@ async fn parse_questions(page: &Page) -> Result<Vec<Question>> {
 			}
 		}
 	}
+	// Items that didn't make a question left their info behind
+	infos.resize(questions.len(), QuestionInfo::default());
 
-	Ok(questions)
+	Ok(questions.into_iter().zip(infos).collect())
 }
 
-/// Toggle an answer by clicking the input (select or deselect)
-async fn toggle_answer(page: &Page, input_name: &str, input_value: &str) -> Result<()> {
+/// Check or uncheck a radio/checkbox by clicking it, only if it isn't in that state already: `Choice::selected` is
+/// from when the page was parsed, and a click on a stale state would turn a saved answer off
+async fn set_choice(page: &Page, input_name: &str, input_value: &str, checked: bool) -> Result<()> {
+	let (name_js, value_js) = (js_string(input_name), js_string(input_value));
 	let script = format!(
 		r#"
 		(function() {{
-			const input = document.querySelector('input[name="{input_name}"][value="{input_value}"]');
-			if (input) {{ input.click(); return true; }}
-			return false;
+			const value = {value_js};
+			const input = Array.from(document.getElementsByName({name_js})).find(el => el.tagName === 'INPUT' && el.value === value);
+			if (!input) return false;
+			if (input.checked !== {checked}) input.click();
+			return true;
 		}})()
 		"#
 	);
@@ -1796,7 +4469,7 @@ async fn toggle_answer(page: &Page, input_name: &str, input_value: &str) -> Resu
 	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to select answer: {e}"))?;
 
 	if result.value().and_then(|v| v.as_bool()) != Some(true) {
-		bail!("Failed to find input element");
+		bail!("Failed to find input[name=\"{input_name}\"] with value \"{input_value}\"");
 	}
 
 	Ok(())
@@ -1805,14 +4478,14 @@ async fn toggle_answer(page: &Page, input_name: &str, input_value: &str) -> Resu
 /// Set a value on an input or select element found by name attribute.
 /// Dispatches `input` and `change` events to trigger form reactivity.
 async fn set_input_value(page: &Page, element: &str, name: &str, value: &str) -> Result<()> {
-	let escaped_value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n").replace('\r', "\\r");
+	let (name_js, value_js) = (js_string(name), js_string(value));
 
 	let script = format!(
 		r#"
 		(function() {{
-			const el = document.querySelector('{element}[name="{name}"]');
+			const el = Array.from(document.getElementsByName({name_js})).find(e => e.tagName.toLowerCase() === '{element}');
 			if (el) {{
-				el.value = "{escaped_value}";
+				el.value = {value_js};
 				el.dispatchEvent(new Event('input', {{ bubbles: true }}));
 				el.dispatchEvent(new Event('change', {{ bubbles: true }}));
 				return true;
@@ -1831,18 +4504,129 @@ async fn set_input_value(page: &Page, element: &str, name: &str, value: &str) ->
 	Ok(())
 }
 
+/// Set a hidden input's value (drag-drop placements) and dispatch `change` so Moodle's ddwtos/ddimageortext
+/// JS moves the corresponding drag item into its drop zone.
+async fn set_hidden_input_value(page: &Page, input_name: &str, value: &str) -> Result<()> {
+	let (name_js, value_js) = (js_string(input_name), js_string(value));
+
+	let script = format!(
+		r#"
+		(function() {{
+			const el = Array.from(document.getElementsByName({name_js})).find(e => e.type === 'hidden');
+			if (!el) return false;
+			el.value = {value_js};
+			el.dispatchEvent(new Event('change', {{ bubbles: true }}));
+			return true;
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to set hidden input value: {e}"))?;
+
+	if result.value().and_then(|v| v.as_bool()) != Some(true) {
+		bail!("Failed to find input[type=\"hidden\"][name=\"{input_name}\"]");
+	}
+
+	Ok(())
+}
+
+/// Reorder the sortable list of an ordering question and write the sequence into its hidden response input.
+/// Mirrors what Moodle's ordering JS does on drag end: the response is the item ids joined by commas.
+async fn set_ordering_response(page: &Page, input_name: &str, item_ids: &[&str]) -> Result<()> {
+	let ids_json = serde_json::to_string(item_ids).map_err(|e| eyre!("Failed to serialize ordering: {e}"))?;
+	let name_js = js_string(input_name);
+
+	let script = format!(
+		r#"
+		(function() {{
+			const ids = {ids_json};
+			const input = Array.from(document.getElementsByName({name_js})).find(e => e.tagName === 'INPUT');
+			if (!input) return false;
+
+			// Move the list items so the visual order matches the response
+			for (const id of ids) {{
+				const li = document.getElementById(id) || document.querySelector('li[data-id="' + id + '"]');
+				if (li && li.parentElement) li.parentElement.appendChild(li);
+			}}
+
+			input.value = ids.join(',');
+			input.dispatchEvent(new Event('input', {{ bubbles: true }}));
+			input.dispatchEvent(new Event('change', {{ bubbles: true }}));
+			return true;
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to set ordering response: {e}"))?;
+
+	if result.value().and_then(|v| v.as_bool()) != Some(true) {
+		bail!("Failed to find input[name=\"{input_name}\"]");
+	}
+
+	Ok(())
+}
+
+/// Write an essay answer into both the hidden textarea and the visible rich-text editor
+async fn set_essay_content(page: &Page, input_name: &str, editor_kind: EditorKind, html: &str) -> Result<()> {
+	let (name_js, html_json) = (js_string(input_name), js_string(html));
+	let editor = match editor_kind {
+		EditorKind::Atto => "atto",
+		EditorKind::TinyMce => "tinymce",
+		EditorKind::Plain => "plain",
+	};
+
+	let script = format!(
+		r#"
+		(function() {{
+			const html = {html_json};
+			const textarea = Array.from(document.getElementsByName({name_js})).find(e => e.tagName === 'TEXTAREA');
+			if (!textarea) return false;
+
+			if ('{editor}' === 'atto') {{
+				const editable = document.getElementById(textarea.id + 'editable');
+				if (editable) {{
+					editable.innerHTML = html;
+					editable.dispatchEvent(new Event('input', {{ bubbles: true }}));
+				}}
+			}} else if ('{editor}' === 'tinymce') {{
+				const instance = typeof tinymce !== 'undefined' ? tinymce.get(textarea.id) : null;
+				if (instance) {{
+					instance.setContent(html);
+					instance.save();
+				}} else {{
+					const iframe = document.getElementById(textarea.id + '_ifr');
+					if (iframe && iframe.contentDocument) iframe.contentDocument.body.innerHTML = html;
+				}}
+			}}
+
+			textarea.value = html;
+			textarea.dispatchEvent(new Event('input', {{ bubbles: true }}));
+			textarea.dispatchEvent(new Event('change', {{ bubbles: true }}));
+			return true;
+		}})()
+		"#
+	);
+
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to set essay content: {e}"))?;
+
+	if result.value().and_then(|v| v.as_bool()) != Some(true) {
+		bail!("Failed to find textarea[name=\"{input_name}\"]");
+	}
+
+	Ok(())
+}
+
 /// Set code in a code editor (ACE editor or textarea with code-editor role)
 async fn set_code_editor_content(page: &Page, input_name: &str, code: &str) -> Result<()> {
-	let escaped_code = escape_for_js_template(code);
+	let (name_js, code_js) = (js_string(input_name), js_string(code));
 
 	let script = format!(
 		r#"
 		(function() {{
-			const inputName = "{input_name}";
-			const code = `{escaped_code}`;
+			const code = {code_js};
 
 			// Find the textarea with this name
-			const textarea = document.querySelector('textarea[name="' + inputName + '"]');
+			const textarea = Array.from(document.getElementsByName({name_js})).find(e => e.tagName === 'TEXTAREA');
 			if (!textarea) return false;
 
 			// Try ACE editor first - look for editor instance
@@ -1884,41 +4668,259 @@ async fn set_code_editor_content(page: &Page, input_name: &str, code: &str) -> R
 	Ok(())
 }
 
-/// Click the submit/next button on the quiz page
-async fn click_submit(page: &Page) -> Result<()> {
+/// Parse the status table on the quiz summary page (summary.php)
+/// Returns `(question_number, status)` for every numbered question; description items ("i") are skipped
+async fn parse_quiz_summary(page: &Page) -> Result<Vec<(u32, String)>> {
 	let script = r#"
 		(function() {
-			const selectors = [
-				'input[type="submit"][name="next"]',
-				'input[type="submit"]',
-				'button[type="submit"]',
-				'.submitbtns input[type="submit"]',
-				'#responseform input[type="submit"]'
-			];
-
-			for (const selector of selectors) {
-				const btn = document.querySelector(selector);
-				if (btn) { btn.click(); return true; }
+			const rows = document.querySelectorAll('table.quizsummaryofattempt tbody tr');
+			const result = [];
+			for (const row of rows) {
+				const cells = row.querySelectorAll('td, th');
+				if (cells.length < 2) continue;
+				const number = cells[0].textContent.trim();
+				let status = cells[1].textContent.replace(/\s+/g, ' ').trim();
+				// Newer Moodle marks the state on the row itself
+				if (row.classList.contains('notyetanswered') && !status) status = 'Not yet answered';
+				result.push({ number: number, status: status });
 			}
-			return false;
+			return JSON.stringify(result);
 		})()
 	"#;
 
-	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to click submit: {e}"))?;
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to parse quiz summary: {e}"))?;
+	let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("[]");
+	let rows: Vec<serde_json::Value> = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse quiz summary JSON: {e}"))?;
+
+	Ok(rows
+		.iter()
+		.filter_map(|row| {
+			let number = row["number"].as_str()?.parse::<u32>().ok()?;
+			Some((number, row["status"].as_str().unwrap_or("").to_string()))
+		})
+		.collect())
+}
 
-	if result.value().and_then(|v| v.as_bool()) != Some(true) {
+/// Whether a summary-table status means the question has no saved answer
+fn is_unanswered_status(status: &str) -> bool {
+	let s = status.to_lowercase();
+	s.contains("not yet answered") || s.contains("pas encore répondu") || s.contains("incomplete") || s.contains("incomplet")
+}
+
+/// From the summary page, go back to the attempt page containing the given question
+/// Uses the question's link in the summary table, falling back to "Return to attempt"
+/// Returns true if navigation was triggered
+async fn return_to_summary_question(page: &Page, number: u32) -> Result<bool> {
+	let script = format!(
+		r#"
+		(function() {{
+			const rows = document.querySelectorAll('table.quizsummaryofattempt tbody tr');
+			for (const row of rows) {{
+				const cell = row.querySelector('td, th');
+				const link = cell ? cell.querySelector('a') : null;
+				if (link && cell.textContent.trim() === '{number}') {{
+					link.click();
+					return true;
+				}}
+			}}
+			const buttons = document.querySelectorAll('form[action*="attempt.php"] button, form[action*="attempt.php"] input[type="submit"]');
+			for (const btn of buttons) {{
+				const text = (btn.textContent || btn.value || '').toLowerCase();
+				if (text.includes('return to attempt') || text.includes('retour à la tentative')) {{
+					btn.click();
+					return true;
+				}}
+			}}
+			return false;
+		}})()
+	"#
+	);
+
+	action_delay().await;
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to return to question {number}: {e}"))?;
+	let clicked = result.value().and_then(|v| v.as_bool()) == Some(true);
+	if clicked {
+		wait(timings().post_submit_wait_ms).await;
+	}
+
+	Ok(clicked)
+}
+
+/// Click the submit/next button on the quiz page, returning its label
+///
+/// Submit buttons are ranked: `name="next"`, then a next/finish label, then inside `.submitbtns`, then any other.
+/// "Previous page", "Clear my choice" and the per-question Check buttons are never clicked: they'd send the run
+/// back to a page it already answered.
+async fn click_submit(page: &Page) -> Result<String> {
+	let before = page_fingerprint(page).await;
+	action_delay().await;
+	let script = format!(
+		r#"
+		(function() {{
+			return JSON.stringify(Array.from(document.querySelectorAll({selector})).map(btn => ({{
+				label: (btn.value || btn.textContent || '').replace(/\s+/g, ' ').trim(),
+				name: btn.name || '',
+				disabled: btn.disabled,
+				clear_choice: !!btn.closest('.qtype_multichoice_clearchoice'),
+				in_submitbtns: !!btn.closest('.submitbtns'),
+			}})));
+		}})()
+	"#,
+		selector = js_string(SUBMIT_BUTTONS_SELECTOR)
+	);
+	let result = page.evaluate(script).await.map_err(|e| eyre!("Failed to list submit buttons: {e}"))?;
+	let buttons: Vec<SubmitButton> = serde_json::from_str(result.value().and_then(|v| v.as_str()).unwrap_or("[]")).wrap_err("Failed to read submit buttons")?;
+	let Some(index) = best_submit_button(&buttons) else {
 		bail!("Failed to find submit button");
+	};
+	let script = format!("document.querySelectorAll({})[{index}].click()", js_string(SUBMIT_BUTTONS_SELECTOR));
+	page.evaluate(script).await.map_err(|e| eyre!("Failed to click submit: {e}"))?;
+	let button = &buttons[index];
+	let clicked = [&button.label, &button.name].into_iter().find(|s| !s.is_empty()).cloned().unwrap_or_else(|| "submit".to_string());
+
+	// A swallowed click (required-field validation, a JS error, the wrong button) would have the loop parse and
+	// answer the same page forever
+	let Some(before) = before else {
+		wait(timings().post_submit_wait_ms).await;
+		return Ok(clicked);
+	};
+	if page_moved_from(page, &before, timings().submit_navigation_timeout_ms).await? {
+		wait(timings().post_submit_wait_ms).await;
+		return Ok(clicked);
+	}
+
+	let validation = read_validation_errors(page).await;
+	match &validation {
+		Some(errors) => elog!("Clicking \"{clicked}\" didn't leave the page ({errors}), retrying with a plain form submit"),
+		None => elog!("Clicking \"{clicked}\" didn't leave the page, retrying with a plain form submit"),
+	}
+	let script = r#"
+		(function() {
+			const form = document.getElementById('responseform');
+			if (!form) return false;
+			HTMLFormElement.prototype.submit.call(form);
+			return true;
+		})()
+	"#;
+	let submitted = page.evaluate(script).await.ok().and_then(|r| r.value().and_then(|v| v.as_bool())) == Some(true);
+	if submitted && page_moved_from(page, &before, timings().submit_navigation_timeout_ms).await? {
+		wait(timings().post_submit_wait_ms).await;
+		return Ok(clicked);
+	}
+
+	let validation = read_validation_errors(page).await.or(validation);
+	match validation {
+		Some(errors) => bail!("Submitting the page didn't navigate away from it: {errors}"),
+		None => bail!("Submitting the page didn't navigate away from it, and it shows no validation error"),
 	}
+}
 
-	// Wait for page to process submission
-	tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+const SUBMIT_BUTTONS_SELECTOR: &str = r#"input[type="submit"], button[type="submit"], #responseform button:not([type])"#;
+
+/// A submit-like button of the quiz page, as read by [`click_submit`]
+#[derive(Debug, Default, Deserialize)]
+struct SubmitButton {
+	/// Value or text, whitespace-collapsed
+	label: String,
+	name: String,
+	disabled: bool,
+	/// Inside a multichoice question's "Clear my choice"
+	clear_choice: bool,
+	/// Inside the quiz's `.submitbtns` bar
+	in_submitbtns: bool,
+}
 
-	Ok(())
+impl SubmitButton {
+	/// Previous page, clear my choice, a question's own Check/Try again: never the way forward
+	fn excluded(&self) -> bool {
+		let name = self.name.to_lowercase();
+		let label = self.label.to_lowercase();
+		name == "previous"
+			|| name == "prev"
+			|| name.ends_with("_-submit")
+			|| name.ends_with("_-tryagain")
+			|| self.clear_choice
+			|| ["previous", "précédent", "clear", "effacer"].iter().any(|w| label.contains(w))
+	}
+
+	fn score(&self) -> u8 {
+		let label = self.label.to_lowercase();
+		if self.name == "next" {
+			4
+		} else if ["next", "suivant", "finish", "terminer", "submit", "envoyer"].iter().any(|w| label.contains(w)) {
+			3
+		} else if self.in_submitbtns {
+			2
+		} else {
+			1
+		}
+	}
+}
+
+/// Index of the button [`click_submit`] should click: the highest-scoring enabled one that isn't excluded, the first
+/// of them on a tie
+fn best_submit_button(buttons: &[SubmitButton]) -> Option<usize> {
+	buttons
+		.iter()
+		.enumerate()
+		.filter(|(_, b)| !b.disabled && !b.excluded())
+		.min_by_key(|(_, b)| std::cmp::Reverse(b.score()))
+		.map(|(i, _)| i)
+}
+
+/// Validation messages shown on the page (Moodle's `.validationerror`, error banners, fields the browser rejects),
+/// joined; None when there are none
+async fn read_validation_errors(page: &Page) -> Option<String> {
+	let html = page.evaluate("document.documentElement.outerHTML").await.ok()?;
+	let mut messages = validation_errors_in(html.value()?.as_str()?);
+	// Constraint validation only exists in the live DOM
+	let script = r#"
+		(function() {
+			return JSON.stringify(Array.from(document.querySelectorAll('#responseform :invalid'))
+				.filter(field => field.name)
+				.map(field => field.name + ': ' + field.validationMessage));
+		})()
+	"#;
+	if let Some(invalid) = page
+		.evaluate(script)
+		.await
+		.ok()
+		.and_then(|r| r.value().and_then(|v| v.as_str()).and_then(|s| serde_json::from_str::<Vec<String>>(s).ok()))
+	{
+		for message in invalid {
+			if !messages.contains(&message) {
+				messages.push(message);
+			}
+		}
+	}
+	(!messages.is_empty()).then(|| messages.join("; "))
+}
+
+/// Texts of the validation messages and error banners in `html`, deduplicated, skipping the hidden ones
+/// (`hidden`, `.d-none`, `display: none` on the element or an ancestor)
+fn validation_errors_in(html: &str) -> Vec<String> {
+	let document = scraper::Html::parse_document(html);
+	let selector = scraper::Selector::parse(".validationerror, .invalid-feedback, #responseform .alert-danger, .alert-error").expect("static selector");
+	let hidden = |el: scraper::ElementRef<'_>| {
+		std::iter::once(el).chain(el.ancestors().filter_map(scraper::ElementRef::wrap)).any(|el| {
+			let el = el.value();
+			el.attr("hidden").is_some() || el.classes().any(|c| c == "d-none") || el.attr("style").is_some_and(|s| s.replace(' ', "").contains("display:none"))
+		})
+	};
+	let mut messages: Vec<String> = Vec::new();
+	for el in document.select(&selector).filter(|el| !hidden(*el)) {
+		let text = crate::answers::normalize_whitespace(&el.text().collect::<String>());
+		if !text.is_empty() && !messages.contains(&text) {
+			messages.push(text);
+		}
+	}
+	messages
 }
 
 /// Click the next page button without submitting answers
 /// Returns true if found and clicked, false if not found
 async fn click_next_page(page: &Page) -> Result<bool> {
+	action_delay().await;
 	let script = r#"
 		(function() {
 			// Look for "Next page" navigation links/buttons (common in Moodle quizzes)
@@ -1948,79 +4950,303 @@ async fn click_next_page(page: &Page) -> Result<bool> {
 	let clicked = result.value().and_then(|v| v.as_bool()).unwrap_or(false);
 	if clicked {
 		// Wait for page to load
-		tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+		wait(timings().post_submit_wait_ms).await;
 	}
 
 	Ok(clicked)
 }
 
-/// Wait for the page URL to change (indicating form submission)
-async fn wait_for_page_change(page: &Page) -> Result<()> {
-	let initial_url = page.url().await.map_err(|e| eyre!("Failed to get URL: {e}"))?;
+/// Outcome of waiting for a page change
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum PageChange {
+	Changed,
+	TimedOut,
+}
+
+/// Identifies the rendered page beyond its URL: many themes post back to the same processattempt.php URL,
+/// so the question ids, current quiz page and sesskey are included as well
+async fn page_fingerprint(page: &Page) -> Option<String> {
+	let script = r#"
+		(function() {
+			const ids = Array.from(document.querySelectorAll('.que[id]')).map(q => q.id).join(',');
+			const thispage = document.querySelector('input[name="thispage"]');
+			const sesskey = document.querySelector('input[name="sesskey"]');
+			return [ids, thispage ? thispage.value : '', sesskey ? sesskey.value : ''].join('|');
+		})()
+	"#;
+	let url = page.url().await.ok().flatten()?;
+	// Evaluation fails mid-navigation; treat that as "unknown" rather than a change
+	let dom = page.evaluate(script).await.ok()?.value().and_then(|v| v.as_str()).map(|s| s.to_string())?;
+	Some(format!("{url}#{dom}"))
+}
+
+/// Whether the page moved away from `before` (a [`page_fingerprint`]) within `timeout_ms`
+async fn page_moved_from(page: &Page, before: &str, timeout_ms: u64) -> Result<bool> {
+	fingerprint_moved_from(before, timeout_ms, async || page_fingerprint(page).await).await
+}
+
+/// Polls `fingerprint` until it gives something other than `before` (true) or `timeout_ms` runs out (false). An
+/// unknown fingerprint (None, mid-navigation) isn't a change.
+async fn fingerprint_moved_from(before: &str, timeout_ms: u64, mut fingerprint: impl AsyncFnMut() -> Option<String>) -> Result<bool> {
+	let deadline = std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms);
+	while std::time::Instant::now() < deadline {
+		shutdown::sleep(std::time::Duration::from_millis(timings().poll_interval_ms)).await?;
+		if fingerprint().await.is_some_and(|current| current != before) {
+			return Ok(true);
+		}
+	}
+	Ok(false)
+}
+
+/// Wait for the page to change (indicating form submission or navigation), comparing URL and DOM fingerprint.
+/// Gives up after `timeout_secs` (0 = never).
+async fn wait_for_page_change(page: &Page, timeout_secs: u64) -> Result<PageChange> {
+	let initial = page_fingerprint(page).await.ok_or_else(|| eyre!("Failed to read the current page state"))?;
+	let deadline = (timeout_secs > 0).then(|| std::time::Instant::now() + std::time::Duration::from_secs(timeout_secs));
 
 	loop {
-		tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+		shutdown::sleep(std::time::Duration::from_millis(timings().poll_interval_ms)).await?;
 
-		let current_url = page.url().await.map_err(|e| eyre!("Failed to get URL: {e}"))?;
+		if deadline.is_some_and(|d| std::time::Instant::now() >= d) {
+			return Ok(PageChange::TimedOut);
+		}
 
-		if current_url != initial_url {
+		if let Some(current) = page_fingerprint(page).await
+			&& current != initial
+		{
 			// Wait a bit for page to fully load
-			tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-			return Ok(());
+			wait(timings().step_wait_ms).await;
+			return Ok(PageChange::Changed);
 		}
 	}
 }
 
-/// Display an image in terminal using chafa
-async fn display_image_chafa(page: &Page, url: &str, max_cols: u32) -> Result<()> {
-	use std::process::Stdio;
+/// Print what OCR read in an image, if it ran on it, under the image's rendering
+async fn display_ocr_text(page: &Page, images: &ImageCache, output: Emitter, url: &str, indent: &str) {
+	if let Some(text) = images.ocr_text(page, url).await {
+		output.human(format_args!("{indent}[Text extracted from image]"));
+		for line in text.lines() {
+			output.human(format_args!("{indent}{line}"));
+		}
+	}
+}
 
-	use tokio::process::Command;
+/// Draw an image in the terminal, at most `max_cols` wide; its alt text or URL stands in for it when it can't be drawn
+async fn display_image(page: &Page, images: &ImageCache, output: Emitter, img: &Image, max_cols: u32, indent: &str) {
+	let placeholder = || output.human(format_args!("{indent}[Image: {}]", img.alt.as_deref().unwrap_or(&img.url)));
+	if terminal_image::renderer() == ImageRenderer::Text {
+		placeholder();
+		return;
+	}
+	let rendered = match images.get(page, &img.url).await {
+		Ok((base64_data, media_type)) => terminal_image::render(&base64_data, &media_type, max_cols).await,
+		Err(e) => Err(e),
+	};
+	if let Err(e) = rendered {
+		elog!("Failed to display image: {e}");
+		placeholder();
+	}
+}
 
-	let fetch_script = format!(
-		r#"
-		(async function() {{
-			try {{
-				const response = await fetch("{url}");
-				if (!response.ok) return null;
-				const blob = await response.blob();
-				return new Promise((resolve) => {{
-					const reader = new FileReader();
-					reader.onloadend = () => resolve(reader.result);
-					reader.readAsDataURL(blob);
-				}});
-			}} catch (e) {{ return null; }}
-		}})()
-		"#
-	);
+#[cfg(test)]
+mod tests {
+	use chromiumoxide::browser::{Browser, BrowserConfig};
+	use futures::StreamExt;
 
-	let result = page.evaluate(fetch_script).await.map_err(|e| eyre!("Failed to fetch image via browser: {e}"))?;
-	let data_url = result.value().and_then(|v| v.as_str()).ok_or_else(|| eyre!("Failed to fetch image: browser returned null"))?;
-	let base64_data = data_url.split(",").nth(1).ok_or_else(|| eyre!("Invalid data URL format"))?;
+	use super::*;
 
-	use base64::Engine;
-	let bytes = base64::engine::general_purpose::STANDARD.decode(base64_data).map_err(|e| eyre!("Failed to decode base64: {e}"))?;
+	fn fixture_questions(html: &str) -> Vec<Question> {
+		parse_questions_from_html(html).expect("fixture parses")
+	}
 
-	let temp_path = format!("/tmp/quiz_img_{}.tmp", std::process::id());
-	tokio::fs::write(&temp_path, &bytes).await.map_err(|e| eyre!("Failed to write temp file: {e}"))?;
+	/// A headless Chrome tab showing `html`, or `None` (test skipped) where no Chrome can be launched
+	async fn fixture_page(html: &str) -> Option<(Browser, Page)> {
+		let launched = match BrowserConfig::builder().build() {
+			Ok(config) => Browser::launch(config).await.map_err(|e| e.to_string()),
+			Err(e) => Err(e),
+		};
+		let Ok((browser, mut handler)) = launched.inspect_err(|e| eprintln!("skipping browser test, can't launch Chrome: {e}")) else {
+			return None;
+		};
+		tokio::spawn(async move { while handler.next().await.is_some() {} });
+		let page = browser.new_page("about:blank").await.expect("new tab");
+		page.set_content(html).await.expect("fixture loads");
+		Some((browser, page))
+	}
 
-	let output = Command::new("chafa")
-		.arg("--size")
-		.arg(format!("{max_cols}x"))
-		.arg(&temp_path)
-		.stdout(Stdio::piped())
-		.stderr(Stdio::piped())
-		.output()
-		.await
-		.map_err(|e| eyre!("Failed to run chafa: {e}"))?;
+	async fn hidden_values(page: &Page, names: &[&str]) -> Vec<String> {
+		let script = format!(
+			"{}.map(name => Array.from(document.getElementsByName(name)).find(e => e.type === 'hidden').value)",
+			serde_json::to_string(names).unwrap()
+		);
+		page.evaluate(script).await.unwrap().into_value().unwrap()
+	}
 
-	let _ = tokio::fs::remove_file(&temp_path).await;
+	#[test]
+	fn ordering_writes_item_ids_in_answer_order() {
+		let questions = fixture_questions(include_str!("../tests/integration/fixtures/ordering.html"));
+		let answer = LlmAnswerResult::Ordering { order: vec![1, 2, 3, 0] };
+		let expectations = field_expectations(&questions[0], &answer);
+		let [expectation] = &expectations[..] else {
+			panic!("expected one field, got {expectations:?}")
+		};
+		assert_eq!(expectation.name(), "q1207:3_response_1207_3");
+		assert!(expectation.matches(Some("ordering_item_09ab,ordering_item_77d0,ordering_item_c3e2,ordering_item_5f1c")));
+	}
 
-	if output.status.success() {
-		print!("{}", String::from_utf8_lossy(&output.stdout));
-	} else {
-		bail!("chafa failed: {}", String::from_utf8_lossy(&output.stderr));
+	#[test]
+	fn webservice_fields_take_the_answers() {
+		let html = include_str!("../tests/integration/fixtures/quiz_page.html");
+		let questions = fixture_questions(html);
+		let single = questions.iter().find(|q| q.choices().first().is_some_and(|c| c.input_name == "q77:1_answer")).unwrap();
+		let multi = questions.iter().find(|q| q.choices().first().is_some_and(|c| c.input_name == "q77:2_choice0")).unwrap();
+		let answered = [
+			(single, LlmAnswerResult::Single { idx: 2, text: String::new() }),
+			(
+				multi,
+				LlmAnswerResult::Multi {
+					indices: vec![0],
+					texts: Vec::new(),
+				},
+			),
+		];
+
+		let fields = fill_response_fields(parse_response_fields(html).unwrap(), &answered);
+		let values = |name: &str| fields.iter().filter(|(n, _)| n == name).map(|(_, v)| v.as_str()).collect::<Vec<_>>();
+		assert_eq!(values("q77:1_answer"), ["2"]);
+		assert_eq!(values("q77:2_choice0"), ["1"]);
+		assert_eq!(values("q77:2_choice2"), ["0"]);
+		assert_eq!(values("q77:1_:sequencecheck"), ["1"]);
+		assert_eq!(values("q77:4_unit"), ["m"]);
 	}
 
-	Ok(())
+	#[test]
+	fn batched_fill_statuses_are_checked_per_field() {
+		let ops: Vec<FieldOp> = [("q1207:2_p1", "1"), ("q1207:2_p2", "2"), ("q1207:2_p3", "1")]
+			.into_iter()
+			.map(|(name, value)| FieldOp::new(FieldOpKind::Hidden, name, value))
+			.collect();
+
+		check_field_op_statuses(&ops, r#"[{"ok": true}, {"ok": true}, {"ok": true}]"#).unwrap();
+
+		let err = check_field_op_statuses(&ops, r#"[{"ok": true}, {"ok": false, "error": "not found"}, {"ok": false}]"#)
+			.unwrap_err()
+			.to_string();
+		assert!(err.starts_with("Failed to fill 2 of 3 field(s)"), "{err}");
+		assert!(err.contains(r#"Hidden q1207:2_p2="2": not found"#), "{err}");
+		assert!(err.contains(r#"Hidden q1207:2_p3="1": failed"#), "{err}");
+		assert!(!err.contains("q1207:2_p1"), "{err}");
+
+		let err = check_field_op_statuses(&ops, r#"[{"ok": true}]"#).unwrap_err().to_string();
+		assert_eq!(err, "Answer fill reported 1 result(s) for 3 field(s)");
+		assert!(check_field_op_statuses(&ops, "not json").is_err());
+	}
+
+	#[test]
+	fn ddwtos_placements_write_place_inputs() {
+		let questions = fixture_questions(include_str!("../tests/integration/fixtures/ddwtos.html"));
+		let placements = vec![("q1207:2_p1".to_string(), 1), ("q1207:2_p2".to_string(), 2), ("q1207:2_p3".to_string(), 1)];
+		let answer = LlmAnswerResult::DragDropIntoText { placements };
+
+		let ops = field_ops(&questions[0], &answer).expect("drag-drop answers are plain field writes");
+		let writes: Vec<(&str, &str)> = ops.iter().map(|op| (op.name.as_str(), op.value.as_str())).collect();
+		assert_eq!(writes, [("q1207:2_p1", "1"), ("q1207:2_p2", "2"), ("q1207:2_p3", "1")]);
+		assert!(ops.iter().all(|op| matches!(op.kind, FieldOpKind::Hidden)));
+
+		let expectations = field_expectations(&questions[0], &answer);
+		assert!(expectations.iter().zip(["1", "2", "1"]).all(|(e, value)| e.matches(Some(value))));
+	}
+
+	#[tokio::test]
+	async fn ddwtos_placements_land_in_the_page() {
+		let html = include_str!("../tests/integration/fixtures/ddwtos.html");
+		let Some((_browser, page)) = fixture_page(html).await else { return };
+		let questions = fixture_questions(html);
+		let placements = vec![("q1207:2_p1".to_string(), 2), ("q1207:2_p2".to_string(), 3), ("q1207:2_p3".to_string(), 1)];
+
+		apply_answers(&page, &[(&questions[0], LlmAnswerResult::DragDropIntoText { placements })]).await.unwrap();
+
+		assert_eq!(hidden_values(&page, &["q1207:2_p1", "q1207:2_p2", "q1207:2_p3"]).await, ["2", "3", "1"]);
+		// The other question's places are left alone
+		assert_eq!(hidden_values(&page, &["q1207:4_p1", "q1207:4_p2"]).await, ["2", "0"]);
+	}
+
+	#[test]
+	fn webservice_fields_take_drag_drop_placements() {
+		let html = include_str!("../tests/integration/fixtures/ddwtos.html");
+		let questions = fixture_questions(html);
+		let placements = vec![("q1207:2_p1".to_string(), 2), ("q1207:2_p3".to_string(), 1)];
+		let answered = [(&questions[0], LlmAnswerResult::DragDropIntoText { placements })];
+
+		let fields = fill_response_fields(parse_response_fields(html).unwrap(), &answered);
+		let value = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str());
+		assert_eq!(value("q1207:2_p1"), Some("2"));
+		assert_eq!(value("q1207:2_p3"), Some("1"));
+		// Places the answer leaves out keep what the page had, other questions too
+		assert_eq!(value("q1207:2_p2"), Some("1"));
+		assert_eq!(value("q1207:2_:sequencecheck"), Some("1"));
+		assert_eq!(value("q1207:4_p1"), Some("2"));
+	}
+
+	/// A submit Moodle swallowed: the page stays put and shows why
+	#[tokio::test]
+	async fn no_op_submit_is_caught_with_its_validation_errors() {
+		let html = include_str!("../tests/integration/fixtures/quiz_submit_rejected.html");
+		let before = "https://moodle.example.fr/mod/quiz/attempt.php?attempt=5121#question-80-1,question-80-2|0|sk3Yq7".to_string();
+		let timeout_ms = timings().poll_interval_ms * 3;
+
+		let mut polls = 0;
+		let stayed = async || {
+			polls += 1;
+			(polls != 2).then(|| before.clone())
+		};
+		assert!(!fingerprint_moved_from(&before, timeout_ms, stayed).await.unwrap());
+		assert_eq!(
+			validation_errors_in(html),
+			["You must enter a valid number. Do not use a unit in your answer.", "Please enter an answer."]
+		);
+
+		let mut polls = 0;
+		let moved = async || {
+			polls += 1;
+			Some(if polls < 2 { before.clone() } else { before.replace("|0|", "|1|") })
+		};
+		assert!(fingerprint_moved_from(&before, timeout_ms, moved).await.unwrap());
+	}
+
+	fn button(label: &str, name: &str) -> SubmitButton {
+		SubmitButton {
+			label: label.to_string(),
+			name: name.to_string(),
+			..Default::default()
+		}
+	}
+
+	#[test]
+	fn submit_button_ranking() {
+		let nav = [button("Previous page", "previous"), button("Next page", "next")];
+		assert_eq!(best_submit_button(&nav), Some(1));
+
+		// Per-question Check and Clear my choice come before the nav in the DOM
+		let clear = SubmitButton {
+			clear_choice: true,
+			..button("Clear my choice", "")
+		};
+		let page = [button("Check", "q80:1_-submit"), clear, button("Page précédente", "prev"), button("Terminer le test", "")];
+		assert_eq!(best_submit_button(&page), Some(3));
+
+		let disabled_next = SubmitButton {
+			disabled: true,
+			..button("Next page", "next")
+		};
+		let in_bar = SubmitButton {
+			in_submitbtns: true,
+			..button("Continue", "")
+		};
+		assert_eq!(best_submit_button(&[button("Go", ""), disabled_next, in_bar]), Some(2));
+
+		// Ties go to the first
+		assert_eq!(best_submit_button(&[button("Submit all", ""), button("Finish attempt", "")]), Some(0));
+		assert_eq!(best_submit_button(&[button("Effacer mon choix", ""), button("Try again", "q80:1_-tryagain")]), None);
+	}
 }
@@ -0,0 +1,105 @@
+//! Browser session persistence - save cookies after login and restore them on the next run
+
+use std::{
+	fs::{OpenOptions, Permissions},
+	io::Write,
+	os::unix::fs::{OpenOptionsExt, PermissionsExt},
+	path::PathBuf,
+};
+
+use chromiumoxide::{
+	Page,
+	cdp::browser_protocol::{
+		network::{Cookie, CookieParam, SetCookiesParams, TimeSinceEpoch},
+		storage::GetCookiesParams,
+	},
+};
+use color_eyre::{Result, eyre::eyre};
+use serde::{Deserialize, Serialize};
+use v_utils::xdg_state_dir;
+
+/// Cookies saved for one site
+#[derive(Debug, Deserialize, Serialize)]
+struct SavedSession {
+	/// Unix timestamp of when the cookies were saved
+	saved_at: u64,
+	cookies: Vec<Cookie>,
+}
+
+fn session_path(host: &str) -> PathBuf {
+	xdg_state_dir!("sessions").join(format!("{host}.json"))
+}
+
+fn now_secs() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Dump all browser cookies (all domains, so the CAS/IdP session is kept too) for the site at `host`
+/// Returns the number of cookies saved
+pub async fn save_cookies(page: &Page, host: &str) -> Result<usize> {
+	let cookies = page
+		// `Storage.getCookies`: every cookie in the browser, where `Network.getCookies` only has the current page's
+		.execute(GetCookiesParams::default())
+		.await
+		.map_err(|e| eyre!("Failed to read browser cookies: {e}"))?
+		.result
+		.cookies;
+
+	let session = SavedSession { saved_at: now_secs(), cookies };
+	let json = serde_json::to_string_pretty(&session).map_err(|e| eyre!("Failed to serialize cookies: {e}"))?;
+	let path = session_path(host);
+	// The jar holds the Moodle and SSO session cookies: keep it readable by the owner only
+	let write = || -> std::io::Result<()> {
+		let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(&path)?;
+		// `mode` only applies on creation; tighten a file left by an older version too
+		file.set_permissions(Permissions::from_mode(0o600))?;
+		file.write_all(json.as_bytes())
+	};
+	write().map_err(|e| eyre!("Failed to write {}: {e}", path.display()))?;
+
+	Ok(session.cookies.len())
+}
+
+/// Load the saved cookies for the site at `host` into the browser, dropping any that have expired
+/// Returns the number of cookies restored (0 if there is no saved session)
+pub async fn restore_cookies(page: &Page, host: &str) -> Result<usize> {
+	let path = session_path(host);
+	let Ok(content) = std::fs::read_to_string(&path) else {
+		return Ok(0);
+	};
+	let session: SavedSession = serde_json::from_str(&content).map_err(|e| eyre!("Failed to parse {}: {e}", path.display()))?;
+
+	let now = now_secs() as f64;
+	let params: Vec<CookieParam> = session
+		.cookies
+		.into_iter()
+		// Session cookies have no expiry of their own; the server decides whether they're still valid
+		.filter(|c| c.session || c.expires > now)
+		.map(|c| {
+			let mut param = CookieParam::new(c.name, c.value);
+			param.domain = Some(c.domain);
+			param.path = Some(c.path);
+			param.secure = Some(c.secure);
+			param.http_only = Some(c.http_only);
+			param.same_site = c.same_site;
+			if !c.session {
+				param.expires = Some(TimeSinceEpoch::new(c.expires));
+			}
+			param
+		})
+		.collect();
+
+	if params.is_empty() {
+		return Ok(0);
+	}
+	let count = params.len();
+	// Raw CDP call: the page is still on about:blank, which `Page::set_cookies` refuses to derive URLs from
+	page.execute(SetCookiesParams::new(params)).await.map_err(|e| eyre!("Failed to restore cookies: {e}"))?;
+
+	Ok(count)
+}
+
+/// Forget the saved session for the site at `host` (e.g. after it failed to authenticate)
+pub fn clear_cookies(host: &str) {
+	let _ = std::fs::remove_file(session_path(host));
+}
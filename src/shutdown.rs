@@ -0,0 +1,66 @@
+//! Ctrl+C and SIGTERM handling
+//!
+//! The first signal cancels the run: long waits (page changes, VPL evaluations) return an
+//! [`FailureKind::Interrupted`] error, main skips the remaining URLs, writes the run report and closes the browser.
+//! A second signal exits at once.
+
+use std::{sync::LazyLock, time::Duration};
+
+use color_eyre::{Report, Result, eyre::eyre};
+use tokio_util::sync::CancellationToken;
+use v_utils::log;
+
+use crate::failure::FailureKind;
+
+static TOKEN: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+/// Cancelled on the first Ctrl+C/SIGTERM
+pub fn token() -> &'static CancellationToken {
+	&TOKEN
+}
+
+/// Start listening for Ctrl+C/SIGTERM. Call once, from inside the runtime.
+pub fn listen() {
+	tokio::spawn(async {
+		signal().await;
+		log!("Shutting down... (press Ctrl+C again to force exit)");
+		TOKEN.cancel();
+		signal().await;
+		std::process::exit(FailureKind::Interrupted.exit_code());
+	});
+}
+
+async fn signal() {
+	#[cfg(unix)]
+	{
+		use tokio::signal::unix::{SignalKind, signal};
+		match signal(SignalKind::terminate()) {
+			Ok(mut terminate) => tokio::select! {
+				_ = tokio::signal::ctrl_c() => {}
+				_ = terminate.recv() => {}
+			},
+			Err(_) => {
+				let _ = tokio::signal::ctrl_c().await;
+			}
+		}
+	}
+	#[cfg(not(unix))]
+	let _ = tokio::signal::ctrl_c().await;
+}
+
+pub fn interrupted() -> Report {
+	eyre!("Interrupted").wrap_err(FailureKind::Interrupted)
+}
+
+/// Err if the run was cancelled
+pub fn check() -> Result<()> {
+	if TOKEN.is_cancelled() { Err(interrupted()) } else { Ok(()) }
+}
+
+/// `tokio::time::sleep` that returns early with an error when the run gets cancelled
+pub async fn sleep(duration: Duration) -> Result<()> {
+	tokio::select! {
+		_ = TOKEN.cancelled() => Err(interrupted()),
+		_ = tokio::time::sleep(duration) => Ok(()),
+	}
+}
@@ -0,0 +1,67 @@
+//! STACK (maths) questions: answers are Maxima expressions, validated live in the area under the input
+//!
+//! The validation area (`.stackinputfeedback`, id `<input name>_val`) is filled in by STACK's JS once the input has
+//! been typed into and left, showing the expression as STACK read it, or an error for one it couldn't parse.
+
+use chromiumoxide::Page;
+use color_eyre::{Result, eyre::eyre};
+use serde::Deserialize;
+
+use crate::{
+	StackInput, js_string,
+	timing::{timings, wait},
+};
+
+/// What the LLM is told about the input, in place of the plain short answer instructions
+pub fn llm_instructions(input: &StackInput) -> String {
+	let mut instructions = "You are answering a STACK maths question. The answer is typed into a single input and must be a Maxima expression: \
+	                        `*` for every multiplication, `^` for powers, `sqrt()`, `exp()`, `log()`, `%pi`, `%e`, `%i`, e.g. `x^2+3*x`. \
+	                        No LaTeX, no `$`, no `=` unless an equation is asked for, no text around the expression."
+		.to_string();
+	if !input.requirements.is_empty() {
+		instructions.push_str(&format!("\nThe input shows these requirements:\n{}", input.requirements));
+	}
+	instructions
+}
+
+/// The validation area's verdict on the expression in the input named `input_name`, once it has rendered: the
+/// error STACK shows, or None when it accepted the expression (or the question has no validation area)
+pub async fn validation_error(page: &Page, input_name: &str) -> Result<Option<String>> {
+	let script = format!(
+		r#"
+		(function() {{
+			const name = {name};
+			const field = document.getElementsByName(name)[0];
+			const que = field ? field.closest('.que') : null;
+			const area = document.getElementById(name + '_val') || (que ? que.querySelector('.stackinputfeedback') : null);
+			if (!area) return JSON.stringify({{ state: 'none', text: '' }});
+			const text = (area.innerText || '').trim();
+			const error = area.querySelector('.stackinputerror, .stacksyntaxerror, .error') || area.classList.contains('stackinputerror');
+			return JSON.stringify({{ state: error ? 'error' : text ? 'valid' : 'pending', text }});
+		}})()
+		"#,
+		name = js_string(input_name)
+	);
+
+	#[derive(Deserialize)]
+	struct Validation {
+		state: String,
+		text: String,
+	}
+
+	let deadline = std::time::Instant::now() + std::time::Duration::from_millis(timings().stack_validation_timeout_ms);
+	loop {
+		let result = page.evaluate(script.as_str()).await.map_err(|e| eyre!("Failed to read STACK validation: {e}"))?;
+		let json_str = result.value().and_then(|v| v.as_str()).unwrap_or("{}");
+		let validation: Validation = serde_json::from_str(json_str).map_err(|e| eyre!("Failed to parse STACK validation JSON: {e}"))?;
+		match validation.state.as_str() {
+			"error" => return Ok(Some(validation.text)),
+			"pending" if std::time::Instant::now() < deadline => wait(timings().poll_interval_ms).await,
+			"pending" => {
+				tracing::warn!("STACK validation for {input_name} didn't render within {}ms", timings().stack_validation_timeout_ms);
+				return Ok(None);
+			}
+			_ => return Ok(None),
+		}
+	}
+}
@@ -0,0 +1,54 @@
+//! Make headless Chrome pass for a regular one on the login pages: some IdPs serve a JS challenge that never
+//! resolves when `navigator.webdriver` is set or the user agent says `HeadlessChrome`
+
+use chromiumoxide::{
+	Page,
+	cdp::browser_protocol::{network::SetUserAgentOverrideParams, page::AddScriptToEvaluateOnNewDocumentParams},
+};
+use color_eyre::{Result, eyre::eyre};
+
+/// Spoof the user agent, `Accept-Language` and the navigator properties bot checks look at. Call on a blank page,
+/// before its first real navigation, so every document it loads gets them.
+pub async fn apply(page: &Page, language: &str) -> Result<()> {
+	let user_agent = page
+		.user_agent()
+		.await
+		.map_err(|e| eyre!("Failed to read the user agent: {e}"))?
+		.replace("HeadlessChrome", "Chrome");
+	let params = SetUserAgentOverrideParams::builder()
+		.user_agent(user_agent)
+		.accept_language(language)
+		.build()
+		.map_err(|e| eyre!("Failed to build the user agent override: {e}"))?;
+	page.execute(params).await.map_err(|e| eyre!("Failed to override the user agent: {e}"))?;
+
+	let script = format!(
+		r#"
+		delete Object.getPrototypeOf(navigator).webdriver;
+		Object.defineProperty(navigator, 'languages', {{ get: () => {languages} }});
+		Object.defineProperty(navigator, 'plugins', {{
+			get: () => [
+				{{ name: 'PDF Viewer', filename: 'internal-pdf-viewer', description: 'Portable Document Format' }},
+				{{ name: 'Chrome PDF Viewer', filename: 'internal-pdf-viewer', description: 'Portable Document Format' }},
+				{{ name: 'Chromium PDF Viewer', filename: 'internal-pdf-viewer', description: 'Portable Document Format' }},
+			],
+		}});
+		window.chrome = window.chrome || {{ runtime: {{}} }};
+	"#,
+		languages = serde_json::to_string(&navigator_languages(language)).expect("serializing strings is infallible")
+	);
+	page.execute(AddScriptToEvaluateOnNewDocumentParams::new(script))
+		.await
+		.map_err(|e| eyre!("Failed to inject the stealth script: {e}"))?;
+	Ok(())
+}
+
+/// `navigator.languages` for an `Accept-Language` value: "fr-FR,fr;q=0.9,en;q=0.8" -> ["fr-FR", "fr", "en"]
+fn navigator_languages(accept_language: &str) -> Vec<&str> {
+	accept_language
+		.split(',')
+		.filter_map(|part| part.split(';').next())
+		.map(str::trim)
+		.filter(|lang| !lang.is_empty())
+		.collect()
+}
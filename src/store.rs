@@ -0,0 +1,451 @@
+//! Per-run session directory: page snapshots (HTML and a full-page screenshot) for debugging, `meta.json` and the
+//! default run report location
+//!
+//! By default (`xdg` feature) each run gets `persist_htmls/<session id>` in the state dir, and runs older than
+//! [`SESSION_MAX_AGE_SECS`] are cleaned up at startup. `--save-html DIR` writes into `DIR` instead (never cleaned
+//! up), and `--no-save-html`, or a build without the feature, makes the store a no-op.
+
+use std::{
+	io::Write,
+	path::{Path, PathBuf},
+	sync::atomic::{AtomicU32, Ordering},
+	time::Duration,
+};
+
+use chromiumoxide::{Page, cdp::browser_protocol::page::CaptureScreenshotFormat, page::ScreenshotParams};
+use color_eyre::{Result, eyre::eyre};
+use v_utils::{elog, log};
+
+use crate::{answers::fnv1a_hex, config::AppConfig, exchange_log::ExchangeLog, login::url_host, usage::UsageTotals};
+
+/// Session directories older than this are removed when a new run starts
+pub const SESSION_MAX_AGE_SECS: u64 = 12 * 60 * 60;
+
+/// A wedged renderer never answers `Page.captureScreenshot`, which mustn't hold up the error path
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest snapshot file name, extension included; quiz attempt URLs alone can be past the 255-byte limit
+const MAX_FILENAME_CHARS: usize = 120;
+
+/// Snapshots taken so far, to tell apart the ones taken within the same second
+static SNAPSHOT_SEQ: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Clone, Debug)]
+pub struct SessionStore {
+	id: String,
+	/// Directory this run's files go to, None to save nothing
+	dir: Option<PathBuf>,
+	/// Parent of every run's directory, cleaned of expired ones; only set for the state-dir store
+	sessions_root: Option<PathBuf>,
+	/// Take a screenshot with every snapshot, not just the error ones
+	screenshots: bool,
+}
+
+impl SessionStore {
+	/// `persist_htmls/<id>` in the state dir, or a no-op store without the `xdg` feature
+	pub fn state_dir(id: impl Into<String>) -> Self {
+		let id = id.into();
+		match Self::state_root() {
+			Some(root) => Self {
+				dir: Some(root.join(&id)),
+				sessions_root: Some(root),
+				screenshots: true,
+				id,
+			},
+			None => Self::disabled(id),
+		}
+	}
+
+	/// Where [`Self::state_dir`] stores keep their session directories; None without the `xdg` feature
+	pub fn state_root() -> Option<PathBuf> {
+		#[cfg(feature = "xdg")]
+		{
+			Some(v_utils::xdg_state_dir!("persist_htmls"))
+		}
+		#[cfg(not(feature = "xdg"))]
+		{
+			None
+		}
+	}
+
+	/// Write everything straight into `dir`
+	pub fn at(dir: impl Into<PathBuf>, id: impl Into<String>) -> Self {
+		Self {
+			id: id.into(),
+			dir: Some(dir.into()),
+			sessions_root: None,
+			screenshots: true,
+		}
+	}
+
+	/// Save nothing
+	pub fn disabled(id: impl Into<String>) -> Self {
+		Self {
+			id: id.into(),
+			dir: None,
+			sessions_root: None,
+			screenshots: true,
+		}
+	}
+
+	/// Whether routine snapshots include a screenshot (`save_screenshots` in config)
+	pub fn with_screenshots(mut self, screenshots: bool) -> Self {
+		self.screenshots = screenshots;
+		self
+	}
+
+	pub fn id(&self) -> &str {
+		&self.id
+	}
+
+	pub fn dir(&self) -> Option<&Path> {
+		self.dir.as_deref()
+	}
+
+	/// Where the run report goes when `--report` isn't given
+	pub fn report_path(&self) -> Option<PathBuf> {
+		self.dir.as_ref().map(|dir| dir.join("report.json"))
+	}
+
+	/// Log of the run's LLM calls in `llm/`, if `log_llm_exchanges` is on and the store saves anything
+	pub fn exchange_log(&self, config: &AppConfig) -> Option<ExchangeLog> {
+		let dir = self.dir.as_ref().filter(|_| config.log_llm_exchanges)?;
+		Some(ExchangeLog::new(dir.join("llm"), config))
+	}
+
+	/// Create the session directory with its `meta.json`, and clean up expired sessions
+	pub fn init(&self) {
+		let Some(dir) = &self.dir else {
+			return;
+		};
+		if let Err(e) = std::fs::create_dir_all(dir) {
+			elog!("Failed to create session HTML dir: {}", e);
+		}
+
+		let meta = serde_json::json!({ "created_at": now_secs() });
+		self.write_meta(&meta);
+
+		if let Some(root) = &self.sessions_root {
+			cleanup_old_sessions(root, SESSION_MAX_AGE_SECS);
+		}
+	}
+
+	/// Add the run's LLM usage totals to `meta.json`
+	pub fn record_llm_usage(&self, totals: &UsageTotals) {
+		let Some(mut meta) = self.read_meta() else {
+			return;
+		};
+		if let Some(meta) = meta.as_object_mut() {
+			meta.insert(
+				"llm_usage".to_string(),
+				serde_json::json!({
+					"calls": totals.calls,
+					"calls_with_tokens": totals.calls_with_tokens,
+					"input_tokens": totals.input_tokens,
+					"output_tokens": totals.output_tokens,
+					"estimated_cost_usd": totals.cost,
+					"calls_without_cost": totals.calls_without_cost,
+				}),
+			);
+		}
+		self.write_meta(&meta);
+	}
+
+	/// Save the current page's HTML for debugging, named after the page URL (see [`snapshot_stem`]) and listed with
+	/// the full URL in the session's `index.tsv`, plus a full-page PNG of it under the same name if
+	/// `save_screenshots` is on
+	/// Returns the HTML file written, None if the store is disabled
+	pub async fn save_page_snapshot(&self, page: &Page) -> Result<Option<PathBuf>> {
+		self.snapshot(page, self.screenshots).await
+	}
+
+	/// [`Self::save_page_snapshot`] for a page something went wrong on: always with the screenshot
+	pub async fn save_error_snapshot(&self, page: &Page) -> Result<Option<PathBuf>> {
+		self.snapshot(page, true).await
+	}
+
+	async fn snapshot(&self, page: &Page, screenshot: bool) -> Result<Option<PathBuf>> {
+		let Some(html_dir) = &self.dir else {
+			return Ok(None);
+		};
+		std::fs::create_dir_all(html_dir).map_err(|e| eyre!("Failed to create HTML dir: {e}"))?;
+
+		let url = page.url().await.ok().flatten().unwrap_or_default();
+
+		let html = page.evaluate("document.documentElement.outerHTML").await.map_err(|e| eyre!("Failed to get page HTML: {e}"))?;
+		let html_str = html.value().and_then(|v| v.as_str()).unwrap_or("<html></html>");
+
+		let stem = snapshot_stem(&url, now_secs(), SNAPSHOT_SEQ.fetch_add(1, Ordering::Relaxed));
+		let filepath = html_dir.join(format!("{stem}.html"));
+
+		std::fs::write(&filepath, html_str).map_err(|e| eyre!("Failed to write HTML file: {e}"))?;
+		log!("Saved page HTML to: {}", filepath.display());
+		if let Err(e) = append_index(html_dir, &stem, &url) {
+			elog!("Failed to update snapshot index: {e}");
+		}
+
+		// The HTML is saved either way, a screenshot failure is only logged
+		if screenshot {
+			let png_path = html_dir.join(format!("{stem}.png"));
+			match full_page_screenshot(page)
+				.await
+				.and_then(|png| std::fs::write(&png_path, png).map_err(|e| eyre!("Failed to write screenshot: {e}")))
+			{
+				Ok(()) => log!("Saved page screenshot to: {}", png_path.display()),
+				Err(e) => elog!("Failed to save page screenshot: {e}"),
+			}
+		}
+		Ok(Some(filepath))
+	}
+
+	fn read_meta(&self) -> Option<serde_json::Value> {
+		let meta_path = self.dir.as_ref()?.join("meta.json");
+		Some(read_json(&meta_path).unwrap_or_else(|| serde_json::json!({})))
+	}
+
+	fn write_meta(&self, meta: &serde_json::Value) {
+		let Some(dir) = &self.dir else {
+			return;
+		};
+		if let Err(e) = std::fs::write(dir.join("meta.json"), serde_json::to_string_pretty(meta).unwrap_or_default()) {
+			elog!("Failed to write meta.json: {}", e);
+		}
+	}
+}
+
+/// File name (without extension) for a snapshot of `url`: `<secs>_<seq>_<host>_<last path segment>_<url hash>`,
+/// at most [`MAX_FILENAME_CHARS`] with the extension. The hash keeps URLs differing only in their query apart.
+fn snapshot_stem(url: &str, secs: u64, seq: u32) -> String {
+	let path = url.split_once("://").map_or(url, |(_, rest)| rest);
+	let path = path.split(['?', '#']).next().unwrap_or_default();
+	let segment = path.split('/').skip(1).filter(|s| !s.is_empty()).last().unwrap_or_default();
+	let label = if segment.is_empty() {
+		url_host(url).to_string()
+	} else {
+		format!("{}_{segment}", url_host(url))
+	};
+	let safe_label: String = label.chars().map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' }).collect();
+
+	let prefix = format!("{secs}_{seq:04}_");
+	let hash = &fnv1a_hex(url)[..8];
+	// Room for the prefix, "_<hash>" and the longest extension (".html")
+	let room = MAX_FILENAME_CHARS.saturating_sub(prefix.len() + 1 + hash.len() + ".html".len());
+	let safe_label: String = safe_label.chars().take(room).collect();
+	format!("{prefix}{safe_label}_{hash}")
+}
+
+/// Record which URL the snapshot `stem` is of, as a `<stem>\t<url>` line of the directory's `index.tsv`
+fn append_index(dir: &Path, stem: &str, url: &str) -> std::io::Result<()> {
+	let mut index = std::fs::OpenOptions::new().create(true).append(true).open(dir.join("index.tsv"))?;
+	writeln!(index, "{stem}\t{url}")
+}
+
+/// PNG of the whole page, beyond the viewport
+async fn full_page_screenshot(page: &Page) -> Result<Vec<u8>> {
+	let params = ScreenshotParams::builder()
+		.format(CaptureScreenshotFormat::Png)
+		.full_page(true)
+		.capture_beyond_viewport(true)
+		.build();
+	match tokio::time::timeout(SCREENSHOT_TIMEOUT, page.screenshot(params)).await {
+		Ok(result) => result.map_err(|e| eyre!("Failed to capture screenshot: {e}")),
+		Err(_) => Err(eyre!("Screenshot timed out after {}s", SCREENSHOT_TIMEOUT.as_secs())),
+	}
+}
+
+fn now_secs() -> u64 {
+	std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// A saved session directory, as listed by `sessions list`
+#[derive(Clone, Debug)]
+pub struct SessionSummary {
+	pub id: String,
+	pub dir: PathBuf,
+	/// Unix timestamp from `meta.json`, else the directory's modification time
+	pub created_at: Option<u64>,
+	/// URLs the run processed with their outcome, from `report.json`; without one, the URLs snapshotted per
+	/// `index.tsv`, with no outcome
+	pub urls: Vec<(String, Option<String>)>,
+	/// `exit_status` from `report.json`, None if the run never wrote it
+	pub exit_status: Option<String>,
+}
+
+impl SessionSummary {
+	pub fn read(dir: &Path) -> Self {
+		let report = read_json(&dir.join("report.json"));
+		let urls = match report.as_ref().and_then(|r| r["urls"].as_array()) {
+			Some(urls) => urls
+				.iter()
+				.map(|url| {
+					let outcome = url["outcome"]["status"].as_str().map(|status| match url["outcome"]["error"].as_str() {
+						Some(error) => format!("{status}: {error}"),
+						None => status.to_string(),
+					});
+					(url["url"].as_str().unwrap_or_default().to_string(), outcome)
+				})
+				.collect(),
+			None => {
+				let mut urls: Vec<(String, Option<String>)> = Vec::new();
+				for (_, url) in read_index(dir) {
+					if !urls.iter().any(|(seen, _)| *seen == url) {
+						urls.push((url, None));
+					}
+				}
+				urls
+			}
+		};
+		Self {
+			id: dir.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+			dir: dir.to_path_buf(),
+			created_at: session_created_at(dir),
+			urls,
+			exit_status: report.as_ref().and_then(|r| r["exit_status"].as_str()).map(str::to_string),
+		}
+	}
+}
+
+/// Every session directory under `root`, oldest first
+pub fn list_sessions(root: &Path) -> Vec<SessionSummary> {
+	let Ok(entries) = std::fs::read_dir(root) else {
+		return Vec::new();
+	};
+	let mut sessions: Vec<SessionSummary> = entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).map(|p| SessionSummary::read(&p)).collect();
+	sessions.sort_by_key(|s| (s.created_at, s.id.clone()));
+	sessions
+}
+
+/// (snapshot file stem, URL) pairs from the directory's `index.tsv`, in the order they were taken
+pub fn read_index(dir: &Path) -> Vec<(String, String)> {
+	let Ok(content) = std::fs::read_to_string(dir.join("index.tsv")) else {
+		return Vec::new();
+	};
+	content
+		.lines()
+		.filter_map(|line| line.split_once('\t'))
+		.map(|(stem, url)| (stem.to_string(), url.to_string()))
+		.collect()
+}
+
+/// The `.html` files of a directory by name, which for a session's snapshots is the order they were taken in
+pub fn snapshot_files(dir: &Path) -> Result<Vec<PathBuf>> {
+	let entries = std::fs::read_dir(dir).map_err(|e| eyre!("Failed to read session dir {}: {e}", dir.display()))?;
+	let mut files: Vec<PathBuf> = entries.flatten().map(|e| e.path()).filter(|p| p.extension().is_some_and(|ext| ext == "html")).collect();
+	files.sort();
+	Ok(files)
+}
+
+fn read_json(path: &Path) -> Option<serde_json::Value> {
+	std::fs::read_to_string(path).ok().and_then(|content| serde_json::from_str(&content).ok())
+}
+
+fn session_created_at(dir: &Path) -> Option<u64> {
+	let meta_path = dir.join("meta.json");
+	if meta_path.exists() {
+		// Read created_at from meta.json
+		read_json(&meta_path).and_then(|v| v["created_at"].as_u64())
+	} else {
+		// Fallback: use directory modification time
+		std::fs::metadata(dir)
+			.ok()
+			.and_then(|m| m.modified().ok())
+			.and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+			.map(|d| d.as_secs())
+	}
+}
+
+/// A session created at `created_at` is more than `max_age_secs` old at `now`
+fn is_expired(created_at: u64, now: u64, max_age_secs: u64) -> bool {
+	now.saturating_sub(created_at) > max_age_secs
+}
+
+/// Remove the session directories under `root` more than `max_age_secs` old, returning how many were
+pub fn cleanup_old_sessions(root: &Path, max_age_secs: u64) -> usize {
+	let now = now_secs();
+	let mut removed = 0;
+	for session in list_sessions(root) {
+		if let Some(created_at) = session.created_at
+			&& is_expired(created_at, now, max_age_secs)
+		{
+			if let Err(e) = std::fs::remove_dir_all(&session.dir) {
+				elog!("Failed to cleanup old session {}: {}", session.dir.display(), e);
+			} else {
+				log!("Cleaned up old session: {}", session.id);
+				removed += 1;
+			}
+		}
+	}
+	removed
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn label(url: &str) -> String {
+		let stem = snapshot_stem(url, 1700000000, 7);
+		let label = stem.strip_prefix("1700000000_0007_").expect("secs and seq prefix");
+		label.rsplit_once('_').expect("hash suffix").0.to_string()
+	}
+
+	#[test]
+	fn cleanup_removes_sessions_past_12h() {
+		let root = std::env::temp_dir().join(format!("uni_headless_cleanup_{}", std::process::id()));
+		let now = now_secs();
+		let session = |id: &str, age_secs: Option<u64>| {
+			let dir = root.join(id);
+			std::fs::create_dir_all(&dir).unwrap();
+			std::fs::write(dir.join("page.html"), "<html></html>").unwrap();
+			if let Some(age) = age_secs {
+				std::fs::write(dir.join("meta.json"), serde_json::json!({ "created_at": now - age }).to_string()).unwrap();
+			}
+		};
+		session("expired", Some(13 * 60 * 60));
+		session("long_expired", Some(30 * 24 * 60 * 60));
+		session("recent", Some(60 * 60));
+		// Just inside the limit (with room for the test's own run time)
+		session("almost_expired", Some(SESSION_MAX_AGE_SECS - 60));
+		// No meta.json: the directory, just created, is as old as it gets
+		session("no_meta", None);
+
+		let removed = cleanup_old_sessions(&root, SESSION_MAX_AGE_SECS);
+		let mut left: Vec<String> = list_sessions(&root).into_iter().map(|s| s.id).collect();
+		left.sort();
+		std::fs::remove_dir_all(&root).unwrap();
+		assert_eq!(removed, 2);
+		assert_eq!(left, ["almost_expired", "no_meta", "recent"]);
+	}
+
+	#[test]
+	fn session_expiry_boundary() {
+		assert!(!is_expired(1000, 1000 + SESSION_MAX_AGE_SECS, SESSION_MAX_AGE_SECS));
+		assert!(is_expired(1000, 1001 + SESSION_MAX_AGE_SECS, SESSION_MAX_AGE_SECS));
+		// A clock set back never expires anything
+		assert!(!is_expired(2000, 1000, SESSION_MAX_AGE_SECS));
+	}
+
+	#[test]
+	fn snapshot_stem_uses_last_path_segment() {
+		assert_eq!(label("https://moodle.example.fr/mod/quiz/attempt.php?attempt=5&page=2"), "moodle_example_fr_attempt_php");
+		assert_eq!(label("https://moodle.example.fr/course/view/"), "moodle_example_fr_view");
+		assert_eq!(label("https://moodle.example.fr/course//view//#top"), "moodle_example_fr_view");
+		assert_eq!(label("https://moodle.example.fr/"), "moodle_example_fr");
+		assert_eq!(label("https://moodle.example.fr"), "moodle_example_fr");
+	}
+
+	#[test]
+	fn snapshot_stem_keeps_queries_apart() {
+		let a = snapshot_stem("https://m.example.fr/mod/quiz/attempt.php?page=1", 1, 1);
+		let b = snapshot_stem("https://m.example.fr/mod/quiz/attempt.php?page=2", 1, 1);
+		assert_ne!(a, b);
+		assert!(a.ends_with(&fnv1a_hex("https://m.example.fr/mod/quiz/attempt.php?page=1")[..8]));
+	}
+
+	#[test]
+	fn snapshot_stem_fits_file_name_limit() {
+		let url = format!("https://m.example.fr/{}", "segment".repeat(50));
+		let stem = snapshot_stem(&url, 1700000000, 9999);
+		assert!(stem.len() + ".html".len() <= MAX_FILENAME_CHARS);
+		assert!(stem.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_'));
+	}
+}
@@ -0,0 +1,117 @@
+//! Drawing question images in the terminal, with the best backend it supports
+//!
+//! The backend is picked once per run ([`init`], from `image_renderer`): the kitty graphics protocol when the terminal
+//! speaks it, sixel on the terminals known for it, chafa when it's installed, and otherwise no drawing at all: callers
+//! print the image's alt text or URL in its place.
+
+use std::{io::Write, sync::OnceLock};
+
+use base64::Engine;
+use color_eyre::{
+	Result,
+	eyre::{bail, eyre},
+};
+
+use crate::config::ImageRenderer;
+
+/// Base64 data sent per kitty graphics escape, the most the protocol takes
+const KITTY_CHUNK_LEN: usize = 4096;
+
+static RENDERER: OnceLock<ImageRenderer> = OnceLock::new();
+
+/// Draw images with `renderer` for the rest of the run (detecting the backend if it's `auto`); only the first call
+/// has an effect
+pub fn init(configured: ImageRenderer) {
+	let _ = RENDERER.set(resolve(configured));
+	tracing::debug!("Image renderer: {:?}", renderer());
+}
+
+/// The backend images are drawn with, never `Auto`
+pub fn renderer() -> ImageRenderer {
+	*RENDERER.get_or_init(|| resolve(ImageRenderer::Auto))
+}
+
+fn resolve(renderer: ImageRenderer) -> ImageRenderer {
+	match renderer {
+		ImageRenderer::Auto => detect(),
+		chosen => chosen,
+	}
+}
+
+/// Best backend for the terminal we're in, from the environment
+fn detect() -> ImageRenderer {
+	let env = |name: &str| std::env::var(name).unwrap_or_default();
+	let (term, term_program) = (env("TERM"), env("TERM_PROGRAM"));
+	if std::env::var_os("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || term.contains("ghostty") || term_program == "WezTerm" {
+		return ImageRenderer::Kitty;
+	}
+	let sixel_terminal = term.contains("sixel") || ["foot", "mlterm", "contour", "yaft"].iter().any(|t| term.starts_with(t));
+	if sixel_terminal && (on_path("img2sixel") || on_path("chafa")) {
+		return ImageRenderer::Sixel;
+	}
+	if on_path("chafa") {
+		return ImageRenderer::Chafa;
+	}
+	ImageRenderer::Text
+}
+
+fn on_path(program: &str) -> bool {
+	std::env::var_os("PATH").is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Draw the image (base64 data and media type) on stdout, at most `max_cols` columns wide. Nothing is drawn with the
+/// `text` backend.
+pub async fn render(base64_data: &str, media_type: &str, max_cols: u32) -> Result<()> {
+	match renderer() {
+		ImageRenderer::Text => Ok(()),
+		// The protocol only takes PNG directly; chafa converts the rest
+		ImageRenderer::Kitty if media_type == "image/png" => render_kitty(base64_data, max_cols),
+		ImageRenderer::Kitty => render_with(&["chafa", "--format", "kitty", "--size", &format!("{max_cols}x")], base64_data).await,
+		ImageRenderer::Sixel if on_path("img2sixel") => render_with(&["img2sixel", "--width", &format!("{}", max_cols * 8)], base64_data).await,
+		ImageRenderer::Sixel => render_with(&["chafa", "--format", "sixels", "--size", &format!("{max_cols}x")], base64_data).await,
+		ImageRenderer::Chafa | ImageRenderer::Auto => render_with(&["chafa", "--size", &format!("{max_cols}x")], base64_data).await,
+	}
+}
+
+/// Write the PNG straight to the terminal as kitty graphics escapes
+fn render_kitty(base64_data: &str, max_cols: u32) -> Result<()> {
+	let mut escapes = String::new();
+	let chunks: Vec<&[u8]> = base64_data.as_bytes().chunks(KITTY_CHUNK_LEN).collect();
+	for (i, chunk) in chunks.iter().enumerate() {
+		let more = u8::from(i + 1 < chunks.len());
+		let chunk = std::str::from_utf8(chunk).map_err(|e| eyre!("Invalid base64: {e}"))?;
+		match i {
+			0 => escapes.push_str(&format!("\x1b_Gf=100,a=T,c={max_cols},m={more};{chunk}\x1b\\")),
+			_ => escapes.push_str(&format!("\x1b_Gm={more};{chunk}\x1b\\")),
+		}
+	}
+	escapes.push('\n');
+	let mut stdout = std::io::stdout().lock();
+	stdout
+		.write_all(escapes.as_bytes())
+		.and_then(|()| stdout.flush())
+		.map_err(|e| eyre!("Failed to write to the terminal: {e}"))
+}
+
+/// Write the image to a temporary file and print what `command` (followed by the file's path) outputs for it
+async fn render_with(command: &[&str], base64_data: &str) -> Result<()> {
+	let bytes = base64::engine::general_purpose::STANDARD.decode(base64_data).map_err(|e| eyre!("Failed to decode base64: {e}"))?;
+	let mut file = tempfile::Builder::new()
+		.prefix("uni_headless_img_")
+		.tempfile()
+		.map_err(|e| eyre!("Failed to create temp file: {e}"))?;
+	file.write_all(&bytes).map_err(|e| eyre!("Failed to write temp file: {e}"))?;
+
+	let (program, args) = command.split_first().expect("command has a program");
+	let output = tokio::process::Command::new(program)
+		.args(args)
+		.arg(file.path())
+		.output()
+		.await
+		.map_err(|e| eyre!("Failed to run {program}: {e}"))?;
+	if !output.status.success() {
+		bail!("{program} failed: {}", String::from_utf8_lossy(&output.stderr));
+	}
+	print!("{}", String::from_utf8_lossy(&output.stdout));
+	Ok(())
+}
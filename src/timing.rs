@@ -0,0 +1,115 @@
+//! Waits between browser actions, from the `[timings]` section of the config
+//!
+//! The run sets them once at startup with [`init`]; anything waiting before that gets the defaults, which are the
+//! durations that used to be hard-coded. Every click and form submission is also preceded by
+//! [`action_delay`], a random pause so the requests don't come at machine-regular intervals.
+
+use std::{sync::OnceLock, time::Duration};
+
+use color_eyre::{Result, eyre::bail};
+use serde::{Deserialize, Serialize};
+
+/// All in milliseconds
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(default)]
+pub struct Timings {
+	/// After an action that loads another page (a login step, opening the VPL editor), for it to load (default: 3000)
+	pub post_navigation_wait_ms: u64,
+	/// For a page to settle before acting on it: login forms, the federation dropdown filtering (default: 2000)
+	pub page_settle_wait_ms: u64,
+	/// Between the steps of a multi-step action: pasting into the VPL editor, opening a dropdown, after a page
+	/// change was seen (default: 1000)
+	pub step_wait_ms: u64,
+	/// After saving VPL code, before running the evaluation (default: 3000)
+	pub post_save_wait_ms: u64,
+	/// After clicking Evaluate, before reading the result (default: 10000)
+	pub evaluation_wait_ms: u64,
+	/// Between polls of the webservice for a VPL evaluation result (default: 3000)
+	pub evaluation_poll_interval_ms: u64,
+	/// After submitting or checking a quiz question, starting or finishing an attempt (default: 2000)
+	pub post_submit_wait_ms: u64,
+	/// For a submitted quiz page to give way to the next one before the submit counts as swallowed (default: 10000)
+	pub submit_navigation_timeout_ms: u64,
+	/// After a click that only changes the page in place: editor buttons, confirmation modals, and before retrying a
+	/// failed click (default: 500)
+	pub post_click_wait_ms: u64,
+	/// Between checks while waiting on the page: for an attempt to start, for a manual page change (default: 500)
+	pub poll_interval_ms: u64,
+	/// Between checks for a VPL editor tab to show (default: 150)
+	pub editor_poll_interval_ms: u64,
+	/// Random pause before every click and form submission, picked uniformly in `[min, max]`; `[0, 0]` disables it
+	/// (default: [100, 500])
+	pub action_delay_ms: [u64; 2],
+	/// Random pause between the keys when typing into a field that needs real key events, picked like
+	/// `action_delay_ms` (default: [30, 120])
+	pub keystroke_delay_ms: [u64; 2],
+	/// For a STACK input's validation to show up under it after typing (default: 3000)
+	pub stack_validation_timeout_ms: u64,
+}
+
+impl Default for Timings {
+	fn default() -> Self {
+		Self {
+			post_navigation_wait_ms: 3000,
+			page_settle_wait_ms: 2000,
+			step_wait_ms: 1000,
+			post_save_wait_ms: 3000,
+			evaluation_wait_ms: 10_000,
+			evaluation_poll_interval_ms: 3000,
+			post_submit_wait_ms: 2000,
+			submit_navigation_timeout_ms: 10_000,
+			post_click_wait_ms: 500,
+			poll_interval_ms: 500,
+			editor_poll_interval_ms: 150,
+			action_delay_ms: [100, 500],
+			keystroke_delay_ms: [30, 120],
+			stack_validation_timeout_ms: 3000,
+		}
+	}
+}
+
+impl Timings {
+	pub fn validate(&self) -> Result<()> {
+		let [min, max] = self.action_delay_ms;
+		if min > max {
+			bail!("timings.action_delay_ms: min ({min}) is above max ({max})");
+		}
+		let [min, max] = self.keystroke_delay_ms;
+		if min > max {
+			bail!("timings.keystroke_delay_ms: min ({min}) is above max ({max})");
+		}
+		Ok(())
+	}
+}
+
+static TIMINGS: OnceLock<Timings> = OnceLock::new();
+
+/// Use `timings` for the rest of the run; only the first call has an effect
+pub fn init(timings: Timings) {
+	let _ = TIMINGS.set(timings);
+}
+
+pub fn timings() -> &'static Timings {
+	TIMINGS.get_or_init(Timings::default)
+}
+
+/// Sleep for `ms` milliseconds, e.g. `wait(timings().post_submit_wait_ms)`
+pub async fn wait(ms: u64) {
+	tokio::time::sleep(Duration::from_millis(ms)).await;
+}
+
+/// The politeness pause before a click or form submission
+pub async fn action_delay() {
+	let [min, max] = timings().action_delay_ms;
+	if max > 0 {
+		wait(rand::random_range(min..=max)).await;
+	}
+}
+
+/// The pause between two typed keys
+pub async fn keystroke_delay() {
+	let [min, max] = timings().keystroke_delay_ms;
+	if max > 0 {
+		wait(rand::random_range(min..=max)).await;
+	}
+}
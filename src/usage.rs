@@ -0,0 +1,165 @@
+//! Token usage and cost of the LLM calls made during a run. The cost is the one the provider reports (`ask_llm`'s
+//! `cost_cents`), else an estimate from the tokens and the configured `llm_prices`.
+
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+use serde::Serialize;
+
+use crate::config::ModelPrice;
+
+/// What an LLM call was made for
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum LlmTask {
+	/// Answering a quiz question
+	Quiz,
+	/// Writing VPL code
+	Code,
+}
+
+impl fmt::Display for LlmTask {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LlmTask::Quiz => write!(f, "quiz"),
+			LlmTask::Code => write!(f, "code"),
+		}
+	}
+}
+
+/// One LLM call
+#[derive(Clone, Debug)]
+pub struct UsageEntry {
+	/// Number of the question the call was for; None for VPL code
+	pub question: Option<usize>,
+	pub task: LlmTask,
+	pub model: String,
+	/// (input, output) tokens; None when the provider didn't report them
+	pub tokens: Option<(u64, u64)>,
+	/// Cost in US cents; None when the provider didn't report one
+	pub cost_cents: Option<f64>,
+}
+
+impl UsageEntry {
+	/// Cost in USD: the reported one, else from the tokens if its model has a price
+	fn cost(&self, prices: &HashMap<String, ModelPrice>) -> Option<f64> {
+		if let Some(cents) = self.cost_cents {
+			return Some(cents / 100.0);
+		}
+		let (input, output) = self.tokens?;
+		let price = prices.get(&self.model)?;
+		Some((input as f64 * price.input + output as f64 * price.output) / 1_000_000.0)
+	}
+}
+
+/// Sums over a set of calls
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct UsageTotals {
+	pub calls: usize,
+	/// Calls that reported their token counts; the token sums only cover these
+	pub calls_with_tokens: usize,
+	pub input_tokens: u64,
+	pub output_tokens: u64,
+	/// Cost in USD of the calls that reported one or have both tokens and a price
+	pub cost: f64,
+	/// Calls left out of `cost`
+	pub calls_without_cost: usize,
+}
+
+impl UsageTotals {
+	fn add(&mut self, entry: &UsageEntry, prices: &HashMap<String, ModelPrice>) {
+		self.calls += 1;
+		if let Some((input, output)) = entry.tokens {
+			self.calls_with_tokens += 1;
+			self.input_tokens += input;
+			self.output_tokens += output;
+		}
+		match entry.cost(prices) {
+			Some(cost) => self.cost += cost,
+			None => self.calls_without_cost += 1,
+		}
+	}
+
+	/// Cost column: blank when nothing could be priced, marked as a lower bound when some calls couldn't
+	fn cost_display(&self) -> String {
+		match self.calls_without_cost {
+			n if n == self.calls => "-".to_string(),
+			0 => format!("${:.4}", self.cost),
+			_ => format!(">=${:.4}", self.cost),
+		}
+	}
+}
+
+impl fmt::Display for UsageTotals {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{} LLM call(s)", self.calls)?;
+		if self.calls_with_tokens > 0 {
+			write!(f, ", {} tokens in / {} out", self.input_tokens, self.output_tokens)?;
+		}
+		if self.calls_without_cost < self.calls {
+			write!(f, ", ~{}", self.cost_display())?;
+		}
+		Ok(())
+	}
+}
+
+/// Every LLM call of the run. Shared through `&`, so it can sit on the run's `QuizLlm`.
+#[derive(Debug, Default)]
+pub struct UsageTracker {
+	entries: Mutex<Vec<UsageEntry>>,
+}
+
+impl UsageTracker {
+	pub fn record(&self, entry: UsageEntry) {
+		self.entries.lock().unwrap().push(entry);
+	}
+
+	pub fn totals(&self, prices: &HashMap<String, ModelPrice>) -> UsageTotals {
+		let mut totals = UsageTotals::default();
+		for entry in self.entries.lock().unwrap().iter() {
+			totals.add(entry, prices);
+		}
+		totals
+	}
+
+	/// Totals of the calls made for question `question`, retries and escalations included
+	pub fn question_totals(&self, question: usize, prices: &HashMap<String, ModelPrice>) -> UsageTotals {
+		let mut totals = UsageTotals::default();
+		for entry in self.entries.lock().unwrap().iter().filter(|e| e.question == Some(question)) {
+			totals.add(entry, prices);
+		}
+		totals
+	}
+
+	/// Table of calls, tokens and cost by task and model, with a total row. Token columns show "-" for rows where
+	/// no call reported usage.
+	pub fn summary_table(&self, prices: &HashMap<String, ModelPrice>) -> String {
+		let mut rows: Vec<((LlmTask, String), UsageTotals)> = Vec::new();
+		let mut total = UsageTotals::default();
+		for entry in self.entries.lock().unwrap().iter() {
+			let key = (entry.task, entry.model.clone());
+			match rows.iter_mut().find(|(k, _)| *k == key) {
+				Some((_, totals)) => totals.add(entry, prices),
+				None => {
+					let mut totals = UsageTotals::default();
+					totals.add(entry, prices);
+					rows.push((key, totals));
+				}
+			}
+			total.add(entry, prices);
+		}
+		rows.sort_by(|a, b| a.0.cmp(&b.0));
+
+		let tokens = |n: u64, totals: &UsageTotals| if totals.calls_with_tokens == 0 { "-".to_string() } else { n.to_string() };
+		let mut table = format!("{:<6} {:<8} {:>6} {:>12} {:>12} {:>12}\n", "task", "model", "calls", "tokens in", "tokens out", "cost");
+		let rows = rows.iter().map(|((task, model), totals)| (task.to_string(), model.as_str(), totals));
+		for (task, model, totals) in rows.chain(std::iter::once(("total".to_string(), "", &total))) {
+			table.push_str(&format!(
+				"{task:<6} {model:<8} {:>6} {:>12} {:>12} {:>12}\n",
+				totals.calls,
+				tokens(totals.input_tokens, totals),
+				tokens(totals.output_tokens, totals),
+				totals.cost_display()
+			));
+		}
+		table
+	}
+}
@@ -0,0 +1,44 @@
+//! Argument parsing of the binary. Runs stop at a bad `--proxy` scheme, which is checked after the config is built
+//! and before any browser starts, so getting to that error means the arguments parsed and reached the config.
+
+use std::process::{Command, Output};
+
+const URL: &str = "https://moodle.example/mod/quiz/view.php?id=1";
+
+fn run_cli(args: &[&str]) -> Output {
+	let home = tempfile::tempdir().unwrap();
+	let config = home.path().join("config.toml");
+	std::fs::write(&config, "username = \"u\"\n").unwrap();
+	Command::new(env!("CARGO_BIN_EXE_uni_headless"))
+		.args(args)
+		.args(["--config", config.to_str().unwrap()])
+		.env("HOME", home.path())
+		.env("XDG_STATE_HOME", home.path().join("state"))
+		.env("RUST_BACKTRACE", "0")
+		.env("NO_COLOR", "1")
+		.output()
+		.expect("binary runs")
+}
+
+fn assert_reached_config(output: &Output) {
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	assert_eq!(output.status.code(), Some(1), "{stderr}");
+	assert!(stderr.contains("Unsupported proxy scheme \"ftp\""), "{stderr}");
+}
+
+#[test]
+fn bare_url_alias_takes_settings_flags() {
+	assert_reached_config(&run_cli(&[URL, "--proxy", "ftp://proxy.example:21", "--institution", "Example"]));
+}
+
+#[test]
+fn run_subcommand_takes_settings_flags() {
+	assert_reached_config(&run_cli(&["run", URL, "--proxy", "ftp://proxy.example:21", "--institution", "Example"]));
+}
+
+#[test]
+fn unknown_flag_is_a_usage_error() {
+	let output = run_cli(&[URL, "--no-such-flag"]);
+	assert_eq!(output.status.code(), Some(2));
+	assert!(String::from_utf8_lossy(&output.stderr).contains("unexpected argument '--no-such-flag'"));
+}
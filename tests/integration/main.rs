@@ -1 +1,31 @@
-//TODO!!!: This. Should be the entry point to all integration tests of rust projects, following https://matklad.github.io/2021/02/27/delete-cargo-integration-tests.html
+//! Entry point of all integration tests, following https://matklad.github.io/2021/02/27/delete-cargo-integration-tests.html
+//!
+//! Saved pages live in `fixtures/`, next to the JSON their parse is expected to give. `UPDATE_GOLDEN=1 cargo test`
+//! rewrites the JSON from the current output instead of comparing.
+
+mod cli;
+mod page_state;
+mod parse;
+
+use std::path::PathBuf;
+
+fn fixture_path(name: &str) -> PathBuf {
+	PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/integration/fixtures").join(name)
+}
+
+/// Contents of `fixtures/<name>`
+pub fn fixture(name: &str) -> String {
+	let path = fixture_path(name);
+	std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("Failed to read fixture {}: {e}", path.display()))
+}
+
+/// Compare `actual` with the JSON in `fixtures/<name>`
+pub fn assert_golden(name: &str, actual: &impl serde::Serialize) {
+	let actual = serde_json::to_string_pretty(actual).expect("serializable") + "\n";
+	let path = fixture_path(name);
+	if std::env::var_os("UPDATE_GOLDEN").is_some() {
+		std::fs::write(&path, &actual).unwrap_or_else(|e| panic!("Failed to write {}: {e}", path.display()));
+		return;
+	}
+	assert_eq!(actual, fixture(name), "{name} differs from the current output (UPDATE_GOLDEN=1 to rewrite it)");
+}
@@ -0,0 +1,48 @@
+use uni_headless::{
+	failure::FailureKind,
+	page_state::{PageState, classify_page},
+};
+
+use crate::fixture;
+
+fn classify(url: &str, name: &str) -> PageState {
+	classify_page(url, &fixture(name))
+}
+
+#[test]
+fn attempt_already_submitted() {
+	let state = classify("https://moodle.example.fr/mod/quiz/attempt.php?attempt=5120&cmid=388", "error_attempt_submitted.html");
+	assert_eq!(state, PageState::AttemptAlreadySubmitted);
+	assert_eq!(state.failure_kind(), FailureKind::Access);
+}
+
+#[test]
+fn not_enrolled() {
+	// French text, on a URL that doesn't give it away
+	assert_eq!(classify("https://moodle.example.fr/course/view.php?id=42", "error_not_enrolled.html"), PageState::NotEnrolled);
+	assert_eq!(classify("https://moodle.example.fr/enrol/index.php?id=42", "error_not_enrolled.html"), PageState::NotEnrolled);
+}
+
+#[test]
+fn no_permission() {
+	let state = classify("https://moodle.example.fr/mod/quiz/startattempt.php", "error_no_permission.html");
+	assert_eq!(
+		state,
+		PageState::NoPermission("Sorry, but you do not currently have permissions to do that (Attempt quizzes).".to_string())
+	);
+	assert_eq!(state.failure_kind(), FailureKind::Access);
+}
+
+#[test]
+fn other_moodle_error() {
+	let state = classify("https://moodle.example.fr/mod/quiz/view.php?id=99999", "error_invalid_module.html");
+	assert_eq!(state, PageState::MoodleError("Invalid course module ID".to_string()));
+	assert!(state.is_error());
+}
+
+#[test]
+fn quiz_page_is_normal() {
+	let state = classify("https://moodle.example.fr/mod/quiz/attempt.php?attempt=5120&cmid=388", "quiz_page.html");
+	assert_eq!(state, PageState::Normal);
+	assert!(!state.is_error());
+}
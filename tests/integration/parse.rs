@@ -0,0 +1,135 @@
+use uni_headless::{
+	DragDropIntoText, Question, QuestionKind,
+	parse::{parse_questions_from_html, parse_response_fields, parse_vpl_from_html},
+};
+
+use crate::{assert_golden, fixture};
+
+fn parse_fixture(name: &str) -> Vec<Question> {
+	parse_questions_from_html(&fixture(name)).expect("fixture parses")
+}
+
+#[test]
+fn ordering() {
+	let questions = parse_fixture("ordering.html");
+	assert_golden("ordering.json", &questions);
+
+	let [question] = &questions[..] else {
+		panic!("expected one question, got {}", questions.len())
+	};
+	assert_eq!(question.ordering_input_name(), Some("q1207:3_response_1207_3"));
+	let display = question.to_string();
+	assert!(display.contains("Items to order:\n1. Code generation\n2. Lexing\n3. Parsing\n4. Type checking\n"), "{display}");
+}
+
+#[test]
+fn ddwtos() {
+	let questions = parse_fixture("ddwtos.html");
+	assert_golden("ddwtos.json", &questions);
+
+	let [Question::DragDropIntoText(classed), Question::DragDropIntoText(unclassed)] = &questions[..] else {
+		panic!("expected two drag-drop questions, got {questions:?}")
+	};
+	let zones = |dd: &DragDropIntoText| -> Vec<(String, usize, usize, usize)> { dd.drop_zones.iter().map(|z| (z.input_name.clone(), z.place_number, z.group, z.current_choice)).collect() };
+	assert_eq!(zones(classed), [("q1207:2_p1".into(), 1, 1, 0), ("q1207:2_p2".into(), 2, 1, 1), ("q1207:2_p3".into(), 3, 2, 0)]);
+	// The placeholder left behind by a dragged choice isn't a choice of its own
+	assert_eq!(classed.choices.len(), 4);
+	// Without the classes, the place comes from the _pN suffix and the sequencecheck input is left out
+	assert_eq!(zones(unclassed), [("q1207:4_p1".into(), 1, 1, 2), ("q1207:4_p2".into(), 2, 1, 0)]);
+}
+
+#[test]
+fn vpl_multi_file() {
+	let question = parse_vpl_from_html(&fixture("vpl_multi_file.html")).expect("fixture parses").expect("a VPL question");
+	assert_golden("vpl_multi_file.json", &question);
+
+	let Question::CodeSubmission { required_files, module_id, .. } = &question else {
+		panic!("expected a code submission, got {question:?}")
+	};
+	assert_eq!(module_id, "9031");
+	// Each file keeps its own editor's content
+	let files: Vec<(&str, &str)> = required_files.iter().map(|f| (f.name.as_str(), f.content.as_str())).collect();
+	assert_eq!(
+		files,
+		[
+			("main.py", "# Ecrivez votre programme ici\nfrom queue2 import Queue"),
+			("stack.py", "class Stack:\n    pass"),
+			("queue2.py", "from stack import Stack\n\nclass Queue:\n    pass"),
+		]
+	);
+}
+
+#[test]
+fn quiz_page() {
+	let questions = parse_fixture("quiz_page.html");
+	assert_golden("quiz_page.json", &questions);
+
+	use QuestionKind::*;
+	let kinds: Vec<QuestionKind> = questions.iter().map(Question::kind).collect();
+	// The matching table has two selects in its .ablock, which makes it a cloze, as it is for the in-browser parser
+	assert_eq!(
+		kinds,
+		[SingleChoice, MultiChoice, ShortAnswer, Numerical, FillInBlanks, FillInBlanks, Essay, Description, Unknown]
+	);
+	let Question::Unknown { qtype_class, input_names, .. } = &questions[8] else { unreachable!() };
+	assert_eq!(qtype_class, "randomsamatch");
+	assert_eq!(input_names, &["q77:9_slider"]);
+}
+
+#[test]
+fn numerical_units() {
+	let questions = parse_fixture("numerical_units.html");
+	assert_golden("numerical_units.json", &questions);
+
+	let [radios, typed] = &questions[..] else {
+		panic!("expected two questions, got {}", questions.len())
+	};
+	let (name, units) = radios.numerical_units().expect("unit radios");
+	assert_eq!(name, "q91:1_unit");
+	assert_eq!(units.iter().map(|u| (u.value.as_str(), u.text.as_str())).collect::<Vec<_>>(), [("m", "m"), ("km", "km")]);
+	assert!(radios.numerical_unit_radios());
+	assert!(radios.to_string().contains("Unit: select from: m, km"));
+
+	// No unit control at all: the unit, if any, goes into the answer field
+	assert!(typed.numerical_units().is_none());
+	assert!(typed.to_string().contains("Unit: type it after the number in the answer field"));
+}
+
+#[test]
+fn response_fields() {
+	let fields = parse_response_fields(&fixture("quiz_page.html")).expect("fixture parses");
+	let values = |name: &str| fields.iter().filter(|(n, _)| n == name).map(|(_, v)| v.as_str()).collect::<Vec<_>>();
+
+	assert_eq!(values("q77:1_:sequencecheck"), ["1"]);
+	// Only the checked radio, and a checkbox's hidden "0" next to its "1" when checked
+	assert_eq!(values("q77:1_answer"), ["1"]);
+	assert_eq!(values("q77:2_choice0"), ["0"]);
+	assert_eq!(values("q77:2_choice2"), ["0", "1"]);
+	assert_eq!(values("q77:4_answer"), ["120"]);
+	assert_eq!(values("q77:4_unit"), ["m"]);
+	// Selects without a selected option submit their first one
+	assert_eq!(values("q77:5_sub0"), ["0"]);
+	assert_eq!(values("q77:5_sub1"), ["2"]);
+	assert_eq!(values("q77:7_answer"), [""]);
+	assert_eq!(values("q77:9_slider"), ["3"]);
+}
+
+#[test]
+fn question_layout() {
+	let questions = parse_fixture("question_layout.html");
+	assert_golden("question_layout.json", &questions);
+
+	let texts: Vec<&str> = questions.iter().map(Question::question_text).collect();
+	assert_eq!(
+		texts[0],
+		"What does this function print\nwhen called with `n = 3`?\n\n```\ndef f(n):\n    for i in range(n):\n        print(i * 2, end=\" \")\n```\n\nGive the output on `one line`."
+	);
+	assert_eq!(
+		texts[1],
+		"A hash map gives:\n\n• average `O(1)` lookup,\n• no ordering of its keys,\n• and collisions, handled by:\n\n• chaining\n• open addressing\n\nWhich statement is false?"
+	);
+	assert_eq!(
+		texts[2],
+		"Complete the truth table:\n\n| A   | B   | A XOR B |\n| --- | --- | ------- |\n| 0   | 0   | 0       |\n| 0   | 1   | 1       |\n| 1   | 1   | ?       |\n\nWhat is the missing value?"
+	);
+}